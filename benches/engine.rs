@@ -0,0 +1,131 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rust_exercise::{
+    collector::{
+        CsvLayout, CsvSource, MmapReader, ParallelCsvSource, TransactionSource, DEFAULT_BATCH_SIZE,
+    },
+    PaymentsEngine, Transaction, Workload,
+};
+use std::fs::File;
+use tokio::runtime::Runtime;
+
+fn engine_throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let workload = Workload::default();
+    let transactions: Vec<Transaction> = workload.generate().collect();
+
+    let mut group = c.benchmark_group("engine");
+    group.throughput(Throughput::Elements(transactions.len() as u64));
+    group.sample_size(10);
+    for workers in [1, 4] {
+        group.bench_with_input(
+            BenchmarkId::new("workers", workers),
+            &workers,
+            |b, &workers| {
+                b.to_async(&runtime)
+                    .iter(|| process(workers, transactions.clone()))
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("batched", workers),
+            &workers,
+            |b, &workers| {
+                b.to_async(&runtime)
+                    .iter(|| process_batched(workers, transactions.clone()))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn parse_throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let workload = Workload::default();
+    let mut input = Vec::new();
+    workload.write_csv(&mut input).unwrap();
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.to_async(&runtime)
+            .iter(|| drain(CsvSource::new(input.as_slice())))
+    });
+    for threads in [2, 4] {
+        let layout = CsvLayout {
+            parse_threads: threads,
+            ..CsvLayout::default()
+        };
+        group.bench_with_input(
+            BenchmarkId::new("threads", threads),
+            &layout,
+            |b, layout| {
+                b.to_async(&runtime)
+                    .iter(|| drain(ParallelCsvSource::with_layout(input.as_slice(), layout)))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn read_throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let workload = Workload {
+        transactions: 1_000_000,
+        ..Workload::default()
+    };
+    let path = std::env::temp_dir().join("rust-exercise-bench-input.csv");
+    workload.write_csv(File::create(&path).unwrap()).unwrap();
+
+    let mut group = c.benchmark_group("read");
+    group.throughput(Throughput::Bytes(path.metadata().unwrap().len()));
+    group.sample_size(10);
+    group.bench_function("file", |b| {
+        b.to_async(&runtime)
+            .iter(|| drain(CsvSource::new(File::open(&path).unwrap())))
+    });
+    group.bench_function("mmap", |b| {
+        b.to_async(&runtime)
+            .iter(|| drain(CsvSource::new(MmapReader::open(&path).unwrap())))
+    });
+    group.finish();
+}
+
+// Parses all records of `source`
+async fn drain<S: TransactionSource>(mut source: S) {
+    while let Some(result) = source.next_transaction().await {
+        result.unwrap();
+    }
+}
+
+// Sends the transactions through the channel one by one
+async fn process(workers: usize, transactions: Vec<Transaction>) {
+    let (mut payments_engine, sender) = PaymentsEngine::with_workers(workers);
+    let producer = tokio::spawn(async move {
+        for transaction in transactions {
+            sender.send(transaction).await.unwrap();
+        }
+    });
+    payments_engine.process_transactions().await.unwrap();
+    producer.await.unwrap();
+}
+
+// Sends the transactions in batches, like the collector does for files
+async fn process_batched(workers: usize, transactions: Vec<Transaction>) {
+    let (mut payments_engine, _) = PaymentsEngine::with_workers(workers);
+    let sender = payments_engine.batch_sender();
+    let producer = tokio::spawn(async move {
+        for batch in transactions.chunks(DEFAULT_BATCH_SIZE) {
+            sender.send(batch.to_vec().into()).await.unwrap();
+        }
+    });
+    payments_engine.process_transactions().await.unwrap();
+    producer.await.unwrap();
+}
+
+criterion_group!(
+    benches,
+    engine_throughput,
+    parse_throughput,
+    read_throughput
+);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The gRPC server is part of the runtime
+    if std::env::var_os("CARGO_FEATURE_RUNTIME").is_none() {
+        return Ok(());
+    }
+    // Use the bundled protoc, so building doesn't depend on a system installation
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_prost_build::compile_protos("proto/payments.proto")?;
+    tonic_prost_build::compile_protos("proto/health.proto")?;
+    Ok(())
+}
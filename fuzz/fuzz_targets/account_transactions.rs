@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_exercise::{fuzzing, Transaction};
+
+fuzz_target!(|transactions: Vec<Transaction>| fuzzing::account_transactions(&transactions));
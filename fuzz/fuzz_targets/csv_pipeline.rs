@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rust_exercise::fuzzing;
+
+fuzz_target!(|input: &[u8]| fuzzing::csv_pipeline(input));
@@ -1,131 +1,1311 @@
-use crate::{error::EngineError, transaction::Transaction};
-use std::collections::{HashMap, HashSet};
+use crate::{
+    amount::{Amount, RoundingMode, DEFAULT_PRECISION},
+    clock::{default_clock, Clock, SharedClock},
+    dedupe::{DuplicatePolicy, Reuse},
+    dispute::{DisputeState, RedisputePolicy},
+    dispute_window::DisputeWindow,
+    error::EngineError,
+    event::AccountEvent,
+    export::{ExportedAccount, ExportedTransaction},
+    fees::{FeeSchedule, Fees},
+    fx::{Balance, Currency, FxRates},
+    history::{
+        HistoryRetention, HistorySpill, HistoryState, LinkedEntry, SettledHistory,
+        TransactionHistory, TransactionRecord,
+    },
+    limits::{self, DailyVolume, Limits},
+    locked::LockedAccountPolicy,
+    outcome::TransactionOutcome,
+    report::OpenDispute,
+    transaction::{ClientId, Transaction, TransactionId, TransactionType},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
 
-#[derive(serde::Serialize, PartialEq, Debug)]
+/// Balances, transaction history and settings of one client.
+///
+/// The balances can only be changed by applying transactions or events, which keeps the
+/// invariants checked by [`Account::check_invariants`]; they are read with the getters or as an
+/// [`AccountView`].
+#[derive(Clone, PartialEq, Debug)]
 pub struct Account {
-    pub client: u16,
-    #[serde(serialize_with = "round_serialize")]
-    pub available: f32,
-    #[serde(serialize_with = "round_serialize")]
-    pub held: f32,
-    #[serde(serialize_with = "round_serialize")]
-    pub total: f32,
+    client: ClientId,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    // Funds in currencies other than the base currency, which the balances above are in
+    foreign: BTreeMap<Currency, Balance>,
+    transaction_history: TransactionHistory,
+    // Transactions of the history in dispute
+    open_disputes: usize,
+    // Transaction whose chargeback locked the account, while it is locked
+    locking_chargeback: Option<TransactionId>,
+    // Amount still held by every hold, by its id
+    holds: BTreeMap<TransactionId, Amount>,
+    fees: Option<Fees>,
+    // Precision and mode fees and converted amounts are rounded with, exactly without
+    fee_rounding: Option<(u32, RoundingMode)>,
+    fees_collected: Amount,
+    limits: Limits,
+    withdrawn: DailyVolume,
+    fx_rates: Option<Arc<FxRates>>,
+    dispute_window: Option<DisputeWindow>,
+    redispute_policy: RedisputePolicy,
+    duplicate_policy: DuplicatePolicy,
+    locked_policy: LockedAccountPolicy,
+    // Transactions received while locked, applied once the account is unlocked
+    queued: VecDeque<Transaction>,
+    // Transactions above the approval threshold, applied once they are approved
+    pending: BTreeMap<TransactionId, Transaction>,
+    clock: SharedClock,
+    // Number of events applied so far
+    sequence: u64,
+}
+
+// State of an account as written by disk-backed stores, its settings come from the engine
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct AccountState {
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    #[serde(default)]
+    foreign: BTreeMap<Currency, Balance>,
+    history: HistoryState,
+    open_disputes: usize,
+    locking_chargeback: Option<TransactionId>,
+    holds: BTreeMap<TransactionId, Amount>,
+    fees_collected: Amount,
+    withdrawn: DailyVolume,
+    queued: VecDeque<Transaction>,
+    pending: BTreeMap<TransactionId, Transaction>,
+    sequence: u64,
+}
+
+/// Balances of an account at one point in time, as written to the output.
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct AccountView {
+    pub client: ClientId,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
     pub locked: bool,
-    #[serde(skip_serializing)]
-    transaction_history: HashMap<u32, f32>,
-    #[serde(skip_serializing)]
-    transactions_in_dispute: HashSet<u32>,
+    /// Fees charged to the account, only if the engine charges fees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fees_collected: Option<Amount>,
+}
+
+impl AccountView {
+    /// Rounds the balances to `precision` decimal places with `mode`.
+    pub fn round(self, precision: u32, mode: RoundingMode) -> Self {
+        AccountView {
+            available: self.available.round(precision, mode),
+            held: self.held.round(precision, mode),
+            total: self.total.round(precision, mode),
+            fees_collected: self.fees_collected.map(|fees| fees.round(precision, mode)),
+            ..self
+        }
+    }
+}
+
+/// Outcome of a transaction with the event it caused, or why it is invalid.
+pub(crate) type Executed = Result<(TransactionOutcome, Option<AccountEvent>), EngineError>;
+
+/// Rules the accounts of a [`crate::PaymentsEngine`] or a [`crate::Settlement`] are opened with,
+/// so both apply transactions the same way.
+#[derive(Clone, Debug)]
+pub(crate) struct AccountRules {
+    pub precision: u32,
+    pub rounding_mode: RoundingMode,
+    pub limits: Limits,
+    pub fee_schedule: Option<FeeSchedule>,
+    pub fx_rates: Option<Arc<FxRates>>,
+    pub dispute_window: Option<DisputeWindow>,
+    pub redispute_policy: RedisputePolicy,
+    pub duplicate_policy: DuplicatePolicy,
+    pub locked_policy: LockedAccountPolicy,
+    pub clock: Arc<dyn Clock>,
+}
+
+impl Default for AccountRules {
+    fn default() -> Self {
+        AccountRules {
+            precision: DEFAULT_PRECISION,
+            rounding_mode: RoundingMode::default(),
+            limits: Limits::default(),
+            fee_schedule: None,
+            fx_rates: None,
+            dispute_window: None,
+            redispute_policy: RedisputePolicy::default(),
+            duplicate_policy: DuplicatePolicy::default(),
+            locked_policy: LockedAccountPolicy::default(),
+            clock: default_clock(),
+        }
+    }
 }
 
 impl Account {
-    pub fn new(client: u16) -> Self {
+    pub fn new(client: ClientId) -> Self {
+        Self::with_history_spill(client, None)
+    }
+
+    /// Creates an account that moves its least recently used transactions to `history_spill`.
+    pub fn with_history_spill(client: ClientId, history_spill: Option<HistorySpill>) -> Self {
         Account {
             client,
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            total: Amount::ZERO,
             locked: false,
-            transaction_history: HashMap::with_capacity(1),
-            transactions_in_dispute: HashSet::new(),
+            foreign: BTreeMap::new(),
+            transaction_history: TransactionHistory::new(client, history_spill),
+            open_disputes: 0,
+            locking_chargeback: None,
+            holds: BTreeMap::new(),
+            fees: None,
+            fee_rounding: None,
+            fees_collected: Amount::ZERO,
+            limits: Limits::default(),
+            withdrawn: DailyVolume::default(),
+            fx_rates: None,
+            dispute_window: None,
+            redispute_policy: RedisputePolicy::default(),
+            duplicate_policy: DuplicatePolicy::default(),
+            locked_policy: LockedAccountPolicy::default(),
+            queued: VecDeque::new(),
+            pending: BTreeMap::new(),
+            clock: SharedClock::default(),
+            sequence: 0,
+        }
+    }
+
+    /// Subjects the deposits and withdrawals of the account to `limits`.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Charges `fees` on the deposits and withdrawals of the account.
+    pub fn with_fees(mut self, fees: Fees) -> Self {
+        self.fees = Some(fees);
+        self
+    }
+
+    // Replaces the limits and the fees of an existing account, e.g. on a new live policy
+    pub(crate) fn set_policy(&mut self, limits: Limits, fees: Option<Fees>) {
+        self.limits = limits;
+        self.fees = fees;
+    }
+
+    /// Rounds the fees when they are charged, and the amounts conversions credit, to `precision`
+    /// decimal places with `mode`.
+    pub fn with_fee_rounding(mut self, precision: u32, mode: RoundingMode) -> Self {
+        self.fee_rounding = Some((precision, mode));
+        self
+    }
+
+    /// Converts between the currencies of `fx_rates`, which has the currency of the balances as
+    /// its base currency. Without rates conversions are invalid.
+    pub fn with_fx_rates(mut self, fx_rates: Arc<FxRates>) -> Self {
+        self.fx_rates = Some(fx_rates);
+        self
+    }
+
+    /// Declines disputes of transactions older than `dispute_window`.
+    pub fn with_dispute_window(mut self, dispute_window: Option<DisputeWindow>) -> Self {
+        self.dispute_window = dispute_window;
+        self
+    }
+
+    /// Reopens disputes of resolved transactions only if `policy` allows it.
+    pub fn with_redispute_policy(mut self, policy: RedisputePolicy) -> Self {
+        self.redispute_policy = policy;
+        self
+    }
+
+    /// Handles transactions reusing the id of an earlier transaction according to `policy`.
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    /// Handles transactions received while the account is locked according to `policy`.
+    pub fn with_locked_policy(mut self, policy: LockedAccountPolicy) -> Self {
+        self.locked_policy = policy;
+        self
+    }
+
+    /// Remembers only the transactions selected by `retention` for later disputes.
+    pub fn with_history_retention(mut self, retention: HistoryRetention) -> Self {
+        self.transaction_history.set_retention(retention);
+        self
+    }
+
+    /// Makes room in the history for `transactions` deposits and withdrawals, which spares
+    /// growing it step by step for clients with many of them.
+    pub fn with_history_capacity(mut self, transactions: usize) -> Self {
+        self.transaction_history.reserve(transactions);
+        self
+    }
+
+    /// Drops or archives the records of transactions whose dispute is settled per `settled`.
+    pub fn with_settled_history(mut self, settled: SettledHistory) -> Self {
+        self.transaction_history.set_settled(settled);
+        self
+    }
+
+    /// Takes the day of transactions without timestamp from `clock`, instead of the system time.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = SharedClock(clock);
+        self
+    }
+
+    // Subjects the account to `rules`, the fees of its client are taken from their schedule
+    pub(crate) fn with_rules(self, rules: &AccountRules) -> Self {
+        let account = self
+            .with_limits(rules.limits)
+            .with_dispute_window(rules.dispute_window)
+            .with_redispute_policy(rules.redispute_policy)
+            .with_duplicate_policy(rules.duplicate_policy)
+            .with_locked_policy(rules.locked_policy)
+            .with_fee_rounding(rules.precision, rules.rounding_mode)
+            .with_clock(rules.clock.clone());
+        let account = match &rules.fx_rates {
+            Some(fx_rates) => account.with_fx_rates(fx_rates.clone()),
+            None => account,
+        };
+        match &rules.fee_schedule {
+            Some(fee_schedule) => {
+                let fees = fee_schedule.fees_of(account.client);
+                account.with_fees(fees)
+            }
+            None => account,
+        }
+    }
+
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn available(&self) -> Amount {
+        self.available
+    }
+
+    pub fn held(&self) -> Amount {
+        self.held
+    }
+
+    pub fn total(&self) -> Amount {
+        self.total
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Funds in other currencies than the base currency, by currency.
+    pub fn foreign_balances(&self) -> &BTreeMap<Currency, Balance> {
+        &self.foreign
+    }
+
+    /// Whether no transaction ever changed the account, e.g. because it was only opened by a
+    /// dispute for a client without deposits.
+    pub fn is_ghost(&self) -> bool {
+        self.sequence == 0
+    }
+
+    /// Copy of the balances, as written to the output and returned by queries.
+    pub fn view(&self) -> AccountView {
+        AccountView {
+            client: self.client,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            fees_collected: self.fees.map(|_| self.fees_collected),
+        }
+    }
+
+    /// Transactions of the account still in dispute, ordered by id. Their age is taken from the
+    /// clock, for those with a timestamp.
+    pub fn open_disputes(&self) -> Result<Vec<OpenDispute>, EngineError> {
+        if self.open_disputes == 0 {
+            return Ok(Vec::new());
+        }
+        let now = self.clock.0.now();
+        let mut disputes: Vec<_> = self
+            .transaction_history
+            .disputed()?
+            .into_iter()
+            .map(|(tx, record)| OpenDispute {
+                client: self.client,
+                tx,
+                amount: record.funds(),
+                age: record
+                    .timestamp
+                    .map(|timestamp| now.saturating_sub(timestamp)),
+            })
+            .collect();
+        disputes.sort_unstable_by_key(|dispute| dispute.tx);
+        Ok(disputes)
+    }
+
+    pub(crate) fn state(&self) -> AccountState {
+        AccountState {
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            foreign: self.foreign.clone(),
+            history: self.transaction_history.state(),
+            open_disputes: self.open_disputes,
+            locking_chargeback: self.locking_chargeback,
+            holds: self.holds.clone(),
+            fees_collected: self.fees_collected,
+            withdrawn: self.withdrawn,
+            queued: self.queued.clone(),
+            pending: self.pending.clone(),
+            sequence: self.sequence,
         }
     }
 
+    /// Replaces the state of the account, keeping its settings.
+    pub(crate) fn restore(&mut self, state: AccountState) -> Result<(), EngineError> {
+        self.available = state.available;
+        self.held = state.held;
+        self.total = state.total;
+        self.locked = state.locked;
+        self.foreign = state.foreign;
+        self.transaction_history.restore(state.history)?;
+        self.open_disputes = state.open_disputes;
+        self.locking_chargeback = state.locking_chargeback;
+        self.holds = state.holds;
+        self.fees_collected = state.fees_collected;
+        self.withdrawn = state.withdrawn;
+        self.queued = state.queued;
+        self.pending = state.pending;
+        self.sequence = state.sequence;
+        // The state may have been written by another version, or edited on disk
+        self.check_invariants()
+    }
+
+    /// State of the account with its whole transaction history, as exported to JSON.
+    pub(crate) fn export(&self) -> Result<ExportedAccount, EngineError> {
+        let mut records = self.transaction_history.records()?;
+        records.sort_unstable_by_key(|&(transaction_id, record)| (record.sequence, transaction_id));
+        Ok(ExportedAccount {
+            client: self.client,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            foreign: self.foreign.clone(),
+            locking_chargeback: self.locking_chargeback,
+            holds: self.holds.clone(),
+            fees_collected: self.fees_collected,
+            withdrawn: self.withdrawn,
+            queued: self.queued.clone(),
+            pending: self.pending.values().copied().collect(),
+            sequence: self.sequence,
+            transactions: records
+                .into_iter()
+                .map(|(transaction_id, record)| ExportedTransaction::new(transaction_id, record))
+                .collect(),
+        })
+    }
+
+    /// Replaces the state of the account by an exported one, keeping its settings.
+    pub(crate) fn import(&mut self, account: ExportedAccount) -> Result<(), EngineError> {
+        self.available = account.available;
+        self.held = account.held;
+        self.total = account.total;
+        self.locked = account.locked;
+        self.foreign = account.foreign;
+        self.open_disputes = account
+            .transactions
+            .iter()
+            .filter(|transaction| transaction.dispute == DisputeState::Disputed)
+            .count();
+        self.transaction_history.load(
+            account
+                .transactions
+                .iter()
+                .map(|transaction| (transaction.tx, transaction.record()))
+                .collect(),
+        )?;
+        self.locking_chargeback = account.locking_chargeback;
+        self.holds = account.holds;
+        self.fees_collected = account.fees_collected;
+        self.withdrawn = account.withdrawn;
+        self.queued = account.queued;
+        self.pending = account
+            .pending
+            .into_iter()
+            .map(|transaction| (transaction.tx, transaction))
+            .collect();
+        self.sequence = account.sequence;
+        // Documents may have been edited by hand
+        self.check_invariants()
+    }
+
+    /// Rebuilds an account from its events, which fails if they leave it inconsistent.
+    pub fn from_events<'a, I: IntoIterator<Item = &'a AccountEvent>>(
+        client: ClientId,
+        events: I,
+    ) -> Result<Self, EngineError> {
+        let mut account = Account::new(client);
+        events
+            .into_iter()
+            .try_for_each(|event| account.apply(event))?;
+        account.check_invariants()?;
+        Ok(account)
+    }
+
     pub fn apply_transaction(
         &mut self,
+        transaction: Transaction,
+    ) -> Result<TransactionOutcome, EngineError> {
+        self.execute(transaction).map(|(outcome, _)| outcome)
+    }
+
+    /// Applies `transaction` and returns the event it was turned into, if it changed the account.
+    pub fn execute(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<(TransactionOutcome, Option<AccountEvent>), EngineError> {
+        // The chargeback usually locked the account, it can be reversed regardless
+        let unlocking = matches!(
+            transaction.r#type,
+            TransactionType::Unlock | TransactionType::ChargebackReversal
+        );
+        if self.locked && !unlocking {
+            let queueable = match transaction.r#type {
+                TransactionType::Transfer => false,
+                // Approving a transfer credits the counterparty, which the engine does right away
+                TransactionType::Approve => self
+                    .pending
+                    .get(&transaction.tx)
+                    .is_none_or(|staged| staged.r#type != TransactionType::Transfer),
+                _ => true,
+            };
+            return self.refuse_while_locked(transaction, queueable);
+        }
+        let approved = match transaction.r#type {
+            TransactionType::Approve | TransactionType::Reject => {
+                let Some(&staged) = self.pending.get(&transaction.tx) else {
+                    return Ok((TransactionOutcome::NotPending, None));
+                };
+                if transaction.r#type == TransactionType::Reject {
+                    let event = AccountEvent::Unstaged {
+                        client: self.client,
+                        tx: transaction.tx,
+                    };
+                    self.commit(&event)?;
+                    return Ok((TransactionOutcome::Applied, Some(event)));
+                }
+                Some(staged)
+            }
+            _ => None,
+        };
+        // The approved transaction is checked again, against the balances at its approval
+        let transaction = approved.unwrap_or(transaction);
+        let today = transaction.timestamp.unwrap_or_else(|| self.clock.0.now());
+        let today = limits::day_of(today);
+        let (outcome, event) = self.decide(transaction, today)?;
+        let (outcome, event) = match (outcome, transaction.amount) {
+            (TransactionOutcome::Applied, Some(amount))
+                if approved.is_none()
+                    && transaction.r#type.introduces_transaction()
+                    && transaction.r#type != TransactionType::Convert
+                    && self.limits.requires_approval(amount) =>
+            {
+                let event = AccountEvent::Staged {
+                    client: transaction.client,
+                    tx: transaction.tx,
+                    kind: transaction.r#type,
+                    amount,
+                    counterparty: transaction.counterparty,
+                    timestamp: transaction.timestamp,
+                };
+                (TransactionOutcome::PendingApproval, Some(event))
+            }
+            _ => (outcome, event),
+        };
+        match event {
+            Some(AccountEvent::Withdrew { amount, .. })
+            | Some(AccountEvent::TransferredOut { amount, .. }) => {
+                self.withdrawn.add(today, amount)
+            }
+            Some(AccountEvent::Converted { from, amount, .. }) => {
+                let fx_rates = self.fx_rates.as_ref();
+                let debited = fx_rates.and_then(|fx_rates| fx_rates.in_base(from, amount));
+                self.withdrawn.add(today, debited.unwrap_or(amount))
+            }
+            _ => {}
+        }
+        if let Some(event) = &event {
+            self.commit(event)?;
+            match event {
+                AccountEvent::ChargedBack { tx, .. } if self.locked => {
+                    tracing::warn!(client = self.client, tx, "Account locked by chargeback")
+                }
+                AccountEvent::Unlocked { .. } => {
+                    tracing::info!(client = self.client, "Account unlocked")
+                }
+                _ => {}
+            }
+        }
+        Ok((outcome, event))
+    }
+
+    /// Applies `transaction` like [`Self::execute`], or like [`Self::execute_duplicate`] if
+    /// `reuse` tells that its id was seen before. If it unlocked the account, the transactions
+    /// queued while it was locked are returned as well, to be applied next.
+    pub(crate) fn settle(
+        &mut self,
+        transaction: Transaction,
+        reuse: Reuse,
+    ) -> (Executed, VecDeque<Transaction>) {
+        let was_locked = self.locked;
+        let result = match reuse {
+            Reuse::No => self.execute(transaction),
+            reuse => self.execute_duplicate(transaction, reuse == Reuse::Yes),
+        };
+        let unlocked = if was_locked && !self.locked {
+            self.take_queued()
+        } else {
+            VecDeque::new()
+        };
+        (result, unlocked)
+    }
+
+    /// Applies `transaction`, which reuses the id of an earlier transaction, according to the
+    /// duplicate policy.
+    ///
+    /// If the id isn't `confirmed` to belong to this account and the account doesn't remember
+    /// it, the transaction is new after all and applied as usual.
+    pub fn execute_duplicate(
+        &mut self,
+        transaction: Transaction,
+        confirmed: bool,
+    ) -> Result<(TransactionOutcome, Option<AccountEvent>), EngineError> {
+        let Transaction {
+            r#type,
+            client,
+            tx,
+            amount,
+            ..
+        } = transaction;
+        let record = self.transaction_history.peek(tx)?;
+        match (record, self.duplicate_policy) {
+            (None, _) if !confirmed && !self.pending.contains_key(&tx) => self.execute(transaction),
+            // An earlier transaction that was declined or forgotten can't be replaced
+            (None, DuplicatePolicy::LastWriteWins) => self.execute(transaction),
+            (_, DuplicatePolicy::Reject) => Err(EngineError::DuplicateTransactionId(tx)),
+            _ if self.locked => self.refuse_while_locked(transaction, false),
+            (_, DuplicatePolicy::Skip) => Ok((TransactionOutcome::Duplicate, None)),
+            (Some(record), DuplicatePolicy::LastWriteWins) => {
+                if record.kind != r#type
+                    || matches!(r#type, TransactionType::Transfer | TransactionType::Convert)
+                {
+                    return Err(EngineError::DuplicateTransactionId(tx));
+                }
+                match record.state {
+                    DisputeState::Disputed => {
+                        return Ok((TransactionOutcome::AlreadyDisputed, None))
+                    }
+                    // The dispute of the earlier transaction is settled
+                    DisputeState::ChargedBack | DisputeState::ChargebackReversed => {
+                        return Ok((TransactionOutcome::Duplicate, None))
+                    }
+                    DisputeState::Normal | DisputeState::Resolved => {}
+                }
+                let amount = match r#type {
+                    TransactionType::Deposit => amount.ok_or(EngineError::NoAmountInDeposit)?,
+                    _ => amount.ok_or(EngineError::NoAmountInWitdrawal)?,
+                };
+                if amount == record.amount {
+                    return Ok((TransactionOutcome::Duplicate, None));
+                }
+                let fee = self.fee_of(r#type, amount);
+                let change = funds_change(r#type, amount, fee)
+                    - funds_change(r#type, record.amount, record.fee);
+                // A raised withdrawal withdraws the difference today, like a new withdrawal
+                let today =
+                    limits::day_of(transaction.timestamp.unwrap_or_else(|| self.clock.0.now()));
+                let raise = (amount - record.amount).max(Amount::ZERO);
+                let outcome = match r#type {
+                    TransactionType::Deposit => self.limits.check_deposit(amount),
+                    _ => {
+                        self.limits
+                            .check_raised_withdrawal(amount, raise, self.withdrawn.on(today))
+                    }
+                }
+                .or((self.available + change < Amount::ZERO)
+                    .then_some(TransactionOutcome::InsufficientFunds));
+                if let Some(outcome) = outcome {
+                    return Ok((outcome, None));
+                }
+                let event = AccountEvent::Amended {
+                    client,
+                    tx,
+                    amount,
+                    fee,
+                };
+                self.commit(&event)?;
+                if r#type == TransactionType::Withdrawal {
+                    self.withdrawn.add(today, raise);
+                }
+                Ok((TransactionOutcome::Applied, Some(event)))
+            }
+        }
+    }
+
+    // Checks `transaction` against the current state, without changing it
+    fn decide(
+        &self,
         Transaction {
-            r#type, tx, amount, ..
+            r#type,
+            client,
+            tx,
+            amount,
+            counterparty,
+            timestamp,
+            from_ccy,
+            to_ccy,
         }: Transaction,
-    ) -> Result<(), EngineError> {
-        if self.locked {
-            return Ok(());
+        today: u64,
+    ) -> Result<(TransactionOutcome, Option<AccountEvent>), EngineError> {
+        if r#type == TransactionType::Unlock {
+            return Ok((
+                TransactionOutcome::Applied,
+                Some(AccountEvent::Unlocked { client }),
+            ));
+        }
+        // The chargeback usually locked the account
+        if r#type == TransactionType::ChargebackReversal {
+            return self.decide_chargeback_reversal(client, tx);
         }
 
-        match r#type.as_ref() {
-            "withdrawal" => amount
-                .map(|amount| {
-                    self.withdrawal(amount);
-                    self.transaction_history.insert(tx, amount);
+        let event = match r#type {
+            TransactionType::Deposit => {
+                let amount = amount.ok_or(EngineError::NoAmountInDeposit)?;
+                if self.total.checked_add(amount).is_none() {
+                    return Err(EngineError::AmountOutOfRange(tx));
+                }
+                if let Some(outcome) = self.limits.check_deposit(amount) {
+                    return Ok((
+                        outcome,
+                        Some(AccountEvent::DepositDeclined { client, tx, amount }),
+                    ));
+                }
+                Some(AccountEvent::Deposited {
+                    client,
+                    tx,
+                    amount,
+                    fee: self.fee_of(r#type, amount),
+                    timestamp,
+                })
+            }
+            TransactionType::Withdrawal => {
+                let amount = amount.ok_or(EngineError::NoAmountInWitdrawal)?;
+                let fee = self.fee_of(r#type, amount);
+                if let Some(outcome) = self.check_debit(amount, fee, today) {
+                    return Ok((
+                        outcome,
+                        Some(AccountEvent::WithdrawalDeclined { client, tx, amount }),
+                    ));
+                }
+                Some(AccountEvent::Withdrew {
+                    client,
+                    tx,
+                    amount,
+                    fee,
+                    timestamp,
                 })
-                .ok_or(EngineError::NoAmountInWitdrawal),
-            "deposit" => amount
-                .map(|amount| {
-                    self.deposit(amount);
-                    self.transaction_history.insert(tx, amount);
+            }
+            // Only the sending side, the engine credits the counterparty
+            TransactionType::Transfer => {
+                let amount = amount.ok_or(EngineError::NoAmountInTransfer)?;
+                let counterparty = counterparty.ok_or(EngineError::InvalidCounterparty(tx))?;
+                if let Some(outcome) = self.check_debit(amount, Amount::ZERO, today) {
+                    return Ok((
+                        outcome,
+                        Some(AccountEvent::WithdrawalDeclined { client, tx, amount }),
+                    ));
+                }
+                Some(AccountEvent::TransferredOut {
+                    client,
+                    tx,
+                    counterparty,
+                    amount,
+                    timestamp,
+                })
+            }
+            TransactionType::Convert => {
+                let amount = amount.ok_or(EngineError::NoAmountInConversion)?;
+                let (Some(from), Some(to)) = (from_ccy, to_ccy) else {
+                    return Err(EngineError::InvalidConversion(tx));
+                };
+                let Some(fx_rates) = &self.fx_rates else {
+                    return Err(EngineError::NoExchangeRate(tx, from));
+                };
+                let rate = |currency| {
+                    fx_rates
+                        .rate(currency)
+                        .ok_or(EngineError::NoExchangeRate(tx, currency))
+                };
+                let converted = amount.exchange(rate(from)?, rate(to)?);
+                let converted = match self.fee_rounding {
+                    Some((precision, mode)) => converted.round(precision, mode),
+                    None => converted,
+                };
+                if !converted.is_positive() {
+                    return Err(EngineError::NonPositiveAmount(tx));
+                }
+                let base = fx_rates.base;
+                // The withdrawal limits apply to the value of the debited funds
+                let debited = fx_rates.in_base(from, amount).unwrap_or(amount);
+                if let Some(outcome) = self
+                    .limits
+                    .check_withdrawal(debited, self.withdrawn.on(today))
+                {
+                    return Ok((outcome, None));
+                }
+                if self.funds(leg(from, base)).available < amount {
+                    return Ok((TransactionOutcome::InsufficientFunds, None));
+                }
+                if self
+                    .funds(leg(to, base))
+                    .total()
+                    .checked_add(converted)
+                    .is_none()
+                {
+                    return Err(EngineError::AmountOutOfRange(tx));
+                }
+                Some(AccountEvent::Converted {
+                    client,
+                    tx,
+                    from,
+                    to,
+                    amount,
+                    converted,
+                    base,
+                    timestamp,
+                })
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                let Some(record) = self.transaction_history.peek(tx)? else {
+                    return Ok((TransactionOutcome::NoSuchTransaction, None));
+                };
+                let transition = match r#type {
+                    TransactionType::Dispute => record.state.dispute(self.redispute_policy),
+                    TransactionType::Resolve => record.state.resolve(),
+                    _ => record.state.charge_back(),
+                };
+                if let Err(outcome) = transition {
+                    return Ok((outcome, None));
+                }
+                Some(match r#type {
+                    TransactionType::Dispute if !self.within_dispute_window(&record, timestamp) => {
+                        return Ok((TransactionOutcome::OutsideDisputeWindow, None));
+                    }
+                    TransactionType::Dispute => AccountEvent::DisputeOpened { client, tx },
+                    TransactionType::Resolve => AccountEvent::DisputeResolved { client, tx },
+                    _ => AccountEvent::ChargedBack { client, tx },
                 })
-                .ok_or(EngineError::NoAmountInDeposit),
-            "dispute" => {
-                self.dispute(tx);
-                Ok(())
             }
-            "resolve" => {
-                self.resolve(tx);
-                Ok(())
+            TransactionType::Hold => {
+                let amount = amount.ok_or(EngineError::NoAmountInHold)?;
+                if self.holds.contains_key(&tx) {
+                    return Err(EngineError::DuplicateTransactionId(tx));
+                }
+                if self.available < amount {
+                    return Ok((TransactionOutcome::InsufficientFunds, None));
+                }
+                Some(AccountEvent::Held { client, tx, amount })
             }
-            "chargeback" => {
-                self.chargeback(tx);
-                Ok(())
+            // Without amount the whole hold is released
+            TransactionType::Release => {
+                let Some(&held) = self.holds.get(&tx) else {
+                    return Ok((TransactionOutcome::NoSuchTransaction, None));
+                };
+                let amount = amount.unwrap_or(held);
+                if amount > held {
+                    return Ok((TransactionOutcome::HoldExceeded, None));
+                }
+                Some(AccountEvent::Released { client, tx, amount })
+            }
+            TransactionType::Unlock | TransactionType::ChargebackReversal => {
+                unreachable!("handled before the lock check")
+            }
+            TransactionType::Approve | TransactionType::Reject => {
+                unreachable!("replaced by the staged transaction")
+            }
+        };
+        Ok((TransactionOutcome::Applied, event))
+    }
+
+    // Handles a transaction received while the account is locked according to the locked policy
+    fn refuse_while_locked(
+        &mut self,
+        transaction: Transaction,
+        queueable: bool,
+    ) -> Result<(TransactionOutcome, Option<AccountEvent>), EngineError> {
+        match self.locked_policy {
+            LockedAccountPolicy::Ignore => Ok((TransactionOutcome::AccountLocked, None)),
+            LockedAccountPolicy::Reject => Err(EngineError::AccountLocked(
+                transaction.tx,
+                transaction.client,
+            )),
+            LockedAccountPolicy::QueueUntilUnlock(capacity)
+                if queueable && self.queued.len() < capacity =>
+            {
+                self.queued.push_back(transaction);
+                Ok((TransactionOutcome::Queued, None))
+            }
+            LockedAccountPolicy::QueueUntilUnlock(_) => {
+                if queueable {
+                    tracing::warn!(
+                        client = self.client,
+                        tx = transaction.tx,
+                        "Queue of the locked account is full"
+                    );
+                }
+                Ok((TransactionOutcome::AccountLocked, None))
             }
-            unknown => Err(EngineError::InvalidRawTransactionType(unknown.into())),
         }
     }
 
-    fn deposit(&mut self, amount: f32) {
-        self.available += amount;
-        self.update_total()
+    /// Takes the transactions queued while the account was locked, in the order they arrived.
+    pub fn take_queued(&mut self) -> VecDeque<Transaction> {
+        std::mem::take(&mut self.queued)
     }
 
-    fn withdrawal(&mut self, amount: f32) {
-        if self.available - amount >= 0.0 {
-            self.available -= amount;
-            self.update_total();
+    // Undoing the chargeback of a withdrawal or transfer takes back the funds it credited, which
+    // have to be available
+    fn decide_chargeback_reversal(
+        &self,
+        client: ClientId,
+        tx: TransactionId,
+    ) -> Result<(TransactionOutcome, Option<AccountEvent>), EngineError> {
+        let Some(record) = self.transaction_history.peek(tx)? else {
+            return Ok((TransactionOutcome::NoSuchTransaction, None));
+        };
+        if let Err(outcome) = record.state.reverse_chargeback() {
+            return Ok((outcome, None));
+        }
+        // A conversion takes back the funds it debited, in their currency
+        let insufficient = match record.kind {
+            TransactionType::Deposit => false,
+            TransactionType::Convert => self.funds(record.currency).available < record.amount,
+            _ => self.available < record.funds(),
+        };
+        if insufficient {
+            return Ok((TransactionOutcome::InsufficientFunds, None));
         }
+        Ok((
+            TransactionOutcome::Applied,
+            Some(AccountEvent::ChargebackReversed { client, tx }),
+        ))
+    }
+
+    fn within_dispute_window(&self, record: &TransactionRecord, timestamp: Option<u64>) -> bool {
+        self.dispute_window.is_none_or(|window| {
+            window.contains(
+                (record.sequence, record.timestamp),
+                (self.sequence, timestamp),
+            )
+        })
+    }
+
+    // Reason a withdrawal or transfer of `amount` and `fee` can't happen, if any. Limits apply
+    // to the amount only.
+    fn check_debit(&self, amount: Amount, fee: Amount, today: u64) -> Option<TransactionOutcome> {
+        self.limits
+            .check_withdrawal(amount, self.withdrawn.on(today))
+            .or((self.available < amount + fee).then_some(TransactionOutcome::InsufficientFunds))
     }
 
-    fn dispute(&mut self, transaction_id: u32) {
-        if let Some(amount) = self.lookup_transaction_history(transaction_id) {
-            if self.transactions_in_dispute.get(&transaction_id).is_none() {
-                self.apply_dispute(amount, transaction_id)
+    // Fee of a deposit or withdrawal of `amount`, a deposit keeps at most all of its amount
+    fn fee_of(&self, kind: TransactionType, amount: Amount) -> Amount {
+        let Some(fees) = &self.fees else {
+            return Amount::ZERO;
+        };
+        let fee = match kind {
+            TransactionType::Deposit => fees.deposit.on(amount),
+            TransactionType::Withdrawal => fees.withdrawal.on(amount),
+            _ => return Amount::ZERO,
+        };
+        let fee = match self.fee_rounding {
+            Some((precision, mode)) => fee.round(precision, mode),
+            None => fee,
+        };
+        match kind {
+            TransactionType::Deposit => fee.min(amount),
+            _ => fee,
+        }
+    }
+
+    /// Folds `event` into the state of the account.
+    ///
+    /// Events are not checked again, they have to stem from [`Self::execute`] on the same
+    /// sequence of events.
+    ///
+    /// Fails only if the spilled transaction history can't be accessed.
+    pub fn apply(&mut self, event: &AccountEvent) -> Result<(), EngineError> {
+        match *event {
+            AccountEvent::Deposited {
+                tx,
+                amount,
+                fee,
+                timestamp,
+                ..
+            } => {
+                self.available += amount - fee;
+                self.fees_collected += fee;
+                self.record_transaction(tx, TransactionType::Deposit, amount, fee, timestamp)?;
+            }
+            AccountEvent::Withdrew {
+                tx,
+                amount,
+                fee,
+                timestamp,
+                ..
+            } => {
+                self.available -= amount + fee;
+                self.fees_collected += fee;
+                self.record_transaction(tx, TransactionType::Withdrawal, amount, fee, timestamp)?;
+            }
+            AccountEvent::DepositDeclined { .. } | AccountEvent::WithdrawalDeclined { .. } => {}
+            AccountEvent::DisputeOpened { tx, .. } => self.dispute(tx)?,
+            AccountEvent::DisputeResolved { tx, .. } => self.resolve(tx)?,
+            AccountEvent::ChargedBack { tx, .. } => self.chargeback(tx)?,
+            AccountEvent::ChargebackReversed { tx, .. } => self.reverse_chargeback(tx)?,
+            AccountEvent::Unlocked { .. } => self.unlock(),
+            AccountEvent::TransferredOut {
+                tx,
+                amount,
+                timestamp,
+                ..
+            } => {
+                self.available -= amount;
+                let fee = Amount::ZERO;
+                self.record_transaction(tx, TransactionType::Transfer, amount, fee, timestamp)?;
+            }
+            AccountEvent::TransferredIn { amount, .. } => self.available += amount,
+            AccountEvent::TransferReversed { amount, .. } => self.available -= amount,
+            AccountEvent::Held { tx, amount, .. } => {
+                self.available -= amount;
+                self.held += amount;
+                self.holds.insert(tx, amount);
+            }
+            AccountEvent::Released { tx, amount, .. } => {
+                self.available += amount;
+                self.held -= amount;
+                if let Some(held) = self.holds.get_mut(&tx) {
+                    *held -= amount;
+                    if held.is_zero() {
+                        self.holds.remove(&tx);
+                    }
+                }
+            }
+            AccountEvent::Converted {
+                tx,
+                from,
+                to,
+                amount,
+                converted,
+                base,
+                timestamp,
+                ..
+            } => {
+                let (from, to) = (leg(from, base), leg(to, base));
+                *self.funds_mut(from).0 -= amount;
+                *self.funds_mut(to).0 += converted;
+                let record = TransactionRecord {
+                    kind: TransactionType::Convert,
+                    amount,
+                    sequence: self.sequence,
+                    timestamp,
+                    state: DisputeState::Normal,
+                    fee: Amount::ZERO,
+                    currency: from,
+                    credit: Some(LinkedEntry {
+                        currency: to,
+                        amount: converted,
+                    }),
+                };
+                self.remember(tx, record)?;
+            }
+            AccountEvent::Amended {
+                tx, amount, fee, ..
+            } => self.amend(tx, amount, fee)?,
+            AccountEvent::Staged {
+                client,
+                tx,
+                kind,
+                amount,
+                counterparty,
+                timestamp,
+            } => {
+                let transaction = Transaction {
+                    r#type: kind,
+                    client,
+                    tx,
+                    amount: Some(amount),
+                    counterparty,
+                    timestamp,
+                    from_ccy: None,
+                    to_ccy: None,
+                };
+                self.pending.insert(tx, transaction);
+            }
+            AccountEvent::Unstaged { tx, .. } => {
+                self.pending.remove(&tx);
+            }
+        }
+        // An approved transaction is committed by the event it was turned into
+        if !matches!(event, AccountEvent::Staged { .. }) {
+            if let Some(tx) = event.introduced_transaction() {
+                self.pending.remove(&tx);
             }
         }
+        self.update_total();
+        self.sequence += 1;
+        Ok(())
+    }
+
+    // Applies an event the account decided on itself. Debug builds check the invariants after
+    // it, a violation is a bug of the engine rather than invalid input.
+    fn commit(&mut self, event: &AccountEvent) -> Result<(), EngineError> {
+        self.apply(event)?;
+        #[cfg(debug_assertions)]
+        self.check_invariants()?;
+        Ok(())
+    }
+
+    /// Checks the consistency of the balances, which holds after every event.
+    pub fn check_invariants(&self) -> Result<(), EngineError> {
+        let mut foreign_held = self.foreign.values().map(|balance| balance.held);
+        let violation = if self.total != self.available + self.held {
+            "total is not the sum of available and held funds"
+        } else if self.held < Amount::ZERO || foreign_held.any(|held| held < Amount::ZERO) {
+            "held funds are negative"
+        } else if self.open_disputes == 0
+            && (self.holds.is_empty() && self.held != Amount::ZERO
+                || self.foreign.values().any(|balance| !balance.held.is_zero()))
+        {
+            "funds are held without a dispute or hold"
+        } else {
+            return Ok(());
+        };
+        Err(EngineError::InvariantViolated {
+            client: self.client,
+            reason: violation,
+        })
+    }
+
+    // Replaces the amount and fee of an earlier deposit or withdrawal, and their effect on the
+    // balance
+    fn amend(
+        &mut self,
+        transaction_id: TransactionId,
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<(), EngineError> {
+        if let Some(record) = self.transaction_history.peek(transaction_id)? {
+            self.available += funds_change(record.kind, amount, fee)
+                - funds_change(record.kind, record.amount, record.fee);
+            self.fees_collected += fee - record.fee;
+            let record = TransactionRecord {
+                amount,
+                fee,
+                ..record
+            };
+            self.transaction_history.replace(transaction_id, record)?;
+        }
+        Ok(())
     }
 
-    fn apply_dispute(&mut self, amount: f32, transaction_id: u32) {
-        self.available -= amount;
-        self.held += amount;
-        self.transactions_in_dispute.insert(transaction_id);
+    /// Re-enables an account that was locked by a chargeback.
+    pub fn unlock(&mut self) {
+        self.locked = false;
+        self.locking_chargeback = None;
     }
 
-    fn resolve(&mut self, transaction_id: u32) {
-        if self.transactions_in_dispute.get(&transaction_id).is_some() {
-            if let Some(amount) = self.lookup_transaction_history(transaction_id) {
-                self.apply_resolve(amount);
-                self.transactions_in_dispute.remove(&transaction_id);
+    // A disputed deposit moves its funds from available to held, a disputed withdrawal or
+    // transfer holds the withdrawn funds until it is resolved or charged back. A disputed
+    // conversion holds the funds it credited, in their currency.
+    fn dispute(&mut self, transaction_id: TransactionId) -> Result<(), EngineError> {
+        // Events were checked against the redispute policy when they were decided
+        let transition = |state: DisputeState| state.dispute(RedisputePolicy::AfterResolve);
+        if let Some(record) = self.transition(transaction_id, transition)? {
+            match record.credit {
+                Some(credit) => {
+                    let (available, held) = self.funds_mut(credit.currency);
+                    *available -= credit.amount;
+                    *held += credit.amount;
+                }
+                None => {
+                    let (kind, amount) = (record.kind, record.funds());
+                    if kind == TransactionType::Deposit {
+                        self.available -= amount;
+                    }
+                    self.held += amount;
+                }
             }
+            self.open_disputes += 1;
         }
+        Ok(())
     }
 
-    fn apply_resolve(&mut self, amount: f32) {
-        self.available += amount;
-        self.held -= amount;
+    fn resolve(&mut self, transaction_id: TransactionId) -> Result<(), EngineError> {
+        if let Some(record) = self.transition(transaction_id, DisputeState::resolve)? {
+            match record.credit {
+                Some(credit) => {
+                    let (available, held) = self.funds_mut(credit.currency);
+                    *available += credit.amount;
+                    *held -= credit.amount;
+                }
+                None => {
+                    let (kind, amount) = (record.kind, record.funds());
+                    if kind == TransactionType::Deposit {
+                        self.available += amount;
+                    }
+                    self.held -= amount;
+                }
+            }
+            self.open_disputes -= 1;
+        }
+        Ok(())
     }
 
-    fn chargeback(&mut self, transaction_id: u32) {
-        if self.transactions_in_dispute.get(&transaction_id).is_some() {
-            if let Some(amount) = self.lookup_transaction_history(transaction_id) {
-                self.apply_chargeback(amount);
-                self.transactions_in_dispute.remove(&transaction_id);
+    // Reverses the disputed transaction: a deposit is taken back, a withdrawal or transfer is
+    // credited back. A conversion takes back the funds it credited and credits back the ones it
+    // debited.
+    fn chargeback(&mut self, transaction_id: TransactionId) -> Result<(), EngineError> {
+        if let Some(record) = self.transition(transaction_id, DisputeState::charge_back)? {
+            match record.credit {
+                Some(credit) => {
+                    *self.funds_mut(credit.currency).1 -= credit.amount;
+                    *self.funds_mut(record.currency).0 += record.amount;
+                }
+                None => {
+                    let (kind, amount) = (record.kind, record.funds());
+                    if kind != TransactionType::Deposit {
+                        self.available += amount;
+                    }
+                    self.held -= amount;
+                }
+            }
+            self.open_disputes -= 1;
+            if !self.locked {
+                self.locking_chargeback = Some(transaction_id);
             }
+            self.locked = true;
         }
+        Ok(())
     }
 
-    fn apply_chargeback(&mut self, amount: f32) {
-        self.held -= amount;
-        self.update_total();
-        self.locked = true;
+    // Lets the charged back transaction take effect again, and unlocks the account if only its
+    // chargeback locked it.
+    fn reverse_chargeback(&mut self, transaction_id: TransactionId) -> Result<(), EngineError> {
+        if let Some(record) = self.transition(transaction_id, DisputeState::reverse_chargeback)? {
+            let (kind, amount) = (record.kind, record.funds());
+            match record.credit {
+                Some(credit) => {
+                    *self.funds_mut(record.currency).0 -= record.amount;
+                    *self.funds_mut(credit.currency).0 += credit.amount;
+                }
+                None if kind == TransactionType::Deposit => self.available += amount,
+                None => self.available -= amount,
+            }
+            if self.locking_chargeback == Some(transaction_id) {
+                self.unlock();
+            }
+        }
+        Ok(())
+    }
+
+    // Moves a remembered transaction to the state `next` allows, and returns its record if it
+    // could be moved
+    fn transition<F>(
+        &mut self,
+        transaction_id: TransactionId,
+        next: F,
+    ) -> Result<Option<TransactionRecord>, EngineError>
+    where
+        F: FnOnce(DisputeState) -> Result<DisputeState, TransactionOutcome>,
+    {
+        let Some(record) = self.transaction_history.get(transaction_id)? else {
+            return Ok(None);
+        };
+        let Ok(state) = next(record.state) else {
+            return Ok(None);
+        };
+        let record = TransactionRecord { state, ..record };
+        self.transaction_history.replace(transaction_id, record)?;
+        if state.is_settled(self.redispute_policy) {
+            self.transaction_history.settle(transaction_id)?;
+        }
+        Ok(Some(record))
     }
 
-    fn lookup_transaction_history(&self, transaction_id: u32) -> Option<f32> {
-        self.transaction_history.get(&transaction_id).copied()
+    fn record_transaction(
+        &mut self,
+        transaction_id: TransactionId,
+        kind: TransactionType,
+        amount: Amount,
+        fee: Amount,
+        timestamp: Option<u64>,
+    ) -> Result<(), EngineError> {
+        let record = TransactionRecord {
+            kind,
+            amount,
+            sequence: self.sequence,
+            timestamp,
+            state: DisputeState::Normal,
+            fee,
+            currency: None,
+            credit: None,
+        };
+        self.remember(transaction_id, record)
+    }
+
+    fn remember(
+        &mut self,
+        transaction_id: TransactionId,
+        record: TransactionRecord,
+    ) -> Result<(), EngineError> {
+        self.transaction_history.insert(transaction_id, record)?;
+        self.transaction_history.expire()
+    }
+
+    // Funds in `currency`, the balances of the account for the base currency
+    fn funds(&self, currency: Option<Currency>) -> Balance {
+        match currency {
+            None => Balance {
+                available: self.available,
+                held: self.held,
+            },
+            Some(currency) => self.foreign.get(&currency).copied().unwrap_or_default(),
+        }
+    }
+
+    // Available and held funds in `currency`, the balances of the account for the base currency
+    fn funds_mut(&mut self, currency: Option<Currency>) -> (&mut Amount, &mut Amount) {
+        match currency {
+            None => (&mut self.available, &mut self.held),
+            Some(currency) => {
+                let balance = self.foreign.entry(currency).or_default();
+                (&mut balance.available, &mut balance.held)
+            }
+        }
     }
 
     fn update_total(&mut self) {
@@ -133,66 +1313,74 @@ impl Account {
     }
 }
 
-// Precision n -> precision_factor = 10^n
-const PRECISION_FACTOR: f32 = 10000.0; // n = 4
-
-fn round_to_precision_4(value: f32) -> f32 {
-    (value * PRECISION_FACTOR).round() / PRECISION_FACTOR
+// Currency of the funds of one side of a conversion, `None` for the balances in the base
+// currency
+fn leg(currency: Currency, base: Currency) -> Option<Currency> {
+    (currency != base).then_some(currency)
 }
 
-fn round_serialize<S>(x: &f32, s: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    s.serialize_f32(round_to_precision_4(*x))
+// Change of the available funds by a deposit or withdrawal of `amount` and `fee`
+fn funds_change(kind: TransactionType, amount: Amount, fee: Amount) -> Amount {
+    match kind {
+        TransactionType::Deposit => amount - fee,
+        _ => -(amount + fee),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::Account;
-    use crate::{account::round_to_precision_4, transaction::Transaction};
-
-    #[test]
-    fn invalid_transaction() {
-        let mut account = Account::new(0);
-
-        let invalid_transaction = make_transaction("invalid", 0, 0, Some(1.0));
-        assert!(account.apply_transaction(invalid_transaction).is_err());
-    }
+    use crate::{
+        amount::{Amount, RoundingMode},
+        dedupe::DuplicatePolicy,
+        dispute::RedisputePolicy,
+        dispute_window::DisputeWindow,
+        error::EngineError,
+        fees::{Fee, Fees},
+        fx::{Currency, FxRates},
+        history::SettledHistory,
+        limits::Limits,
+        locked::LockedAccountPolicy,
+        outcome::TransactionOutcome,
+        transaction::{ClientId, Transaction, TransactionId, TransactionType},
+    };
+    use proptest::{prelude::*, sample::Index};
+    use rust_decimal::Decimal;
+    use std::sync::Arc;
 
     #[test]
     fn basic_deposit_and_withdrawal() {
         let mut account = Account::new(0);
 
-        let first_deposit = make_transaction("deposit", 0, 0, Some(1.0));
+        let first_deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
         account.apply_transaction(first_deposit).unwrap();
-        assert_eq!(account.available, 1.0);
-        assert_eq!(account.held, 0.0);
-        assert_eq!(account.total, 1.0);
+        assert_eq!(account.available, amount("1.0"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("1.0"));
         assert_eq!(account.transaction_history.len(), 1);
 
-        let second_deposit = make_transaction("deposit", 0, 1, Some(0.5555));
+        let second_deposit = make_transaction(TransactionType::Deposit, 0, 1, Some("0.5555"));
         account.apply_transaction(second_deposit).unwrap();
-        assert_eq!(account.available, 1.5555);
-        assert_eq!(account.held, 0.0);
-        assert_eq!(account.total, 1.5555);
+        assert_eq!(account.available, amount("1.5555"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("1.5555"));
         assert_eq!(account.transaction_history.len(), 2);
         assert!(!account.locked);
 
-        let first_withdrawal = make_transaction("withdrawal", 0, 2, Some(1.0));
+        let first_withdrawal = make_transaction(TransactionType::Withdrawal, 0, 2, Some("1.0"));
         account.apply_transaction(first_withdrawal).unwrap();
-        assert_eq!(round_to_precision_4(account.available), 0.5555);
-        assert_eq!(account.held, 0.0);
-        assert_eq!(round_to_precision_4(account.total), 0.5555);
+        assert_eq!(account.available, amount("0.5555"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("0.5555"));
         assert_eq!(account.transaction_history.len(), 3);
         assert!(!account.locked);
 
-        let second_withdrawal = make_transaction("withdrawal", 0, 3, Some(2.0));
+        let second_withdrawal = make_transaction(TransactionType::Withdrawal, 0, 3, Some("2.0"));
         account.apply_transaction(second_withdrawal).unwrap();
-        assert_eq!(round_to_precision_4(account.available), 0.5555);
-        assert_eq!(account.held, 0.0);
-        assert_eq!(round_to_precision_4(account.total), 0.5555);
-        assert_eq!(account.transaction_history.len(), 4);
+        assert_eq!(account.available, amount("0.5555"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("0.5555"));
+        assert_eq!(account.transaction_history.len(), 3);
         assert!(!account.locked);
     }
 
@@ -200,15 +1388,29 @@ mod tests {
     fn invalid_deposit_without_amount() {
         let mut account = Account::new(0);
 
-        let invalid_deposit = make_transaction("deposit", 0, 0, None);
+        let invalid_deposit = make_transaction(TransactionType::Deposit, 0, 0, None);
         assert!(account.apply_transaction(invalid_deposit).is_err());
     }
 
+    #[test]
+    fn deposit_out_of_range() {
+        let mut account = Account::new(0);
+
+        let deposit = make_transaction(TransactionType::Deposit, 0, 1, Some("1000000000000000000"));
+        assert!(account.apply_transaction(deposit).is_ok());
+        let deposit = make_transaction(TransactionType::Deposit, 0, 2, Some("1"));
+        assert!(matches!(
+            account.apply_transaction(deposit),
+            Err(EngineError::AmountOutOfRange(2))
+        ));
+        assert_eq!(account.total, Amount::MAX);
+    }
+
     #[test]
     fn invalid_withdrawal_without_amount() {
         let mut account = Account::new(0);
 
-        let invalid_withdrawal = make_transaction("withdrawal", 0, 0, None);
+        let invalid_withdrawal = make_transaction(TransactionType::Withdrawal, 0, 0, None);
         assert!(account.apply_transaction(invalid_withdrawal).is_err());
     }
 
@@ -216,20 +1418,20 @@ mod tests {
     fn valid_disput() {
         let mut account = Account::new(0);
 
-        let deposit = make_transaction("deposit", 0, 0, Some(1.0));
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
         account.apply_transaction(deposit).unwrap();
 
-        let dispute = make_transaction("dispute", 0, 0, None);
+        let dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
         account.apply_transaction(dispute).unwrap();
 
-        let double_dispute = make_transaction("dispute", 0, 0, None);
+        let double_dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
         account.apply_transaction(double_dispute).unwrap();
 
-        assert_eq!(account.available, 0.0);
-        assert_eq!(account.held, 1.0);
-        assert_eq!(account.total, 1.0);
+        assert_eq!(account.available, amount("0.0"));
+        assert_eq!(account.held, amount("1.0"));
+        assert_eq!(account.total, amount("1.0"));
         assert_eq!(account.transaction_history.len(), 1);
-        assert_eq!(account.transactions_in_dispute.len(), 1);
+        assert_eq!(account.open_disputes, 1);
         assert!(!account.locked);
     }
 
@@ -237,17 +1439,17 @@ mod tests {
     fn invalid_dispute() {
         let mut account = Account::new(0);
 
-        let deposit = make_transaction("deposit", 0, 0, Some(1.0));
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
         account.apply_transaction(deposit).unwrap();
 
-        let dispute = make_transaction("dispute", 0, 1, None);
+        let dispute = make_transaction(TransactionType::Dispute, 0, 1, None);
         account.apply_transaction(dispute).unwrap();
 
-        assert_eq!(account.available, 1.0);
-        assert_eq!(account.held, 0.0);
-        assert_eq!(account.total, 1.0);
+        assert_eq!(account.available, amount("1.0"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("1.0"));
         assert_eq!(account.transaction_history.len(), 1);
-        assert_eq!(account.transactions_in_dispute.len(), 0);
+        assert_eq!(account.open_disputes, 0);
         assert!(!account.locked);
     }
 
@@ -255,23 +1457,23 @@ mod tests {
     fn valid_resolve() {
         let mut account = Account::new(0);
 
-        let deposit = make_transaction("deposit", 0, 0, Some(1.0));
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
         account.apply_transaction(deposit).unwrap();
 
-        let dispute = make_transaction("dispute", 0, 0, None);
+        let dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
         account.apply_transaction(dispute).unwrap();
 
-        let resolve = make_transaction("resolve", 0, 0, None);
+        let resolve = make_transaction(TransactionType::Resolve, 0, 0, None);
         account.apply_transaction(resolve).unwrap();
 
-        let double_resolve = make_transaction("resolve", 0, 0, None);
+        let double_resolve = make_transaction(TransactionType::Resolve, 0, 0, None);
         account.apply_transaction(double_resolve).unwrap();
 
-        assert_eq!(account.available, 1.0);
-        assert_eq!(account.held, 0.0);
-        assert_eq!(account.total, 1.0);
+        assert_eq!(account.available, amount("1.0"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("1.0"));
         assert_eq!(account.transaction_history.len(), 1);
-        assert_eq!(account.transactions_in_dispute.len(), 0);
+        assert_eq!(account.open_disputes, 0);
         assert!(!account.locked);
     }
 
@@ -279,89 +1481,1017 @@ mod tests {
     fn invalid_resolve() {
         let mut account = Account::new(0);
 
-        let deposit = make_transaction("deposit", 0, 0, Some(1.0));
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
         account.apply_transaction(deposit).unwrap();
 
-        let first_resolve = make_transaction("resolve", 0, 0, None);
+        let first_resolve = make_transaction(TransactionType::Resolve, 0, 0, None);
         account.apply_transaction(first_resolve).unwrap();
 
-        let second_resolve = make_transaction("resolve", 0, 42, None);
+        let second_resolve = make_transaction(TransactionType::Resolve, 0, 42, None);
         account.apply_transaction(second_resolve).unwrap();
 
-        assert_eq!(account.available, 1.0);
-        assert_eq!(account.held, 0.0);
-        assert_eq!(account.total, 1.0);
+        assert_eq!(account.available, amount("1.0"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("1.0"));
         assert_eq!(account.transaction_history.len(), 1);
-        assert_eq!(account.transactions_in_dispute.len(), 0);
+        assert_eq!(account.open_disputes, 0);
     }
 
     #[test]
     fn valid_chargeback() {
         let mut account = Account::new(0);
 
-        let deposit = make_transaction("deposit", 0, 0, Some(1.0));
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
         account.apply_transaction(deposit).unwrap();
 
-        let dispute = make_transaction("dispute", 0, 0, None);
+        let dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
         account.apply_transaction(dispute).unwrap();
 
-        let chargeback = make_transaction("chargeback", 0, 0, None);
+        let chargeback = make_transaction(TransactionType::Chargeback, 0, 0, None);
         account.apply_transaction(chargeback).unwrap();
 
-        let double_chargeback = make_transaction("chargeback", 0, 0, None);
+        let double_chargeback = make_transaction(TransactionType::Chargeback, 0, 0, None);
         account.apply_transaction(double_chargeback).unwrap();
 
-        assert_eq!(account.available, 0.0);
-        assert_eq!(account.held, 0.0);
-        assert_eq!(account.total, 0.0);
+        assert_eq!(account.available, amount("0.0"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("0.0"));
         assert_eq!(account.transaction_history.len(), 1);
-        assert_eq!(account.transactions_in_dispute.len(), 0);
+        assert_eq!(account.open_disputes, 0);
         assert!(account.locked);
 
-        let deposit_after_lock = make_transaction("deposit", 0, 1, Some(1.0));
+        let deposit_after_lock = make_transaction(TransactionType::Deposit, 0, 1, Some("1.0"));
         // Should have no effect
-        account.apply_transaction(deposit_after_lock).unwrap();
+        assert_eq!(
+            account.apply_transaction(deposit_after_lock).unwrap(),
+            TransactionOutcome::AccountLocked
+        );
 
-        assert_eq!(account.available, 0.0);
-        assert_eq!(account.held, 0.0);
-        assert_eq!(account.total, 0.0);
+        assert_eq!(account.available, amount("0.0"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("0.0"));
         assert_eq!(account.transaction_history.len(), 1);
-        assert_eq!(account.transactions_in_dispute.len(), 0);
+        assert_eq!(account.open_disputes, 0);
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn unlock_after_chargeback() {
+        let mut account = Account::new(0);
+
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
+        account.apply_transaction(deposit).unwrap();
+        let dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
+        account.apply_transaction(dispute).unwrap();
+        let chargeback = make_transaction(TransactionType::Chargeback, 0, 0, None);
+        account.apply_transaction(chargeback).unwrap();
+        assert!(account.locked);
+
+        let unlock = make_transaction(TransactionType::Unlock, 0, 1, None);
+        account.apply_transaction(unlock).unwrap();
+        assert!(!account.locked);
+
+        let deposit_after_unlock = make_transaction(TransactionType::Deposit, 0, 2, Some("2.0"));
+        assert_eq!(
+            account.apply_transaction(deposit_after_unlock).unwrap(),
+            TransactionOutcome::Applied
+        );
+        assert_eq!(account.available, amount("2.0"));
+    }
+
+    #[test]
+    fn locked_account_policies() {
+        let locked = |policy| {
+            let mut account = Account::new(0).with_locked_policy(policy);
+            for transaction in [
+                make_transaction(TransactionType::Deposit, 0, 0, Some("1.0")),
+                make_transaction(TransactionType::Dispute, 0, 0, None),
+                make_transaction(TransactionType::Chargeback, 0, 0, None),
+            ] {
+                account.apply_transaction(transaction).unwrap();
+            }
+            account
+        };
+        let deposit = |tx| make_transaction(TransactionType::Deposit, 0, tx, Some("2.0"));
+
+        let mut account = locked(LockedAccountPolicy::Ignore);
+        assert_eq!(
+            account.apply_transaction(deposit(1)).unwrap(),
+            TransactionOutcome::AccountLocked
+        );
+
+        let mut account = locked(LockedAccountPolicy::Reject);
+        assert!(matches!(
+            account.apply_transaction(deposit(1)),
+            Err(EngineError::AccountLocked(1, 0))
+        ));
+
+        let mut account = locked(LockedAccountPolicy::QueueUntilUnlock(1));
+        assert_eq!(
+            account.apply_transaction(deposit(1)).unwrap(),
+            TransactionOutcome::Queued
+        );
+        assert_eq!(
+            account.apply_transaction(deposit(2)).unwrap(),
+            TransactionOutcome::AccountLocked
+        );
+        assert_eq!(account.available, amount("0"));
+        let unlock = make_transaction(TransactionType::Unlock, 0, 0, None);
+        account.apply_transaction(unlock).unwrap();
+        assert_eq!(account.take_queued(), [deposit(1)]);
+        assert!(account.take_queued().is_empty());
+    }
+
+    #[test]
+    fn reverse_chargeback() {
+        let mut account = Account::new(0);
+        let dispute = |tx| make_transaction(TransactionType::Dispute, 0, tx, None);
+        let chargeback = |tx| make_transaction(TransactionType::Chargeback, 0, tx, None);
+        let reversal = |tx| make_transaction(TransactionType::ChargebackReversal, 0, tx, None);
+        for transaction in [
+            make_transaction(TransactionType::Deposit, 0, 0, Some("1.0")),
+            make_transaction(TransactionType::Deposit, 0, 1, Some("2.0")),
+            dispute(0),
+            chargeback(0),
+            make_transaction(TransactionType::Unlock, 0, 2, None),
+            dispute(1),
+            chargeback(1),
+        ] {
+            account.apply_transaction(transaction).unwrap();
+        }
+        assert_eq!(account.total, amount("0.0"));
+
+        // The account stays locked by the later chargeback
+        assert_eq!(
+            account.apply_transaction(reversal(0)).unwrap(),
+            TransactionOutcome::Applied
+        );
         assert!(account.locked);
+        assert_eq!(account.available, amount("1.0"));
+        assert_eq!(
+            account.apply_transaction(reversal(0)).unwrap(),
+            TransactionOutcome::NotChargedBack
+        );
+
+        assert_eq!(
+            account.apply_transaction(reversal(1)).unwrap(),
+            TransactionOutcome::Applied
+        );
+        assert!(!account.locked);
+        assert_eq!(account.available, amount("3.0"));
+        assert_eq!(
+            account.apply_transaction(dispute(1)).unwrap(),
+            TransactionOutcome::DisputeClosed
+        );
+    }
+
+    #[test]
+    fn hold_and_release() {
+        let mut account = Account::new(0);
+        let hold = |tx, amount| make_transaction(TransactionType::Hold, 0, tx, Some(amount));
+        let release = |tx, amount| make_transaction(TransactionType::Release, 0, tx, amount);
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("3.0"));
+        account.apply_transaction(deposit).unwrap();
+
+        assert_eq!(
+            account.apply_transaction(hold(1, "2.0")).unwrap(),
+            TransactionOutcome::Applied
+        );
+        assert!(account.apply_transaction(hold(1, "0.5")).is_err());
+        assert_eq!(
+            account.apply_transaction(hold(2, "1.5")).unwrap(),
+            TransactionOutcome::InsufficientFunds
+        );
+        let withdrawal = make_transaction(TransactionType::Withdrawal, 0, 3, Some("1.5"));
+        assert_eq!(
+            account.apply_transaction(withdrawal).unwrap(),
+            TransactionOutcome::InsufficientFunds
+        );
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (amount("1.0"), amount("2.0"), amount("3.0"))
+        );
+
+        assert_eq!(
+            account.apply_transaction(release(1, Some("2.5"))).unwrap(),
+            TransactionOutcome::HoldExceeded
+        );
+        account.apply_transaction(release(1, Some("0.5"))).unwrap();
+        assert_eq!(account.held, amount("1.5"));
+        account.apply_transaction(release(1, None)).unwrap();
+        assert_eq!(
+            (account.available, account.held),
+            (amount("3.0"), amount("0"))
+        );
+        assert_eq!(
+            account.apply_transaction(release(1, None)).unwrap(),
+            TransactionOutcome::NoSuchTransaction
+        );
+    }
+
+    #[test]
+    fn dispute_again() {
+        let dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
+        let resolve = make_transaction(TransactionType::Resolve, 0, 0, None);
+        let chargeback = make_transaction(TransactionType::Chargeback, 0, 0, None);
+        let unlock = make_transaction(TransactionType::Unlock, 0, 1, None);
+        for (policy, redispute) in [
+            (RedisputePolicy::AfterResolve, TransactionOutcome::Applied),
+            (RedisputePolicy::Never, TransactionOutcome::DisputeClosed),
+        ] {
+            let mut account = Account::new(0).with_redispute_policy(policy);
+            let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
+            account.apply_transaction(deposit).unwrap();
+            account.apply_transaction(dispute).unwrap();
+            account.apply_transaction(resolve).unwrap();
+            assert_eq!(account.apply_transaction(dispute).unwrap(), redispute);
+        }
+
+        // A charged back transaction stays closed, even once the account is unlocked
+        let mut account = Account::new(0);
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
+        for transaction in [deposit, dispute, chargeback, unlock] {
+            account.apply_transaction(transaction).unwrap();
+        }
+        assert_eq!(
+            account.apply_transaction(dispute).unwrap(),
+            TransactionOutcome::DisputeClosed
+        );
+        assert_eq!(account.open_disputes, 0);
+        assert_eq!(account.held, amount("0.0"));
+    }
+
+    #[test]
+    fn drop_settled_history() {
+        let mut account = Account::new(0)
+            .with_redispute_policy(RedisputePolicy::Never)
+            .with_settled_history(SettledHistory::Drop);
+        let transactions = [
+            make_transaction(TransactionType::Deposit, 0, 0, Some("1.0")),
+            make_transaction(TransactionType::Deposit, 0, 1, Some("2.0")),
+            make_transaction(TransactionType::Dispute, 0, 0, None),
+            make_transaction(TransactionType::Resolve, 0, 0, None),
+            make_transaction(TransactionType::Dispute, 0, 1, None),
+        ];
+        for transaction in transactions {
+            account.apply_transaction(transaction).unwrap();
+        }
+        // The resolved deposit is forgotten, the disputed one is still remembered
+        assert_eq!(account.transaction_history.len(), 1);
+        let dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
+        assert_eq!(
+            account.apply_transaction(dispute).unwrap(),
+            TransactionOutcome::NoSuchTransaction
+        );
+
+        let chargeback = make_transaction(TransactionType::Chargeback, 0, 1, None);
+        account.apply_transaction(chargeback).unwrap();
+        assert_eq!(account.transaction_history.len(), 0);
+        assert_eq!(
+            (account.available, account.held),
+            (amount("1.0"), amount("0"))
+        );
     }
 
     #[test]
     fn invalid_chargeback() {
         let mut account = Account::new(0);
 
-        let deposit = make_transaction("deposit", 0, 0, Some(1.0));
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
         account.apply_transaction(deposit).unwrap();
 
-        let first_chargeback = make_transaction("chargeback", 0, 0, None);
-        account.apply_transaction(first_chargeback).unwrap();
+        let first_chargeback = make_transaction(TransactionType::Chargeback, 0, 0, None);
+        assert_eq!(
+            account.apply_transaction(first_chargeback).unwrap(),
+            TransactionOutcome::NotUnderDispute
+        );
 
-        let second_chargeback = make_transaction("chargeback", 0, 42, None);
-        account.apply_transaction(second_chargeback).unwrap();
+        let second_chargeback = make_transaction(TransactionType::Chargeback, 0, 42, None);
+        assert_eq!(
+            account.apply_transaction(second_chargeback).unwrap(),
+            TransactionOutcome::NoSuchTransaction
+        );
 
-        assert_eq!(account.available, 1.0);
-        assert_eq!(account.held, 0.0);
-        assert_eq!(account.total, 1.0);
+        assert_eq!(account.available, amount("1.0"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("1.0"));
         assert_eq!(account.transaction_history.len(), 1);
-        assert_eq!(account.transactions_in_dispute.len(), 0);
+        assert_eq!(account.open_disputes, 0);
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn replayed_after_chargeback() {
+        const STEPS: u32 = 5;
+        let types = [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+            TransactionType::Unlock,
+        ];
+        // Every ordering of disputes, resolves, chargebacks and unlocks of the same deposit
+        for ordering in 0..types.len().pow(STEPS) {
+            let mut account = Account::new(0);
+            let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
+            account.apply_transaction(deposit).unwrap();
+
+            let mut charged_back = None;
+            let mut remaining = ordering;
+            for _ in 0..STEPS {
+                let r#type = types[remaining % types.len()];
+                remaining /= types.len();
+                let transaction = make_transaction(r#type, 0, 0, None);
+                let outcome = account.apply_transaction(transaction).unwrap();
+                let balances = (account.available, account.held, account.total);
+                match charged_back {
+                    Some(charged_back) => {
+                        assert_eq!(balances, charged_back, "ordering {ordering}");
+                        if r#type != TransactionType::Unlock {
+                            assert!(matches!(
+                                outcome,
+                                TransactionOutcome::DisputeClosed
+                                    | TransactionOutcome::AccountLocked
+                            ));
+                        }
+                    }
+                    None if r#type == TransactionType::Chargeback && outcome.is_applied() => {
+                        charged_back = Some(balances);
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_dispute_and_resolve() {
+        let mut account = Account::new(0);
+
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
+        account.apply_transaction(deposit).unwrap();
+
+        let unknown_dispute = make_transaction(TransactionType::Dispute, 0, 42, None);
+        let resolve = make_transaction(TransactionType::Resolve, 0, 0, None);
+        let dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
+        for (transaction, outcome) in [
+            (unknown_dispute, TransactionOutcome::NoSuchTransaction),
+            (resolve, TransactionOutcome::NotUnderDispute),
+            (dispute, TransactionOutcome::Applied),
+            (dispute, TransactionOutcome::AlreadyDisputed),
+        ] {
+            assert_eq!(account.apply_transaction(transaction).unwrap(), outcome);
+        }
+        assert_eq!(account.held, amount("1.0"));
+    }
+
+    #[test]
+    fn dispute_and_resolve_withdrawal() {
+        let mut account = Account::new(0);
+
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("2.0"));
+        account.apply_transaction(deposit).unwrap();
+
+        let withdrawal = make_transaction(TransactionType::Withdrawal, 0, 1, Some("1.5"));
+        account.apply_transaction(withdrawal).unwrap();
+
+        let dispute = make_transaction(TransactionType::Dispute, 0, 1, None);
+        account.apply_transaction(dispute).unwrap();
+
+        assert_eq!(account.available, amount("0.5"));
+        assert_eq!(account.held, amount("1.5"));
+        assert_eq!(account.total, amount("2.0"));
+
+        let resolve = make_transaction(TransactionType::Resolve, 0, 1, None);
+        account.apply_transaction(resolve).unwrap();
+
+        assert_eq!(account.available, amount("0.5"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("0.5"));
         assert!(!account.locked);
     }
 
-    fn make_transaction<T: Into<String>>(
-        r#type: T,
-        client: u16,
-        tx: u32,
-        amount: Option<f32>,
+    #[test]
+    fn dispute_and_chargeback_withdrawal() {
+        let mut account = Account::new(0);
+
+        let deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("2.0"));
+        account.apply_transaction(deposit).unwrap();
+
+        let withdrawal = make_transaction(TransactionType::Withdrawal, 0, 1, Some("1.5"));
+        account.apply_transaction(withdrawal).unwrap();
+
+        let dispute = make_transaction(TransactionType::Dispute, 0, 1, None);
+        account.apply_transaction(dispute).unwrap();
+
+        let chargeback = make_transaction(TransactionType::Chargeback, 0, 1, None);
+        account.apply_transaction(chargeback).unwrap();
+
+        assert_eq!(account.available, amount("2.0"));
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.total, amount("2.0"));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn failed_withdrawal_can_not_be_disputed() {
+        let mut account = Account::new(0);
+
+        let withdrawal = make_transaction(TransactionType::Withdrawal, 0, 0, Some("1.0"));
+        assert_eq!(
+            account.apply_transaction(withdrawal).unwrap(),
+            TransactionOutcome::InsufficientFunds
+        );
+
+        let dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
+        account.apply_transaction(dispute).unwrap();
+
+        assert_eq!(account.held, amount("0.0"));
+        assert_eq!(account.open_disputes, 0);
+    }
+
+    #[test]
+    fn fees() {
+        let fees = Fees {
+            deposit: Fee {
+                flat: amount("0.1"),
+                percent: Amount::ZERO,
+            },
+            withdrawal: Fee {
+                flat: amount("0.5"),
+                percent: amount("1"),
+            },
+        };
+        let mut account = Account::new(0).with_fees(fees);
+        for (r#type, tx, value, outcome) in [
+            (
+                TransactionType::Deposit,
+                0,
+                "10.0",
+                TransactionOutcome::Applied,
+            ),
+            (
+                TransactionType::Withdrawal,
+                1,
+                "5.0",
+                TransactionOutcome::Applied,
+            ),
+            // The fee comes on top of the amount
+            (
+                TransactionType::Withdrawal,
+                2,
+                "4.0",
+                TransactionOutcome::InsufficientFunds,
+            ),
+            (TransactionType::Dispute, 0, "", TransactionOutcome::Applied),
+        ] {
+            let transaction =
+                make_transaction(r#type, 0, tx, Some(value).filter(|v| !v.is_empty()));
+            assert_eq!(account.apply_transaction(transaction).unwrap(), outcome);
+        }
+
+        // The dispute holds what the deposit credited, the fees are kept
+        assert_eq!(account.held, amount("9.9"));
+        assert_eq!(account.available, amount("-5.55"));
+        assert_eq!(account.view().fees_collected, Some(amount("0.65")));
+        assert_eq!(Account::new(1).view().fees_collected, None);
+
+        // The withdrawal fee of 0.505 is rounded to two decimal places when it is charged
+        for (mode, fee) in [
+            (RoundingMode::HalfEven, "0.5"),
+            (RoundingMode::HalfUp, "0.51"),
+        ] {
+            let mut account = Account::new(1).with_fees(fees).with_fee_rounding(2, mode);
+            for (r#type, tx, value) in [
+                (TransactionType::Deposit, 0, "10.0"),
+                (TransactionType::Withdrawal, 1, "0.5"),
+            ] {
+                let transaction = make_transaction(r#type, 1, tx, Some(value));
+                account.apply_transaction(transaction).unwrap();
+            }
+            assert_eq!(
+                account.view().fees_collected,
+                Some(amount("0.1") + amount(fee))
+            );
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let limits = Limits {
+            max_deposit: Some(amount("100.0")),
+            max_withdrawal: Some(amount("50.0")),
+            max_daily_withdrawal: Some(amount("60.0")),
+            approval_threshold: None,
+        };
+        let mut account = Account::new(0).with_limits(limits);
+        let transactions = [
+            (
+                TransactionType::Deposit,
+                0,
+                "150.0",
+                TransactionOutcome::DepositLimitExceeded,
+            ),
+            (
+                TransactionType::Deposit,
+                1,
+                "100.0",
+                TransactionOutcome::Applied,
+            ),
+            (
+                TransactionType::Withdrawal,
+                2,
+                "51.0",
+                TransactionOutcome::WithdrawalLimitExceeded,
+            ),
+            (
+                TransactionType::Withdrawal,
+                3,
+                "40.0",
+                TransactionOutcome::Applied,
+            ),
+            (
+                TransactionType::Withdrawal,
+                4,
+                "30.0",
+                TransactionOutcome::DailyLimitExceeded,
+            ),
+            (
+                TransactionType::Withdrawal,
+                5,
+                "20.0",
+                TransactionOutcome::Applied,
+            ),
+        ];
+        for (r#type, tx, value, outcome) in transactions {
+            let transaction = make_transaction(r#type, 0, tx, Some(value));
+            assert_eq!(account.apply_transaction(transaction).unwrap(), outcome);
+        }
+        assert_eq!(account.available, amount("40.0"));
+
+        let dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
+        account.apply_transaction(dispute).unwrap();
+        assert_eq!(account.held, Amount::ZERO);
+    }
+
+    #[test]
+    fn amended_withdrawal_limits() {
+        let limits = Limits {
+            max_withdrawal: Some(amount("50.0")),
+            max_daily_withdrawal: Some(amount("60.0")),
+            ..Limits::default()
+        };
+        let mut account = Account::new(0)
+            .with_limits(limits)
+            .with_duplicate_policy(DuplicatePolicy::LastWriteWins);
+        let deposit = make_transaction(TransactionType::Deposit, 0, 1, Some("200.0"));
+        account.apply_transaction(deposit).unwrap();
+        let withdrawal = make_transaction(TransactionType::Withdrawal, 0, 2, Some("30.0"));
+        account.apply_transaction(withdrawal).unwrap();
+
+        // The amended amount is a single withdrawal, its raise counts towards today's
+        let mut amend = |tx, value| {
+            let transaction = make_transaction(TransactionType::Withdrawal, 0, tx, Some(value));
+            account.execute_duplicate(transaction, true).unwrap().0
+        };
+        assert_eq!(
+            amend(2, "55.0"),
+            TransactionOutcome::WithdrawalLimitExceeded
+        );
+        assert_eq!(amend(2, "45.0"), TransactionOutcome::Applied);
+        let withdrawal = make_transaction(TransactionType::Withdrawal, 0, 3, Some("20.0"));
+        assert_eq!(
+            account.apply_transaction(withdrawal).unwrap(),
+            TransactionOutcome::DailyLimitExceeded
+        );
+        let withdrawal = make_transaction(TransactionType::Withdrawal, 0, 4, Some("15.0"));
+        account.apply_transaction(withdrawal).unwrap();
+        let mut amend = |tx, value| {
+            let transaction = make_transaction(TransactionType::Withdrawal, 0, tx, Some(value));
+            account.execute_duplicate(transaction, true).unwrap().0
+        };
+        assert_eq!(amend(2, "50.0"), TransactionOutcome::DailyLimitExceeded);
+        assert_eq!(amend(4, "10.0"), TransactionOutcome::Applied);
+        assert_eq!(account.available, amount("145.0"));
+    }
+
+    #[test]
+    fn approval() {
+        let limits = Limits {
+            approval_threshold: Some(amount("100.0")),
+            ..Limits::default()
+        };
+        let mut account = Account::new(0).with_limits(limits);
+        let transactions = [
+            (
+                TransactionType::Deposit,
+                1,
+                Some("100.0"),
+                TransactionOutcome::Applied,
+            ),
+            (
+                TransactionType::Deposit,
+                2,
+                Some("500.0"),
+                TransactionOutcome::PendingApproval,
+            ),
+            (
+                TransactionType::Dispute,
+                2,
+                None,
+                TransactionOutcome::NoSuchTransaction,
+            ),
+            (
+                TransactionType::Approve,
+                2,
+                None,
+                TransactionOutcome::Applied,
+            ),
+            (
+                TransactionType::Approve,
+                2,
+                None,
+                TransactionOutcome::NotPending,
+            ),
+            (
+                TransactionType::Withdrawal,
+                3,
+                Some("550.0"),
+                TransactionOutcome::PendingApproval,
+            ),
+            (
+                TransactionType::Withdrawal,
+                4,
+                Some("150.0"),
+                TransactionOutcome::PendingApproval,
+            ),
+            (
+                TransactionType::Approve,
+                3,
+                None,
+                TransactionOutcome::Applied,
+            ),
+            (
+                TransactionType::Approve,
+                4,
+                None,
+                TransactionOutcome::InsufficientFunds,
+            ),
+            (
+                TransactionType::Withdrawal,
+                5,
+                Some("200.0"),
+                TransactionOutcome::InsufficientFunds,
+            ),
+            (
+                TransactionType::Deposit,
+                6,
+                Some("200.0"),
+                TransactionOutcome::PendingApproval,
+            ),
+            (
+                TransactionType::Reject,
+                6,
+                None,
+                TransactionOutcome::Applied,
+            ),
+            (
+                TransactionType::Approve,
+                6,
+                None,
+                TransactionOutcome::NotPending,
+            ),
+        ];
+        let mut events = Vec::new();
+        for (r#type, tx, value, outcome) in transactions {
+            let transaction = make_transaction(r#type, 0, tx, value);
+            let (result, event) = account.execute(transaction).unwrap();
+            assert_eq!(result, outcome);
+            events.extend(event);
+        }
+        // The withdrawal declined on its approval isn't pending anymore
+        assert_eq!(account.available, amount("50.0"));
+        assert!(account.pending.is_empty());
+
+        let deposit = make_transaction(TransactionType::Deposit, 0, 7, Some("300.0"));
+        let (_, event) = account.execute(deposit).unwrap();
+        events.extend(event);
+        assert_eq!(account.available, amount("50.0"));
+        assert_eq!(account.pending.len(), 1);
+        let replayed = Account::from_events(0, &events)
+            .unwrap()
+            .with_limits(limits);
+        assert_eq!(replayed, account);
+    }
+
+    #[test]
+    fn dispute_window() {
+        let mut account = Account::new(0).with_dispute_window(Some(DisputeWindow::Transactions(2)));
+        for tx in 0..3 {
+            let deposit = make_transaction(TransactionType::Deposit, 0, tx, Some("1.0"));
+            account.apply_transaction(deposit).unwrap();
+        }
+
+        let dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
+        assert_eq!(
+            account.apply_transaction(dispute).unwrap(),
+            TransactionOutcome::OutsideDisputeWindow
+        );
+        let dispute = make_transaction(TransactionType::Dispute, 0, 1, None);
+        assert_eq!(
+            account.apply_transaction(dispute).unwrap(),
+            TransactionOutcome::Applied
+        );
+        assert_eq!(account.held, amount("1.0"));
+
+        let mut account = Account::new(0).with_dispute_window(Some(DisputeWindow::Seconds(60)));
+        let mut deposit = make_transaction(TransactionType::Deposit, 0, 0, Some("1.0"));
+        deposit.timestamp = Some(1000);
+        account.apply_transaction(deposit).unwrap();
+        let mut dispute = make_transaction(TransactionType::Dispute, 0, 0, None);
+        dispute.timestamp = Some(1061);
+        assert_eq!(
+            account.apply_transaction(dispute).unwrap(),
+            TransactionOutcome::OutsideDisputeWindow
+        );
+    }
+
+    #[test]
+    fn replay_events() {
+        let mut account = Account::new(0);
+        let transactions = [
+            make_transaction(TransactionType::Deposit, 0, 0, Some("2.0")),
+            make_transaction(TransactionType::Withdrawal, 0, 1, Some("0.5")),
+            make_transaction(TransactionType::Withdrawal, 0, 2, Some("5.0")),
+            make_transaction(TransactionType::Dispute, 0, 0, None),
+            make_transaction(TransactionType::Dispute, 0, 7, None),
+            make_transaction(TransactionType::Chargeback, 0, 0, None),
+            make_transaction(TransactionType::Deposit, 0, 3, Some("1.0")),
+        ];
+        let events: Vec<_> = transactions
+            .into_iter()
+            .filter_map(|transaction| account.execute(transaction).unwrap().1)
+            .collect();
+
+        assert_eq!(events.len(), 5);
+        assert_eq!(Account::from_events(0, &events).unwrap(), account);
+        assert_eq!(account.available, amount("-0.5"));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn conversion_limits() {
+        let limits = Limits {
+            max_withdrawal: Some(amount("50.0")),
+            max_daily_withdrawal: Some(amount("60.0")),
+            ..Limits::default()
+        };
+        let mut account = Account::new(0)
+            .with_limits(limits)
+            .with_fx_rates(fx_rates());
+        let deposit = make_transaction(TransactionType::Deposit, 0, 1, Some("200.0"));
+        account.apply_transaction(deposit).unwrap();
+
+        // The debited funds are limited by their value in the base currency
+        let transactions = [
+            (
+                2,
+                "60.0",
+                "USD",
+                TransactionOutcome::WithdrawalLimitExceeded,
+            ),
+            (3, "40.0", "USD", TransactionOutcome::Applied),
+            (4, "20.0", "EUR", TransactionOutcome::DailyLimitExceeded),
+            (5, "16.0", "EUR", TransactionOutcome::Applied),
+        ];
+        for (tx, value, from, outcome) in transactions {
+            let to = if from == "USD" { "EUR" } else { "USD" };
+            let convert = make_conversion(tx, value, from, to);
+            assert_eq!(account.apply_transaction(convert).unwrap(), outcome);
+        }
+        assert_eq!(account.available, amount("180.0"));
+        assert_eq!(
+            account.foreign_balances()[&currency("EUR")].available,
+            amount("16.0")
+        );
+    }
+
+    #[test]
+    fn convert_between_currencies() {
+        let mut account = Account::new(0)
+            .with_fx_rates(fx_rates())
+            .with_fee_rounding(4, RoundingMode::HalfEven);
+        let deposit = make_transaction(TransactionType::Deposit, 0, 1, Some("10.0"));
+        account.apply_transaction(deposit).unwrap();
+
+        // 1 EUR is worth 1.25 USD
+        let convert = make_conversion(2, "4.0", "USD", "EUR");
+        assert_eq!(
+            account.apply_transaction(convert).unwrap(),
+            TransactionOutcome::Applied
+        );
+        assert_eq!(account.available, amount("6.0"));
+        assert_eq!(account.total, amount("6.0"));
+        assert_eq!(
+            account.foreign_balances()[&currency("EUR")].available,
+            amount("3.2")
+        );
+
+        let convert = make_conversion(3, "5.0", "EUR", "USD");
+        assert_eq!(
+            account.apply_transaction(convert).unwrap(),
+            TransactionOutcome::InsufficientFunds
+        );
+        let convert = make_conversion(4, "2.0", "EUR", "USD");
+        account.apply_transaction(convert).unwrap();
+        assert_eq!(account.available, amount("8.5"));
+        assert_eq!(
+            account.foreign_balances()[&currency("EUR")].available,
+            amount("1.2")
+        );
+        // Converted amounts are rounded to the precision
+        let convert = make_conversion(5, "0.0001", "EUR", "GBP");
+        account.apply_transaction(convert).unwrap();
+        assert_eq!(
+            account.foreign_balances()[&currency("GBP")].available,
+            amount("0.0001")
+        );
+
+        let convert = make_conversion(6, "1.0", "USD", "JPY");
+        assert!(matches!(
+            account.apply_transaction(convert),
+            Err(EngineError::NoExchangeRate(6, currency)) if currency.to_string() == "JPY"
+        ));
+        let convert = make_conversion(7, "1.0", "USD", "EUR");
+        assert!(matches!(
+            Account::new(0).apply_transaction(convert),
+            Err(EngineError::NoExchangeRate(7, _))
+        ));
+    }
+
+    #[test]
+    fn dispute_conversion_as_a_pair() {
+        let mut account = Account::new(0)
+            .with_fx_rates(fx_rates())
+            .with_fee_rounding(4, RoundingMode::HalfEven);
+        let transactions = [
+            make_transaction(TransactionType::Deposit, 0, 1, Some("10.0")),
+            make_conversion(2, "4.0", "USD", "EUR"),
+            make_transaction(TransactionType::Dispute, 0, 2, None),
+        ];
+        let mut events: Vec<_> = transactions
+            .into_iter()
+            .filter_map(|transaction| account.execute(transaction).unwrap().1)
+            .collect();
+        let eur = currency("EUR");
+
+        // Both entries are remembered under the id of the conversion
+        let record = account.transaction_history.peek(2).unwrap().unwrap();
+        assert_eq!((record.amount, record.currency), (amount("4.0"), None));
+        let credit = record.credit.unwrap();
+        assert_eq!((credit.amount, credit.currency), (amount("3.2"), Some(eur)));
+
+        // The dispute holds the credited euros, the debited dollars stay debited
+        assert_eq!(account.available, amount("6.0"));
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(account.foreign_balances()[&eur].available, Amount::ZERO);
+        assert_eq!(account.foreign_balances()[&eur].held, amount("3.2"));
+
+        let resolve = make_transaction(TransactionType::Resolve, 0, 2, None);
+        events.extend(account.execute(resolve).unwrap().1);
+        assert_eq!(account.foreign_balances()[&eur].available, amount("3.2"));
+        assert_eq!(account.foreign_balances()[&eur].held, Amount::ZERO);
+
+        // The chargeback undoes both entries
+        for r#type in [TransactionType::Dispute, TransactionType::Chargeback] {
+            let transaction = make_transaction(r#type, 0, 2, None);
+            events.extend(account.execute(transaction).unwrap().1);
+        }
+        assert_eq!(account.available, amount("10.0"));
+        assert_eq!(account.foreign_balances()[&eur].total(), Amount::ZERO);
+        assert!(account.locked);
+
+        let replayed = Account::from_events(0, &events).unwrap();
+        assert_eq!(replayed.view(), account.view());
+        assert_eq!(replayed.foreign_balances(), account.foreign_balances());
+
+        let reversal = make_transaction(TransactionType::ChargebackReversal, 0, 2, None);
+        account.apply_transaction(reversal).unwrap();
+        assert_eq!(account.available, amount("6.0"));
+        assert_eq!(account.foreign_balances()[&eur].available, amount("3.2"));
+        assert!(!account.locked);
+    }
+
+    // Deposits, withdrawals, transfers and conversions get fresh ids, the other transactions
+    // refer to any earlier id
+    fn transactions() -> impl Strategy<Value = Vec<Transaction>> {
+        let r#type = prop_oneof![
+            4 => Just(TransactionType::Deposit),
+            4 => Just(TransactionType::Withdrawal),
+            2 => Just(TransactionType::Dispute),
+            1 => Just(TransactionType::Resolve),
+            1 => Just(TransactionType::Chargeback),
+            1 => Just(TransactionType::Unlock),
+            1 => Just(TransactionType::Transfer),
+            1 => Just(TransactionType::Convert),
+        ];
+        prop::collection::vec((r#type, any::<Index>(), 1..1_000_000i64), 0..200).prop_map(|steps| {
+            steps
+                .into_iter()
+                .enumerate()
+                .map(|(tx, (r#type, reference, amount))| {
+                    let tx = tx as TransactionId;
+                    let (from, to) = match amount % 3 {
+                        0 => ("USD", "EUR"),
+                        1 => ("EUR", "GBP"),
+                        _ => ("GBP", "USD"),
+                    };
+                    match r#type {
+                        _ if r#type.introduces_transaction() => Transaction {
+                            r#type,
+                            client: 0,
+                            tx,
+                            amount: Some(Decimal::new(amount, 4).into()),
+                            counterparty: Some(1),
+                            timestamp: None,
+                            from_ccy: Some(currency(from)),
+                            to_ccy: Some(currency(to)),
+                        },
+                        _ => Transaction {
+                            r#type,
+                            client: 0,
+                            tx: reference.index(tx as usize + 1) as TransactionId,
+                            amount: None,
+                            counterparty: None,
+                            timestamp: None,
+                            from_ccy: None,
+                            to_ccy: None,
+                        },
+                    }
+                })
+                .collect()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn invariants_hold(transactions in transactions()) {
+            let mut account = Account::new(0).with_fx_rates(fx_rates());
+            let mut events = Vec::new();
+            for transaction in transactions {
+                let before = account.view();
+                let (_, event) = account.execute(transaction).unwrap();
+                events.extend(event);
+                prop_assert!(account.check_invariants().is_ok());
+
+                if before.locked && transaction.r#type != TransactionType::Unlock {
+                    prop_assert_eq!(account.view(), before);
+                }
+                // Only disputes can take the available funds below zero
+                if transaction.r#type.introduces_transaction() && before.available >= Amount::ZERO {
+                    prop_assert!(account.available >= Amount::ZERO);
+                }
+            }
+            let replayed = Account::from_events(0, &events).unwrap().with_fx_rates(fx_rates());
+            prop_assert_eq!(replayed, account);
+        }
+    }
+
+    fn make_conversion(tx: TransactionId, amount: &str, from: &str, to: &str) -> Transaction {
+        Transaction {
+            from_ccy: Some(currency(from)),
+            to_ccy: Some(currency(to)),
+            ..make_transaction(TransactionType::Convert, 0, tx, Some(amount))
+        }
+    }
+
+    fn fx_rates() -> Arc<FxRates> {
+        let rates = "base = \"USD\"\nrates = { EUR = \"1.25\", GBP = \"1.5\" }";
+        Arc::new(toml::from_str(rates).unwrap())
+    }
+
+    fn currency(code: &str) -> Currency {
+        code.parse().unwrap()
+    }
+
+    fn make_transaction(
+        r#type: TransactionType,
+        client: ClientId,
+        tx: TransactionId,
+        amount: Option<&str>,
     ) -> Transaction {
         Transaction {
-            r#type: r#type.into(),
+            r#type,
             client,
             tx,
-            amount,
+            amount: amount.map(self::amount),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
         }
     }
+
+    fn amount(value: &str) -> Amount {
+        value.parse().unwrap()
+    }
 }
@@ -0,0 +1,321 @@
+use crate::error::EngineError;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Neg, Sub, SubAssign},
+    str::FromStr,
+};
+
+/// Number of decimal places amounts are reported with, unless configured otherwise
+pub const DEFAULT_PRECISION: u32 = 4;
+
+/// Largest number of decimal places an amount can have
+pub const MAX_PRECISION: u32 = Decimal::MAX_SCALE;
+
+/// How amounts are rounded to the configured precision.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum RoundingMode {
+    /// Midpoints go to the even neighbour, e.g. 0.125 to 0.12, also known as banker's rounding
+    #[default]
+    HalfEven,
+    /// Midpoints go away from zero, e.g. 0.125 to 0.13
+    HalfUp,
+}
+
+/// Exact decimal amount of money.
+///
+/// Arithmetic is carried out without loss of precision, rounding only happens on serialization.
+/// It saturates at the limits of `Decimal` instead of panicking, which amounts up to
+/// [`Amount::MAX`] are far from.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Amount(Decimal);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(Decimal::ZERO);
+
+    /// Largest amount of a transaction and balance of an account, 10^18
+    pub const MAX: Amount = Amount(Decimal::from_parts(0xA764_0000, 0x0DE0_B6B3, 0, false, 0));
+
+    /// Sum of both amounts, `None` if it exceeds [`Amount::MAX`] either way.
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        let sum = self.0.checked_add(rhs.0)?;
+        (sum.abs() <= Amount::MAX.0).then_some(Amount(sum))
+    }
+
+    /// Rounds to `precision` decimal places, as amounts are reported. Trailing zeros are dropped,
+    /// but one decimal place is kept, so whole amounts are written as e.g. `1.0` and `0.0`.
+    pub fn round(self, precision: u32, mode: RoundingMode) -> Self {
+        let strategy = match mode {
+            RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+        };
+        let mut rounded = self
+            .0
+            .round_dp_with_strategy(precision, strategy)
+            .normalize();
+        if rounded.scale() == 0 && precision > 0 {
+            rounded.rescale(1);
+        }
+        Amount(rounded)
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0.is_sign_positive() && !self.0.is_zero()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// `percent` percent of the amount.
+    pub fn percent(self, percent: Amount) -> Amount {
+        Amount(self.0.saturating_mul(percent.0) / Decimal::ONE_HUNDRED)
+    }
+
+    /// Value of the amount in a currency worth `to_rate`, if its own currency is worth
+    /// `from_rate`, both in the same base currency.
+    pub fn exchange(self, from_rate: Amount, to_rate: Amount) -> Amount {
+        let value = self.0.saturating_mul(from_rate.0);
+        Amount(value.checked_div(to_rate.0).unwrap_or(value))
+    }
+
+    /// Digits of the amount rounded to `scale` decimal places, e.g. 150 for 1.5 with scale 2.
+    pub fn to_scaled_integer(self, scale: u32) -> i128 {
+        let mut value = self.0.round_dp(scale);
+        value.rescale(scale);
+        value.mantissa()
+    }
+
+    /// Whether the amount can be represented exactly with `precision` decimal places.
+    pub fn has_valid_precision(self, precision: u32) -> bool {
+        self.0.normalize().scale() <= precision
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half-even" => Ok(RoundingMode::HalfEven),
+            "half-up" => Ok(RoundingMode::HalfUp),
+            unknown => Err(EngineError::InvalidArgumentValue(
+                "--rounding".into(),
+                unknown.into(),
+            )),
+        }
+    }
+}
+
+impl From<Decimal> for Amount {
+    fn from(value: Decimal) -> Self {
+        Amount(value)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = rust_decimal::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Decimal::from_str(s).map(Amount)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, rhs: Amount) -> Amount {
+        Amount(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, rhs: Amount) {
+        self.0 = self.0.saturating_add(rhs.0);
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, rhs: Amount) -> Amount {
+        Amount(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, rhs: Amount) {
+        self.0 = self.0.saturating_sub(rhs.0);
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(-self.0)
+    }
+}
+
+// Amounts of at most four decimal places, far from the limits of `Decimal`
+#[cfg(feature = "fuzzing")]
+impl<'a> arbitrary::Arbitrary<'a> for Amount {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let scale = u.int_in_range(0..=4)?;
+        Ok(Amount(Decimal::new(
+            i64::from(u.arbitrary::<i32>()?),
+            scale,
+        )))
+    }
+}
+
+impl serde::Serialize for Amount {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Amount {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        d.deserialize_any(AmountVisitor)
+    }
+}
+
+// Accepts amounts given as string as well as numbers, as used in JSON input.
+struct AmountVisitor;
+
+impl<'de> serde::de::Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a decimal amount")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Amount, E> {
+        value.parse().map_err(E::custom)
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Amount, E> {
+        Ok(Amount(value.into()))
+    }
+
+    fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Amount, E> {
+        Ok(Amount(value.into()))
+    }
+
+    // The shortest representation of a float is the decimal it was parsed from
+    fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Amount, E> {
+        if !value.is_finite() {
+            return Err(E::custom(format!("{value} is not a finite amount")));
+        }
+        self.visit_str(&value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Amount, RoundingMode};
+
+    #[test]
+    fn exact_arithmetic() {
+        let mut total = Amount::ZERO;
+        for _ in 0..10 {
+            total += "0.1".parse().unwrap();
+        }
+        assert_eq!(total, "1.0".parse().unwrap());
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(Amount::MAX, amount("1000000000000000000"));
+        assert_eq!(amount("1").checked_add(amount("2")), Some(amount("3")));
+        assert_eq!(Amount::MAX.checked_add(amount("0.0001")), None);
+        assert_eq!((-Amount::MAX).checked_add(amount("-1")), None);
+
+        // Saturates instead of overflowing
+        let largest = amount("79228162514264337593543950335");
+        assert_eq!(largest + largest, largest);
+        assert_eq!(-largest - largest, -largest);
+
+        assert!(serde_json::from_str::<Amount>("1e400").is_err());
+        assert!("NaN".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn precision() {
+        assert!(amount("1.50000").has_valid_precision(4));
+        assert!(amount("1.5555").has_valid_precision(4));
+        assert!(!amount("1.55556").has_valid_precision(4));
+        assert!(amount("1.55556").has_valid_precision(8));
+        assert!(!amount("1.555").has_valid_precision(2));
+    }
+
+    #[test]
+    fn rounds_to_precision() {
+        let amount: Amount = "1.55556".parse().unwrap();
+        assert_eq!(
+            amount.round(4, RoundingMode::HalfEven).to_string(),
+            "1.5556"
+        );
+        assert_eq!(amount.round(2, RoundingMode::HalfEven).to_string(), "1.56");
+    }
+
+    #[test]
+    fn rounding_modes() {
+        for (value, half_even, half_up) in [
+            ("0.125", "0.12", "0.13"),
+            ("0.135", "0.14", "0.14"),
+            ("-0.125", "-0.12", "-0.13"),
+            ("0.1251", "0.13", "0.13"),
+        ] {
+            assert_eq!(
+                amount(value).round(2, RoundingMode::HalfEven),
+                amount(half_even)
+            );
+            assert_eq!(
+                amount(value).round(2, RoundingMode::HalfUp),
+                amount(half_up)
+            );
+        }
+        assert_eq!(
+            "half-up".parse::<RoundingMode>().unwrap(),
+            RoundingMode::HalfUp
+        );
+        assert!("up".parse::<RoundingMode>().is_err());
+    }
+
+    #[test]
+    fn reports_whole_amounts_with_a_decimal_place() {
+        for (value, reported) in [
+            ("1", "1.0"),
+            ("0.0000", "0.0"),
+            ("2.50", "2.5"),
+            ("-3", "-3.0"),
+        ] {
+            assert_eq!(
+                amount(value).round(4, RoundingMode::HalfEven).to_string(),
+                reported
+            );
+        }
+        assert_eq!(
+            amount("1.4").round(0, RoundingMode::HalfEven).to_string(),
+            "1"
+        );
+    }
+
+    fn amount(value: &str) -> Amount {
+        value.parse().unwrap()
+    }
+}
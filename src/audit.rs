@@ -0,0 +1,213 @@
+use crate::{
+    amount::Amount,
+    error::EngineError,
+    outcome::TransactionOutcome,
+    redaction::Redaction,
+    transaction::{ClientId, Transaction, TransactionId, TransactionType},
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Sink that records what the engine did with every transaction, one JSON object per line.
+///
+/// The log can be cloned and shared between the workers of the engine.
+#[derive(Clone)]
+pub struct AuditLog {
+    sink: Arc<Mutex<Box<dyn Write + Send>>>,
+    redaction: Option<Arc<Redaction>>,
+}
+
+#[derive(Serialize)]
+struct AuditRecord {
+    client: ClientId,
+    tx: TransactionId,
+    r#type: TransactionType,
+    amount: Option<Amount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    counterparty: Option<ClientId>,
+    #[serde(flatten)]
+    verdict: Verdict,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "outcome", rename_all = "lowercase")]
+enum Verdict {
+    Accepted,
+    Rejected { reason: String },
+    Ignored { reason: String },
+}
+
+impl AuditLog {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        AuditLog {
+            sink: Arc::new(Mutex::new(Box::new(writer))),
+            redaction: None,
+        }
+    }
+
+    /// Masks the fields and patterns of `redaction` in every record before it is written.
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = (!redaction.is_empty()).then(|| Arc::new(redaction));
+        self
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn record(
+        &self,
+        transaction: &Transaction,
+        result: &Result<TransactionOutcome, EngineError>,
+    ) -> Result<(), EngineError> {
+        let verdict = match result {
+            Ok(outcome) if outcome.is_applied() => Verdict::Accepted,
+            Ok(outcome) => Verdict::Ignored {
+                reason: outcome.to_string(),
+            },
+            Err(error) => Verdict::Rejected {
+                reason: error.to_string(),
+            },
+        };
+        let record = AuditRecord {
+            client: transaction.client,
+            tx: transaction.tx,
+            r#type: transaction.r#type,
+            amount: transaction.amount,
+            counterparty: transaction.counterparty,
+            verdict,
+        };
+        // Redacted records are written from a map, whose fields are sorted by name
+        let redacted = match &self.redaction {
+            Some(redaction) => {
+                let mut value = serde_json::to_value(&record).map_err(io::Error::from)?;
+                if let Value::Object(fields) = &mut value {
+                    redaction.apply(fields);
+                }
+                Some(value)
+            }
+            None => None,
+        };
+
+        let mut sink = self
+            .sink
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match redacted {
+            Some(value) => serde_json::to_writer(&mut *sink, &value),
+            None => serde_json::to_writer(&mut *sink, &record),
+        }
+        .map_err(io::Error::from)?;
+        sink.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<(), EngineError> {
+        let mut sink = self
+            .sink
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        sink.flush().map_err(EngineError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditLog;
+    use crate::{
+        error::EngineError,
+        outcome::TransactionOutcome,
+        redaction::Redaction,
+        transaction::{Transaction, TransactionType},
+    };
+    use std::{
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_lines() {
+        let buffer = SharedBuffer::default();
+        let audit_log = AuditLog::new(buffer.clone());
+        let transaction = Transaction {
+            r#type: TransactionType::Withdrawal,
+            client: 1,
+            tx: 2,
+            amount: None,
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+
+        audit_log
+            .record(&transaction, &Ok(TransactionOutcome::Applied))
+            .unwrap();
+        audit_log
+            .record(&transaction, &Ok(TransactionOutcome::AccountLocked))
+            .unwrap();
+        audit_log
+            .record(&transaction, &Err(EngineError::NoAmountInWitdrawal))
+            .unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"client":1,"tx":2,"type":"withdrawal","amount":null,"outcome":"accepted"}"#,
+                r#"{"client":1,"tx":2,"type":"withdrawal","amount":null,"outcome":"ignored","reason":"Account is locked"}"#,
+                r#"{"client":1,"tx":2,"type":"withdrawal","amount":null,"outcome":"rejected","reason":"Amount can't be None in withdrawal transaction"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn redacted() {
+        let buffer = SharedBuffer::default();
+        let audit_log =
+            AuditLog::new(buffer.clone()).with_redaction(Redaction::default().field("client"));
+        let transaction = Transaction {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 2,
+            amount: Some("1.5".parse().unwrap()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        audit_log
+            .record(&transaction, &Ok(TransactionOutcome::Applied))
+            .unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output.trim_end(),
+            r#"{"amount":"1.5","client":"***","outcome":"accepted","tx":2,"type":"deposit"}"#
+        );
+    }
+}
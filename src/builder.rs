@@ -0,0 +1,335 @@
+use crate::{
+    amount::{RoundingMode, DEFAULT_PRECISION, MAX_PRECISION},
+    clock::{Clock, SystemClock},
+    dedupe::DuplicatePolicy,
+    dispute::RedisputePolicy,
+    dispute_window::DisputeWindow,
+    error::ErrorPolicy,
+    fees::FeeSchedule,
+    filter::TransactionFilter,
+    fx::FxRates,
+    history::{HistoryRetention, HistorySpill, SettledHistory},
+    limits::Limits,
+    locked::LockedAccountPolicy,
+    observer::EngineObserver,
+    ordering::OrderingPolicy,
+    payment_engine::PaymentsEngine,
+    policy::LivePolicy,
+    risk::RiskThresholds,
+    store::{AccountStore, StoreFactory},
+    transaction::Transaction,
+    unknown_client::UnknownClientPolicy,
+};
+use std::{path::PathBuf, sync::Arc, thread, time::Duration};
+use tokio::sync::mpsc::Sender;
+
+const DEFAULT_CHANNEL_CAPACITY: usize = 16;
+
+/// Configures a [`PaymentsEngine`] before it is created.
+#[derive(Clone, Debug)]
+pub struct EngineBuilder {
+    pub(crate) workers: usize,
+    pub(crate) channel_capacity: usize,
+    pub(crate) admin_commands: bool,
+    pub(crate) precision: u32,
+    pub(crate) rounding_mode: RoundingMode,
+    pub(crate) error_policy: ErrorPolicy,
+    pub(crate) history_spill: Option<HistorySpill>,
+    pub(crate) history_retention: HistoryRetention,
+    pub(crate) settled_history: SettledHistory,
+    pub(crate) limits: Limits,
+    pub(crate) fee_schedule: Option<FeeSchedule>,
+    pub(crate) fx_rates: Option<Arc<FxRates>>,
+    pub(crate) live_policy: Option<LivePolicy>,
+    pub(crate) dispute_window: Option<DisputeWindow>,
+    pub(crate) redispute_policy: RedisputePolicy,
+    pub(crate) duplicate_policy: DuplicatePolicy,
+    pub(crate) locked_policy: LockedAccountPolicy,
+    pub(crate) unknown_client_policy: UnknownClientPolicy,
+    pub(crate) risk_thresholds: Option<RiskThresholds>,
+    pub(crate) bloom_filter: Option<usize>,
+    pub(crate) expected_clients: Option<usize>,
+    pub(crate) expected_transactions: Option<usize>,
+    pub(crate) ordering: OrderingPolicy,
+    pub(crate) sort_output: bool,
+    pub(crate) output_threads: usize,
+    pub(crate) flush_interval: Option<Duration>,
+    pub(crate) flush_snapshot: Option<PathBuf>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) stores: StoreFactory,
+    pub(crate) observers: Vec<Arc<dyn EngineObserver>>,
+    pub(crate) filters: Vec<Arc<dyn TransactionFilter>>,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        EngineBuilder {
+            workers: thread::available_parallelism().map_or(1, |workers| workers.get()),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            admin_commands: false,
+            precision: DEFAULT_PRECISION,
+            rounding_mode: RoundingMode::default(),
+            error_policy: ErrorPolicy::default(),
+            history_spill: None,
+            history_retention: HistoryRetention::default(),
+            settled_history: SettledHistory::default(),
+            limits: Limits::default(),
+            fee_schedule: None,
+            fx_rates: None,
+            live_policy: None,
+            dispute_window: None,
+            redispute_policy: RedisputePolicy::default(),
+            duplicate_policy: DuplicatePolicy::default(),
+            locked_policy: LockedAccountPolicy::default(),
+            unknown_client_policy: UnknownClientPolicy::default(),
+            risk_thresholds: None,
+            bloom_filter: None,
+            expected_clients: None,
+            expected_transactions: None,
+            ordering: OrderingPolicy::default(),
+            sort_output: true,
+            output_threads: 1,
+            flush_interval: None,
+            flush_snapshot: None,
+            clock: Arc::new(SystemClock),
+            stores: StoreFactory::default(),
+            observers: Vec::new(),
+            filters: Vec::new(),
+        }
+    }
+}
+
+impl EngineBuilder {
+    /// Number of tasks the accounts are distributed over.
+    ///
+    /// Transactions of the same client are always handled by the same worker, so their order is
+    /// preserved.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Number of transactions that can be queued in the input channel, and in the channel of each
+    /// worker, before senders have to wait.
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity.max(1);
+        self
+    }
+
+    /// Accept administrative commands like `unlock`, which are rejected otherwise.
+    pub fn admin_commands(mut self, admin_commands: bool) -> Self {
+        self.admin_commands = admin_commands;
+        self
+    }
+
+    /// Number of decimal places amounts may have, and are reported with.
+    pub fn precision(mut self, precision: u32) -> Self {
+        self.precision = precision.min(MAX_PRECISION);
+        self
+    }
+
+    /// How amounts are rounded to the precision, in the output and when fees are charged. By
+    /// default midpoints are rounded to the even neighbour.
+    pub fn rounding_mode(mut self, rounding_mode: RoundingMode) -> Self {
+        self.rounding_mode = rounding_mode;
+        self
+    }
+
+    /// How invalid transactions are handled, by default they abort the processing.
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Shorthand for the strict or the lenient [`ErrorPolicy`].
+    pub fn strict(self, strict: bool) -> Self {
+        self.error_policy(if strict {
+            ErrorPolicy::Strict
+        } else {
+            ErrorPolicy::Lenient
+        })
+    }
+
+    /// Keeps the transaction history of the accounts in memory only up to the capacity of
+    /// `history_spill`, the least recently used transactions are moved to disk.
+    pub fn history_spill(mut self, history_spill: HistorySpill) -> Self {
+        self.history_spill = Some(history_spill);
+        self
+    }
+
+    /// Which transactions of each account are remembered for later disputes, by default all of
+    /// them.
+    pub fn history_retention(mut self, history_retention: HistoryRetention) -> Self {
+        self.history_retention = history_retention;
+        self
+    }
+
+    /// What happens to the transactions whose dispute is settled for good, by default they are
+    /// remembered. Dropping them shrinks the history of dispute-heavy workloads.
+    pub fn settled_history(mut self, settled_history: SettledHistory) -> Self {
+        self.settled_history = settled_history;
+        self
+    }
+
+    /// Risk limits every account is subject to.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Fees charged on deposits and withdrawals, by default none. The fees of every account are
+    /// reported with its balances.
+    pub fn fee_schedule(mut self, fee_schedule: FeeSchedule) -> Self {
+        self.fee_schedule = Some(fee_schedule);
+        self
+    }
+
+    /// Exchange rates `convert` transactions are applied with, by default conversions are
+    /// invalid. The balances of the accounts are in the base currency of the rates.
+    pub fn fx_rates(mut self, fx_rates: FxRates) -> Self {
+        self.fx_rates = Some(Arc::new(fx_rates));
+        self
+    }
+
+    /// Takes the limits, the fees and the error policy from `live_policy` instead, which can be
+    /// swapped while the engine runs.
+    pub fn live_policy(mut self, live_policy: LivePolicy) -> Self {
+        self.live_policy = Some(live_policy);
+        self
+    }
+
+    /// Disputes of transactions older than `dispute_window` are declined, by default any
+    /// transaction can be disputed.
+    pub fn dispute_window(mut self, dispute_window: DisputeWindow) -> Self {
+        self.dispute_window = Some(dispute_window);
+        self
+    }
+
+    /// Whether a resolved transaction can be disputed again, by default it can.
+    pub fn redispute_policy(mut self, redispute_policy: RedisputePolicy) -> Self {
+        self.redispute_policy = redispute_policy;
+        self
+    }
+
+    /// How deposits, withdrawals and transfers reusing the id of an earlier transaction of the
+    /// same client are handled, by default they are rejected.
+    pub fn duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    /// How transactions for an account locked by a chargeback are handled, by default they are
+    /// ignored.
+    pub fn locked_policy(mut self, locked_policy: LockedAccountPolicy) -> Self {
+        self.locked_policy = locked_policy;
+        self
+    }
+
+    /// How disputes, resolves, chargebacks and chargeback reversals for clients without account
+    /// are handled, by default an empty account is opened for them.
+    pub fn unknown_client_policy(mut self, unknown_client_policy: UnknownClientPolicy) -> Self {
+        self.unknown_client_policy = unknown_client_policy;
+        self
+    }
+
+    /// Raises a [`crate::RiskAlert`] when a client crosses one of `risk_thresholds`, which is
+    /// logged and passed to the observers.
+    pub fn risk_thresholds(mut self, risk_thresholds: RiskThresholds) -> Self {
+        self.risk_thresholds = Some(risk_thresholds);
+        self
+    }
+
+    /// Tracks transaction ids in a bloom filter sized for `expected_transactions` instead of a
+    /// map, which needs far less memory but doesn't know the client of an id.
+    pub fn bloom_filter(mut self, expected_transactions: usize) -> Self {
+        self.bloom_filter = Some(expected_transactions);
+        self
+    }
+
+    /// Sizes the account stores for about `clients` clients up front, instead of growing them as
+    /// clients arrive. Only a hint, more or fewer clients work the same.
+    pub fn expected_clients(mut self, clients: usize) -> Self {
+        self.expected_clients = Some(clients);
+        self
+    }
+
+    /// Sizes the index of transaction ids for about `transactions` deposits, withdrawals and
+    /// transfers up front, and together with [`Self::expected_clients`] the history of every
+    /// account for its share of them. Only a hint, like the number of clients.
+    pub fn expected_transactions(mut self, transactions: usize) -> Self {
+        self.expected_transactions = Some(transactions);
+        self
+    }
+
+    /// How transactions older than a previous transaction of the same client are handled, by
+    /// default they are reported on stderr.
+    pub fn ordering(mut self, ordering: OrderingPolicy) -> Self {
+        self.ordering = ordering;
+        self
+    }
+
+    /// Write the accounts ordered by client id, which is the default. Otherwise they are written
+    /// in no particular order, which saves sorting them.
+    pub fn sort_output(mut self, sort_output: bool) -> Self {
+        self.sort_output = sort_output;
+        self
+    }
+
+    /// Number of threads serializing the accounts at the same time when they are written, the
+    /// output stays the same. By default they are serialized on the calling thread.
+    pub fn output_threads(mut self, output_threads: usize) -> Self {
+        self.output_threads = output_threads.max(1);
+        self
+    }
+
+    /// Flushes the audit log, and writes the snapshot given with [`Self::flush_snapshot`], at the
+    /// end of every `interval` in which transactions were processed, so a crash of a long-running
+    /// engine loses at most the work of the last interval. An engine that becomes idle is thus
+    /// flushed once, at most `interval` after its last transaction.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = Some(interval.max(Duration::from_millis(1)));
+        self
+    }
+
+    /// Replaces the snapshot at `path` by the event log on every flush of
+    /// [`Self::flush_interval`], which [`PaymentsEngine::load_snapshot`] restores after a crash.
+    pub fn flush_snapshot<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.flush_snapshot = Some(path.into());
+        self
+    }
+
+    /// Source of the current time for transactions without timestamp, by default the system
+    /// time.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Keeps the accounts of each worker in the store returned by `open` for the index of the
+    /// worker, instead of in memory.
+    pub fn account_store<F>(mut self, open: F) -> Self
+    where
+        F: Fn(usize) -> Box<dyn AccountStore> + Send + Sync + 'static,
+    {
+        self.stores = StoreFactory::new(open);
+        self
+    }
+
+    /// Registers `observer` to be told about applied and rejected transactions and locked
+    /// accounts.
+    pub fn observer<O: EngineObserver + 'static>(mut self, observer: O) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// Passes every received transaction through `filter`, after the filters registered before.
+    pub fn filter<F: TransactionFilter + 'static>(mut self, filter: F) -> Self {
+        self.filters.push(Arc::new(filter));
+        self
+    }
+
+    /// Creates the engine together with the sender feeding transactions into it.
+    pub fn build(self) -> (PaymentsEngine, Sender<Transaction>) {
+        PaymentsEngine::from_builder(self)
+    }
+}
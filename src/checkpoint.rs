@@ -0,0 +1,75 @@
+use crate::{payment_engine::QueryHandle, snapshot::Snapshot};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Records fed into the engine between two checkpoints, unless configured otherwise
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 100_000;
+
+/// Position in the input files up to which all records have been fed into the engine.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct InputOffset {
+    /// Index of the file among the expanded input paths
+    pub file: usize,
+    pub path: PathBuf,
+    /// Records of the file already fed, including invalid ones
+    pub records: u64,
+}
+
+/// Saves the event log of the engine together with the input offset it reflects, so an aborted
+/// run can be resumed from there.
+#[derive(Clone)]
+pub struct Checkpoints {
+    path: PathBuf,
+    interval: u64,
+    queries: QueryHandle,
+    resume: InputOffset,
+}
+
+impl Checkpoints {
+    /// Writes a checkpoint to `path` every `interval` records and at the end of every file.
+    pub fn new<P: Into<PathBuf>>(path: P, interval: u64, queries: QueryHandle) -> Self {
+        Checkpoints {
+            path: path.into(),
+            interval: interval.max(1),
+            queries,
+            resume: InputOffset::default(),
+        }
+    }
+
+    /// Skips the input up to `offset`, which was loaded from a previous checkpoint.
+    pub fn resume_at(mut self, offset: InputOffset) -> Self {
+        self.resume = offset;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn resume(&self) -> &InputOffset {
+        &self.resume
+    }
+
+    pub(crate) fn is_due(&self, offset: &InputOffset) -> bool {
+        offset.records.is_multiple_of(self.interval)
+    }
+
+    /// Waits until the engine processed all records up to `offset` and saves its events.
+    ///
+    /// The checkpoint is replaced atomically, so a crash leaves the previous one intact.
+    pub async fn save(&self, offset: InputOffset) -> Result<()> {
+        // A stopped engine reports its error itself
+        let Some(events) = self.queries.events().await else {
+            return Ok(());
+        };
+        let offset_records = offset.records;
+        Snapshot {
+            events,
+            offset: Some(offset),
+        }
+        .replace(&self.path)?;
+        tracing::debug!(path = %self.path.display(), records = offset_records, "Saved checkpoint");
+        Ok(())
+    }
+}
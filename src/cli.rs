@@ -0,0 +1,1771 @@
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use regex::Regex;
+#[cfg(feature = "kafka")]
+use rust_exercise::collector::kafka::KafkaSource;
+#[cfg(feature = "postgres")]
+use rust_exercise::postgres::DEFAULT_POSTGRES_BATCH_SIZE;
+use rust_exercise::{
+    checkpoint::DEFAULT_CHECKPOINT_INTERVAL,
+    collector::{ClientFilter, CsvLayout, InputFormat, DEFAULT_BATCH_SIZE},
+    simulation::DEFAULT_SIMULATION_START,
+    ClientId, DisputeWindow, DuplicatePolicy, EngineError, ErrorPolicy, LockedAccountPolicy,
+    OrderingPolicy, OutputFormat, PointInTime, RateLimit, RateLimits, Redaction, RedisputePolicy,
+    RoundingMode, SettledHistory, Simulation, Tenancy, TransactionId, UnknownClientPolicy,
+    Workload,
+};
+use std::{collections::HashSet, env, iter, net::SocketAddr, path::PathBuf, str::FromStr};
+use tracing::Level;
+
+const DEFAULT_GRPC_ADDRESS: &str = "127.0.0.1:50051";
+const DEFAULT_TCP_ADDRESS: &str = "127.0.0.1:7878";
+const DEFAULT_HISTORY_CAPACITY: usize = 1024;
+const DEFAULT_STORE_CACHE: usize = 10_000;
+// Prefix of an output path naming a SQLite database
+const SQLITE_PREFIX: &str = "sqlite:";
+#[cfg(feature = "kafka")]
+const DEFAULT_KAFKA_GROUP_ID: &str = "rust-exercise";
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    pub output: Option<PathBuf>,
+    /// Header and column order of CSV input files
+    pub csv_layout: CsvLayout,
+    pub output_format: OutputFormat,
+    pub resume_from: Option<PathBuf>,
+    pub snapshot_out: Option<PathBuf>,
+    /// Seconds after which the transactions processed since are flushed while processing
+    pub flush_interval: Option<u64>,
+    /// Checkpoint file of the input offset and the state reached
+    pub checkpoint: Option<PathBuf>,
+    /// Records between two checkpoints
+    pub checkpoint_interval: u64,
+    /// Continue from the checkpoint, if there is one
+    pub resume: bool,
+    /// Path of the audit log, `-` for stderr
+    pub audit_log: Option<PathBuf>,
+    /// Fields and patterns masked in the audit log
+    pub redaction: Redaction,
+    /// CSV file the rejected transactions and invalid records are written to
+    pub dead_letter: Option<PathBuf>,
+    pub error_policy: ErrorPolicy,
+    pub channel_capacity: Option<usize>,
+    /// Transactions read from files sent to the engine at once
+    pub batch_size: usize,
+    /// Number of decimal places of amounts
+    pub precision: Option<u32>,
+    /// Rounding of amounts to the precision
+    pub rounding: RoundingMode,
+    /// Directory the transaction history is spilled to
+    pub spill_history: Option<PathBuf>,
+    /// Transactions of each account kept in memory when spilling the history
+    pub history_capacity: usize,
+    /// Directory the accounts are stored in with sled, in memory if not given
+    pub store: Option<PathBuf>,
+    /// Accounts of each worker kept in memory when storing them on disk
+    pub store_cache: usize,
+    /// Latest transactions of each account remembered for disputes, all if not given
+    pub retain_history: Option<usize>,
+    /// What happens to the transactions whose dispute is settled
+    pub settled_history: SettledHistory,
+    /// TOML file with the deposit and withdrawal limits of the accounts
+    pub limits: Option<PathBuf>,
+    /// TOML file with the fees charged on deposits and withdrawals
+    pub fees: Option<PathBuf>,
+    /// TOML file with the exchange rates conversions are applied with
+    pub fx_rates: Option<PathBuf>,
+    /// TOML file with the limits, fees and error policy, reloaded when it changes
+    pub policy: Option<PathBuf>,
+    /// TOML file with the exposure of a client that raises an alert
+    pub risk_thresholds: Option<PathBuf>,
+    /// How long after a transaction it can be disputed
+    pub dispute_window: Option<DisputeWindow>,
+    /// Handling of transactions older than a previous one of the same client
+    pub ordering: OrderingPolicy,
+    /// Whether resolved transactions can be disputed again
+    pub redispute: RedisputePolicy,
+    /// Handling of transactions reusing the id of an earlier one of the same client
+    pub duplicates: DuplicatePolicy,
+    /// Handling of transactions for locked accounts
+    pub locked_accounts: LockedAccountPolicy,
+    /// Handling of disputes and the like for clients without account
+    pub unknown_clients: UnknownClientPolicy,
+    /// Expected number of transaction ids, tracked in a bloom filter if given
+    pub bloom_filter: Option<usize>,
+    /// Expected number of clients the account stores are sized for
+    pub expect_clients: Option<usize>,
+    /// Expected number of transactions the maps are sized for, estimated from the input files if
+    /// not given
+    pub expect_transactions: Option<usize>,
+    /// Write the accounts ordered by client id
+    pub sort_output: bool,
+    /// Threads serializing the accounts at the same time
+    pub output_threads: usize,
+    /// Accept administrative commands like `unlock`
+    pub admin_commands: bool,
+    /// Directory the ledger of every client is written to
+    pub export_ledger: Option<PathBuf>,
+    /// SQLite database the accounts are written to instead of `output`
+    pub sqlite_output: Option<PathBuf>,
+    /// Write the ledger of every client into the SQLite database as well
+    pub sqlite_ledger: bool,
+    /// Connection string of the Postgres database the accounts and audit records are written to
+    #[cfg(feature = "postgres")]
+    pub postgres: Option<String>,
+    /// Rows written to Postgres with a single statement
+    #[cfg(feature = "postgres")]
+    pub postgres_batch_size: usize,
+    /// Path the summary of the run is written to, `-` for stderr
+    pub report: Option<PathBuf>,
+    /// Path the summary of the run is written to as JSON, `-` for stderr
+    pub summary_json: Option<PathBuf>,
+    /// Path the transactions still in dispute are written to, `-` for stderr
+    pub disputes_report: Option<PathBuf>,
+    /// Report the channel metrics on stderr after processing
+    pub channel_metrics: bool,
+    /// Report the progress on stderr periodically
+    pub progress: bool,
+    /// Most verbose level of the log messages on stderr
+    pub log_level: Level,
+    pub log_format: LogFormat,
+}
+
+/// Format of the log messages.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(s.into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Processes the transactions of the input files one after another
+    Process {
+        /// Paths or glob patterns of the input files
+        inputs: Vec<PathBuf>,
+        /// Format of all input files, detected per file if not given
+        format: Option<InputFormat>,
+        /// Clients whose transactions are processed, the others are skipped
+        clients: ClientFilter,
+        /// Tenant of the input files, whose accounts are kept apart from those of other tenants
+        tenancy: Option<Tenancy>,
+    },
+    /// Processes the files dropped into a directory until the process is interrupted
+    Watch {
+        directory: PathBuf,
+        format: Option<InputFormat>,
+        clients: ClientFilter,
+    },
+    /// Checks the transactions of the input files without processing them
+    Validate {
+        inputs: Vec<PathBuf>,
+        format: Option<InputFormat>,
+    },
+    /// Accepts transactions via gRPC, and optionally HTTP, until the process is interrupted
+    Serve {
+        grpc_listen: SocketAddr,
+        listen: Option<SocketAddr>,
+        rate_limits: RateLimits,
+        /// Transactions waiting for the engine above which the server isn't ready
+        max_backlog: Option<usize>,
+    },
+    /// Accepts transactions, one per line, over TCP connections until the process is interrupted
+    Tcp {
+        listen: SocketAddr,
+        format: InputFormat,
+        rate_limits: RateLimits,
+    },
+    /// Writes a synthetic workload as CSV instead of processing transactions
+    Generate(Workload),
+    /// Processes a synthetic workload with simulated time
+    Simulate(Simulation),
+    /// Reads commands like `deposit 1 2.5` from stdin and applies them until `quit`
+    Interactive,
+    /// Writes the accounts of a snapshot without processing transactions, optionally as they
+    /// were at a historical point
+    Snapshot {
+        path: PathBuf,
+        at: Option<PointInTime>,
+    },
+    /// Writes a snapshot as a self-describing JSON document of the accounts
+    ExportSnapshot { snapshot: PathBuf },
+    /// Writes the accounts of a JSON document written by `snapshot export`
+    ImportSnapshot { document: PathBuf },
+    /// Consumes transactions from a Kafka topic until the process is interrupted
+    #[cfg(feature = "kafka")]
+    Kafka(KafkaSource),
+}
+
+/// Processes payment transactions and writes the final state of the client accounts.
+///
+/// Input files given without a command are processed, e.g. `rust-exercise transactions.csv`.
+#[derive(Parser, Debug)]
+#[command(name = env!("CARGO_PKG_NAME"), version)]
+struct Cli {
+    #[command(subcommand)]
+    command: CliCommand,
+    /// Number of decimal places amounts may have, and are reported with
+    #[arg(long, global = true)]
+    precision: Option<u32>,
+    /// Rounding of amounts to the precision in the output and of fees, `half-even` or `half-up`
+    #[arg(long, global = true)]
+    rounding: Option<RoundingMode>,
+    /// Abort on the first invalid transaction, the default
+    #[arg(long, global = true, overrides_with = "lenient")]
+    strict: bool,
+    /// Skip invalid transactions with a warning
+    #[arg(long, global = true, overrides_with = "strict")]
+    lenient: bool,
+    /// Most verbose level of the log messages on stderr
+    #[arg(long, global = true, default_value_t = Level::WARN)]
+    log_level: Level,
+    /// Format of the log messages, `text` or `json`
+    #[arg(long, global = true, default_value = "text")]
+    log_format: LogFormat,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CliCommand {
+    /// Processes the transactions of the input files one after another
+    Process {
+        /// Paths or glob patterns of the input files, `-` for stdin
+        #[arg(required_unless_present = "watch")]
+        inputs: Vec<PathBuf>,
+        /// Keep processing the files dropped into the directory until interrupted, moving every
+        /// finished file to `processed/` inside it
+        #[arg(
+            long,
+            value_name = "DIR",
+            conflicts_with_all = ["inputs", "tenant", "tenant_per_file", "checkpoint"]
+        )]
+        watch: Option<PathBuf>,
+        #[command(flatten)]
+        input: InputArgs,
+        /// Comma separated clients whose transactions are processed, the others are skipped
+        #[arg(long, value_parser = parse_only_clients)]
+        only_clients: Option<HashSet<ClientId>>,
+        /// Comma separated clients whose transactions are skipped
+        #[arg(long, value_parser = parse_excluded_clients)]
+        exclude_clients: Option<HashSet<ClientId>>,
+        /// Tenant all input files belong to, written in the first column of the output
+        #[arg(long)]
+        tenant: Option<String>,
+        /// Every input file belongs to the tenant named like the file without its extension
+        #[arg(long, conflicts_with = "tenant")]
+        tenant_per_file: bool,
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Checks the transactions of the input files without processing them
+    Validate {
+        /// Paths or glob patterns of the input files, `-` for stdin
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+        #[command(flatten)]
+        input: InputArgs,
+    },
+    /// Accepts transactions via gRPC, and optionally HTTP, until the process is interrupted
+    Serve {
+        /// Address of the gRPC server
+        #[arg(long, default_value = DEFAULT_GRPC_ADDRESS)]
+        grpc_listen: SocketAddr,
+        /// Address of the HTTP server, which is only started if given
+        #[arg(long)]
+        listen: Option<SocketAddr>,
+        /// Report the server as not ready once more transactions wait for the engine, by default
+        /// only once the channel is full
+        #[arg(long, value_name = "N")]
+        max_backlog: Option<usize>,
+        #[command(flatten)]
+        rate_limits: RateLimitArgs,
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Accepts transactions, one per line, over TCP connections until the process is interrupted
+    Tcp {
+        #[arg(long, default_value = DEFAULT_TCP_ADDRESS)]
+        listen: SocketAddr,
+        /// Format of the lines, `csv` or `json`
+        #[arg(long, short, default_value = "csv")]
+        format: InputFormat,
+        #[command(flatten)]
+        rate_limits: RateLimitArgs,
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Reads commands like `deposit 1 2.5` from stdin and applies them until `quit`
+    Interactive {
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Writes the accounts of a snapshot without processing transactions
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Snapshot {
+        // Boxed, its own engine flags would double the size of every command
+        #[command(subcommand)]
+        action: Option<Box<SnapshotAction>>,
+        /// Snapshot written with `--snapshot-out`
+        #[arg(required = true)]
+        snapshot: Option<PathBuf>,
+        /// Write the accounts as they were after the first `<n>` events of the snapshot, or
+        /// after their last transaction up to the timestamp `@<seconds since the Unix epoch>`
+        #[arg(long)]
+        at: Option<PointInTime>,
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Writes a synthetic workload as CSV instead of processing transactions
+    Gen {
+        #[command(flatten)]
+        workload: WorkloadArgs,
+        /// File the workload is written to, stdout if not given
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Processes a synthetic workload with simulated time, so the same seed always results in
+    /// the same output
+    Simulate {
+        #[command(flatten)]
+        workload: WorkloadArgs,
+        /// Simulated time of the first transaction in seconds since the Unix epoch, the others
+        /// follow one second apart
+        #[arg(long, default_value_t = DEFAULT_SIMULATION_START)]
+        start: u64,
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Consumes transactions from a Kafka topic until the process is interrupted
+    #[cfg(feature = "kafka")]
+    Kafka {
+        #[arg(long)]
+        brokers: String,
+        #[arg(long)]
+        topic: String,
+        #[arg(long, default_value = DEFAULT_KAFKA_GROUP_ID)]
+        group_id: String,
+        /// Format of the messages, `json` or `csv`
+        #[arg(long, short, default_value = "json")]
+        format: InputFormat,
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum SnapshotAction {
+    /// Writes a snapshot as a self-describing JSON document of the accounts with their
+    /// transaction histories and disputes, and the event log
+    Export {
+        /// Snapshot written with `--snapshot-out`
+        snapshot: PathBuf,
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Writes the accounts of a JSON document written by `snapshot export`, which
+    /// `--snapshot-out` turns back into a snapshot
+    Import {
+        document: PathBuf,
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+}
+
+// Flags of the commands generating a workload
+#[derive(clap::Args, Debug)]
+struct WorkloadArgs {
+    #[arg(long, default_value_t = Workload::default().clients)]
+    clients: ClientId,
+    #[arg(long, default_value_t = Workload::default().transactions)]
+    transactions: TransactionId,
+    /// Share of the transactions disputing a previous deposit
+    #[arg(long, default_value_t = Workload::default().dispute_ratio, value_parser = parse_ratio)]
+    dispute_ratio: f64,
+    #[arg(long, default_value_t = Workload::default().seed)]
+    seed: u64,
+}
+
+impl From<WorkloadArgs> for Workload {
+    fn from(args: WorkloadArgs) -> Self {
+        Workload {
+            clients: args.clients,
+            transactions: args.transactions,
+            dispute_ratio: args.dispute_ratio,
+            seed: args.seed,
+        }
+    }
+}
+
+// Flags of the commands reading input files
+#[derive(clap::Args, Debug)]
+struct InputArgs {
+    /// Format of all input files, `csv` or `json`, detected per file if not given
+    #[arg(long, short)]
+    format: Option<InputFormat>,
+    /// CSV input files have no header row
+    #[arg(long)]
+    no_header: bool,
+    /// Comma separated column order of CSV input files, `_` skips a column
+    // The full path keeps clap from taking it for a repeated flag
+    #[arg(long, value_parser = CsvLayout::parse_columns)]
+    columns: Option<::std::vec::Vec<String>>,
+    /// Threads parsing the records of CSV input files, which are still processed in order
+    #[arg(long, default_value_t = 1)]
+    parse_threads: usize,
+    /// Amounts of CSV input files may have a decimal comma, e.g. `1,5`, or be in scientific
+    /// notation, e.g. `1.5e3`
+    #[arg(long)]
+    tolerant_amounts: bool,
+    /// Read input files through a memory map, which is faster for large files not yet cached
+    #[arg(long)]
+    mmap: bool,
+}
+
+// Flags of the commands accepting transactions over connections
+#[derive(clap::Args, Debug)]
+struct RateLimitArgs {
+    /// Transactions per second accepted over all connections, unlimited if not given
+    #[arg(long)]
+    rate_limit: Option<RateLimit>,
+    /// Transactions per second accepted over each connection, unlimited if not given
+    #[arg(long, value_parser = parse_connection_rate_limit)]
+    rate_limit_per_connection: Option<RateLimit>,
+}
+
+impl From<RateLimitArgs> for RateLimits {
+    fn from(args: RateLimitArgs) -> Self {
+        RateLimits {
+            global: args.rate_limit,
+            per_connection: args.rate_limit_per_connection,
+        }
+    }
+}
+
+// Flags of the commands running an engine
+#[derive(Parser, Debug)]
+struct EngineArgs {
+    /// File the accounts are written to, stdout if not given, or `sqlite:<path>` for a SQLite
+    /// database
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Format the accounts are written in, `csv` or `json`
+    #[arg(long)]
+    output_format: Option<OutputFormat>,
+    /// Snapshot the accounts are restored from before processing
+    #[arg(long)]
+    resume_from: Option<PathBuf>,
+    /// File a snapshot of the accounts is written to after processing
+    #[arg(long)]
+    snapshot_out: Option<PathBuf>,
+    /// Seconds after which transactions processed since are flushed to `--snapshot-out` and the
+    /// audit log, while the engine is running
+    #[arg(long)]
+    flush_interval: Option<u64>,
+    /// Checkpoint file of the input offset and the state reached
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    /// Records between two checkpoints
+    #[arg(long, default_value_t = DEFAULT_CHECKPOINT_INTERVAL)]
+    checkpoint_interval: u64,
+    /// Continue from the checkpoint, if there is one
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+    /// Path of the audit log, `-` for stderr
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+    /// Comma separated fields of the audit records whose values are masked, e.g. `client,reason`
+    #[arg(long, value_name = "FIELDS", requires = "audit_log")]
+    redact: Option<String>,
+    /// Regular expression whose matches are masked in the audit records, can be repeated
+    #[arg(long, value_name = "REGEX", value_parser = parse_redact_pattern, requires = "audit_log")]
+    redact_pattern: Vec<Regex>,
+    /// CSV file the rejected transactions and invalid records are written to, with the reason
+    #[arg(long)]
+    dead_letter: Option<PathBuf>,
+    /// Transactions queued in each channel before senders have to wait
+    #[arg(long)]
+    channel_capacity: Option<usize>,
+    /// Transactions read from files sent to the engine at once
+    #[arg(long, default_value_t = DEFAULT_BATCH_SIZE)]
+    batch_size: usize,
+    /// Report the channel metrics on stderr after processing
+    #[arg(long)]
+    channel_metrics: bool,
+    /// Directory the transaction history is spilled to
+    #[arg(long)]
+    spill_history: Option<PathBuf>,
+    /// Transactions of each account kept in memory when spilling the history
+    #[arg(long, default_value_t = DEFAULT_HISTORY_CAPACITY)]
+    history_capacity: usize,
+    /// Latest transactions of each account remembered for disputes, all if not given
+    #[arg(long)]
+    retain_history: Option<usize>,
+    /// Transactions whose dispute is settled for good are remembered with `keep`, the default,
+    /// forgotten with `drop` or moved to the `--spill-history` directory with `archive`
+    #[arg(long)]
+    settled_history: Option<SettledHistory>,
+    /// Where the accounts are kept, `memory` or `sled:<directory>`
+    #[arg(long, value_parser = parse_store)]
+    store: Option<Store>,
+    /// Accounts of each worker kept in memory when storing them on disk
+    #[arg(long, default_value_t = DEFAULT_STORE_CACHE)]
+    store_cache: usize,
+    /// TOML file with the deposit and withdrawal limits of the accounts
+    #[arg(long)]
+    limits: Option<PathBuf>,
+    /// TOML file with the fees charged on deposits and withdrawals
+    #[arg(long)]
+    fees: Option<PathBuf>,
+    /// TOML file with the base currency and the exchange rates conversions are applied with
+    #[arg(long)]
+    fx_rates: Option<PathBuf>,
+    /// TOML file with the limits, fees and error policy, which is reloaded when it changes
+    #[arg(long, conflicts_with_all = ["limits", "fees"])]
+    policy: Option<PathBuf>,
+    /// TOML file with the held funds and number of disputes of a client that raise an alert
+    #[arg(long)]
+    risk_thresholds: Option<PathBuf>,
+    /// How long after a transaction it can be disputed, `<n>` transactions or `<n>s` seconds
+    #[arg(long)]
+    dispute_window: Option<DisputeWindow>,
+    /// Handling of out-of-order transactions, `reject`, `warn` or `reorder`
+    #[arg(long = "out-of-order")]
+    ordering: Option<OrderingPolicy>,
+    /// Transactions buffered to restore their order with `--out-of-order reorder`
+    #[arg(long)]
+    reorder_window: Option<usize>,
+    /// Whether resolved transactions can be disputed again, `after-resolve` or `never`
+    #[arg(long)]
+    redispute: Option<RedisputePolicy>,
+    /// Handling of reused transaction ids, `reject`, `skip` or `last-write-wins`
+    #[arg(long)]
+    duplicates: Option<DuplicatePolicy>,
+    /// Handling of transactions for locked accounts, `ignore`, `reject-with-error` or
+    /// `queue-until-unlock`
+    #[arg(long)]
+    locked_accounts: Option<LockedAccountPolicy>,
+    /// Transactions each locked account queues with `--locked-accounts queue-until-unlock`
+    #[arg(long)]
+    locked_queue_capacity: Option<usize>,
+    /// Handling of disputes, resolves and chargebacks for clients without account, `create`,
+    /// `skip` or `reject`
+    #[arg(long)]
+    unknown_clients: Option<UnknownClientPolicy>,
+    /// Expected number of transaction ids, tracked in a bloom filter if given
+    #[arg(long)]
+    bloom_filter: Option<usize>,
+    /// Expected number of clients, which the account stores are sized for up front
+    #[arg(long, value_name = "N")]
+    expect_clients: Option<usize>,
+    /// Expected number of transactions, which the transaction ids and histories are sized for up
+    /// front. Estimated from the size of the input files if not given
+    #[arg(long, value_name = "N")]
+    expect_transactions: Option<usize>,
+    /// Write the accounts ordered by client id, the default
+    #[arg(long, overrides_with = "no_sort_output")]
+    sort_output: bool,
+    /// Write the accounts in no particular order
+    #[arg(long, overrides_with = "sort_output")]
+    no_sort_output: bool,
+    /// Threads serializing the accounts at the same time, for CSV and JSON output
+    #[arg(long, default_value_t = 1)]
+    output_threads: usize,
+    /// Accept administrative commands like `unlock`
+    #[arg(long = "allow-admin")]
+    admin_commands: bool,
+    /// Directory the ledger of every client is written to
+    #[arg(long)]
+    export_ledger: Option<PathBuf>,
+    /// Write the ledger of every client into the SQLite output as well
+    #[arg(long)]
+    sqlite_ledger: bool,
+    /// Connection string of a Postgres database the accounts and the outcome of every
+    /// transaction are written to, e.g. `host=localhost user=postgres`
+    #[cfg(feature = "postgres")]
+    #[arg(long)]
+    postgres: Option<String>,
+    /// Rows written to Postgres with a single statement
+    #[cfg(feature = "postgres")]
+    #[arg(long, default_value_t = DEFAULT_POSTGRES_BATCH_SIZE)]
+    postgres_batch_size: usize,
+    /// Path the summary of the run is written to, `-` for stderr
+    #[arg(long)]
+    report: Option<PathBuf>,
+    /// Path the summary of the run is written to as JSON with its runtime, `-` for stderr
+    #[arg(long)]
+    summary_json: Option<PathBuf>,
+    /// Path the transactions still in dispute at the end are written to as CSV, `-` for stderr
+    #[arg(long)]
+    disputes_report: Option<PathBuf>,
+    /// Report the progress on stderr periodically
+    #[arg(long)]
+    progress: bool,
+}
+
+impl EngineArgs {
+    // The first flag given that writes or keeps the state of a single engine, which the engines
+    // of several tenants don't support
+    fn without_tenants(&self) -> Option<&'static str> {
+        let output = self.output.as_ref().and_then(|path| path.to_str());
+        #[cfg(feature = "postgres")]
+        let postgres = self.postgres.is_some();
+        #[cfg(not(feature = "postgres"))]
+        let postgres = false;
+        [
+            ("--resume-from", self.resume_from.is_some()),
+            ("--snapshot-out", self.snapshot_out.is_some()),
+            ("--flush-interval", self.flush_interval.is_some()),
+            ("--checkpoint", self.checkpoint.is_some()),
+            ("--audit-log", self.audit_log.is_some()),
+            ("--channel-metrics", self.channel_metrics),
+            ("--spill-history", self.spill_history.is_some()),
+            ("--store", self.store.is_some()),
+            ("--export-ledger", self.export_ledger.is_some()),
+            (
+                "--output sqlite:<path>",
+                output.is_some_and(|path| path.starts_with(SQLITE_PREFIX)),
+            ),
+            ("--postgres", postgres),
+            ("--report", self.report.is_some()),
+            ("--summary-json", self.summary_json.is_some()),
+            ("--disputes-report", self.disputes_report.is_some()),
+            ("--progress", self.progress),
+        ]
+        .into_iter()
+        .find_map(|(flag, given)| given.then_some(flag))
+    }
+}
+
+impl Default for EngineArgs {
+    // The values of the flags when none is given
+    fn default() -> Self {
+        EngineArgs::parse_from([env!("CARGO_PKG_NAME")])
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Store {
+    Memory,
+    Sled(PathBuf),
+}
+
+impl Options {
+    /// Parses the arguments of the process, or exits with the usage if they are invalid.
+    pub fn from_args() -> Self {
+        Self::parse(env::args().skip(1)).unwrap_or_else(|error| error.exit())
+    }
+
+    fn parse<I: Iterator<Item = String>>(args: I) -> Result<Self, clap::Error> {
+        let args = with_default_command(args.collect());
+        let cli = Cli::try_parse_from(iter::once(env!("CARGO_PKG_NAME").into()).chain(args))?;
+        let error_policy = if cli.lenient {
+            ErrorPolicy::Lenient
+        } else {
+            ErrorPolicy::Strict
+        };
+
+        let mut csv_layout = CsvLayout::default();
+        let (command, engine) = match cli.command {
+            CliCommand::Process {
+                inputs,
+                watch,
+                input,
+                only_clients,
+                exclude_clients,
+                tenant,
+                tenant_per_file,
+                engine,
+            } => {
+                csv_layout = input.csv_layout();
+                let format = input.format;
+                let clients = ClientFilter {
+                    only: only_clients,
+                    excluded: exclude_clients.unwrap_or_default(),
+                };
+                let tenancy = match (tenant, tenant_per_file) {
+                    (Some(tenant), _) => Some(Tenancy::Named(tenant)),
+                    (None, true) => Some(Tenancy::PerFile),
+                    (None, false) => None,
+                };
+                if let Some(flag) = tenancy.as_ref().and_then(|_| engine.without_tenants()) {
+                    return Err(Cli::command().error(
+                        ErrorKind::ArgumentConflict,
+                        format!("{flag} can't be combined with tenants"),
+                    ));
+                }
+                let command = match watch {
+                    Some(directory) => Command::Watch {
+                        directory,
+                        format,
+                        clients,
+                    },
+                    None => Command::Process {
+                        inputs,
+                        format,
+                        clients,
+                        tenancy,
+                    },
+                };
+                (command, engine)
+            }
+            CliCommand::Validate { inputs, input } => {
+                csv_layout = input.csv_layout();
+                let format = input.format;
+                (Command::Validate { inputs, format }, EngineArgs::default())
+            }
+            CliCommand::Serve {
+                grpc_listen,
+                listen,
+                max_backlog,
+                rate_limits,
+                engine,
+            } => (
+                Command::Serve {
+                    grpc_listen,
+                    listen,
+                    rate_limits: rate_limits.into(),
+                    max_backlog,
+                },
+                engine,
+            ),
+            CliCommand::Tcp {
+                listen,
+                format,
+                rate_limits,
+                engine,
+            } => (
+                Command::Tcp {
+                    listen,
+                    format,
+                    rate_limits: rate_limits.into(),
+                },
+                engine,
+            ),
+            CliCommand::Interactive { engine } => (Command::Interactive, engine),
+            CliCommand::Snapshot {
+                action: Some(action),
+                ..
+            } => match *action {
+                SnapshotAction::Export { snapshot, engine } => {
+                    (Command::ExportSnapshot { snapshot }, engine)
+                }
+                SnapshotAction::Import { document, engine } => {
+                    (Command::ImportSnapshot { document }, engine)
+                }
+            },
+            CliCommand::Snapshot {
+                action: None,
+                snapshot,
+                at,
+                engine,
+            } => {
+                let path = snapshot.expect("the snapshot is required without a subcommand");
+                (Command::Snapshot { path, at }, engine)
+            }
+            CliCommand::Gen { workload, output } => {
+                let engine = EngineArgs {
+                    output,
+                    ..EngineArgs::default()
+                };
+                (Command::Generate(workload.into()), engine)
+            }
+            CliCommand::Simulate {
+                workload,
+                start,
+                engine,
+            } => {
+                let simulation = Simulation {
+                    workload: workload.into(),
+                    start,
+                };
+                (Command::Simulate(simulation), engine)
+            }
+            #[cfg(feature = "kafka")]
+            CliCommand::Kafka {
+                brokers,
+                topic,
+                group_id,
+                format,
+                engine,
+            } => {
+                let source = KafkaSource {
+                    brokers,
+                    topic,
+                    group_id,
+                    format,
+                };
+                (Command::Kafka(source), engine)
+            }
+        };
+
+        let (output, sqlite_output) = match engine.output {
+            Some(path) => match path
+                .to_str()
+                .and_then(|path| path.strip_prefix(SQLITE_PREFIX))
+            {
+                Some(_) if cfg!(not(feature = "sqlite")) => {
+                    return Err(Cli::command().error(
+                        ErrorKind::InvalidValue,
+                        "SQLite output requires the `sqlite` feature",
+                    ))
+                }
+                Some(database) => (None, Some(PathBuf::from(database))),
+                None => (Some(path), None),
+            },
+            None => (None, None),
+        };
+        if engine.sqlite_ledger && sqlite_output.is_none() {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "--sqlite-ledger requires --output sqlite:<path>",
+            ));
+        }
+        let settled_history = engine.settled_history.unwrap_or_default();
+        if settled_history == SettledHistory::Archive && engine.spill_history.is_none() {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "--settled-history archive requires --spill-history",
+            ));
+        }
+        if engine.dead_letter.is_some() && error_policy == ErrorPolicy::Strict {
+            return Err(Cli::command().error(
+                ErrorKind::ArgumentConflict,
+                "--dead-letter requires --lenient",
+            ));
+        }
+
+        let mut ordering = engine.ordering.unwrap_or_default();
+        if let (OrderingPolicy::Reorder(window), Some(reorder_window)) =
+            (&mut ordering, engine.reorder_window)
+        {
+            *window = reorder_window;
+        }
+        let mut locked_accounts = engine.locked_accounts.unwrap_or_default();
+        if let (LockedAccountPolicy::QueueUntilUnlock(capacity), Some(queue_capacity)) =
+            (&mut locked_accounts, engine.locked_queue_capacity)
+        {
+            *capacity = queue_capacity;
+        }
+        let fields = engine.redact.iter().flat_map(|fields| fields.split(','));
+        let mut redaction = fields.fold(Redaction::default(), |redaction, field| {
+            redaction.field(field.trim())
+        });
+        for pattern in engine.redact_pattern {
+            redaction = redaction.pattern(pattern);
+        }
+
+        Ok(Options {
+            command,
+            output,
+            csv_layout,
+            output_format: engine.output_format.unwrap_or_default(),
+            resume_from: engine.resume_from,
+            snapshot_out: engine.snapshot_out,
+            flush_interval: engine.flush_interval,
+            checkpoint: engine.checkpoint,
+            checkpoint_interval: engine.checkpoint_interval,
+            resume: engine.resume,
+            audit_log: engine.audit_log,
+            redaction,
+            dead_letter: engine.dead_letter,
+            error_policy,
+            channel_capacity: engine.channel_capacity,
+            batch_size: engine.batch_size,
+            precision: cli.precision,
+            rounding: cli.rounding.unwrap_or_default(),
+            spill_history: engine.spill_history,
+            history_capacity: engine.history_capacity,
+            store: match engine.store {
+                Some(Store::Sled(directory)) => Some(directory),
+                Some(Store::Memory) | None => None,
+            },
+            store_cache: engine.store_cache,
+            retain_history: engine.retain_history,
+            settled_history,
+            limits: engine.limits,
+            fees: engine.fees,
+            fx_rates: engine.fx_rates,
+            policy: engine.policy,
+            risk_thresholds: engine.risk_thresholds,
+            dispute_window: engine.dispute_window,
+            ordering,
+            redispute: engine.redispute.unwrap_or_default(),
+            duplicates: engine.duplicates.unwrap_or_default(),
+            locked_accounts,
+            unknown_clients: engine.unknown_clients.unwrap_or_default(),
+            bloom_filter: engine.bloom_filter,
+            expect_clients: engine.expect_clients,
+            expect_transactions: engine.expect_transactions,
+            sort_output: !engine.no_sort_output,
+            output_threads: engine.output_threads,
+            admin_commands: engine.admin_commands,
+            export_ledger: engine.export_ledger,
+            sqlite_output,
+            sqlite_ledger: engine.sqlite_ledger,
+            #[cfg(feature = "postgres")]
+            postgres: engine.postgres,
+            #[cfg(feature = "postgres")]
+            postgres_batch_size: engine.postgres_batch_size,
+            report: engine.report,
+            summary_json: engine.summary_json,
+            disputes_report: engine.disputes_report,
+            channel_metrics: engine.channel_metrics,
+            progress: engine.progress,
+            log_level: cli.log_level,
+            log_format: cli.log_format,
+        })
+    }
+}
+
+impl InputArgs {
+    fn csv_layout(&self) -> CsvLayout {
+        CsvLayout {
+            header: !self.no_header,
+            columns: self.columns.clone(),
+            parse_threads: self.parse_threads,
+            tolerant_amounts: self.tolerant_amounts,
+            mmap: self.mmap,
+        }
+    }
+}
+
+// Input files given without a command are processed, as before there were commands
+fn with_default_command(mut args: Vec<String>) -> Vec<String> {
+    let cli = Cli::command();
+    let names_command = |arg: &String| {
+        cli.get_subcommands()
+            .any(|command| command.get_name() == arg)
+            || ["-h", "--help", "-V", "--version"].contains(&arg.as_str())
+    };
+    if !args.is_empty() && !args.iter().any(names_command) {
+        args.insert(0, "process".into());
+    }
+    args
+}
+
+fn parse_only_clients(value: &str) -> Result<HashSet<ClientId>, EngineError> {
+    ClientFilter::parse_clients("--only-clients", value)
+}
+
+fn parse_excluded_clients(value: &str) -> Result<HashSet<ClientId>, EngineError> {
+    ClientFilter::parse_clients("--exclude-clients", value)
+}
+
+// `memory`, or `sled:<directory>` for a store on disk
+fn parse_store(value: &str) -> Result<Store, EngineError> {
+    match value.split_once(':') {
+        None if value == "memory" => Ok(Store::Memory),
+        Some(("sled", directory)) if !directory.is_empty() => Ok(Store::Sled(directory.into())),
+        _ => Err(EngineError::InvalidArgumentValue(
+            "--store".into(),
+            value.into(),
+        )),
+    }
+}
+
+fn parse_connection_rate_limit(value: &str) -> Result<RateLimit, EngineError> {
+    value.parse().map_err(|_| {
+        EngineError::InvalidArgumentValue("--rate-limit-per-connection".into(), value.into())
+    })
+}
+
+fn parse_redact_pattern(value: &str) -> Result<Regex, EngineError> {
+    Regex::new(value)
+        .map_err(|_| EngineError::InvalidArgumentValue("--redact-pattern".into(), value.into()))
+}
+
+fn parse_ratio(value: &str) -> Result<f64, EngineError> {
+    match value.parse() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(EngineError::InvalidArgumentValue(
+            "--dispute-ratio".into(),
+            value.into(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, LogFormat, Options};
+    use clap::error::ErrorKind;
+    use regex::Regex;
+    use rust_exercise::{
+        collector::{ClientFilter, CsvLayout, InputFormat},
+        DisputeWindow, DuplicatePolicy, ErrorPolicy, LockedAccountPolicy, OrderingPolicy,
+        OutputFormat, PointInTime, RateLimits, Redaction, RedisputePolicy, RoundingMode,
+        SettledHistory, Simulation, Tenancy, UnknownClientPolicy, Workload,
+    };
+    use std::path::{Path, PathBuf};
+    use tracing::Level;
+
+    #[test]
+    fn input_only() {
+        let options = parse(&["input.csv"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Process {
+                inputs: vec![PathBuf::from("input.csv")],
+                format: None,
+                clients: ClientFilter::default(),
+                tenancy: None,
+            }
+        );
+        assert_eq!(options.output, None);
+        assert_eq!(options.error_policy, ErrorPolicy::Strict);
+        assert!(!options.progress);
+    }
+
+    #[test]
+    fn output_flag() {
+        let options = parse(&["--output", "out.csv", "input.csv"]).unwrap();
+        assert!(
+            matches!(options.command, Command::Process { inputs, .. } if inputs == [Path::new("input.csv")])
+        );
+        assert_eq!(options.output, Some(PathBuf::from("out.csv")));
+    }
+
+    #[test]
+    fn dead_letter_flag() {
+        let options = parse(&["input.csv", "--lenient", "--dead-letter", "rejected.csv"]).unwrap();
+        assert_eq!(options.dead_letter, Some(PathBuf::from("rejected.csv")));
+
+        assert!(parse(&["input.csv", "--dead-letter", "rejected.csv"]).is_err());
+        assert!(parse(&[
+            "input.csv",
+            "--lenient",
+            "--strict",
+            "--dead-letter",
+            "r.csv"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn lenient_flag() {
+        let options = parse(&["input.csv", "--lenient"]).unwrap();
+        assert_eq!(options.error_policy, ErrorPolicy::Lenient);
+
+        let options = parse(&["serve", "--lenient", "--strict"]).unwrap();
+        assert_eq!(options.error_policy, ErrorPolicy::Strict);
+    }
+
+    #[test]
+    fn process_command() {
+        let options = parse(&["process", "input.csv"]).unwrap();
+        assert_eq!(options, parse(&["input.csv"]).unwrap());
+
+        // Global flags may precede the command
+        let options = parse(&["--precision", "2", "serve"]).unwrap();
+        assert!(matches!(options.command, Command::Serve { .. }));
+        assert_eq!(options.precision, Some(2));
+
+        assert!(parse(&["process"]).is_err());
+        assert!(parse(&["--help"]).is_err());
+    }
+
+    #[test]
+    fn watch_flag() {
+        let options = parse(&["--watch", "drop", "--only-clients", "1"]).unwrap();
+        let Command::Watch {
+            directory, clients, ..
+        } = options.command
+        else {
+            panic!("expected the watch command, got {:?}", options.command);
+        };
+        assert_eq!(directory, PathBuf::from("drop"));
+        assert!(clients.admits(1) && !clients.admits(2));
+
+        assert!(parse(&["input.csv", "--watch", "drop"]).is_err());
+        assert!(parse(&["--watch", "drop", "--tenant", "acme"]).is_err());
+        assert!(parse(&["--watch", "drop", "--checkpoint", "checkpoint.json"]).is_err());
+    }
+
+    #[test]
+    fn precision_flag() {
+        let options = parse(&["input.csv", "--precision", "2"]).unwrap();
+        assert_eq!(options.precision, Some(2));
+
+        assert!(parse(&["input.csv", "--precision", "-1"]).is_err());
+
+        let options = parse(&["input.csv", "--rounding", "half-up"]).unwrap();
+        assert_eq!(options.rounding, RoundingMode::HalfUp);
+        assert_eq!(
+            parse(&["input.csv"]).unwrap().rounding,
+            RoundingMode::HalfEven
+        );
+        assert!(parse(&["input.csv", "--rounding", "up"]).is_err());
+    }
+
+    #[test]
+    fn validate_command() {
+        let options = parse(&["validate", "input.csv", "--precision", "2"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Validate {
+                inputs: vec![PathBuf::from("input.csv")],
+                format: None
+            }
+        );
+        assert_eq!(options.precision, Some(2));
+
+        assert!(parse(&["validate"]).is_err());
+        assert!(parse(&["validate", "input.csv", "--output", "out.csv"]).is_err());
+    }
+
+    #[test]
+    fn log_flags() {
+        let options = parse(&["input.csv"]).unwrap();
+        assert_eq!(options.log_level, Level::WARN);
+        assert_eq!(options.log_format, LogFormat::Text);
+
+        let options =
+            parse(&["input.csv", "--log-level", "debug", "--log-format", "json"]).unwrap();
+        assert_eq!(options.log_level, Level::DEBUG);
+        assert_eq!(options.log_format, LogFormat::Json);
+
+        assert!(parse(&["input.csv", "--log-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn report_flag() {
+        assert_eq!(parse(&["input.csv"]).unwrap().report, None);
+        let options = parse(&["input.csv", "--report", "-"]).unwrap();
+        assert_eq!(options.report, Some(PathBuf::from("-")));
+        let options = parse(&["input.csv", "--summary-json", "summary.json"]).unwrap();
+        assert_eq!(options.summary_json, Some(PathBuf::from("summary.json")));
+        let options = parse(&["input.csv", "--disputes-report", "disputes.csv"]).unwrap();
+        assert_eq!(options.disputes_report, Some(PathBuf::from("disputes.csv")));
+
+        let options = parse(&["input.csv", "--export-ledger", "ledger"]).unwrap();
+        assert_eq!(options.export_ledger, Some(PathBuf::from("ledger")));
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn postgres_flags() {
+        let options = parse(&["input.csv"]).unwrap();
+        assert_eq!(options.postgres, None);
+        let options = parse(&[
+            "input.csv",
+            "--postgres",
+            "host=localhost user=postgres",
+            "--postgres-batch-size",
+            "100",
+        ])
+        .unwrap();
+        assert_eq!(
+            options.postgres.as_deref(),
+            Some("host=localhost user=postgres")
+        );
+        assert_eq!(options.postgres_batch_size, 100);
+    }
+
+    #[test]
+    fn sqlite_output() {
+        let options = parse(&["input.csv", "--output", "sqlite:accounts.db"]);
+        #[cfg(feature = "sqlite")]
+        {
+            let options = options.unwrap();
+            assert_eq!(options.sqlite_output, Some(PathBuf::from("accounts.db")));
+            assert_eq!(options.output, None);
+        }
+        #[cfg(not(feature = "sqlite"))]
+        assert_eq!(options.unwrap_err().kind(), ErrorKind::InvalidValue);
+
+        assert_eq!(
+            parse(&["input.csv", "--sqlite-ledger"]).unwrap_err().kind(),
+            ErrorKind::ArgumentConflict
+        );
+    }
+
+    #[test]
+    fn store_flags() {
+        let options = parse(&["input.csv"]).unwrap();
+        assert_eq!(options.store, None);
+
+        let options = parse(&["input.csv", "--store", "sled:/tmp/accounts"]).unwrap();
+        assert_eq!(options.store, Some(PathBuf::from("/tmp/accounts")));
+        assert_eq!(options.store_cache, 10_000);
+
+        let options = parse(&["input.csv", "--store", "memory", "--store-cache", "5"]).unwrap();
+        assert_eq!((options.store, options.store_cache), (None, 5));
+
+        assert!(parse(&["input.csv", "--store", "rocksdb:/tmp/accounts"]).is_err());
+    }
+
+    #[test]
+    fn history_flags() {
+        let options = parse(&["input.csv", "--spill-history", "/tmp/history"]).unwrap();
+        assert_eq!(options.spill_history, Some(PathBuf::from("/tmp/history")));
+        assert_eq!(options.history_capacity, 1024);
+
+        let options = parse(&["input.csv", "--history-capacity", "10"]).unwrap();
+        assert_eq!(options.history_capacity, 10);
+        assert_eq!(options.retain_history, None);
+
+        let options = parse(&["input.csv", "--retain-history", "100"]).unwrap();
+        assert_eq!(options.retain_history, Some(100));
+        assert_eq!(options.settled_history, SettledHistory::Keep);
+
+        let options = parse(&["input.csv", "--settled-history", "drop"]).unwrap();
+        assert_eq!(options.settled_history, SettledHistory::Drop);
+        let options = parse(&[
+            "input.csv",
+            "--settled-history",
+            "archive",
+            "--spill-history",
+            "/tmp/history",
+        ])
+        .unwrap();
+        assert_eq!(options.settled_history, SettledHistory::Archive);
+        assert!(parse(&["input.csv", "--settled-history", "archive"]).is_err());
+        assert!(parse(&["input.csv", "--settled-history", "forget"]).is_err());
+    }
+
+    #[test]
+    fn limits_flag() {
+        let options = parse(&["input.csv", "--limits", "limits.toml"]).unwrap();
+        assert_eq!(options.limits, Some(PathBuf::from("limits.toml")));
+
+        let options = parse(&["input.csv", "--fees", "fees.toml"]).unwrap();
+        assert_eq!(options.fees, Some(PathBuf::from("fees.toml")));
+
+        let options = parse(&["input.csv", "--risk-thresholds", "risk.toml"]).unwrap();
+        assert_eq!(options.risk_thresholds, Some(PathBuf::from("risk.toml")));
+
+        let options = parse(&["serve", "--policy", "policy.toml"]).unwrap();
+        assert_eq!(options.policy, Some(PathBuf::from("policy.toml")));
+        let options = parse(&[
+            "input.csv",
+            "--policy",
+            "policy.toml",
+            "--fees",
+            "fees.toml",
+        ]);
+        assert_eq!(options.unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        let options = parse(&["input.csv", "--dispute-window", "3600s"]).unwrap();
+        assert_eq!(options.dispute_window, Some(DisputeWindow::Seconds(3600)));
+
+        let options = parse(&["input.csv", "--redispute", "never"]).unwrap();
+        assert_eq!(options.redispute, RedisputePolicy::Never);
+
+        let options = parse(&["input.csv"]).unwrap();
+        assert_eq!(options.locked_accounts, LockedAccountPolicy::Ignore);
+        let options = parse(&[
+            "input.csv",
+            "--locked-accounts",
+            "queue-until-unlock",
+            "--locked-queue-capacity",
+            "5",
+        ])
+        .unwrap();
+        assert_eq!(
+            options.locked_accounts,
+            LockedAccountPolicy::QueueUntilUnlock(5)
+        );
+
+        let options = parse(&["input.csv"]).unwrap();
+        assert_eq!(options.unknown_clients, UnknownClientPolicy::Create);
+        let options = parse(&["input.csv", "--unknown-clients", "skip"]).unwrap();
+        assert_eq!(options.unknown_clients, UnknownClientPolicy::Skip);
+        assert!(parse(&["input.csv", "--unknown-clients", "ignore"]).is_err());
+    }
+
+    #[test]
+    fn ordering_flags() {
+        let options = parse(&["input.csv"]).unwrap();
+        assert_eq!(options.ordering, OrderingPolicy::Warn);
+
+        let options = parse(&["input.csv", "--out-of-order", "reject"]).unwrap();
+        assert_eq!(options.ordering, OrderingPolicy::Reject);
+
+        let options = parse(&[
+            "input.csv",
+            "--reorder-window",
+            "10",
+            "--out-of-order",
+            "reorder",
+        ])
+        .unwrap();
+        assert_eq!(options.ordering, OrderingPolicy::Reorder(10));
+
+        assert!(parse(&["input.csv", "--out-of-order", "sort"]).is_err());
+    }
+
+    #[test]
+    fn duplicate_flags() {
+        let options = parse(&["input.csv"]).unwrap();
+        assert_eq!(options.duplicates, DuplicatePolicy::Reject);
+        assert_eq!(options.bloom_filter, None);
+
+        let options = parse(&[
+            "input.csv",
+            "--duplicates",
+            "skip",
+            "--bloom-filter",
+            "1000000",
+        ])
+        .unwrap();
+        assert_eq!(options.duplicates, DuplicatePolicy::Skip);
+        assert_eq!(options.bloom_filter, Some(1_000_000));
+
+        assert!(parse(&["input.csv", "--duplicates", "ignore"]).is_err());
+    }
+
+    #[test]
+    fn size_hint_flags() {
+        let options = parse(&["input.csv"]).unwrap();
+        assert_eq!(options.expect_clients, None);
+        assert_eq!(options.expect_transactions, None);
+
+        let options = parse(&[
+            "input.csv",
+            "--expect-clients",
+            "1000",
+            "--expect-transactions",
+            "5000000",
+        ])
+        .unwrap();
+        assert_eq!(options.expect_clients, Some(1000));
+        assert_eq!(options.expect_transactions, Some(5_000_000));
+    }
+
+    #[test]
+    fn redact_flags() {
+        let options = parse(&["input.csv"]).unwrap();
+        assert!(options.redaction.is_empty());
+
+        let options = parse(&[
+            "input.csv",
+            "--audit-log",
+            "audit.jsonl",
+            "--redact",
+            "client, counterparty",
+            "--redact-pattern",
+            "[0-9]{16}",
+        ])
+        .unwrap();
+        let redaction = Redaction::default()
+            .field("client")
+            .field("counterparty")
+            .pattern(Regex::new("[0-9]{16}").unwrap());
+        assert_eq!(options.redaction, redaction);
+
+        assert!(parse(&["input.csv", "--redact", "client"]).is_err());
+        let invalid = ["input.csv", "--audit-log", "-", "--redact-pattern", "("];
+        assert!(parse(&invalid).is_err());
+    }
+
+    #[test]
+    fn csv_layout_flags() {
+        let options = parse(&["input.csv"]).unwrap();
+        assert_eq!(options.csv_layout, CsvLayout::default());
+
+        let options = parse(&["input.csv", "--no-header", "--columns", "client,type,tx"]).unwrap();
+        assert!(!options.csv_layout.header);
+        assert_eq!(
+            options.csv_layout.columns.unwrap(),
+            ["client", "type", "tx"]
+        );
+
+        assert!(parse(&["input.csv", "--columns", "type,client"]).is_err());
+
+        let options = parse(&["input.csv", "--parse-threads", "4"]).unwrap();
+        assert_eq!(options.csv_layout.parse_threads, 4);
+
+        let options = parse(&["input.csv", "--tolerant-amounts"]).unwrap();
+        assert!(options.csv_layout.tolerant_amounts);
+        assert!(parse(&["input.csv", "--mmap"]).unwrap().csv_layout.mmap);
+    }
+
+    #[test]
+    fn output_format_flag() {
+        assert_eq!(
+            parse(&["input.csv"]).unwrap().output_format,
+            OutputFormat::Csv
+        );
+        assert_eq!(
+            parse(&["input.csv", "--output-format", "json"])
+                .unwrap()
+                .output_format,
+            OutputFormat::JsonLines
+        );
+        assert_eq!(
+            parse(&["input.csv", "--output-format", "xml"])
+                .unwrap_err()
+                .kind(),
+            ErrorKind::ValueValidation
+        );
+    }
+
+    #[test]
+    fn checkpoint_flags() {
+        let options = parse(&["input.csv", "--checkpoint", "run.ckpt", "--resume"]).unwrap();
+        assert_eq!(options.checkpoint, Some(PathBuf::from("run.ckpt")));
+        assert!(options.resume);
+        assert_eq!(
+            parse(&["input.csv", "--resume"]).unwrap_err().kind(),
+            ErrorKind::MissingRequiredArgument
+        );
+    }
+
+    #[test]
+    fn sort_output_flags() {
+        assert!(parse(&["input.csv"]).unwrap().sort_output);
+        assert!(
+            !parse(&["input.csv", "--no-sort-output"])
+                .unwrap()
+                .sort_output
+        );
+    }
+
+    #[test]
+    fn output_threads_flag() {
+        assert_eq!(parse(&["input.csv"]).unwrap().output_threads, 1);
+        let options = parse(&["input.csv", "--output-threads", "4"]).unwrap();
+        assert_eq!(options.output_threads, 4);
+    }
+
+    #[test]
+    fn progress_flag() {
+        let options = parse(&["--progress", "input.csv"]).unwrap();
+        assert!(options.progress);
+    }
+
+    #[test]
+    fn client_flags() {
+        let options = parse(&[
+            "input.csv",
+            "--only-clients",
+            "1, 2,3",
+            "--exclude-clients",
+            "2",
+        ])
+        .unwrap();
+        let Command::Process { clients, .. } = options.command else {
+            panic!("expected the process command");
+        };
+        assert_eq!(clients.only, Some([1, 2, 3].into()));
+        assert_eq!(clients.excluded, [2].into());
+        assert!(clients.admits(1));
+        assert!(!clients.admits(2));
+        assert!(!clients.admits(4));
+
+        assert!(parse(&["input.csv", "--only-clients", "1,two"]).is_err());
+        assert!(parse(&["input.csv", "--exclude-clients", ""]).is_err());
+    }
+
+    #[test]
+    fn tenant_flags() {
+        let tenancy = |args: &[&str]| match parse(args).unwrap().command {
+            Command::Process { tenancy, .. } => tenancy,
+            _ => panic!("expected the process command"),
+        };
+        assert_eq!(tenancy(&["input.csv"]), None);
+        assert_eq!(
+            tenancy(&["input.csv", "--tenant", "bank"]),
+            Some(Tenancy::Named("bank".into()))
+        );
+        assert_eq!(
+            tenancy(&["a.csv", "b.csv", "--tenant-per-file"]),
+            Some(Tenancy::PerFile)
+        );
+
+        let conflicts = [
+            &["input.csv", "--tenant", "bank", "--tenant-per-file"][..],
+            &[
+                "input.csv",
+                "--tenant-per-file",
+                "--snapshot-out",
+                "snapshot.json",
+            ],
+            &[
+                "input.csv",
+                "--tenant",
+                "bank",
+                "--output",
+                "sqlite:accounts.db",
+            ],
+        ];
+        for args in conflicts {
+            assert_eq!(parse(args).unwrap_err().kind(), ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn format_flag() {
+        let options = parse(&["--format", "json", "input.txt"]).unwrap();
+        assert!(matches!(
+            options.command,
+            Command::Process {
+                format: Some(InputFormat::JsonLines),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn multiple_inputs() {
+        let options = parse(&["monday.csv", "tuesday/*.csv"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Process {
+                inputs: vec![PathBuf::from("monday.csv"), PathBuf::from("tuesday/*.csv")],
+                format: None,
+                clients: ClientFilter::default(),
+                tenancy: None,
+            }
+        );
+
+        let options = parse(&["-", "--format", "json"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Process {
+                inputs: vec![PathBuf::from("-")],
+                format: Some(InputFormat::JsonLines),
+                clients: ClientFilter::default(),
+                tenancy: None,
+            }
+        );
+    }
+
+    #[test]
+    fn channel_flags() {
+        let options = parse(&[
+            "input.csv",
+            "--channel-capacity",
+            "1024",
+            "--channel-metrics",
+            "--batch-size",
+            "64",
+        ])
+        .unwrap();
+        assert_eq!(options.channel_capacity, Some(1024));
+        assert!(options.channel_metrics);
+        assert_eq!(options.batch_size, 64);
+
+        assert!(parse(&["input.csv", "--channel-capacity", "many"]).is_err());
+    }
+
+    #[test]
+    fn serve_command() {
+        let options = parse(&["serve"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Serve {
+                grpc_listen: "127.0.0.1:50051".parse().unwrap(),
+                listen: None,
+                rate_limits: RateLimits::default(),
+                max_backlog: None,
+            }
+        );
+
+        let options =
+            parse(&["serve", "--listen", "0.0.0.0:8080", "--max-backlog", "100"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Serve {
+                grpc_listen: "127.0.0.1:50051".parse().unwrap(),
+                listen: Some("0.0.0.0:8080".parse().unwrap()),
+                rate_limits: RateLimits::default(),
+                max_backlog: Some(100),
+            }
+        );
+
+        let options = parse(&[
+            "serve",
+            "--rate-limit",
+            "1000",
+            "--rate-limit-per-connection",
+            "0.5",
+        ])
+        .unwrap();
+        let Command::Serve { rate_limits, .. } = options.command else {
+            panic!("not the serve command");
+        };
+        assert_eq!(rate_limits.global.unwrap().per_second(), 1000.0);
+        assert_eq!(rate_limits.per_connection.unwrap().per_second(), 0.5);
+        assert!(parse(&["serve", "--rate-limit", "0"]).is_err());
+        assert!(parse(&["input.csv", "--rate-limit", "10"]).is_err());
+    }
+
+    #[test]
+    fn tcp_command() {
+        let options = parse(&["tcp", "--listen", "0.0.0.0:7000", "--format", "json"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Tcp {
+                listen: "0.0.0.0:7000".parse().unwrap(),
+                format: InputFormat::JsonLines,
+                rate_limits: RateLimits::default(),
+            }
+        );
+        assert!(parse(&["tcp", "input.csv"]).is_err());
+    }
+
+    #[test]
+    fn gen_command() {
+        let options = parse(&["gen", "--clients", "10", "--dispute-ratio", "0.5"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Generate(Workload {
+                clients: 10,
+                dispute_ratio: 0.5,
+                ..Workload::default()
+            })
+        );
+
+        assert!(parse(&["gen", "--dispute-ratio", "2"]).is_err());
+        assert!(parse(&["gen", "input.csv"]).is_err());
+    }
+
+    #[test]
+    fn simulate_command() {
+        let options = parse(&["simulate", "--seed", "7", "-o", "accounts.csv"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Simulate(Simulation::new(Workload {
+                seed: 7,
+                ..Workload::default()
+            }))
+        );
+        assert_eq!(options.output, Some(PathBuf::from("accounts.csv")));
+
+        let options = parse(&["simulate", "--start", "86400"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Simulate(Simulation {
+                workload: Workload::default(),
+                start: 86_400,
+            })
+        );
+        assert!(parse(&["simulate", "input.csv"]).is_err());
+    }
+
+    #[test]
+    fn snapshot_command() {
+        let options = parse(&["snapshot", "accounts.snap", "--output-format", "json"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Snapshot {
+                path: PathBuf::from("accounts.snap"),
+                at: None
+            }
+        );
+        assert_eq!(options.output_format, OutputFormat::JsonLines);
+
+        let options = parse(&["snapshot", "accounts.snap", "--at", "@1700000000"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Snapshot {
+                path: PathBuf::from("accounts.snap"),
+                at: Some(PointInTime::Timestamp(1_700_000_000))
+            }
+        );
+        assert!(parse(&["snapshot", "accounts.snap", "--at", "yesterday"]).is_err());
+
+        assert!(parse(&["snapshot"]).is_err());
+    }
+
+    #[test]
+    fn snapshot_export_and_import_commands() {
+        let options =
+            parse(&["snapshot", "export", "accounts.snap", "-o", "accounts.json"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::ExportSnapshot {
+                snapshot: PathBuf::from("accounts.snap")
+            }
+        );
+        assert_eq!(options.output, Some(PathBuf::from("accounts.json")));
+
+        let options = parse(&[
+            "snapshot",
+            "import",
+            "accounts.json",
+            "--snapshot-out",
+            "accounts.snap",
+        ])
+        .unwrap();
+        assert_eq!(
+            options.command,
+            Command::ImportSnapshot {
+                document: PathBuf::from("accounts.json")
+            }
+        );
+        assert_eq!(options.snapshot_out, Some(PathBuf::from("accounts.snap")));
+
+        assert!(parse(&["snapshot", "export"]).is_err());
+        assert!(parse(&["snapshot", "accounts.snap", "import", "accounts.json"]).is_err());
+    }
+
+    #[test]
+    fn flush_interval_flag() {
+        assert_eq!(parse(&["serve"]).unwrap().flush_interval, None);
+        let options = parse(&[
+            "serve",
+            "--flush-interval",
+            "30",
+            "--snapshot-out",
+            "s.json",
+        ]);
+        assert_eq!(options.unwrap().flush_interval, Some(30));
+        assert!(parse(&["serve", "--flush-interval", "soon"]).is_err());
+    }
+
+    #[test]
+    fn interactive_command() {
+        let options = parse(&["interactive", "--allow-admin"]).unwrap();
+        assert_eq!(options.command, Command::Interactive);
+        assert!(options.admin_commands);
+        assert!(parse(&["interactive", "input.csv"]).is_err());
+    }
+
+    #[cfg(feature = "kafka")]
+    #[test]
+    fn kafka_command() {
+        use rust_exercise::collector::kafka::KafkaSource;
+
+        let options = parse(&["kafka", "--brokers", "localhost:9092", "--topic", "tx"]).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Kafka(KafkaSource {
+                brokers: "localhost:9092".into(),
+                group_id: "rust-exercise".into(),
+                topic: "tx".into(),
+                format: InputFormat::JsonLines,
+            })
+        );
+
+        assert!(parse(&["kafka", "--topic", "tx"]).is_err());
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["input.csv", "--output"]).is_err());
+        assert!(parse(&["input.csv", "--unknown"]).is_err());
+        assert!(parse(&["input.csv", "--format", "xml"]).is_err());
+        assert!(parse(&["serve", "input.csv"]).is_err());
+        assert!(parse(&["serve", "--listen", "localhost"]).is_err());
+    }
+
+    fn parse(args: &[&str]) -> Result<Options, clap::Error> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Source of the current time, for transactions without a timestamp.
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// Seconds since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+/// Time of the operating system.
+///
+/// Not available on `wasm32-unknown-unknown`, which has no system time.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+}
+
+/// Clock that always returns the same time, e.g. for reproducible tests.
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Simulated time, which only moves when it is advanced, so runs advancing it the same way read
+/// the same times.
+///
+/// Clones share the time, the clock an engine was built with can be advanced from outside.
+#[derive(Clone, Default, Debug)]
+pub struct SimClock(Arc<AtomicU64>);
+
+impl SimClock {
+    pub fn new(now: u64) -> Self {
+        SimClock(Arc::new(AtomicU64::new(now)))
+    }
+
+    /// Moves the time forward by `seconds`.
+    pub fn advance(&self, seconds: u64) {
+        self.0.fetch_add(seconds, Ordering::Relaxed);
+    }
+
+    /// Moves the time forward to `now`, it never goes back.
+    pub fn advance_to(&self, now: u64) {
+        self.0.fetch_max(now, Ordering::Relaxed);
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Clock accounts use unless another one is given: the [`SystemClock`], or on
+/// `wasm32-unknown-unknown`, which has no system time, a [`SimClock`] at the Unix epoch.
+pub fn default_clock() -> Arc<dyn Clock> {
+    if cfg!(all(target_family = "wasm", target_os = "unknown")) {
+        Arc::new(SimClock::default())
+    } else {
+        Arc::new(SystemClock)
+    }
+}
+
+/// Clock shared by the accounts of an engine.
+#[derive(Clone, Debug)]
+pub(crate) struct SharedClock(pub Arc<dyn Clock>);
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock(default_clock())
+    }
+}
+
+// The clock only matters for new transactions, so accounts rebuilt from the same events are equal
+// regardless of it
+impl PartialEq for SharedClock {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
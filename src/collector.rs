@@ -1,29 +1,960 @@
-use crate::error::EngineError;
-use crate::transaction::Transaction;
+use crate::checkpoint::{Checkpoints, InputOffset};
+use crate::dead_letter::DeadLetters;
+use crate::error::{with_sources, EngineError, ErrorContext, ErrorPolicy};
+use crate::payment_engine::QueryHandle;
+use crate::progress::Progress;
+use crate::transaction::{Batch, ClientId, Transaction};
+use crate::validation::Validator;
 use anyhow::Result;
 use csv::{Reader, ReaderBuilder, Trim};
-use std::{env, fs::File};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, Read},
+    mem,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use tokio::sync::mpsc::Sender;
+use tracing::{info_span, Instrument};
 
-pub async fn start_processing_input_data(transaction_sink: Sender<Transaction>) -> Result<()> {
-    let mut reader = initialize_reader()?;
+pub mod ack;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod mmap;
+#[cfg(feature = "object-store")]
+pub mod object;
+pub mod source;
+pub mod tcp;
+pub mod watch;
 
-    let mut transaction_stream = reader.deserialize::<Transaction>();
-    for result in transaction_stream.by_ref() {
-        let transaction = result?;
-        transaction_sink.send(transaction).await?;
+pub use ack::Acknowledgements;
+pub use mmap::MmapReader;
+pub use source::{
+    normalize_amount, CsvSource, JsonLinesSource, MemorySource, ParallelCsvSource,
+    TransactionSource,
+};
+
+/// Input path that reads from stdin instead of a file
+pub const STDIN_PATH: &str = "-";
+
+/// Transactions read from files are sent to the engine in batches of this size, unless another
+/// is given.
+pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// Bytes of a typical CSV record, which the number of transactions in input files is estimated
+/// with.
+pub const ESTIMATED_RECORD_SIZE: u64 = 24;
+
+/// Columns of CSV input without a header row, unless others are given.
+pub const DEFAULT_COLUMNS: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "counterparty",
+    "timestamp",
+    "from_ccy",
+    "to_ccy",
+];
+
+// Columns every CSV layout has to contain, a placeholder skips a column
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+const SKIPPED_COLUMN: &str = "_";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputFormat {
+    Csv,
+    /// One JSON encoded transaction per line
+    JsonLines,
+}
+
+impl InputFormat {
+    /// Detects the format by the file extension, falling back to CSV.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("json" | "jsonl" | "ndjson") => InputFormat::JsonLines,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+/// How the columns of CSV input are laid out, and how many threads parse its records.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CsvLayout {
+    /// Whether the first row is a header, which names the columns unless `columns` is given
+    pub header: bool,
+    /// Names of the columns in order, `_` for a column that is skipped
+    pub columns: Option<Vec<String>>,
+    /// Threads parsing the records of input files, see [`ParallelCsvSource`]. With one the
+    /// records are parsed as they are read.
+    pub parse_threads: usize,
+    /// Whether amounts may be written with a decimal comma or in scientific notation, see
+    /// [`normalize_amount`]
+    pub tolerant_amounts: bool,
+    /// Whether input files are read through a memory map, see [`MmapReader`]
+    pub mmap: bool,
+}
+
+impl Default for CsvLayout {
+    fn default() -> Self {
+        CsvLayout {
+            header: true,
+            columns: None,
+            parse_threads: 1,
+            tolerant_amounts: false,
+            mmap: false,
+        }
+    }
+}
+
+impl CsvLayout {
+    /// Parses a comma separated list of columns, e.g. `client,type,tx,amount`.
+    pub fn parse_columns(columns: &str) -> Result<Vec<String>, EngineError> {
+        let invalid = || EngineError::InvalidArgumentValue("--columns".into(), columns.into());
+        let columns: Vec<String> = columns
+            .split(',')
+            .map(|column| column.trim().to_owned())
+            .collect();
+        let named = columns.iter().filter(|column| *column != SKIPPED_COLUMN);
+        for (position, column) in named.clone().enumerate() {
+            if !DEFAULT_COLUMNS.contains(&column.as_str())
+                || named
+                    .clone()
+                    .skip(position + 1)
+                    .any(|other| other == column)
+            {
+                return Err(invalid());
+            }
+        }
+        if !REQUIRED_COLUMNS
+            .iter()
+            .all(|required| columns.iter().any(|column| column == required))
+        {
+            return Err(invalid());
+        }
+        Ok(columns)
+    }
+
+    // Names the columns are deserialized by, `None` to take them from the header row
+    fn column_names(&self) -> Option<Vec<String>> {
+        match (&self.columns, self.header) {
+            (Some(columns), _) => Some(columns.clone()),
+            (None, true) => None,
+            (None, false) => Some(DEFAULT_COLUMNS.map(str::to_owned).to_vec()),
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "json" | "jsonl" | "ndjson" => Ok(InputFormat::JsonLines),
+            unknown => Err(EngineError::InvalidInputFormat(unknown.into())),
+        }
+    }
+}
+
+/// Clients whose transactions are sent to the engine, the transactions of the others are
+/// skipped before they reach it.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct ClientFilter {
+    /// Only the transactions of these clients, of all if not given
+    pub only: Option<HashSet<ClientId>>,
+    /// Clients whose transactions are skipped, even if they are among `only`
+    pub excluded: HashSet<ClientId>,
+}
+
+impl ClientFilter {
+    /// Parses a comma separated list of clients given with `flag`, e.g. `1,2,3`.
+    pub fn parse_clients(flag: &str, clients: &str) -> Result<HashSet<ClientId>, EngineError> {
+        clients
+            .split(',')
+            .map(|client| {
+                client
+                    .trim()
+                    .parse()
+                    .map_err(|_| EngineError::InvalidArgumentValue(flag.into(), clients.into()))
+            })
+            .collect()
+    }
+
+    pub fn admits(&self, client: ClientId) -> bool {
+        !self.excluded.contains(&client)
+            && self.only.as_ref().is_none_or(|only| only.contains(&client))
+    }
+}
+
+/// Collects transactions into batches for [`crate::PaymentsEngine::batch_sender`], which saves
+/// the engine from synchronizing on every single transaction. The lines the transactions were
+/// read from are sent along, so errors caused by them point at their records.
+pub struct BatchSender {
+    sink: Sender<Batch>,
+    batch: Batch,
+    batch_size: usize,
+    clients: ClientFilter,
+    dead_letters: Option<DeadLetters>,
+}
+
+impl BatchSender {
+    pub fn new(sink: Sender<Batch>, batch_size: usize) -> Self {
+        let batch_size = batch_size.max(1);
+        BatchSender {
+            sink,
+            batch: Self::empty_batch(batch_size),
+            batch_size,
+            clients: ClientFilter::default(),
+            dead_letters: None,
+        }
+    }
+
+    /// Skips the transactions of the clients `clients` doesn't admit.
+    pub fn clients(mut self, clients: ClientFilter) -> Self {
+        self.clients = clients;
+        self
+    }
+
+    /// Writes the records skipped as invalid in lenient mode to `dead_letters`.
+    pub fn dead_letters(mut self, dead_letters: DeadLetters) -> Self {
+        self.dead_letters = Some(dead_letters);
+        self
+    }
+
+    /// Adds `transaction` to the batch, which is sent once it is full.
+    pub async fn send(&mut self, transaction: Transaction) -> Result<()> {
+        self.send_with_line(transaction, None).await
+    }
+
+    /// Adds `transaction`, read from `line` of the input if known, to the batch.
+    pub async fn send_with_line(
+        &mut self,
+        transaction: Transaction,
+        line: Option<u64>,
+    ) -> Result<()> {
+        if !self.clients.admits(transaction.client) {
+            return Ok(());
+        }
+        self.batch.transactions.push(transaction);
+        self.batch.lines.push(line);
+        if self.batch.len() >= self.batch_size {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the transactions collected so far, even if they don't fill a batch.
+    pub async fn flush(&mut self) -> Result<()> {
+        if !self.batch.is_empty() {
+            let batch = mem::replace(&mut self.batch, Self::empty_batch(self.batch_size));
+            self.sink.send(batch).await?;
+        }
+        Ok(())
+    }
+
+    fn empty_batch(batch_size: usize) -> Batch {
+        Batch {
+            transactions: Vec::with_capacity(batch_size),
+            lines: Vec::with_capacity(batch_size),
+        }
+    }
+}
+
+// Where records are fed into the engine, one by one or in batches
+enum Sink<'a> {
+    Transactions(&'a Sender<Transaction>),
+    Batches(&'a mut BatchSender),
+}
+
+impl Sink<'_> {
+    // Only batches keep the line the transaction was read from
+    async fn send(&mut self, transaction: Transaction, line: Option<u64>) -> Result<()> {
+        match self {
+            Sink::Transactions(sink) => Ok(sink.send(transaction).await?),
+            Sink::Batches(sink) => sink.send_with_line(transaction, line).await,
+        }
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        match self {
+            Sink::Transactions(_) => Ok(()),
+            Sink::Batches(sink) => sink.flush().await,
+        }
+    }
+
+    fn dead_letters(&self) -> Option<&DeadLetters> {
+        match self {
+            Sink::Transactions(_) => None,
+            Sink::Batches(sink) => sink.dead_letters.as_ref(),
+        }
+    }
+}
+
+/// Processes the given files one after another into the same engine.
+///
+/// Paths containing glob patterns are expanded in alphabetical order, `-` reads stdin. Without a `format` it is
+/// detected for each file by its extension, CSV files are read according to `csv_layout`. With
+/// `checkpoints` the input is skipped up to their resume offset, and checkpoints of the progress
+/// are written along the way. The transactions are sent in batches through `batch_sink`.
+pub async fn process_files(
+    paths: Vec<PathBuf>,
+    format: Option<InputFormat>,
+    csv_layout: CsvLayout,
+    mut batch_sink: BatchSender,
+    error_policy: ErrorPolicy,
+    progress: Progress,
+    checkpoints: Option<Checkpoints>,
+) -> Result<()> {
+    let paths = expand_paths(paths)?;
+    let resume = checkpoints
+        .as_ref()
+        .map(|checkpoints| checkpoints.resume().clone())
+        .unwrap_or_default();
+    if resume != InputOffset::default() && paths.get(resume.file) != Some(&resume.path) {
+        return Err(EngineError::CheckpointMismatch(resume.path.display().to_string()).into());
+    }
+
+    for (file, path) in paths.into_iter().enumerate().skip(resume.file) {
+        let format = format.unwrap_or_else(|| InputFormat::from_path(&path));
+        let span = info_span!("input", path = %path.display(), ?format);
+        tracing::info!(parent: &span, "Reading input");
+        let mut cursor = Cursor {
+            resume_at: if file == resume.file {
+                resume.records
+            } else {
+                0
+            },
+            offset: InputOffset {
+                file,
+                path: path.clone(),
+                records: 0,
+            },
+            checkpoints: checkpoints.as_ref(),
+            acknowledgements: None,
+        };
+        let input = open(&path, csv_layout.mmap).await?;
+        read(
+            input,
+            format,
+            &csv_layout,
+            &mut Sink::Batches(&mut batch_sink),
+            error_policy,
+            &progress,
+            &mut cursor,
+        )
+        .instrument(span)
+        .await?;
+        if let Some(checkpoints) = &checkpoints {
+            checkpoints.save(cursor.offset).await?;
+        }
     }
 
     Ok(())
 }
 
-fn initialize_reader() -> Result<Reader<File>> {
-    let path = env::args().nth(1).ok_or(EngineError::NoInputArgument)?;
+// Position in the file being read, which is skipped up to `resume_at` and checkpointed
+#[derive(Default)]
+struct Cursor<'a> {
+    offset: InputOffset,
+    resume_at: u64,
+    checkpoints: Option<&'a Checkpoints>,
+    // Records sent directly to the engine are acknowledged to the source, with the watermark
+    // acknowledged last
+    acknowledgements: Option<(Acknowledgements, u64)>,
+}
+
+impl Cursor<'_> {
+    // Whether the next record was fed into the engine before resuming, and is skipped
+    fn skip(&mut self) -> bool {
+        let skip = self.offset.records < self.resume_at;
+        if skip {
+            self.offset.records += 1;
+        }
+        skip
+    }
+
+    async fn fed(&mut self, sink: &mut Sink<'_>) -> Result<()> {
+        self.offset.records += 1;
+        match self.checkpoints {
+            Some(checkpoints) if checkpoints.is_due(&self.offset) => {
+                // The checkpoint waits for the engine, which has to get the whole batch first
+                sink.flush().await?;
+                checkpoints.save(self.offset.clone()).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Acknowledges the records the engine processed since, all of them once `finished`
+    async fn acknowledge<S: TransactionSource>(
+        &mut self,
+        source: &mut S,
+        finished: bool,
+    ) -> Result<()> {
+        let Some((acknowledgements, acknowledged)) = &mut self.acknowledgements else {
+            return Ok(());
+        };
+        acknowledgements.fed(self.offset.records);
+        let confirmed = if finished {
+            acknowledgements.confirm_all().await?
+        } else {
+            acknowledgements.confirmed()
+        };
+        if confirmed > *acknowledged {
+            source.acknowledge(confirmed).await?;
+            *acknowledged = confirmed;
+        }
+        Ok(())
+    }
+}
+
+// Opens stdin, the URL of an object, e.g. `s3://bucket/key`, or a file, which is memory-mapped
+// with `mmap`
+async fn open(path: &Path, mmap: bool) -> Result<Box<dyn Read + Send>> {
+    if path.as_os_str() == STDIN_PATH {
+        return Ok(Box::new(io::stdin()));
+    }
+    match path.to_str() {
+        #[cfg(feature = "object-store")]
+        Some(url) if is_object_url(path) => Ok(Box::new(object::ObjectReader::open(url).await?)),
+        #[cfg(not(feature = "object-store"))]
+        Some(url) if is_object_url(path) => Err(anyhow::anyhow!(
+            "Reading `{}` requires the `object-store` feature",
+            url
+        )),
+        _ if mmap => Ok(Box::new(MmapReader::open(path)?)),
+        _ => Ok(Box::new(File::open(path)?)),
+    }
+}
+
+fn is_object_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.contains("://"))
+}
+
+/// Estimates the number of transactions in the input files at `paths` from their size, `None` if
+/// the size of none of them is known, e.g. for stdin and objects.
+pub fn estimate_transactions(paths: &[PathBuf]) -> Option<usize> {
+    let sizes: Vec<_> = expand_paths(paths.to_vec())
+        .ok()?
+        .iter()
+        .filter(|path| path.as_os_str() != STDIN_PATH && !is_object_url(path))
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .collect();
+    (!sizes.is_empty()).then(|| (sizes.iter().sum::<u64>() / ESTIMATED_RECORD_SIZE) as usize)
+}
+
+pub(crate) fn expand_paths(paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    let mut expanded = Vec::with_capacity(paths.len());
+    for path in paths {
+        let pattern = path.to_string_lossy();
+        if !pattern.contains(['*', '?', '[']) || is_object_url(&path) {
+            expanded.push(path);
+            continue;
+        }
+
+        let mut matches = glob::glob(&pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(EngineError::NoMatchingInput(pattern.into_owned()).into());
+        }
+        matches.sort();
+        expanded.extend(matches);
+    }
+
+    Ok(expanded)
+}
+
+pub async fn process_file<P: AsRef<Path>>(
+    path: P,
+    format: InputFormat,
+    transaction_sink: Sender<Transaction>,
+    error_policy: ErrorPolicy,
+    progress: Progress,
+) -> Result<()> {
     let file = File::open(path)?;
+    process_reader(file, format, transaction_sink, error_policy, progress).await
+}
+
+pub async fn process_reader<R: Read + Send>(
+    input: R,
+    format: InputFormat,
+    transaction_sink: Sender<Transaction>,
+    error_policy: ErrorPolicy,
+    progress: Progress,
+) -> Result<()> {
+    read(
+        input,
+        format,
+        &CsvLayout::default(),
+        &mut Sink::Transactions(&transaction_sink),
+        error_policy,
+        &progress,
+        &mut Cursor::default(),
+    )
+    .await
+}
+
+/// Feeds all transactions of `source` into the engine.
+pub async fn process_source<S: TransactionSource>(
+    source: S,
+    transaction_sink: Sender<Transaction>,
+    error_policy: ErrorPolicy,
+    progress: Progress,
+) -> Result<()> {
+    feed(
+        source,
+        &mut Sink::Transactions(&transaction_sink),
+        error_policy,
+        &progress,
+        &mut Cursor::default(),
+    )
+    .await
+}
+
+/// Feeds all transactions of `source` into the engine like [`process_source`], and tells the
+/// source how many of its records the engine processed along the way, see
+/// [`TransactionSource::acknowledge`]. Returns once all records are acknowledged.
+pub async fn process_acknowledged_source<S: TransactionSource>(
+    source: S,
+    transaction_sink: Sender<Transaction>,
+    queries: QueryHandle,
+    error_policy: ErrorPolicy,
+    progress: Progress,
+) -> Result<()> {
+    let mut cursor = Cursor {
+        acknowledgements: Some((Acknowledgements::new(queries), 0)),
+        ..Cursor::default()
+    };
+    feed(
+        source,
+        &mut Sink::Transactions(&transaction_sink),
+        error_policy,
+        &progress,
+        &mut cursor,
+    )
+    .await
+}
+
+/// Validates the records of the files at `paths` with `validator`, without processing them.
+pub async fn validate_files(
+    paths: Vec<PathBuf>,
+    format: Option<InputFormat>,
+    csv_layout: &CsvLayout,
+    validator: &mut Validator,
+) -> Result<()> {
+    for path in expand_paths(paths)? {
+        let format = format.unwrap_or_else(|| InputFormat::from_path(&path));
+        let input = open(&path, csv_layout.mmap).await?;
+        match format {
+            InputFormat::Csv => {
+                let source = CsvSource::with_layout(input, csv_layout);
+                validator.check_source(source, &path).await
+            }
+            InputFormat::JsonLines => {
+                validator
+                    .check_source(JsonLinesSource::new(input), &path)
+                    .await
+            }
+        }
+    }
+    Ok(())
+}
 
-    let reader = ReaderBuilder::new()
+async fn read<R: Read + Send>(
+    input: R,
+    format: InputFormat,
+    csv_layout: &CsvLayout,
+    transaction_sink: &mut Sink<'_>,
+    error_policy: ErrorPolicy,
+    progress: &Progress,
+    cursor: &mut Cursor<'_>,
+) -> Result<()> {
+    match format {
+        InputFormat::Csv if csv_layout.parse_threads > 1 => {
+            let source = ParallelCsvSource::with_layout(input, csv_layout);
+            feed(source, transaction_sink, error_policy, progress, cursor).await
+        }
+        InputFormat::Csv => {
+            let source = CsvSource::with_layout(input, csv_layout);
+            feed(source, transaction_sink, error_policy, progress, cursor).await
+        }
+        InputFormat::JsonLines => {
+            let source = JsonLinesSource::new(input);
+            feed(source, transaction_sink, error_policy, progress, cursor).await
+        }
+    }
+}
+
+async fn feed<S: TransactionSource>(
+    mut source: S,
+    transaction_sink: &mut Sink<'_>,
+    error_policy: ErrorPolicy,
+    progress: &Progress,
+    cursor: &mut Cursor<'_>,
+) -> Result<()> {
+    while let Some(result) = source.next_transaction().await {
+        if cursor.skip() {
+            continue;
+        }
+        let reason = result.as_ref().err().map(|error| with_sources(error));
+        let checked = match check(result, Some(source.context()), error_policy, progress) {
+            Ok(checked) => checked,
+            Err(error) => {
+                // The transactions read before the record are still sent to the engine, which
+                // reports an invalid one among them as well
+                let _ = transaction_sink.flush().await;
+                return Err(error);
+            }
+        };
+        match (checked, reason) {
+            (Some(transaction), _) => transaction_sink.send(transaction, source.line()).await?,
+            (None, Some(reason)) => {
+                if let Some(dead_letters) = transaction_sink.dead_letters() {
+                    dead_letters.invalid_record(source.record(), reason);
+                }
+            }
+            (None, None) => {}
+        }
+        cursor.fed(transaction_sink).await?;
+        cursor.acknowledge(&mut source, false).await?;
+    }
+
+    transaction_sink.flush().await?;
+    cursor.acknowledge(&mut source, true).await
+}
+
+async fn send<E: std::error::Error + Send + Sync + 'static>(
+    result: Result<Transaction, E>,
+    transaction_sink: &Sender<Transaction>,
+    error_policy: ErrorPolicy,
+    progress: &Progress,
+) -> Result<()> {
+    // The errors of streamed records tell the peer or offset they came from themselves
+    if let Some(transaction) = check(result, None, error_policy, progress)? {
+        transaction_sink.send(transaction).await?;
+    }
+
+    Ok(())
+}
+
+// Counts the record, and returns its transaction unless it is invalid and skipped. Errors are
+// reported with the `context` of the record if it is given.
+fn check<E: std::error::Error + Send + Sync + 'static>(
+    result: Result<Transaction, E>,
+    context: Option<ErrorContext>,
+    error_policy: ErrorPolicy,
+    progress: &Progress,
+) -> Result<Option<Transaction>> {
+    progress.record_row();
+    let transaction = match context {
+        Some(context) => error_policy.check_in_context(result, context)?,
+        None => error_policy.check(result)?,
+    };
+    if transaction.is_none() {
+        progress.record_rejected();
+    }
+
+    Ok(transaction)
+}
+
+// Parses a single transaction, a CSV row without header or a JSON object
+pub(crate) fn parse_payload(payload: &[u8], format: InputFormat) -> Result<Transaction, String> {
+    match format {
+        InputFormat::Csv => {
+            let mut reader = ReaderBuilder::new()
+                .has_headers(false)
+                .trim(Trim::All)
+                .flexible(true)
+                .from_reader(payload);
+            match reader.deserialize().next() {
+                Some(result) => result.map_err(|error| error.to_string()),
+                None => Err("Empty payload".into()),
+            }
+        }
+        InputFormat::JsonLines => {
+            serde_json::from_slice(payload).map_err(|error| error.to_string())
+        }
+    }
+}
+
+fn initialize_reader<R: Read>(input: R, has_headers: bool) -> Reader<R> {
+    ReaderBuilder::new()
+        .has_headers(has_headers)
         .trim(Trim::All)
         .flexible(true)
-        .from_reader(file);
-    Ok(reader)
+        .from_reader(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        estimate_transactions, expand_paths, parse_payload, process_files, process_reader,
+        BatchSender, ClientFilter, CsvLayout, InputFormat, ESTIMATED_RECORD_SIZE,
+    };
+    use crate::{
+        checkpoint::Checkpoints,
+        error::{EngineError, ErrorContext, ErrorPolicy},
+        progress::Progress,
+        transaction::TransactionType,
+        PaymentsEngine,
+    };
+    use std::{fs, io::Write};
+    use tokio::sync::mpsc::channel;
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(InputFormat::from_path("in.jsonl"), InputFormat::JsonLines);
+        assert_eq!(InputFormat::from_path("in.csv"), InputFormat::Csv);
+        assert_eq!(InputFormat::from_path("in"), InputFormat::Csv);
+    }
+
+    #[test]
+    fn expand_glob_patterns() {
+        let paths = expand_paths(vec!["csv/disputes*.csv".into(), "missing.csv".into()]).unwrap();
+        assert_eq!(
+            paths,
+            [
+                "csv/disputes&chargebacks.csv",
+                "csv/disputes&resolves.csv",
+                "csv/disputes.csv",
+                "missing.csv"
+            ]
+            .map(std::path::PathBuf::from)
+        );
+
+        assert!(expand_paths(vec!["csv/*.xml".into()]).is_err());
+    }
+
+    #[test]
+    fn estimate_from_file_size() {
+        let size = fs::metadata("csv/disputes.csv").unwrap().len();
+        assert_eq!(
+            estimate_transactions(&["csv/disputes.csv".into(), "-".into()]),
+            Some((size / ESTIMATED_RECORD_SIZE) as usize)
+        );
+        assert_eq!(estimate_transactions(&["-".into()]), None);
+        assert_eq!(estimate_transactions(&["missing.csv".into()]), None);
+    }
+
+    #[test]
+    fn parse_payloads() {
+        let deposit = parse_payload(b"deposit, 1, 2, 1.5", InputFormat::Csv).unwrap();
+        assert_eq!(deposit.r#type, TransactionType::Deposit);
+        assert_eq!(deposit.amount, Some("1.5".parse().unwrap()));
+
+        let dispute = parse_payload(b"dispute, 1, 2,", InputFormat::Csv).unwrap();
+        assert!(dispute.amount.is_none());
+
+        let convert = parse_payload(b"convert, 1, 4, 2.5, , , eur, USD", InputFormat::Csv).unwrap();
+        assert_eq!(convert.r#type, TransactionType::Convert);
+        assert_eq!(convert.from_ccy, Some("EUR".parse().unwrap()));
+        assert_eq!(convert.to_ccy, Some("USD".parse().unwrap()));
+
+        let json = br#"{"type": "withdrawal", "client": 1, "tx": 3, "amount": 0.5}"#;
+        let withdrawal = parse_payload(json, InputFormat::JsonLines).unwrap();
+        assert_eq!(withdrawal.tx, 3);
+
+        assert!(parse_payload(b"", InputFormat::Csv).is_err());
+        assert!(parse_payload(b"{}", InputFormat::JsonLines).is_err());
+    }
+
+    #[tokio::test]
+    async fn json_lines() {
+        let input = concat!(
+            "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"1.5\"}\n",
+            "\n",
+            "{\"type\": \"withdrawal\", \"client\": 1, \"tx\": 2, \"amount\": 0.25}\n",
+            "{\"type\": \"dispute\", \"client\": 1, \"tx\": 1}\n",
+        );
+        let (sender, mut receiver) = channel(4);
+
+        process_reader(
+            input.as_bytes(),
+            InputFormat::JsonLines,
+            sender,
+            ErrorPolicy::Strict,
+            Progress::default(),
+        )
+        .await
+        .unwrap();
+
+        let deposit = receiver.recv().await.unwrap();
+        assert_eq!(deposit.amount, Some("1.5".parse().unwrap()));
+        let withdrawal = receiver.recv().await.unwrap();
+        assert_eq!(withdrawal.amount, Some("0.25".parse().unwrap()));
+        let dispute = receiver.recv().await.unwrap();
+        assert_eq!(dispute.r#type, TransactionType::Dispute);
+        assert!(dispute.amount.is_none());
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn skip_filtered_clients() {
+        let (mut payments_engine, _) = PaymentsEngine::new();
+        let mut batch_sink =
+            BatchSender::new(payments_engine.batch_sender(), 4).clients(ClientFilter {
+                only: Some([1, 2].into()),
+                excluded: [2].into(),
+            });
+        let collector = tokio::spawn(async move {
+            for client in 1..=3 {
+                let deposit = format!("deposit,{client},{client},1.0");
+                let transaction = parse_payload(deposit.as_bytes(), InputFormat::Csv).unwrap();
+                batch_sink.send(transaction).await.unwrap();
+            }
+            batch_sink.flush().await.unwrap();
+        });
+        payments_engine.process_transactions().await.unwrap();
+        collector.await.unwrap();
+
+        assert!(payments_engine.account(1).is_some());
+        assert!(payments_engine.account(2).is_none());
+        assert!(payments_engine.account(3).is_none());
+    }
+
+    #[tokio::test]
+    async fn resume_from_checkpoint() {
+        let directory = std::env::temp_dir();
+        let input = directory.join("rust-exercise-resume-from-checkpoint.csv");
+        let checkpoint = directory.join("rust-exercise-resume-from-checkpoint.json");
+        let _ = fs::remove_file(&checkpoint);
+        fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\ndeposit,1,3,3.0\n",
+        )
+        .unwrap();
+
+        let run = |resume: bool| {
+            let (input, checkpoint) = (input.clone(), checkpoint.clone());
+            async move {
+                let (mut payments_engine, _) = PaymentsEngine::new();
+                let mut checkpoints =
+                    Checkpoints::new(&checkpoint, 2, payments_engine.query_handle());
+                if resume {
+                    let offset = payments_engine.load_checkpoint(&checkpoint).unwrap();
+                    checkpoints = checkpoints.resume_at(offset);
+                }
+                let collector = tokio::spawn(process_files(
+                    vec![input],
+                    None,
+                    CsvLayout::default(),
+                    // Checkpoints are due within a batch
+                    BatchSender::new(payments_engine.batch_sender(), 16),
+                    ErrorPolicy::Strict,
+                    Progress::default(),
+                    Some(checkpoints),
+                ));
+                payments_engine.process_transactions().await.unwrap();
+                collector.await.unwrap().unwrap();
+                payments_engine.account(1).unwrap().available
+            }
+        };
+        assert_eq!(run(false).await, "6.0".parse().unwrap());
+
+        // Rows appended after the checkpoint are the only ones processed, each exactly once
+        let mut file = fs::OpenOptions::new().append(true).open(&input).unwrap();
+        file.write_all(b"withdrawal,1,4,0.5\n").unwrap();
+        assert_eq!(run(true).await, "5.5".parse().unwrap());
+        assert_eq!(run(true).await, "5.5".parse().unwrap());
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(checkpoint).unwrap();
+    }
+
+    #[tokio::test]
+    async fn errors_point_at_their_records() {
+        let input = std::env::temp_dir().join("rust-exercise-errors-point-at-their-records.csv");
+        let run = |rows: &'static str| {
+            let input = input.clone();
+            async move {
+                fs::write(&input, format!("type,client,tx,amount\n{rows}")).unwrap();
+                let (mut payments_engine, _) = PaymentsEngine::new();
+                let collector = tokio::spawn(process_files(
+                    vec![input],
+                    None,
+                    CsvLayout::default(),
+                    BatchSender::new(payments_engine.batch_sender(), 4),
+                    ErrorPolicy::Strict,
+                    Progress::default(),
+                    None,
+                ));
+                let processed = payments_engine.process_transactions().await;
+                (collector.await.unwrap(), processed)
+            }
+        };
+        let context = |error: anyhow::Error| {
+            let error = error.downcast::<EngineError>().unwrap();
+            (
+                error.context().unwrap(),
+                error.without_context().to_string(),
+            )
+        };
+
+        // A record that isn't a transaction stops the collector
+        let (collected, _) = run("deposit,1,1,1.0\nrefund,2,7,1.0\n").await;
+        let (error_context, _) = context(collected.unwrap_err());
+        let expected = ErrorContext {
+            line: Some(3),
+            client: Some(2),
+            tx: Some(7),
+        };
+        assert_eq!(error_context, expected);
+
+        // A transaction the engine rejects keeps the line of its record through the batch
+        let (collected, processed) = run("deposit,1,7,1.0\ndeposit,2,7,1.0\n").await;
+        collected.unwrap();
+        let error = processed.unwrap_err();
+        // The reason is the source of the error, and isn't repeated in its message
+        assert_eq!(
+            format!("{:#}", error),
+            "Invalid transaction at line 3, client `2`, transaction `7`: \
+             Transaction id `7` is not unique"
+        );
+        let (error_context, reason) = context(error);
+        assert_eq!(error_context, expected);
+        assert_eq!(reason, EngineError::DuplicateTransactionId(7).to_string());
+
+        fs::remove_file(input).unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_transaction_before_invalid_record() {
+        let input = std::env::temp_dir().join("rust-exercise-invalid-before-invalid-record.csv");
+        fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\ndeposit,2,3,\nfoo\n",
+        )
+        .unwrap();
+        let (mut payments_engine, _) = PaymentsEngine::new();
+        let collector = tokio::spawn(process_files(
+            vec![input.clone()],
+            None,
+            CsvLayout::default(),
+            BatchSender::new(payments_engine.batch_sender(), 256),
+            ErrorPolicy::Strict,
+            Progress::default(),
+            None,
+        ));
+        let processed = payments_engine.process_transactions().await;
+
+        // The transactions before the malformed record reach the engine, which rejects the
+        // deposit without an amount
+        let error = processed.unwrap_err().downcast::<EngineError>().unwrap();
+        assert_eq!(error.context().unwrap().line, Some(4));
+        assert_eq!(
+            error.without_context().to_string(),
+            EngineError::NoAmountInDeposit.to_string()
+        );
+        let error = collector.await.unwrap().unwrap_err();
+        let error = error.downcast::<EngineError>().unwrap();
+        assert_eq!(error.context().unwrap().line, Some(5));
+
+        fs::remove_file(input).unwrap();
+    }
 }
@@ -0,0 +1,153 @@
+use crate::payment_engine::QueryHandle;
+use anyhow::{anyhow, Result};
+use tokio::sync::watch;
+
+/// Watermarks of the records of a source fed into the engine and of those it confirmed it
+/// processed, applied or rejected, by their sequence number starting at 1.
+///
+/// A queue-backed source commits its offsets only up to the confirmed watermark, so a restarted
+/// consumer continues with the first record that might not have been processed. The engine
+/// confirms the records in the background, one barrier at a time covering all records fed
+/// before it.
+#[derive(Debug)]
+pub struct Acknowledgements {
+    fed: watch::Sender<u64>,
+    confirmed: watch::Receiver<u64>,
+}
+
+impl Acknowledgements {
+    pub fn new(queries: QueryHandle) -> Self {
+        let (fed, fed_watermark) = watch::channel(0);
+        let (confirm, confirmed) = watch::channel(0);
+        tokio::spawn(confirm_fed(queries, fed_watermark, confirm));
+        Acknowledgements { fed, confirmed }
+    }
+
+    /// Marks the records up to `sequence` as sent to the engine.
+    pub fn fed(&self, sequence: u64) {
+        self.fed.send_replace(sequence);
+    }
+
+    /// Sequence number up to which the engine processed the records.
+    pub fn confirmed(&self) -> u64 {
+        *self.confirmed.borrow()
+    }
+
+    /// Waits until the engine processed all records fed so far, and returns their watermark.
+    ///
+    /// Fails if the engine stopped before, e.g. because of an invalid transaction.
+    pub async fn confirm_all(&mut self) -> Result<u64> {
+        let fed = *self.fed.borrow();
+        self.confirmed
+            .wait_for(|&confirmed| confirmed >= fed)
+            .await
+            .map(|confirmed| *confirmed)
+            .map_err(|_| anyhow!("Engine stopped before the fed transactions were processed"))
+    }
+}
+
+// Confirms the watermark fed before each barrier once the engine passed it, until the records are
+// no longer fed or the engine stopped
+async fn confirm_fed(
+    queries: QueryHandle,
+    mut fed: watch::Receiver<u64>,
+    confirm: watch::Sender<u64>,
+) {
+    while fed.changed().await.is_ok() {
+        let sequence = *fed.borrow_and_update();
+        if !queries.sync().await {
+            return;
+        }
+        confirm.send_replace(sequence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Acknowledgements;
+    use crate::{
+        collector::{process_acknowledged_source, TransactionSource},
+        error::ErrorPolicy,
+        progress::Progress,
+        transaction::{Transaction, TransactionType},
+        PaymentsEngine,
+    };
+    use anyhow::Result;
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    // Deposits of one unit, which remembers the watermarks it was acknowledged
+    struct Deposits {
+        remaining: u64,
+        acknowledged: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl TransactionSource for Deposits {
+        type Error = Infallible;
+
+        async fn next_transaction(&mut self) -> Option<Result<Transaction, Infallible>> {
+            self.remaining = self.remaining.checked_sub(1)?;
+            Some(Ok(Transaction {
+                r#type: TransactionType::Deposit,
+                client: 1,
+                tx: self.remaining.try_into().unwrap(),
+                amount: Some("1".parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            }))
+        }
+
+        async fn acknowledge(&mut self, records: u64) -> Result<()> {
+            self.acknowledged.lock().unwrap().push(records);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn acknowledge_processed_records() {
+        let (mut payments_engine, sender) = PaymentsEngine::new();
+        let queries = payments_engine.query_handle();
+        let engine = tokio::spawn(async move {
+            payments_engine.process_transactions().await.unwrap();
+            payments_engine
+        });
+
+        let acknowledged = Arc::new(Mutex::new(Vec::new()));
+        let source = Deposits {
+            remaining: 100,
+            acknowledged: acknowledged.clone(),
+        };
+        process_acknowledged_source(
+            source,
+            sender,
+            queries,
+            ErrorPolicy::Strict,
+            Progress::default(),
+        )
+        .await
+        .unwrap();
+        let payments_engine = engine.await.unwrap();
+
+        // Watermarks only grow, and the last one covers all records
+        let acknowledged = acknowledged.lock().unwrap();
+        assert!(acknowledged.is_sorted());
+        assert_eq!(acknowledged.last(), Some(&100));
+        let account = payments_engine.account(1).unwrap();
+        assert_eq!(account.available, "100".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn engine_stopped() {
+        let (payments_engine, _sender) = PaymentsEngine::new();
+        let mut acknowledgements = Acknowledgements::new(payments_engine.query_handle());
+        drop(payments_engine);
+
+        acknowledgements.fed(1);
+        assert!(acknowledgements.confirm_all().await.is_err());
+        assert_eq!(acknowledgements.confirmed(), 0);
+    }
+}
@@ -0,0 +1,106 @@
+use super::{parse_payload, send, Acknowledgements, InputFormat};
+use crate::{
+    error::{EngineError, ErrorPolicy},
+    payment_engine::QueryHandle,
+    progress::Progress,
+    transaction::Transaction,
+};
+use anyhow::Result;
+use rdkafka::{
+    consumer::{CommitMode, Consumer, StreamConsumer},
+    ClientConfig, Message,
+};
+use std::{collections::VecDeque, future::Future, time::Duration};
+use tokio::sync::mpsc::Sender;
+
+const COMMIT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Kafka topic transactions are consumed from.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KafkaSource {
+    /// Comma separated list of `host:port` pairs
+    pub brokers: String,
+    pub group_id: String,
+    pub topic: String,
+    /// Format of the message payloads, a CSV payload is a single row without header
+    pub format: InputFormat,
+}
+
+/// Feeds the transactions of `source` into the engine until `shutdown` completes.
+///
+/// Offsets are only committed after the engine confirmed it processed the transactions up to
+/// them, see [`Acknowledgements`], so a restarted consumer continues with the first transaction
+/// that might not have been processed (at-least-once delivery).
+pub async fn consume<F: Future<Output = ()>>(
+    source: KafkaSource,
+    transaction_sink: Sender<Transaction>,
+    queries: QueryHandle,
+    error_policy: ErrorPolicy,
+    progress: Progress,
+    shutdown: F,
+) -> Result<()> {
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &source.brokers)
+        .set("group.id", &source.group_id)
+        .set("enable.auto.commit", "false")
+        .set("enable.auto.offset.store", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()?;
+    consumer.subscribe(&[&source.topic])?;
+
+    tokio::pin!(shutdown);
+    let mut acknowledgements = Acknowledgements::new(queries);
+    // Partition and offset of every message not committed yet, by its sequence number
+    let mut uncommitted = VecDeque::new();
+    let mut sequence = 0;
+    let mut commit_timer = tokio::time::interval(COMMIT_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = commit_timer.tick(), if !uncommitted.is_empty() => {
+                let confirmed = acknowledgements.confirmed();
+                commit(&consumer, &source.topic, &mut uncommitted, confirmed)?;
+            }
+            message = consumer.recv() => {
+                let message = message?;
+                let result = parse_payload(message.payload().unwrap_or_default(), source.format)
+                    .map_err(|reason| EngineError::InvalidMessage {
+                        offset: message.offset(),
+                        reason,
+                    });
+                send(result, &transaction_sink, error_policy, &progress).await?;
+                sequence += 1;
+                uncommitted.push_back((sequence, message.partition(), message.offset()));
+                acknowledgements.fed(sequence);
+            }
+        }
+    }
+
+    if !uncommitted.is_empty() {
+        let confirmed = acknowledgements.confirm_all().await?;
+        commit(&consumer, &source.topic, &mut uncommitted, confirmed)?;
+    }
+    Ok(())
+}
+
+// Commits the offsets of the messages up to the `confirmed` sequence number
+fn commit(
+    consumer: &StreamConsumer,
+    topic: &str,
+    uncommitted: &mut VecDeque<(u64, i32, i64)>,
+    confirmed: u64,
+) -> Result<()> {
+    let mut stored = false;
+    while let Some(&(sequence, partition, offset)) = uncommitted.front() {
+        if sequence > confirmed {
+            break;
+        }
+        consumer.store_offset(topic, partition, offset)?;
+        uncommitted.pop_front();
+        stored = true;
+    }
+    if stored {
+        consumer.commit_consumer_state(CommitMode::Sync)?;
+    }
+    Ok(())
+}
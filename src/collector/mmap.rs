@@ -0,0 +1,119 @@
+use memmap2::Mmap;
+use std::{
+    fs::File,
+    io::{self, BufRead, Read},
+    path::Path,
+};
+
+/// Bytes the kernel is asked to read ahead of the position of an [`MmapReader`].
+pub const MMAP_CHUNK_SIZE: usize = 8 << 20;
+
+/// Reads a file through a memory map, in chunks that the kernel is asked to read ahead while
+/// the chunk before is parsed.
+///
+/// The records are copied out of the page cache without a `read` system call for every block,
+/// and the disk keeps reading ahead while they are parsed, which speeds up reading large files
+/// that aren't cached.
+///
+/// The file must not be truncated while it is read, which ends the process with `SIGBUS` instead
+/// of an I/O error.
+pub struct MmapReader {
+    map: Mmap,
+    position: usize,
+    // End of the chunks the kernel was asked to read ahead
+    prefetched: usize,
+}
+
+impl MmapReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the map is only read, and the file is expected not to change while it is
+        // read, as documented above
+        let map = unsafe { Mmap::map(&file)? };
+        #[cfg(unix)]
+        map.advise(memmap2::Advice::Sequential)?;
+        Ok(MmapReader {
+            map,
+            position: 0,
+            prefetched: 0,
+        })
+    }
+
+    // Asks the kernel to read the chunk after the current one, once the current one is reached
+    fn prefetch(&mut self) {
+        if self.position < self.prefetched.saturating_sub(MMAP_CHUNK_SIZE) {
+            return;
+        }
+        let end = (self.prefetched + MMAP_CHUNK_SIZE).min(self.map.len());
+        #[cfg(unix)]
+        if end > self.prefetched {
+            // Only a hint, reading works the same without it
+            let _ = self.map.advise_range(
+                memmap2::Advice::WillNeed,
+                self.prefetched,
+                end - self.prefetched,
+            );
+        }
+        self.prefetched = end;
+    }
+}
+
+impl Read for MmapReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for MmapReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.prefetch();
+        Ok(&self.map[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.map.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MmapReader;
+    use crate::collector::{CsvSource, TransactionSource};
+    use std::{fs, io::Read};
+
+    #[tokio::test]
+    async fn reads_like_a_file() {
+        let path = std::env::temp_dir().join("rust-exercise-mmap.csv");
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=1000 {
+            input.push_str(&format!("deposit,{},{tx},1.5\n", tx % 7));
+        }
+        fs::write(&path, &input).unwrap();
+
+        let mut read = String::new();
+        MmapReader::open(&path)
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, input);
+
+        let mut source = CsvSource::new(MmapReader::open(&path).unwrap());
+        let mut transactions = 0;
+        while let Some(transaction) = source.next_transaction().await {
+            transactions += 1;
+            assert_eq!(transaction.unwrap().tx, transactions);
+        }
+        assert_eq!(transactions, 1000);
+
+        fs::write(&path, "").unwrap();
+        assert_eq!(
+            MmapReader::open(&path).unwrap().read(&mut [0; 8]).unwrap(),
+            0
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
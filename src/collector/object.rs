@@ -0,0 +1,64 @@
+use anyhow::Result;
+use bytes::Bytes;
+use futures_util::{stream::BoxStream, TryStreamExt};
+use std::io::{self, Read};
+use tokio::{io::AsyncReadExt, runtime::Handle, task};
+use tokio_util::io::StreamReader;
+use url::Url;
+
+/// Object read from an object store while it is downloaded, e.g. `s3://bucket/key`.
+///
+/// The store is configured by the environment, e.g. `AWS_REGION` and `AWS_ACCESS_KEY_ID` for
+/// S3. Reading blocks the thread in place, which requires the multi-threaded runtime.
+pub struct ObjectReader {
+    stream: StreamReader<BoxStream<'static, io::Result<Bytes>>, Bytes>,
+    runtime: Handle,
+}
+
+impl ObjectReader {
+    pub async fn open(url: &str) -> Result<Self> {
+        let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+        let (store, path) = object_store::parse_url_opts(&Url::parse(url)?, options)?;
+        let stream = store
+            .get(&path)
+            .await?
+            .into_stream()
+            .map_err(io::Error::other);
+        Ok(ObjectReader {
+            stream: StreamReader::new(Box::pin(stream)),
+            runtime: Handle::current(),
+        })
+    }
+}
+
+impl Read for ObjectReader {
+    // The readers of the input are synchronous, while the object arrives in chunks
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (stream, runtime) = (&mut self.stream, &self.runtime);
+        task::block_in_place(|| runtime.block_on(stream.read(buf)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ObjectReader;
+    use std::{fs, io::Read};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stream_object() {
+        let path = std::env::temp_dir().join("rust-exercise-stream-object.csv");
+        let content = "type,client,tx,amount\ndeposit,1,1,1.0\n".repeat(1000);
+        fs::write(&path, &content).unwrap();
+
+        let url = format!("file://{}", path.display());
+        let mut reader = ObjectReader::open(&url).await.unwrap();
+        let mut read = String::new();
+        reader.read_to_string(&mut read).unwrap();
+        assert_eq!(read, content);
+
+        assert!(ObjectReader::open("file:///no/such/object.csv")
+            .await
+            .is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
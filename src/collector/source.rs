@@ -0,0 +1,783 @@
+use super::{initialize_reader, CsvLayout, DEFAULT_COLUMNS};
+use crate::{
+    error::{EngineError, ErrorContext},
+    fx::Currency,
+    transaction::{ClientId, Transaction, TransactionId, TransactionType},
+};
+use anyhow::Result;
+use csv::{ByteRecord, Reader};
+use rust_decimal::Decimal;
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    fs::File,
+    future::Future,
+    io::{self, BufRead, BufReader, Lines, Read, Stdin},
+    panic,
+    path::Path,
+    str,
+    sync::Arc,
+    vec,
+};
+use tokio::task::{self, JoinHandle};
+
+// Records parsed by one thread at a time
+const PARSE_CHUNK_SIZE: usize = 4096;
+
+/// Stream of transactions the collector feeds into the engine.
+pub trait TransactionSource: Send {
+    /// Why a record of the source is not a valid transaction.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Returns the next transaction, or the reason the next record isn't one, and `None` once
+    /// the source is exhausted.
+    fn next_transaction(
+        &mut self,
+    ) -> impl Future<Output = Option<Result<Transaction, Self::Error>>> + Send;
+
+    /// Fields of the record read last, e.g. to write an invalid one to the dead letters, none
+    /// if the source doesn't keep them.
+    fn record(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Line of the record read last in the input, if the source knows it.
+    fn line(&self) -> Option<u64> {
+        None
+    }
+
+    /// Where the record read last came from, which errors caused by it point at. Besides its
+    /// line, a source may tell its client and transaction id even if the record is invalid.
+    fn context(&self) -> ErrorContext {
+        ErrorContext {
+            line: self.line(),
+            ..ErrorContext::default()
+        }
+    }
+
+    /// Called with the number of leading records the engine processed, applied or rejected, if
+    /// the source is fed with [`super::process_acknowledged_source`]. A queue-backed source
+    /// commits its offsets up to there. Invalid records skipped in lenient mode count as
+    /// processed, the watermarks only grow.
+    fn acknowledge(&mut self, _records: u64) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// Transactions read as CSV, by default with a header row naming the columns.
+///
+/// Records are read into a reused buffer and parsed straight from their bytes. Records the fast
+/// path doesn't handle, e.g. amounts in exponent notation or invalid records, are deserialized
+/// with serde instead, which reports why they are invalid.
+pub struct CsvSource<R> {
+    reader: Reader<R>,
+    record: ByteRecord,
+    // Names of the configured columns, otherwise they are read from the header row
+    columns: Option<ByteRecord>,
+    // Whether amounts are normalized before they are parsed
+    tolerant_amounts: bool,
+    // Resolved once the column names are known
+    layout: Option<CsvLayoutFields>,
+}
+
+impl<R: Read> CsvSource<R> {
+    pub fn new(input: R) -> Self {
+        Self::with_layout(input, &CsvLayout::default())
+    }
+
+    /// Reads the columns in the order of `layout` instead of by the names in the header row.
+    pub fn with_layout(input: R, layout: &CsvLayout) -> Self {
+        CsvSource {
+            reader: initialize_reader(input, layout.header),
+            record: ByteRecord::new(),
+            columns: layout.column_names().map(ByteRecord::from),
+            tolerant_amounts: layout.tolerant_amounts,
+            layout: None,
+        }
+    }
+}
+
+impl CsvSource<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(File::open(path)?))
+    }
+}
+
+impl CsvSource<Stdin> {
+    pub fn stdin() -> Self {
+        Self::new(io::stdin())
+    }
+}
+
+impl<R: Read + Send> TransactionSource for CsvSource<R> {
+    type Error = csv::Error;
+
+    async fn next_transaction(&mut self) -> Option<Result<Transaction, csv::Error>> {
+        if self.layout.is_none() {
+            match resolve_layout(&mut self.reader, &mut self.columns, self.tolerant_amounts) {
+                Ok(layout) => self.layout = Some(layout),
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        match self.reader.read_byte_record(&mut self.record) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(error) => return Some(Err(error)),
+        }
+
+        Some(parse_record(self.layout.as_ref()?, &self.record))
+    }
+
+    fn record(&self) -> Vec<String> {
+        self.record
+            .iter()
+            .map(|field| String::from_utf8_lossy(field).into_owned())
+            .collect()
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.record.position().map(csv::Position::line)
+    }
+
+    fn context(&self) -> ErrorContext {
+        record_context(self.layout.as_ref(), &self.record)
+    }
+}
+
+/// Transactions read as CSV like [`CsvSource`], whose records are parsed by several threads.
+///
+/// The records are read in chunks, which are parsed on the blocking thread pool while the
+/// following ones are read. The parsed chunks are handed off in the order they were read, so the
+/// transactions come in the order of the input.
+pub struct ParallelCsvSource<R> {
+    reader: Reader<R>,
+    columns: Option<ByteRecord>,
+    tolerant_amounts: bool,
+    layout: Option<Arc<CsvLayoutFields>>,
+    threads: usize,
+    // Chunks being parsed, in the order they were read
+    parsing: VecDeque<JoinHandle<Vec<ParsedRecord>>>,
+    // Rest of the chunk parsed first
+    parsed: vec::IntoIter<ParsedRecord>,
+    // Record returned last
+    record: ByteRecord,
+    exhausted: bool,
+}
+
+// Column names of CSV input, the positions of the fields if the fast path handles them, and the
+// position of the amounts if they are normalized before parsing
+struct CsvLayoutFields {
+    columns: ByteRecord,
+    fields: Option<CsvFields>,
+    tolerant_amount: Option<usize>,
+}
+
+type ParsedRecord = (ByteRecord, Result<Transaction, csv::Error>);
+
+impl<R: Read> ParallelCsvSource<R> {
+    /// Reads the columns in the order of `layout` and parses the records with as many threads as
+    /// it gives.
+    pub fn with_layout(input: R, layout: &CsvLayout) -> Self {
+        ParallelCsvSource {
+            reader: initialize_reader(input, layout.header),
+            columns: layout.column_names().map(ByteRecord::from),
+            tolerant_amounts: layout.tolerant_amounts,
+            layout: None,
+            threads: layout.parse_threads.max(1),
+            parsing: VecDeque::new(),
+            parsed: Vec::new().into_iter(),
+            record: ByteRecord::new(),
+            exhausted: false,
+        }
+    }
+
+    // Reads chunks until `threads` of them are being parsed, or the input is exhausted
+    fn read_ahead(&mut self, layout: &Arc<CsvLayoutFields>) {
+        while !self.exhausted && self.parsing.len() < self.threads {
+            let mut records = Vec::with_capacity(PARSE_CHUNK_SIZE);
+            let mut error = None;
+            while records.len() < PARSE_CHUNK_SIZE {
+                let mut record = ByteRecord::new();
+                match self.reader.read_byte_record(&mut record) {
+                    Ok(true) => records.push(record),
+                    Ok(false) => break,
+                    Err(read_error) => {
+                        error = Some(read_error);
+                        break;
+                    }
+                }
+            }
+            // The input ends with the chunk, after an error it isn't read any further
+            self.exhausted = records.len() < PARSE_CHUNK_SIZE;
+            let layout = layout.clone();
+            self.parsing.push_back(task::spawn_blocking(move || {
+                let mut parsed: Vec<ParsedRecord> = records
+                    .into_iter()
+                    .map(|record| {
+                        let result = parse_record(&layout, &record);
+                        (record, result)
+                    })
+                    .collect();
+                parsed.extend(error.map(|error| (ByteRecord::new(), Err(error))));
+                parsed
+            }));
+        }
+    }
+}
+
+impl<R: Read + Send> TransactionSource for ParallelCsvSource<R> {
+    type Error = csv::Error;
+
+    async fn next_transaction(&mut self) -> Option<Result<Transaction, csv::Error>> {
+        let layout = match &self.layout {
+            Some(layout) => layout.clone(),
+            None => {
+                match resolve_layout(&mut self.reader, &mut self.columns, self.tolerant_amounts) {
+                    Ok(layout) => self.layout.insert(Arc::new(layout)).clone(),
+                    Err(error) => return Some(Err(error)),
+                }
+            }
+        };
+        loop {
+            if let Some((record, result)) = self.parsed.next() {
+                self.record = record;
+                return Some(result);
+            }
+            self.read_ahead(&layout);
+            let chunk = self.parsing.pop_front()?;
+            self.parsed = match chunk.await {
+                Ok(parsed) => parsed.into_iter(),
+                Err(error) => panic::resume_unwind(error.into_panic()),
+            };
+        }
+    }
+
+    fn record(&self) -> Vec<String> {
+        self.record
+            .iter()
+            .map(|field| String::from_utf8_lossy(field).into_owned())
+            .collect()
+    }
+
+    fn line(&self) -> Option<u64> {
+        self.record.position().map(csv::Position::line)
+    }
+
+    fn context(&self) -> ErrorContext {
+        record_context(self.layout.as_deref(), &self.record)
+    }
+}
+
+// Line of `record`, and its client and transaction id if they can be read even though the
+// record may be invalid
+fn record_context(layout: Option<&CsvLayoutFields>, record: &ByteRecord) -> ErrorContext {
+    let field = |name: &[u8]| {
+        let position = layout?.columns.iter().position(|column| column == name)?;
+        record.get(position)
+    };
+    ErrorContext {
+        line: record.position().map(csv::Position::line),
+        client: field(b"client").and_then(parse_integer::<ClientId>),
+        tx: field(b"tx").and_then(parse_integer::<TransactionId>),
+    }
+}
+
+// Takes the configured column names, or reads them from the header row
+fn resolve_layout<R: Read>(
+    reader: &mut Reader<R>,
+    columns: &mut Option<ByteRecord>,
+    tolerant_amounts: bool,
+) -> Result<CsvLayoutFields, csv::Error> {
+    let columns = match columns.take() {
+        Some(columns) => columns,
+        None => reader.byte_headers()?.clone(),
+    };
+    let fields = CsvFields::new(&columns);
+    let tolerant_amount = if tolerant_amounts {
+        columns.iter().position(|column| column == b"amount")
+    } else {
+        None
+    };
+    Ok(CsvLayoutFields {
+        columns,
+        fields,
+        tolerant_amount,
+    })
+}
+
+// Parses a record on the fast path, or with serde if it doesn't handle the record
+fn parse_record(layout: &CsvLayoutFields, record: &ByteRecord) -> Result<Transaction, csv::Error> {
+    // The record itself stays as it was read, e.g. for the dead letters
+    let normalized = layout
+        .tolerant_amount
+        .and_then(|position| normalize_amount_field(record, position));
+    let record = normalized.as_ref().unwrap_or(record);
+    match layout
+        .fields
+        .as_ref()
+        .and_then(|fields| fields.parse(record))
+    {
+        Some(transaction) => Ok(transaction),
+        None => record.deserialize(Some(&layout.columns)),
+    }
+}
+
+// Copy of `record` with the amount at `position` normalized, `None` if it doesn't need to be
+fn normalize_amount_field(record: &ByteRecord, position: usize) -> Option<ByteRecord> {
+    let amount = normalize_amount(str::from_utf8(record.get(position)?).ok()?)?;
+    let mut normalized = ByteRecord::with_capacity(record.as_slice().len(), record.len());
+    for (index, field) in record.iter().enumerate() {
+        if index == position {
+            normalized.push_field(amount.as_bytes());
+        } else {
+            normalized.push_field(field);
+        }
+    }
+    normalized.set_position(record.position().cloned());
+    Some(normalized)
+}
+
+/// Rewrites an amount in decimal comma or scientific notation, e.g. `1,2345`, `1.234,5` or
+/// `1.2e3`, with a decimal point, as amounts are parsed. Returns `None` if the amount is written
+/// with a decimal point already, or isn't an amount at all.
+///
+/// If both a comma and a point are given, the one written last separates the decimals and the
+/// other one groups the thousands.
+pub fn normalize_amount(amount: &str) -> Option<String> {
+    let trimmed = amount.trim();
+    let separated = match (trimmed.rfind(','), trimmed.rfind('.')) {
+        (Some(comma), Some(point)) if comma > point => trimmed.replace('.', "").replace(',', "."),
+        (Some(_), Some(_)) => trimmed.replace(',', ""),
+        (Some(_), None) if trimmed.matches(',').count() == 1 => trimmed.replace(',', "."),
+        (Some(_), None) => return None,
+        (None, _) => trimmed.to_owned(),
+    };
+    if separated.contains(['e', 'E']) {
+        return Decimal::from_scientific(&separated)
+            .ok()
+            .map(|amount| amount.to_string());
+    }
+    (separated != trimmed).then_some(separated)
+}
+
+// Positions of the fields of a transaction in plain CSV records
+struct CsvFields {
+    r#type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    counterparty: Option<usize>,
+    timestamp: Option<usize>,
+    from_ccy: Option<usize>,
+    to_ccy: Option<usize>,
+    // Serde rejects records ending before a column that isn't an optional field
+    min_fields: usize,
+}
+
+impl CsvFields {
+    // `None` unless each field is named once and the required ones are there
+    fn new(columns: &ByteRecord) -> Option<Self> {
+        let mut positions: [Option<usize>; 8] = [None; 8];
+        let mut min_fields = 0;
+        for (position, column) in columns.iter().enumerate() {
+            let field = DEFAULT_COLUMNS
+                .iter()
+                .position(|name| name.as_bytes() == column);
+            if !matches!(
+                column,
+                b"amount" | b"counterparty" | b"timestamp" | b"from_ccy" | b"to_ccy"
+            ) {
+                min_fields = position + 1;
+            }
+            if let Some(field) = field {
+                if positions[field].replace(position).is_some() {
+                    return None;
+                }
+            }
+        }
+        let [r#type, client, tx, amount, counterparty, timestamp, from_ccy, to_ccy] = positions;
+        Some(CsvFields {
+            r#type: r#type?,
+            client: client?,
+            tx: tx?,
+            amount,
+            counterparty,
+            timestamp,
+            from_ccy,
+            to_ccy,
+            min_fields,
+        })
+    }
+
+    // Parses a record without allocating, `None` if it is anything but a plain transaction
+    fn parse(&self, record: &ByteRecord) -> Option<Transaction> {
+        if record.len() < self.min_fields {
+            return None;
+        }
+        let r#type = match record.get(self.r#type)? {
+            b"deposit" => TransactionType::Deposit,
+            b"withdrawal" => TransactionType::Withdrawal,
+            b"dispute" => TransactionType::Dispute,
+            b"resolve" => TransactionType::Resolve,
+            b"chargeback" => TransactionType::Chargeback,
+            b"unlock" => TransactionType::Unlock,
+            b"transfer" => TransactionType::Transfer,
+            b"chargeback_reversal" => TransactionType::ChargebackReversal,
+            b"hold" => TransactionType::Hold,
+            b"release" => TransactionType::Release,
+            b"convert" => TransactionType::Convert,
+            b"approve" => TransactionType::Approve,
+            b"reject" => TransactionType::Reject,
+            _ => return None,
+        };
+        Some(Transaction {
+            r#type,
+            client: parse_integer(record.get(self.client)?)?,
+            tx: parse_integer(record.get(self.tx)?)?,
+            amount: optional(record, self.amount, |field| {
+                str::from_utf8(field).ok()?.parse().ok()
+            })?,
+            counterparty: optional(record, self.counterparty, parse_integer)?,
+            timestamp: optional(record, self.timestamp, parse_integer)?,
+            from_ccy: optional(record, self.from_ccy, parse_currency)?,
+            to_ccy: optional(record, self.to_ccy, parse_currency)?,
+        })
+    }
+}
+
+// Value of an optional field, which is `None` if the field is missing or empty
+fn optional<T>(
+    record: &ByteRecord,
+    position: Option<usize>,
+    parse: impl FnOnce(&[u8]) -> Option<T>,
+) -> Option<Option<T>> {
+    match position.and_then(|position| record.get(position)) {
+        None | Some(b"") => Some(None),
+        Some(field) => parse(field).map(Some),
+    }
+}
+
+fn parse_currency(field: &[u8]) -> Option<Currency> {
+    str::from_utf8(field).ok()?.parse().ok()
+}
+
+fn parse_integer<T: TryFrom<u64>>(field: &[u8]) -> Option<T> {
+    if field.is_empty() {
+        return None;
+    }
+    let value = field.iter().try_fold(0u64, |value, &digit| {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        value.checked_mul(10)?.checked_add(u64::from(digit - b'0'))
+    })?;
+    T::try_from(value).ok()
+}
+
+/// Transactions read as one JSON object per line, empty lines are skipped.
+pub struct JsonLinesSource<R> {
+    lines: Lines<BufReader<R>>,
+    line: usize,
+    // Text of the line read last
+    text: String,
+    failed: bool,
+}
+
+impl<R: Read> JsonLinesSource<R> {
+    pub fn new(input: R) -> Self {
+        JsonLinesSource {
+            lines: BufReader::new(input).lines(),
+            line: 0,
+            text: String::new(),
+            failed: false,
+        }
+    }
+}
+
+impl JsonLinesSource<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(File::open(path)?))
+    }
+}
+
+impl JsonLinesSource<Stdin> {
+    pub fn stdin() -> Self {
+        Self::new(io::stdin())
+    }
+}
+
+impl<R: Read + Send> TransactionSource for JsonLinesSource<R> {
+    type Error = EngineError;
+
+    // The source ends after the input couldn't be read
+    async fn next_transaction(&mut self) -> Option<Result<Transaction, EngineError>> {
+        while !self.failed {
+            self.line += 1;
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(error) => {
+                    self.failed = true;
+                    return Some(Err(EngineError::ReadInput(error)));
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            self.text = line;
+            return Some(serde_json::from_str(&self.text).map_err(|source| {
+                EngineError::InvalidJsonLine {
+                    line: self.line,
+                    source,
+                }
+            }));
+        }
+        None
+    }
+
+    fn record(&self) -> Vec<String> {
+        vec![self.text.clone()]
+    }
+
+    fn line(&self) -> Option<u64> {
+        Some(self.line as u64)
+    }
+
+    // The client and id are taken from the object even if it isn't a valid transaction
+    fn context(&self) -> ErrorContext {
+        let object = serde_json::from_str::<serde_json::Value>(&self.text).ok();
+        let field = |name| object.as_ref()?.get(name)?.as_u64();
+        ErrorContext {
+            line: self.line(),
+            client: field("client").and_then(|client| client.try_into().ok()),
+            tx: field("tx").and_then(|tx| tx.try_into().ok()),
+        }
+    }
+}
+
+/// Transactions held in memory, e.g. for tests.
+pub struct MemorySource {
+    transactions: vec::IntoIter<Transaction>,
+}
+
+impl From<Vec<Transaction>> for MemorySource {
+    fn from(transactions: Vec<Transaction>) -> Self {
+        MemorySource {
+            transactions: transactions.into_iter(),
+        }
+    }
+}
+
+impl TransactionSource for MemorySource {
+    type Error = Infallible;
+
+    async fn next_transaction(&mut self) -> Option<Result<Transaction, Infallible>> {
+        self.transactions.next().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        normalize_amount, CsvSource, MemorySource, ParallelCsvSource, TransactionSource,
+        PARSE_CHUNK_SIZE,
+    };
+    use crate::{
+        collector::{initialize_reader, process_source, CsvLayout},
+        error::ErrorPolicy,
+        progress::Progress,
+        transaction::{Transaction, TransactionType},
+        PaymentsEngine,
+    };
+
+    #[tokio::test]
+    async fn csv_source() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.5\nunknown,1,2,\n";
+        let mut source = CsvSource::new(input.as_bytes());
+        let deposit = source.next_transaction().await.unwrap().unwrap();
+        assert_eq!(deposit.amount, Some("1.5".parse().unwrap()));
+        assert!(source.next_transaction().await.unwrap().is_err());
+        assert!(source.next_transaction().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn csv_layout() {
+        let columns = CsvLayout::parse_columns("client, _, type, tx, amount").unwrap();
+        let layout = CsvLayout {
+            header: false,
+            columns: Some(columns),
+            ..CsvLayout::default()
+        };
+        let input = "1,ignored,deposit,1,1.5\n2,,dispute,1,\n";
+        let mut source = CsvSource::with_layout(input.as_bytes(), &layout);
+        let deposit = source.next_transaction().await.unwrap().unwrap();
+        assert_eq!((deposit.client, deposit.tx), (1, 1));
+        assert_eq!(deposit.amount, Some("1.5".parse().unwrap()));
+        let dispute = source.next_transaction().await.unwrap().unwrap();
+        assert_eq!(dispute.r#type, TransactionType::Dispute);
+        assert!(source.next_transaction().await.is_none());
+
+        // Without columns, the default order applies
+        let layout = CsvLayout {
+            header: false,
+            columns: None,
+            ..CsvLayout::default()
+        };
+        let mut source = CsvSource::with_layout("withdrawal,3,4,2.0\n".as_bytes(), &layout);
+        let withdrawal = source.next_transaction().await.unwrap().unwrap();
+        assert_eq!((withdrawal.client, withdrawal.tx), (3, 4));
+
+        // A header row is skipped if the columns are given
+        let layout = CsvLayout {
+            header: true,
+            columns: Some(CsvLayout::parse_columns("tx,client,type,amount").unwrap()),
+            ..CsvLayout::default()
+        };
+        let input = "id,account,kind,value\n5,6,deposit,1.0\n";
+        let mut source = CsvSource::with_layout(input.as_bytes(), &layout);
+        let deposit = source.next_transaction().await.unwrap().unwrap();
+        assert_eq!((deposit.client, deposit.tx), (6, 5));
+
+        for invalid in [
+            "client,tx,amount",
+            "type,client,tx,tx",
+            "type,client,tx,fee",
+        ] {
+            assert!(CsvLayout::parse_columns(invalid).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn fast_path_matches_serde() {
+        let input = concat!(
+            "type, client, tx, amount, fee, timestamp\n",
+            "deposit, 1, 1, 1.5, 0.1, 1700000000\n",
+            "withdrawal, 1, 2, 2, , \n",
+            "dispute, 1, 1, , , \n",
+            "deposit, 1, 3, 1e2, , \n",
+            "deposit, +1, 4, 1.0, , \n",
+            "deposit, 4294967296, 5, 1.0, , \n",
+            "Deposit, 1, 6, 1.0, , \n",
+            "deposit, 1, 7, 1.0\n",
+        );
+        let mut fast = Vec::new();
+        let mut source = CsvSource::new(input.as_bytes());
+        while let Some(result) = source.next_transaction().await {
+            fast.push(result.map_err(|error| error.to_string()));
+        }
+        let serde: Vec<_> = initialize_reader(input.as_bytes(), true)
+            .into_deserialize::<Transaction>()
+            .map(|result| result.map_err(|error| error.to_string()))
+            .collect();
+        assert_eq!(fast, serde);
+        assert_eq!(fast[0].as_ref().unwrap().timestamp, Some(1_700_000_000));
+        assert_eq!(
+            fast.iter().filter(|result| result.is_err()).count(),
+            3,
+            "{fast:?}"
+        );
+
+        // Without an ignored column, short records are fine
+        let input = "type,client,tx,amount,timestamp\ndeposit,1,1,2\ndispute,1,1\n";
+        let mut source = CsvSource::new(input.as_bytes());
+        assert!(source.next_transaction().await.unwrap().is_ok());
+        assert!(source.next_transaction().await.unwrap().is_ok());
+
+        // Fields named twice are left to serde
+        let input = "type,client,tx,tx\ndeposit,1,1,2\n";
+        let mut source = CsvSource::new(input.as_bytes());
+        assert!(source.next_transaction().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn tolerant_amounts() {
+        for (amount, normalized) in [
+            ("1,2345", Some("1.2345")),
+            ("1.234,5", Some("1234.5")),
+            ("1,234.5", Some("1234.5")),
+            ("1.2e3", Some("1200")),
+            ("1,5E-2", Some("0.015")),
+            ("1.5", None),
+            ("1,2,3", None),
+            ("one", None),
+        ] {
+            assert_eq!(normalize_amount(amount).as_deref(), normalized, "{amount}");
+        }
+
+        let input = "type,client,tx,amount\ndeposit,1,1,\"1,5\"\ndeposit,1,2,2.5e1\n";
+        let layout = CsvLayout {
+            tolerant_amounts: true,
+            ..CsvLayout::default()
+        };
+        let mut source = CsvSource::with_layout(input.as_bytes(), &layout);
+        let deposit = source.next_transaction().await.unwrap().unwrap();
+        assert_eq!(deposit.amount, Some("1.5".parse().unwrap()));
+        assert_eq!(source.record(), ["deposit", "1", "1", "1,5"]);
+        let deposit = source.next_transaction().await.unwrap().unwrap();
+        assert_eq!(deposit.amount, Some("25".parse().unwrap()));
+
+        // Without the flag, a decimal comma is an invalid amount
+        let mut source = CsvSource::new(input.as_bytes());
+        assert!(source.next_transaction().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn parallel_csv_source() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 0..3 * PARSE_CHUNK_SIZE + 10 {
+            match tx % 1000 {
+                999 => input.push_str("invalid,1,1,\n"),
+                _ => input.push_str(&format!("deposit,{},{tx},1.5\n", tx % 7)),
+            }
+        }
+        let mut sequential = Vec::new();
+        let mut source = CsvSource::new(input.as_bytes());
+        while let Some(result) = source.next_transaction().await {
+            sequential.push(result.map_err(|error| error.to_string()));
+        }
+
+        let layout = CsvLayout {
+            parse_threads: 4,
+            ..CsvLayout::default()
+        };
+        let mut parallel = Vec::new();
+        let mut source = ParallelCsvSource::with_layout(input.as_bytes(), &layout);
+        while let Some(result) = source.next_transaction().await {
+            if result.is_err() {
+                assert_eq!(source.record(), ["invalid", "1", "1", ""]);
+            }
+            parallel.push(result.map_err(|error| error.to_string()));
+        }
+        assert_eq!(parallel.len(), 3 * PARSE_CHUNK_SIZE + 10);
+        assert_eq!(parallel, sequential);
+    }
+
+    #[tokio::test]
+    async fn memory_source() {
+        let transactions = (1..=3)
+            .map(|tx| Transaction {
+                r#type: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some("1.0".parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            })
+            .collect::<Vec<_>>();
+        let (mut payments_engine, sender) = PaymentsEngine::new();
+
+        let collector = tokio::spawn(process_source(
+            MemorySource::from(transactions),
+            sender,
+            ErrorPolicy::Strict,
+            Progress::default(),
+        ));
+        payments_engine.process_transactions().await.unwrap();
+        collector.await.unwrap().unwrap();
+        assert_eq!(
+            payments_engine.account(1).unwrap().available,
+            "3.0".parse().unwrap()
+        );
+    }
+}
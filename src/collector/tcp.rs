@@ -0,0 +1,168 @@
+use super::{parse_payload, send, InputFormat};
+use crate::{
+    error::{EngineError, ErrorPolicy},
+    progress::Progress,
+    rate_limit::RateLimiter,
+    transaction::Transaction,
+};
+use anyhow::Result;
+use std::{future::Future, net::SocketAddr};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::Sender,
+    task::JoinSet,
+};
+
+/// Accepts connections on `address` until `shutdown` completes, and feeds the transactions sent
+/// over each of them into the engine.
+///
+/// Every line holds one transaction in `format`, a CSV header row is skipped. In strict mode an
+/// invalid transaction closes its connection, the others are not affected. A connection over the
+/// rate limits isn't read until its transactions are admitted again.
+pub async fn listen<F: Future<Output = ()>>(
+    address: SocketAddr,
+    format: InputFormat,
+    transaction_sink: Sender<Transaction>,
+    error_policy: ErrorPolicy,
+    progress: Progress,
+    rate_limiter: RateLimiter,
+    shutdown: F,
+) -> Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    accept(
+        listener,
+        format,
+        transaction_sink,
+        error_policy,
+        progress,
+        rate_limiter,
+        shutdown,
+    )
+    .await
+}
+
+async fn accept<F: Future<Output = ()>>(
+    listener: TcpListener,
+    format: InputFormat,
+    transaction_sink: Sender<Transaction>,
+    error_policy: ErrorPolicy,
+    progress: Progress,
+    rate_limiter: RateLimiter,
+    shutdown: F,
+) -> Result<()> {
+    tokio::pin!(shutdown);
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted?;
+                let connection = receive(
+                    stream,
+                    peer,
+                    format,
+                    transaction_sink.clone(),
+                    error_policy,
+                    progress.clone(),
+                    rate_limiter.clone(),
+                );
+                tracing::info!(%peer, "Accepted connection");
+                connections.spawn(async move {
+                    if let Err(error) = connection.await {
+                        tracing::warn!(%peer, %error, "Closed connection");
+                    }
+                });
+            }
+            // Reaps the finished connections
+            Some(_) = connections.join_next() => {}
+        }
+    }
+
+    connections.shutdown().await;
+    Ok(())
+}
+
+async fn receive(
+    stream: TcpStream,
+    peer: SocketAddr,
+    format: InputFormat,
+    transaction_sink: Sender<Transaction>,
+    error_policy: ErrorPolicy,
+    progress: Progress,
+    rate_limiter: RateLimiter,
+) -> Result<()> {
+    let mut lines = BufReader::new(stream).lines();
+    let mut line_number = 0;
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        if line.trim().is_empty() || (format == InputFormat::Csv && is_header(&line)) {
+            continue;
+        }
+
+        rate_limiter.wait(Some(peer)).await;
+        let result = parse_payload(line.as_bytes(), format).map_err(|reason| {
+            EngineError::InvalidStreamLine {
+                peer,
+                line: line_number,
+                reason,
+            }
+        });
+        send(result, &transaction_sink, error_policy, &progress).await?;
+    }
+
+    Ok(())
+}
+
+fn is_header(line: &str) -> bool {
+    line.split(',').next().map(str::trim) == Some("type")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::accept;
+    use crate::{
+        collector::InputFormat, error::ErrorPolicy, progress::Progress, rate_limit::RateLimiter,
+    };
+    use tokio::{io::AsyncWriteExt, net::TcpListener, net::TcpStream, sync::mpsc::channel};
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn concurrent_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, mut receiver) = channel(8);
+        let shutdown = CancellationToken::new();
+        let server = tokio::spawn(accept(
+            listener,
+            InputFormat::Csv,
+            sender,
+            ErrorPolicy::Lenient,
+            Progress::default(),
+            RateLimiter::default(),
+            shutdown.clone().cancelled_owned(),
+        ));
+
+        let mut first = TcpStream::connect(address).await.unwrap();
+        let mut second = TcpStream::connect(address).await.unwrap();
+        first
+            .write_all(b"type,client,tx,amount\ndeposit,1,1,1.0\n")
+            .await
+            .unwrap();
+        second
+            .write_all(b"deposit,2,2,2.0\ninvalid\ndeposit,2,3,3.0\n")
+            .await
+            .unwrap();
+        drop((first, second));
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(receiver.recv().await.unwrap().tx);
+        }
+        received.sort();
+        assert_eq!(received, [1, 2, 3]);
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use super::{open, read, BatchSender, CsvLayout, Cursor, InputFormat, Sink};
+use crate::{error::ErrorPolicy, progress::Progress};
+use anyhow::{anyhow, Result};
+use notify::{Event, RecursiveMode, Watcher};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tokio::sync::mpsc::channel;
+use tracing::{info_span, Instrument};
+
+/// Directory inside the watched one the processed files are moved to.
+pub const PROCESSED_DIRECTORY: &str = "processed";
+
+/// Processes the files in `directory`, and every file moved or written into it later, until the
+/// returned future is dropped. The accounts keep growing with every file, like the input files
+/// of a single run.
+///
+/// Files are processed in alphabetical order, and moved to the `processed` directory inside
+/// `directory` once their transactions were sent. Files whose name starts with a dot are ignored,
+/// so a file written under such a name and renamed once complete is never read half written.
+pub async fn watch(
+    directory: PathBuf,
+    format: Option<InputFormat>,
+    csv_layout: CsvLayout,
+    mut batch_sink: BatchSender,
+    error_policy: ErrorPolicy,
+    progress: Progress,
+) -> Result<()> {
+    let processed = directory.join(PROCESSED_DIRECTORY);
+    fs::create_dir_all(&processed)?;
+
+    let (changed, mut changes) = channel(1);
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if event.is_ok_and(|event| event.kind.is_create() || event.kind.is_modify()) {
+            // A full channel already makes the directory be scanned again
+            let _ = changed.try_send(());
+        }
+    })?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    tracing::info!(directory = %directory.display(), "Watching for input files");
+
+    loop {
+        for path in pending_files(&directory)? {
+            let format = format.unwrap_or_else(|| InputFormat::from_path(&path));
+            let span = info_span!("input", path = %path.display(), ?format);
+            tracing::info!(parent: &span, "Reading input");
+            read(
+                open(&path, csv_layout.mmap).await?,
+                format,
+                &csv_layout,
+                &mut Sink::Batches(&mut batch_sink),
+                error_policy,
+                &progress,
+                &mut Cursor::default(),
+            )
+            .instrument(span)
+            .await?;
+
+            let file_name = path.file_name().expect("pending files have a name");
+            fs::rename(&path, processed.join(file_name))?;
+        }
+        if changes.recv().await.is_none() {
+            return Err(anyhow!("Stopped watching {}", directory.display()));
+        }
+    }
+}
+
+// Files in `directory` that are waiting to be processed, in alphabetical order
+fn pending_files(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{watch, PROCESSED_DIRECTORY};
+    use crate::{
+        collector::{BatchSender, CsvLayout},
+        error::ErrorPolicy,
+        progress::Progress,
+    };
+    use std::fs;
+    use tokio::sync::mpsc::channel;
+
+    #[tokio::test]
+    async fn processes_new_files() {
+        let directory = std::env::temp_dir().join("rust-exercise-watch");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("first.csv"),
+            "type,client,tx,amount\ndeposit,1,1,1.0\n",
+        )
+        .unwrap();
+
+        let (sender, mut batches) = channel(8);
+        let watcher = tokio::spawn(watch(
+            directory.clone(),
+            None,
+            CsvLayout::default(),
+            BatchSender::new(sender, 16),
+            ErrorPolicy::Strict,
+            Progress::default(),
+        ));
+        assert_eq!(batches.recv().await.unwrap().transactions[0].tx, 1);
+
+        // Written under a hidden name first, and only picked up once renamed
+        let partial = directory.join(".second.csv");
+        fs::write(&partial, "type,client,tx,amount\ndeposit,1,2,1.0\n").unwrap();
+        fs::rename(&partial, directory.join("second.csv")).unwrap();
+        assert_eq!(batches.recv().await.unwrap().transactions[0].tx, 2);
+
+        watcher.abort();
+        let processed = directory.join(PROCESSED_DIRECTORY);
+        assert!(processed.join("first.csv").exists());
+        assert!(!directory.join("first.csv").exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{
+    observer::{EngineObserver, Rejection},
+    transaction::Transaction,
+};
+use anyhow::{anyhow, Result};
+use csv::{Writer, WriterBuilder};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
+
+// Columns of the dead letters, the fields of invalid records are written as they were read
+const COLUMNS: [&str; 9] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "counterparty",
+    "timestamp",
+    "from_ccy",
+    "to_ccy",
+    "reason",
+];
+
+/// CSV file of the rejected transactions and the invalid records of the input, each followed by
+/// the reason, so they can be fixed and submitted again.
+///
+/// The rows are written by a background task, the queue can be cloned and shared between the
+/// collector and the workers of the engine, which it observes.
+#[derive(Clone, Debug)]
+pub struct DeadLetters {
+    sink: UnboundedSender<Message>,
+}
+
+#[derive(Debug)]
+enum Message {
+    Row(Vec<String>),
+    Flush(oneshot::Sender<Result<(), String>>),
+}
+
+impl DeadLetters {
+    pub fn new<W: Write + Send + 'static>(output: W) -> Self {
+        let (sink, messages) = unbounded_channel();
+        let writer = WriterBuilder::new().flexible(true).from_writer(output);
+        tokio::task::spawn_blocking(move || write(writer, messages));
+        DeadLetters { sink }
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Writes the fields of an invalid `record` of the input, which couldn't be parsed.
+    pub fn invalid_record(&self, mut record: Vec<String>, reason: String) {
+        // Missing optional fields are left empty, so the reason stays in its column
+        if record.len() < COLUMNS.len() - 1 {
+            record.resize(COLUMNS.len() - 1, String::new());
+        }
+        record.push(reason);
+        self.write(record);
+    }
+
+    /// Waits until the dead letters sent so far are written.
+    pub async fn flush(&self) -> Result<()> {
+        let (reply, written) = oneshot::channel();
+        self.sink
+            .send(Message::Flush(reply))
+            .map_err(|_| anyhow!("The dead letter writer stopped"))?;
+        written
+            .await
+            .map_err(|_| anyhow!("The dead letter writer stopped"))?
+            .map_err(|error| anyhow!("Writing dead letters failed: {}", error))
+    }
+
+    fn write(&self, row: Vec<String>) {
+        // A stopped writer reports its error on the next flush
+        let _ = self.sink.send(Message::Row(row));
+    }
+}
+
+impl EngineObserver for DeadLetters {
+    fn on_rejected(&self, transaction: &Transaction, rejection: Rejection<'_>) {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        self.write(vec![
+            transaction.r#type.to_string(),
+            transaction.client.to_string(),
+            transaction.tx.to_string(),
+            optional(transaction.amount.map(|amount| amount.to_string())),
+            optional(transaction.counterparty.map(|client| client.to_string())),
+            optional(transaction.timestamp.map(|timestamp| timestamp.to_string())),
+            optional(transaction.from_ccy.map(|currency| currency.to_string())),
+            optional(transaction.to_ccy.map(|currency| currency.to_string())),
+            rejection.to_string(),
+        ]);
+    }
+}
+
+// Writes the rows until all queues are dropped, the first failure is reported to every later
+// flush
+fn write<W: Write>(mut writer: Writer<W>, mut messages: UnboundedReceiver<Message>) {
+    let mut failure = writer
+        .write_record(COLUMNS)
+        .err()
+        .map(|error| error.to_string());
+    while let Some(message) = messages.blocking_recv() {
+        match message {
+            Message::Row(row) if failure.is_none() => {
+                failure = writer
+                    .write_record(row)
+                    .err()
+                    .map(|error| error.to_string());
+            }
+            Message::Row(_) => {}
+            Message::Flush(reply) => {
+                if failure.is_none() {
+                    failure = writer.flush().err().map(|error| error.to_string());
+                }
+                let _ = reply.send(failure.clone().map_or(Ok(()), Err));
+            }
+        }
+    }
+    let _ = writer.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeadLetters;
+    use crate::{
+        collector::{process_files, BatchSender, CsvLayout},
+        error::ErrorPolicy,
+        progress::Progress,
+        PaymentsEngine,
+    };
+    use std::fs;
+
+    #[tokio::test]
+    async fn rejected_and_invalid_rows() {
+        let directory = std::env::temp_dir();
+        let input = directory.join("rust-exercise-dead-letters-input.csv");
+        let output = directory.join("rust-exercise-dead-letters.csv");
+        fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0\ndeposit,1,3,abc\n",
+        )
+        .unwrap();
+
+        let dead_letters = DeadLetters::create(&output).unwrap();
+        let (mut payments_engine, _) = PaymentsEngine::builder()
+            .error_policy(ErrorPolicy::Lenient)
+            .observer(dead_letters.clone())
+            .build();
+        let collector = tokio::spawn(process_files(
+            vec![input.clone()],
+            None,
+            CsvLayout::default(),
+            BatchSender::new(payments_engine.batch_sender(), 4).dead_letters(dead_letters.clone()),
+            ErrorPolicy::Lenient,
+            Progress::default(),
+            None,
+        ));
+        payments_engine.process_transactions().await.unwrap();
+        collector.await.unwrap().unwrap();
+        dead_letters.flush().await.unwrap();
+
+        let written = fs::read_to_string(&output).unwrap();
+        let mut rows: Vec<_> = written.lines().collect();
+        assert_eq!(
+            rows.remove(0),
+            "type,client,tx,amount,counterparty,timestamp,from_ccy,to_ccy,reason"
+        );
+        // The collector and the engine write concurrently
+        rows.sort_unstable();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].starts_with("deposit,1,3,abc,,,,,\"CSV deserialize error"));
+        assert_eq!(rows[1], "withdrawal,1,2,5.0,,,,,Insufficient funds");
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{
+    error::EngineError,
+    transaction::{ClientId, Transaction, TransactionId},
+};
+use rustc_hash::FxHashMap;
+use std::str::FromStr;
+
+/// Bloom filters are sized for this rate of new transaction ids mistaken for seen ones.
+pub const BLOOM_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// How a deposit, withdrawal or transfer is handled that reuses the id of an earlier transaction
+/// of the same client, e.g. when overlapping files are processed.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum DuplicatePolicy {
+    /// Reject it as an invalid transaction
+    #[default]
+    Reject,
+    /// Skip it with the outcome `duplicate`, the earlier transaction stands
+    Skip,
+    /// Replace the amount of the earlier deposit or withdrawal by the one of the duplicate
+    LastWriteWins,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" | "error" => Ok(DuplicatePolicy::Reject),
+            "skip" => Ok(DuplicatePolicy::Skip),
+            "last-write-wins" => Ok(DuplicatePolicy::LastWriteWins),
+            _ => Err(EngineError::InvalidArgumentValue(
+                "--duplicates".into(),
+                s.into(),
+            )),
+        }
+    }
+}
+
+/// Whether a transaction reuses the id of an earlier one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Reuse {
+    No,
+    /// The id was seen before, the account of the client confirms whether it is a duplicate
+    Maybe,
+    /// The id was seen before for the same client
+    Yes,
+}
+
+// Ids of the deposits, withdrawals and transfers seen so far
+#[derive(Debug)]
+pub(crate) enum TransactionIds {
+    // Client of every id
+    Exact(FxHashMap<TransactionId, ClientId>),
+    Bloom(BloomFilter),
+}
+
+impl TransactionIds {
+    /// Ids tracked in a bloom filter sized for `bloom_filter` transactions if it is given, or in
+    /// a map with room for `expected` ids otherwise.
+    pub(crate) fn new(bloom_filter: Option<usize>, expected: usize) -> Self {
+        match bloom_filter {
+            Some(expected) => TransactionIds::Bloom(BloomFilter::new(expected)),
+            None => TransactionIds::Exact(FxHashMap::with_capacity_and_hasher(
+                expected,
+                Default::default(),
+            )),
+        }
+    }
+
+    /// Remembers the id of a transaction introducing it, and tells whether it was seen before.
+    pub(crate) fn register(
+        &mut self,
+        tx: TransactionId,
+        client: ClientId,
+    ) -> Result<Reuse, EngineError> {
+        match self {
+            TransactionIds::Exact(ids) => match ids.insert(tx, client) {
+                None => Ok(Reuse::No),
+                Some(owner) if owner == client => Ok(Reuse::Yes),
+                Some(owner) => {
+                    // The id stays with the client that introduced it
+                    ids.insert(tx, owner);
+                    Err(EngineError::DuplicateTransactionId(tx))
+                }
+            },
+            TransactionIds::Bloom(filter) => Ok(if filter.insert(tx) {
+                Reuse::No
+            } else {
+                Reuse::Maybe
+            }),
+        }
+    }
+
+    /// Registers the id `transaction` introduces, if any, and tells whether it was seen before.
+    ///
+    /// Transaction ids are unique across all clients, disputes and their follow-ups refer to an
+    /// existing id of the same client instead of introducing a new one. A reused id of the same
+    /// client is handled by its account according to the duplicate policy.
+    pub(crate) fn admit(&mut self, transaction: &Transaction) -> Result<Reuse, EngineError> {
+        let Transaction {
+            r#type, client, tx, ..
+        } = *transaction;
+        if r#type.introduces_transaction() {
+            self.register(tx, client)
+        } else if r#type.refers_to_transaction()
+            && self.owner(tx).is_some_and(|owner| owner != client)
+        {
+            Err(EngineError::ClientMismatchOnDispute(tx, client))
+        } else {
+            Ok(Reuse::No)
+        }
+    }
+
+    /// Client of the transaction with id `tx`, if it is known.
+    ///
+    /// A bloom filter doesn't know the clients, the account then checks the id.
+    pub(crate) fn owner(&self, tx: TransactionId) -> Option<ClientId> {
+        match self {
+            TransactionIds::Exact(ids) => ids.get(&tx).copied(),
+            TransactionIds::Bloom(_) => None,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match self {
+            TransactionIds::Exact(ids) => ids.clear(),
+            TransactionIds::Bloom(filter) => filter.clear(),
+        }
+    }
+}
+
+// Set of transaction ids in fixed memory, which may mistake a new id for a seen one
+#[derive(Debug)]
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    fn new(expected: usize) -> Self {
+        let expected = expected.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-expected * BLOOM_FALSE_POSITIVE_RATE.ln() / (ln2 * ln2)).ceil() as usize;
+        let hashes = ((bits as f64 / expected) * ln2).round().max(1.0) as u32;
+        BloomFilter {
+            bits: vec![0; bits.div_ceil(64)],
+            hashes,
+        }
+    }
+
+    // Returns whether `tx` is new, i.e. any of its bits wasn't set yet
+    fn insert(&mut self, tx: TransactionId) -> bool {
+        let size = self.bits.len() as u64 * 64;
+        let first = mix(u64::from(tx));
+        let step = mix(first) | 1;
+        let mut new = false;
+        for i in 0..u64::from(self.hashes) {
+            let bit = first.wrapping_add(i.wrapping_mul(step)) % size;
+            let word = &mut self.bits[(bit / 64) as usize];
+            let mask = 1 << (bit % 64);
+            new |= *word & mask == 0;
+            *word |= mask;
+        }
+        new
+    }
+
+    fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+}
+
+// SplitMix64 finalizer, spreads consecutive ids over the filter
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reuse, TransactionIds};
+
+    #[test]
+    fn exact_and_bloom_filter() {
+        let mut ids = TransactionIds::new(None, 0);
+        assert_eq!(ids.register(1, 1).unwrap(), Reuse::No);
+        assert_eq!(ids.register(1, 1).unwrap(), Reuse::Yes);
+        assert!(ids.register(1, 2).is_err());
+        assert_eq!(ids.owner(1), Some(1));
+
+        let mut ids = TransactionIds::new(Some(10_000), 0);
+        let new = (0..10_000)
+            .filter(|&tx| ids.register(tx, 1).unwrap() == Reuse::No)
+            .count();
+        // Mistaken ids are rare
+        assert!(new > 9_800, "{new}");
+        assert!((0..10_000).all(|tx| ids.register(tx, 1).unwrap() == Reuse::Maybe));
+    }
+}
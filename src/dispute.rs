@@ -0,0 +1,161 @@
+use crate::{error::EngineError, outcome::TransactionOutcome};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Where a deposit, withdrawal or transfer is in its dispute lifecycle.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub(crate) enum DisputeState {
+    #[default]
+    Normal,
+    /// Its funds are held until it is resolved or charged back
+    Disputed,
+    Resolved,
+    /// It was reversed and can't be disputed again
+    ChargedBack,
+    /// Its chargeback was undone, it stands and can't be disputed again
+    ChargebackReversed,
+}
+
+/// Whether a transaction can be disputed again once its dispute was resolved.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum RedisputePolicy {
+    /// A resolved transaction can be disputed again
+    #[default]
+    AfterResolve,
+    /// Every transaction can be disputed once
+    Never,
+}
+
+impl DisputeState {
+    /// State after opening a dispute, or the outcome if it can't be opened.
+    pub(crate) fn dispute(self, policy: RedisputePolicy) -> Result<Self, TransactionOutcome> {
+        match (self, policy) {
+            (DisputeState::Normal, _) | (DisputeState::Resolved, RedisputePolicy::AfterResolve) => {
+                Ok(DisputeState::Disputed)
+            }
+            (DisputeState::Disputed, _) => Err(TransactionOutcome::AlreadyDisputed),
+            (DisputeState::Resolved, RedisputePolicy::Never)
+            | (DisputeState::ChargedBack | DisputeState::ChargebackReversed, _) => {
+                Err(TransactionOutcome::DisputeClosed)
+            }
+        }
+    }
+
+    // A replayed resolve or chargeback must not undo the funds a chargeback moved
+    pub(crate) fn resolve(self) -> Result<Self, TransactionOutcome> {
+        match self {
+            DisputeState::Disputed => Ok(DisputeState::Resolved),
+            _ if self.is_terminal() => Err(TransactionOutcome::DisputeClosed),
+            _ => Err(TransactionOutcome::NotUnderDispute),
+        }
+    }
+
+    pub(crate) fn charge_back(self) -> Result<Self, TransactionOutcome> {
+        match self {
+            DisputeState::Disputed => Ok(DisputeState::ChargedBack),
+            _ if self.is_terminal() => Err(TransactionOutcome::DisputeClosed),
+            _ => Err(TransactionOutcome::NotUnderDispute),
+        }
+    }
+
+    /// Whether a chargeback ended the dispute, so no dispute, resolve or chargeback applies
+    /// anymore. Only the chargeback itself can still be reversed.
+    pub(crate) fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            DisputeState::ChargedBack | DisputeState::ChargebackReversed
+        )
+    }
+
+    /// Whether the dispute is over for good, only a chargeback can still be reversed.
+    pub(crate) fn is_settled(self, policy: RedisputePolicy) -> bool {
+        match self {
+            DisputeState::Resolved => policy == RedisputePolicy::Never,
+            _ => self.is_terminal(),
+        }
+    }
+
+    pub(crate) fn reverse_chargeback(self) -> Result<Self, TransactionOutcome> {
+        match self {
+            DisputeState::ChargedBack => Ok(DisputeState::ChargebackReversed),
+            _ => Err(TransactionOutcome::NotChargedBack),
+        }
+    }
+}
+
+impl FromStr for RedisputePolicy {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "after-resolve" => Ok(RedisputePolicy::AfterResolve),
+            "never" => Ok(RedisputePolicy::Never),
+            _ => Err(EngineError::InvalidArgumentValue(
+                "--redispute".into(),
+                s.into(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DisputeState, RedisputePolicy};
+    use crate::outcome::TransactionOutcome;
+
+    #[test]
+    fn transitions() {
+        let policy = RedisputePolicy::AfterResolve;
+        let disputed = DisputeState::Normal.dispute(policy).unwrap();
+        assert_eq!(
+            disputed.dispute(policy),
+            Err(TransactionOutcome::AlreadyDisputed)
+        );
+        let resolved = disputed.resolve().unwrap();
+        assert_eq!(resolved.dispute(policy), Ok(DisputeState::Disputed));
+        assert_eq!(
+            resolved.dispute(RedisputePolicy::Never),
+            Err(TransactionOutcome::DisputeClosed)
+        );
+        assert_eq!(
+            resolved.charge_back(),
+            Err(TransactionOutcome::NotUnderDispute)
+        );
+        let charged_back = disputed.charge_back().unwrap();
+        assert_eq!(
+            charged_back.dispute(policy),
+            Err(TransactionOutcome::DisputeClosed)
+        );
+        let reversed = charged_back.reverse_chargeback().unwrap();
+        assert_eq!(
+            reversed.reverse_chargeback(),
+            Err(TransactionOutcome::NotChargedBack)
+        );
+        assert_eq!(
+            reversed.dispute(policy),
+            Err(TransactionOutcome::DisputeClosed)
+        );
+    }
+
+    #[test]
+    fn terminal_states() {
+        for state in [DisputeState::ChargedBack, DisputeState::ChargebackReversed] {
+            assert!(state.is_terminal());
+            let transitions: [fn(DisputeState) -> Result<DisputeState, TransactionOutcome>; 3] =
+                [DisputeState::resolve, DisputeState::charge_back, |state| {
+                    state.dispute(RedisputePolicy::AfterResolve)
+                }];
+            for transition in transitions {
+                assert_eq!(transition(state), Err(TransactionOutcome::DisputeClosed));
+            }
+        }
+        for state in [DisputeState::Normal, DisputeState::Resolved] {
+            assert!(!state.is_terminal());
+            assert_eq!(state.resolve(), Err(TransactionOutcome::NotUnderDispute));
+            assert_eq!(
+                state.charge_back(),
+                Err(TransactionOutcome::NotUnderDispute)
+            );
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::error::EngineError;
+use std::str::FromStr;
+
+/// How long after a transaction it can still be disputed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DisputeWindow {
+    /// At most this many transactions of the account later
+    Transactions(u64),
+    /// At most this many seconds later, if both the transaction and the dispute have a timestamp
+    Seconds(u64),
+}
+
+impl DisputeWindow {
+    /// Whether a dispute at `sequence` and `timestamp` is within the window of a transaction at
+    /// `original_sequence` and `original_timestamp`.
+    pub(crate) fn contains(
+        self,
+        (original_sequence, original_timestamp): (u64, Option<u64>),
+        (sequence, timestamp): (u64, Option<u64>),
+    ) -> bool {
+        match (self, original_timestamp, timestamp) {
+            (DisputeWindow::Transactions(window), ..) => {
+                sequence.saturating_sub(original_sequence) <= window
+            }
+            (DisputeWindow::Seconds(window), Some(original), Some(timestamp)) => {
+                timestamp.saturating_sub(original) <= window
+            }
+            (DisputeWindow::Seconds(_), ..) => true,
+        }
+    }
+}
+
+/// Parses `<n>` as a number of transactions and `<n>s` as a number of seconds.
+impl FromStr for DisputeWindow {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || EngineError::InvalidArgumentValue("--dispute-window".into(), s.into());
+        match s.strip_suffix('s') {
+            Some(seconds) => seconds
+                .parse()
+                .map(DisputeWindow::Seconds)
+                .map_err(|_| invalid()),
+            None => s
+                .parse()
+                .map(DisputeWindow::Transactions)
+                .map_err(|_| invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DisputeWindow;
+
+    #[test]
+    fn window_in_transactions_or_seconds() {
+        assert_eq!(
+            "3".parse::<DisputeWindow>().unwrap(),
+            DisputeWindow::Transactions(3)
+        );
+        assert_eq!(
+            "60s".parse::<DisputeWindow>().unwrap(),
+            DisputeWindow::Seconds(60)
+        );
+        assert!("60m".parse::<DisputeWindow>().is_err());
+
+        let window = DisputeWindow::Transactions(3);
+        assert!(window.contains((2, None), (5, None)));
+        assert!(!window.contains((2, None), (6, None)));
+
+        let window = DisputeWindow::Seconds(60);
+        assert!(window.contains((0, Some(100)), (9, Some(160))));
+        assert!(!window.contains((0, Some(100)), (1, Some(161))));
+        assert!(window.contains((0, None), (1, Some(1000))));
+    }
+}
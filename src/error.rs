@@ -1,13 +1,249 @@
+use crate::{
+    fx::Currency,
+    transaction::{ClientId, Transaction, TransactionId},
+};
+use std::fmt::{self, Display};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum EngineError {
-    #[error("Path to input file not given as argument")]
-    NoInputArgument,
-    #[error("Transaction has invalid type `{0}`")]
-    InvalidRawTransactionType(String),
+    #[error("Invalid value `{1}` for argument `{0}`")]
+    InvalidArgumentValue(String, String),
     #[error("Amount can't be None in deposit transaction")]
     NoAmountInDeposit,
     #[error("Amount can't be None in withdrawal transaction")]
     NoAmountInWitdrawal,
+    #[error("Amount can't be None in transfer transaction")]
+    NoAmountInTransfer,
+    #[error("Amount can't be None in hold transaction")]
+    NoAmountInHold,
+    #[error("Transfer `{0}` needs a counterparty other than its client")]
+    InvalidCounterparty(TransactionId),
+    #[error("Amount can't be None in convert transaction")]
+    NoAmountInConversion,
+    #[error("Conversion `{0}` needs two different currencies")]
+    InvalidConversion(TransactionId),
+    #[error("No exchange rate of `{1}` to convert transaction `{0}` with")]
+    NoExchangeRate(TransactionId, Currency),
+    #[error("Invalid currency code `{0}`")]
+    InvalidCurrency(String),
+    #[error("No input file matches `{0}`")]
+    NoMatchingInput(String),
+    #[error("Checkpoint was taken while reading `{0}`, which isn't at the same position among the input files")]
+    CheckpointMismatch(String),
+    #[error("Invalid message at offset {offset}: {reason}")]
+    InvalidMessage { offset: i64, reason: String },
+    #[error("Unknown input format `{0}`")]
+    InvalidInputFormat(String),
+    #[error("Unknown output format `{0}`")]
+    InvalidOutputFormat(String),
+    #[error("Invalid transaction in line {line} from {peer}: {reason}")]
+    InvalidStreamLine {
+        peer: std::net::SocketAddr,
+        line: usize,
+        reason: String,
+    },
+    #[error("Failed to read the input")]
+    ReadInput(#[source] std::io::Error),
+    #[error("Invalid transaction in line {line}")]
+    InvalidJsonLine {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error("Amount of transaction `{0}` must be positive")]
+    NonPositiveAmount(TransactionId),
+    #[error(
+        "Amount of transaction `{0}`, or the balance it results in, exceeds the largest amount"
+    )]
+    AmountOutOfRange(TransactionId),
+    #[error("Amount of transaction `{0}` has more than {1} decimal places")]
+    AmountTooPrecise(TransactionId, u32),
+    #[error("Transaction `{0}` is an administrative command, but admin commands are disabled")]
+    AdminCommandsDisabled(TransactionId),
+    #[error("Transaction id `{0}` is not unique")]
+    DuplicateTransactionId(TransactionId),
+    #[error("Transaction `{0}` is older than a previous transaction of client `{1}`")]
+    OutOfOrder(TransactionId, ClientId),
+    #[error("Transaction `{0}` does not belong to client `{1}`")]
+    ClientMismatchOnDispute(TransactionId, ClientId),
+    #[error("Transaction `{0}` is for the locked account of client `{1}`")]
+    AccountLocked(TransactionId, ClientId),
+    #[error("Transaction `{0}` refers to an unknown transaction")]
+    UnknownTransaction(TransactionId),
+    #[error("Transaction `{0}` refers to a transaction of client `{1}`, who has no account")]
+    UnknownClient(TransactionId, ClientId),
+    #[error("Input contains {0} invalid records")]
+    InvalidInput(u64),
+    #[error("Account `{client}` violates an invariant: {reason}")]
+    InvariantViolated {
+        client: ClientId,
+        reason: &'static str,
+    },
+    #[error("Failed to access the spilled transaction history")]
+    TransactionHistory(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failed to access the account store")]
+    AccountStore(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failed to write audit log")]
+    AuditLog(#[from] std::io::Error),
+    /// Error caused by a transaction, with where it came from
+    #[error("Invalid transaction at {context}")]
+    InvalidTransaction {
+        context: ErrorContext,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+/// Where the transaction an error was caused by came from: the line of its record in the
+/// input, its client and its id, as far as they are known.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct ErrorContext {
+    pub line: Option<u64>,
+    pub client: Option<ClientId>,
+    pub tx: Option<TransactionId>,
+}
+
+impl ErrorContext {
+    /// Context of `transaction`, whose record is in `line` of the input if that is known.
+    pub fn of(transaction: &Transaction, line: Option<u64>) -> Self {
+        ErrorContext {
+            line,
+            client: Some(transaction.client),
+            tx: Some(transaction.tx),
+        }
+    }
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::with_capacity(3);
+        parts.extend(self.line.map(|line| format!("line {line}")));
+        parts.extend(self.client.map(|client| format!("client `{client}`")));
+        parts.extend(self.tx.map(|tx| format!("transaction `{tx}`")));
+        if parts.is_empty() {
+            f.write_str("an unknown position")
+        } else {
+            f.write_str(&parts.join(", "))
+        }
+    }
+}
+
+impl EngineError {
+    /// Wraps `error`, caused by the transaction `context` describes.
+    pub fn in_context<E>(error: E, context: ErrorContext) -> Self
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        EngineError::InvalidTransaction {
+            context,
+            source: error.into(),
+        }
+    }
+
+    /// Where the transaction causing the error came from, if it is known.
+    pub fn context(&self) -> Option<ErrorContext> {
+        match self {
+            EngineError::InvalidTransaction { context, .. } => Some(*context),
+            _ => None,
+        }
+    }
+
+    /// The error itself, without the context of the transaction it was caused by.
+    pub fn without_context(&self) -> &EngineError {
+        match self {
+            EngineError::InvalidTransaction { source, .. } => {
+                source.downcast_ref::<EngineError>().unwrap_or(self)
+            }
+            _ => self,
+        }
+    }
+
+    /// Short reason a transaction was rejected for, without the details of the transaction.
+    pub fn rejection_reason(&self) -> &'static str {
+        match self.without_context() {
+            EngineError::NoAmountInDeposit
+            | EngineError::NoAmountInWitdrawal
+            | EngineError::NoAmountInTransfer
+            | EngineError::NoAmountInHold
+            | EngineError::NoAmountInConversion => "Missing amount",
+            EngineError::InvalidCounterparty(_) => "Invalid counterparty",
+            EngineError::InvalidConversion(_) => "Invalid conversion",
+            EngineError::NoExchangeRate(..) => "No exchange rate",
+            EngineError::NonPositiveAmount(_) => "Non-positive amount",
+            EngineError::AmountOutOfRange(_) => "Amount out of range",
+            EngineError::AmountTooPrecise(..) => "Too many decimal places",
+            EngineError::AdminCommandsDisabled(_) => "Admin commands disabled",
+            EngineError::DuplicateTransactionId(_) => "Duplicate transaction id",
+            EngineError::OutOfOrder(..) => "Out of order",
+            EngineError::ClientMismatchOnDispute(..) => "Client mismatch on dispute",
+            EngineError::UnknownTransaction(_) => "Unknown transaction",
+            EngineError::UnknownClient(..) => "Unknown client",
+            EngineError::AccountLocked(..) => "Account locked",
+            EngineError::InvariantViolated { .. } => "Invariant violated",
+            _ => "Other error",
+        }
+    }
+}
+
+/// How invalid transactions are handled.
+#[derive(serde::Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorPolicy {
+    /// Abort the processing on the first invalid transaction
+    #[default]
+    Strict,
+    /// Log invalid transactions as warnings and skip them
+    Lenient,
+}
+
+impl ErrorPolicy {
+    /// Passes errors on in strict mode, while in lenient mode they are logged and turned into `None`.
+    pub fn check<T, E: Display>(self, result: Result<T, E>) -> Result<Option<T>, E> {
+        match (self, result) {
+            (_, Ok(value)) => Ok(Some(value)),
+            (ErrorPolicy::Strict, Err(error)) => Err(error),
+            (ErrorPolicy::Lenient, Err(error)) => {
+                tracing::warn!(%error, "Skipping invalid transaction");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Like [`ErrorPolicy::check`] for an error caused by the transaction `context` describes,
+    /// which is passed on with the context in strict mode, and logged with it in lenient mode.
+    pub fn check_in_context<T, E>(
+        self,
+        result: Result<T, E>,
+        context: ErrorContext,
+    ) -> Result<Option<T>, EngineError>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        match (self, result) {
+            (_, Ok(value)) => Ok(Some(value)),
+            (ErrorPolicy::Strict, Err(error)) => Err(EngineError::in_context(error, context)),
+            (ErrorPolicy::Lenient, Err(error)) => {
+                tracing::warn!(
+                    line = context.line,
+                    client = context.client,
+                    tx = context.tx,
+                    error = %with_sources(&error),
+                    "Skipping invalid transaction"
+                );
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Message of `error` followed by the messages of its sources, like `{:#}` of an
+/// [`anyhow::Error`].
+pub fn with_sources(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
 }
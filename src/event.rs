@@ -0,0 +1,225 @@
+use crate::{
+    amount::Amount,
+    fx::Currency,
+    transaction::{ClientId, TransactionId, TransactionType},
+};
+use serde::{Deserialize, Serialize};
+
+/// Change of the state of an account.
+///
+/// Every accepted transaction is turned into at most one event, the state of an account is the
+/// fold of its events in order, see [`crate::Account::apply`].
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AccountEvent {
+    Deposited {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+        /// Part of the amount kept as fee
+        #[serde(default, skip_serializing_if = "Amount::is_zero")]
+        fee: Amount,
+        /// Seconds since the Unix epoch, if the transaction had a timestamp
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    Withdrew {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+        /// Fee charged on top of the amount
+        #[serde(default, skip_serializing_if = "Amount::is_zero")]
+        fee: Amount,
+        /// Seconds since the Unix epoch, if the transaction had a timestamp
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// Deposit that exceeded the deposit limit, it only uses up its transaction id
+    DepositDeclined {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    /// Withdrawal or transfer that exceeded the available funds or a limit, it only uses up its
+    /// transaction id
+    WithdrawalDeclined {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    DisputeOpened {
+        client: ClientId,
+        tx: TransactionId,
+    },
+    DisputeResolved {
+        client: ClientId,
+        tx: TransactionId,
+    },
+    ChargedBack {
+        client: ClientId,
+        tx: TransactionId,
+    },
+    /// Chargeback undone, the charged back transaction takes effect again
+    ChargebackReversed {
+        client: ClientId,
+        tx: TransactionId,
+    },
+    Unlocked {
+        client: ClientId,
+    },
+    /// Funds sent to the account of `counterparty`
+    TransferredOut {
+        client: ClientId,
+        tx: TransactionId,
+        counterparty: ClientId,
+        amount: Amount,
+        /// Seconds since the Unix epoch, if the transaction had a timestamp
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// Funds received from the account of `counterparty`
+    TransferredIn {
+        client: ClientId,
+        tx: TransactionId,
+        counterparty: ClientId,
+        amount: Amount,
+    },
+    /// Funds of a received transfer taken back, because the sender charged it back
+    TransferReversed {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    /// Available funds held until they are released
+    Held {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    /// Funds of the hold `tx` made available again
+    Released {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+    },
+    /// `amount` of `from` exchanged for `converted` of `to`
+    Converted {
+        client: ClientId,
+        tx: TransactionId,
+        from: Currency,
+        to: Currency,
+        amount: Amount,
+        converted: Amount,
+        /// Currency of the balances of the account, funds in other currencies are kept apart
+        base: Currency,
+        /// Seconds since the Unix epoch, if the transaction had a timestamp
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// Amount of an earlier deposit or withdrawal replaced by the one of a duplicate
+    Amended {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Amount,
+        /// Fee of the new amount
+        #[serde(default, skip_serializing_if = "Amount::is_zero")]
+        fee: Amount,
+    },
+    /// Deposit, withdrawal or transfer above the approval threshold, it only takes effect once
+    /// it is approved
+    Staged {
+        client: ClientId,
+        tx: TransactionId,
+        #[serde(rename = "type")]
+        kind: TransactionType,
+        amount: Amount,
+        /// Client receiving the funds of a transfer
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        counterparty: Option<ClientId>,
+        /// Seconds since the Unix epoch, if the transaction had a timestamp
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+    },
+    /// Staged transaction discarded by its rejection
+    Unstaged {
+        client: ClientId,
+        tx: TransactionId,
+    },
+}
+
+impl AccountEvent {
+    pub fn client(&self) -> ClientId {
+        match *self {
+            AccountEvent::Deposited { client, .. }
+            | AccountEvent::Withdrew { client, .. }
+            | AccountEvent::DepositDeclined { client, .. }
+            | AccountEvent::WithdrawalDeclined { client, .. }
+            | AccountEvent::DisputeOpened { client, .. }
+            | AccountEvent::DisputeResolved { client, .. }
+            | AccountEvent::ChargedBack { client, .. }
+            | AccountEvent::ChargebackReversed { client, .. }
+            | AccountEvent::Unlocked { client }
+            | AccountEvent::TransferredOut { client, .. }
+            | AccountEvent::TransferredIn { client, .. }
+            | AccountEvent::TransferReversed { client, .. }
+            | AccountEvent::Held { client, .. }
+            | AccountEvent::Released { client, .. }
+            | AccountEvent::Converted { client, .. }
+            | AccountEvent::Amended { client, .. }
+            | AccountEvent::Staged { client, .. }
+            | AccountEvent::Unstaged { client, .. } => client,
+        }
+    }
+
+    /// Seconds since the Unix epoch of the transaction that caused this event, if it had a
+    /// timestamp.
+    pub fn timestamp(&self) -> Option<u64> {
+        match *self {
+            AccountEvent::Deposited { timestamp, .. }
+            | AccountEvent::Withdrew { timestamp, .. }
+            | AccountEvent::TransferredOut { timestamp, .. }
+            | AccountEvent::Converted { timestamp, .. }
+            | AccountEvent::Staged { timestamp, .. } => timestamp,
+            _ => None,
+        }
+    }
+
+    /// Id of the transaction this event introduced, which can't be used again.
+    ///
+    /// A received transfer belongs to the sending client, who introduced its id.
+    pub(crate) fn introduced_transaction(&self) -> Option<TransactionId> {
+        match *self {
+            AccountEvent::Deposited { tx, .. }
+            | AccountEvent::Withdrew { tx, .. }
+            | AccountEvent::DepositDeclined { tx, .. }
+            | AccountEvent::WithdrawalDeclined { tx, .. }
+            | AccountEvent::TransferredOut { tx, .. }
+            | AccountEvent::Converted { tx, .. }
+            | AccountEvent::Staged { tx, .. } => Some(tx),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AccountEvent;
+    use crate::amount::Amount;
+
+    #[test]
+    fn serialize_event() {
+        let event = AccountEvent::Deposited {
+            client: 1,
+            tx: 2,
+            amount: "1.5".parse().unwrap(),
+            fee: Amount::ZERO,
+            timestamp: None,
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            json,
+            r#"{"event":"deposited","client":1,"tx":2,"amount":"1.5"}"#
+        );
+        assert_eq!(serde_json::from_str::<AccountEvent>(&json).unwrap(), event);
+    }
+}
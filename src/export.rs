@@ -0,0 +1,190 @@
+use crate::{
+    amount::Amount,
+    dispute::DisputeState,
+    event::AccountEvent,
+    fx::{Balance, Currency},
+    history::{LinkedEntry, TransactionRecord},
+    limits::DailyVolume,
+    transaction::{ClientId, Transaction, TransactionId, TransactionType},
+};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{BufWriter, Read, Write},
+};
+
+/// Identifies the documents written by [`crate::PaymentsEngine::export_json`].
+const FORMAT: &str = "rust-exercise/accounts";
+/// Raised whenever a field is removed or changes its meaning, new fields are optional.
+const VERSION: u32 = 1;
+
+/// Accounts of an engine with their transaction histories and disputes, written as JSON for
+/// humans and other versions of the engine.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct Export {
+    format: String,
+    version: u32,
+    /// Number of decimal places the amounts were validated with
+    pub precision: u32,
+    pub accounts: Vec<ExportedAccount>,
+    /// Event log of the engine, so ledgers and snapshots still cover the exported transactions
+    #[serde(default)]
+    pub events: Vec<AccountEvent>,
+}
+
+/// State of one account, the settings it is processed with come from the engine.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct ExportedAccount {
+    pub client: ClientId,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    /// Funds in other currencies than the base currency, by currency
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub foreign: BTreeMap<Currency, Balance>,
+    /// Transaction whose chargeback locked the account, while it is locked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locking_chargeback: Option<TransactionId>,
+    /// Amount still held by every hold, by its id
+    #[serde(default)]
+    pub holds: BTreeMap<TransactionId, Amount>,
+    #[serde(default)]
+    pub fees_collected: Amount,
+    /// Withdrawals and transfers of the current day, counted towards the daily limit
+    #[serde(default)]
+    pub withdrawn: DailyVolume,
+    /// Transactions received while locked, applied once the account is unlocked
+    #[serde(default)]
+    pub queued: VecDeque<Transaction>,
+    /// Transactions above the approval threshold, applied once they are approved
+    #[serde(default)]
+    pub pending: Vec<Transaction>,
+    /// Number of events applied to the account
+    #[serde(default)]
+    pub sequence: u64,
+    /// Deposits, withdrawals, transfers and conversions remembered for disputes, in the order
+    /// they were applied
+    #[serde(default)]
+    pub transactions: Vec<ExportedTransaction>,
+}
+
+/// Deposit, withdrawal, transfer or conversion of an account, with where it is in its dispute
+/// lifecycle.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub(crate) struct ExportedTransaction {
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub kind: TransactionType,
+    pub amount: Amount,
+    /// Fee kept out of a deposit or charged on top of a withdrawal
+    #[serde(default)]
+    pub fee: Amount,
+    /// Client a transfer went to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<ClientId>,
+    /// Number of events applied to the account before
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// `Normal`, `Disputed`, `Resolved`, `ChargedBack` or `ChargebackReversed`
+    #[serde(default)]
+    pub dispute: DisputeState,
+    /// Currency a conversion debited the amount in, if it isn't the base currency
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// Funds a conversion credited, the entry linked to the debited amount
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credit: Option<LinkedEntry>,
+}
+
+impl Export {
+    pub fn new(precision: u32, accounts: Vec<ExportedAccount>, events: Vec<AccountEvent>) -> Self {
+        Export {
+            format: FORMAT.to_owned(),
+            version: VERSION,
+            precision,
+            accounts,
+            events,
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = BufWriter::new(writer);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a document, rejecting other JSON and documents of newer engine versions.
+    pub fn read<R: Read>(reader: R) -> Result<Self> {
+        let export: Export = serde_json::from_reader(reader)?;
+        if export.format != FORMAT {
+            return Err(anyhow!(
+                "Expected an account export, found the format `{}`",
+                export.format
+            ));
+        }
+        if export.version > VERSION {
+            return Err(anyhow!(
+                "The account export has version {}, only versions up to {} are supported",
+                export.version,
+                VERSION
+            ));
+        }
+        Ok(export)
+    }
+}
+
+impl ExportedTransaction {
+    pub fn new(tx: TransactionId, record: TransactionRecord) -> Self {
+        ExportedTransaction {
+            tx,
+            kind: record.kind,
+            amount: record.amount,
+            fee: record.fee,
+            counterparty: None,
+            sequence: record.sequence,
+            timestamp: record.timestamp,
+            dispute: record.state,
+            currency: record.currency,
+            credit: record.credit,
+        }
+    }
+
+    pub fn record(&self) -> TransactionRecord {
+        TransactionRecord {
+            kind: self.kind,
+            amount: self.amount,
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            state: self.dispute,
+            fee: self.fee,
+            currency: self.currency,
+            credit: self.credit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_other_documents() {
+        let mut document = Vec::new();
+        Export::new(4, Vec::new(), Vec::new())
+            .write(&mut document)
+            .unwrap();
+        assert!(Export::read(document.as_slice()).is_ok());
+
+        let snapshot = r#"{"format":"something else","version":1,"precision":4,"accounts":[]}"#;
+        assert!(Export::read(snapshot.as_bytes()).is_err());
+        let newer =
+            r#"{"format":"rust-exercise/accounts","version":2,"precision":4,"accounts":[]}"#;
+        assert!(Export::read(newer.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{amount::Amount, transaction::ClientId};
+use anyhow::Result;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Fee of a single deposit or withdrawal, a flat amount plus a percentage of the transaction.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Fee {
+    #[serde(default)]
+    pub flat: Amount,
+    /// Percent of the amount, e.g. `1.5` for 1.5%
+    #[serde(default)]
+    pub percent: Amount,
+}
+
+/// Fees charged to one account.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Fees {
+    #[serde(default)]
+    pub deposit: Fee,
+    #[serde(default)]
+    pub withdrawal: Fee,
+}
+
+/// Fees of every account, with overrides for single clients.
+#[derive(Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FeeSchedule {
+    #[serde(default)]
+    pub deposit: Fee,
+    #[serde(default)]
+    pub withdrawal: Fee,
+    /// Fees of single clients, by client id, replacing the ones they name
+    #[serde(default)]
+    pub clients: BTreeMap<ClientId, ClientFees>,
+}
+
+/// Fees of a single client, the ones not given are taken from the schedule.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ClientFees {
+    pub deposit: Option<Fee>,
+    pub withdrawal: Option<Fee>,
+}
+
+impl Fee {
+    /// Fee of a transaction of `amount`.
+    pub fn on(&self, amount: Amount) -> Amount {
+        self.flat + amount.percent(self.percent)
+    }
+}
+
+impl FeeSchedule {
+    /// Reads the schedule from a TOML file, e.g. `withdrawal = { flat = "0.5" }`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Fees charged to the account of `client`.
+    pub fn fees_of(&self, client: ClientId) -> Fees {
+        let overrides = self.clients.get(&client).copied().unwrap_or_default();
+        Fees {
+            deposit: overrides.deposit.unwrap_or(self.deposit),
+            withdrawal: overrides.withdrawal.unwrap_or(self.withdrawal),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeeSchedule;
+
+    #[test]
+    fn client_overrides() {
+        let schedule: FeeSchedule = toml::from_str(concat!(
+            "deposit = { flat = \"0.1\" }\n",
+            "withdrawal = { flat = \"0.5\", percent = \"1\" }\n",
+            "[clients.7]\n",
+            "withdrawal = { percent = \"2\" }\n",
+        ))
+        .unwrap();
+        let amount = |amount: &str| amount.parse().unwrap();
+
+        let fees = schedule.fees_of(1);
+        assert_eq!(fees.deposit.on(amount("10")), amount("0.1"));
+        assert_eq!(fees.withdrawal.on(amount("10")), amount("0.6"));
+        let fees = schedule.fees_of(7);
+        assert_eq!(fees.deposit.on(amount("10")), amount("0.1"));
+        assert_eq!(fees.withdrawal.on(amount("10")), amount("0.2"));
+
+        assert!(toml::from_str::<FeeSchedule>("transfer = { flat = 1 }").is_err());
+    }
+}
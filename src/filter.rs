@@ -0,0 +1,150 @@
+use crate::transaction::Transaction;
+use std::fmt;
+
+/// Stage between the collector and the engine, registered with
+/// [`crate::EngineBuilder::filter`], e.g. to normalize amounts, drop clients on a blocklist,
+/// remap ids or inject synthetic test transactions.
+///
+/// Every transaction the engine receives passes through the filters in the order they were
+/// registered, before it is validated. A transaction the filters drop is acknowledged to
+/// [`crate::QueryHandle::outcomes`] with the outcome `filtered`. Otherwise the first
+/// transaction passed on in its place, e.g. an enriched copy, is acknowledged as the transaction
+/// received, the others are processed as if they were received.
+pub trait TransactionFilter: Send + Sync + fmt::Debug {
+    /// Pushes the transactions passed on in place of `transaction` to `output`: the transaction
+    /// itself, a changed copy, none to drop it, or more to inject transactions.
+    fn filter(&self, transaction: Transaction, output: &mut Vec<Transaction>);
+}
+
+#[cfg(all(test, feature = "runtime"))]
+mod tests {
+    use super::TransactionFilter;
+    use crate::{
+        transaction::{ClientId, Transaction, TransactionType},
+        PaymentsEngine, TransactionOutcome,
+    };
+
+    #[derive(Debug)]
+    struct Blocklist(Vec<ClientId>);
+
+    impl TransactionFilter for Blocklist {
+        fn filter(&self, transaction: Transaction, output: &mut Vec<Transaction>) {
+            if !self.0.contains(&transaction.client) {
+                output.push(transaction);
+            }
+        }
+    }
+
+    // Moves the transactions of client 1 to client 10, and deposits a bonus with every deposit
+    #[derive(Debug)]
+    struct RemapAndBonus;
+
+    impl TransactionFilter for RemapAndBonus {
+        fn filter(&self, mut transaction: Transaction, output: &mut Vec<Transaction>) {
+            if transaction.client == 1 {
+                transaction.client = 10;
+            }
+            output.push(transaction);
+            if transaction.r#type == TransactionType::Deposit {
+                output.push(Transaction {
+                    tx: transaction.tx + 1000,
+                    amount: Some("0.5".parse().unwrap()),
+                    ..transaction
+                });
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn filters_are_chained() {
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(2)
+            .filter(Blocklist(vec![2]))
+            .filter(RemapAndBonus)
+            .build();
+        let mut outcomes = payments_engine.query_handle().outcomes();
+        for (client, tx) in [(1, 1), (2, 2), (3, 3)] {
+            let deposit = Transaction {
+                r#type: TransactionType::Deposit,
+                client,
+                tx,
+                amount: Some("1.0".parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(deposit).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let balance = |client| payments_engine.account(client).map(|view| view.total);
+        assert_eq!(balance(1), None);
+        assert_eq!(balance(2), None);
+        assert_eq!(balance(10), Some("1.5".parse().unwrap()));
+        assert_eq!(balance(3), Some("1.5".parse().unwrap()));
+
+        let mut acknowledged = Vec::new();
+        while let Ok(acknowledgement) = outcomes.try_recv() {
+            let transaction = acknowledgement.transaction;
+            acknowledged.push((transaction.client, transaction.tx, acknowledgement.outcome));
+        }
+        // The remapped deposit of client 1 is acknowledged as the deposit received, the bonuses
+        // as themselves
+        acknowledged.sort_unstable_by_key(|(client, tx, _)| (*client, *tx));
+        let applied = Ok(TransactionOutcome::Applied);
+        assert_eq!(
+            acknowledged,
+            [
+                (1, 1, applied.clone()),
+                (2, 2, Ok(TransactionOutcome::Filtered)),
+                (3, 3, applied.clone()),
+                (3, 1003, applied.clone()),
+                (10, 1001, applied),
+            ]
+        );
+    }
+
+    // Stamps every transaction with the same time
+    #[derive(Debug)]
+    struct Timestamp;
+
+    impl TransactionFilter for Timestamp {
+        fn filter(&self, transaction: Transaction, output: &mut Vec<Transaction>) {
+            output.push(Transaction {
+                timestamp: Some(1_700_000_000),
+                ..transaction
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn enriched_transactions_are_acknowledged_as_received() {
+        let (mut payments_engine, sender) = PaymentsEngine::builder().filter(Timestamp).build();
+        let query_handle = payments_engine.query_handle();
+        let engine = tokio::spawn(async move {
+            payments_engine.process_transactions().await.unwrap();
+            payments_engine
+        });
+        let deposit = Transaction {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some("2.0".parse().unwrap()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        let outcome = query_handle.submit(&sender, deposit).await;
+        assert_eq!(outcome, Some(Ok(TransactionOutcome::Applied)));
+        drop(sender);
+
+        let payments_engine = engine.await.unwrap();
+        assert_eq!(
+            payments_engine.account(1).map(|view| view.total),
+            Some("2.0".parse().unwrap())
+        );
+    }
+}
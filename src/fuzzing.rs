@@ -0,0 +1,90 @@
+//! Entry points of the fuzz targets in `fuzz/`, which panic if an invariant doesn't hold.
+
+use crate::{
+    account::Account,
+    amount::DEFAULT_PRECISION,
+    collector::{process_source, CsvSource},
+    error::ErrorPolicy,
+    progress::Progress,
+    transaction::{Transaction, TransactionType},
+    PaymentsEngine,
+};
+use std::collections::HashSet;
+
+/// Feeds `input` as a CSV file through the collector into a lenient engine with two workers.
+///
+/// Checks the invariants of every resulting account, and that replaying the event log
+/// reconstructs the same accounts.
+pub fn csv_pipeline(input: &[u8]) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("runtime can be created");
+    runtime.block_on(async {
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(2)
+            .strict(false)
+            .admin_commands(true)
+            .build();
+        let collected = process_source(
+            CsvSource::new(input),
+            sender,
+            ErrorPolicy::Lenient,
+            Progress::default(),
+        );
+        let (_, processed) = tokio::join!(collected, payments_engine.process_transactions());
+        processed.expect("lenient engines skip invalid transactions");
+
+        let (mut replayed, _) = PaymentsEngine::new();
+        replayed
+            .replay(payments_engine.events().iter().copied())
+            .expect("event log can be replayed");
+        for account in payments_engine.accounts() {
+            let account = account.expect("accounts are kept in memory");
+            account.check_invariants().expect("invariants hold");
+            assert_eq!(replayed.account(account.client()), Some(account.view()));
+        }
+    });
+}
+
+/// Applies `transactions` to a single account, as their client, the way the engine does:
+/// invalid transactions are skipped and reused ids are handled as duplicates.
+///
+/// Checks the invariants after every transaction, that a locked account only changes when it
+/// is unlocked or a chargeback is reversed, and that the events reconstruct the account.
+pub fn account_transactions(transactions: &[Transaction]) {
+    let mut account = Account::new(0);
+    let mut introduced = HashSet::new();
+    let mut events = Vec::new();
+    for &transaction in transactions {
+        let transaction = Transaction {
+            client: account.client(),
+            ..transaction
+        };
+        if transaction.validate(DEFAULT_PRECISION).is_err() {
+            continue;
+        }
+        let before = account.view();
+        let result =
+            if transaction.r#type.introduces_transaction() && !introduced.insert(transaction.tx) {
+                account.execute_duplicate(transaction, true)
+            } else {
+                account.execute(transaction)
+            };
+        if let Ok((_, event)) = result {
+            events.extend(event);
+        }
+        account.check_invariants().expect("invariants hold");
+        let unlocking = matches!(
+            transaction.r#type,
+            TransactionType::Unlock | TransactionType::ChargebackReversal
+        );
+        if before.locked && !unlocking {
+            assert_eq!(account.view(), before, "locked accounts don't change");
+        }
+    }
+    assert_eq!(
+        Account::from_events(account.client(), &events).expect("events can be replayed"),
+        account
+    );
+}
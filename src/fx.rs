@@ -0,0 +1,156 @@
+use crate::{amount::Amount, error::EngineError};
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, fs, path::Path, str::FromStr};
+
+/// Three-letter code of a currency, e.g. `EUR`, parsed case-insensitively.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+/// Exchange rates of the currencies accounts can convert between.
+///
+/// Every rate is the value of one unit of its currency in the base currency, the one the
+/// balances of the accounts are kept in. Other currencies are kept apart, per currency.
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FxRates {
+    pub base: Currency,
+    /// Units of the base currency one unit of each currency is worth
+    #[serde(default)]
+    pub rates: BTreeMap<Currency, Amount>,
+}
+
+/// Funds of an account in one currency.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Balance {
+    pub available: Amount,
+    pub held: Amount,
+}
+
+impl FromStr for Currency {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            &[a, b, c] if s.bytes().all(|letter| letter.is_ascii_alphabetic()) => Ok(Currency(
+                [a, b, c].map(|letter| letter.to_ascii_uppercase()),
+            )),
+            _ => Err(EngineError::InvalidCurrency(s.into())),
+        }
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = EngineError;
+
+    fn try_from(code: String) -> Result<Self, Self::Error> {
+        code.parse()
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.to_string()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Only ASCII letters are accepted
+        self.0
+            .iter()
+            .try_for_each(|&letter| fmt::Write::write_char(f, letter.into()))
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+// A handful of currencies, so conversions between them come up
+#[cfg(feature = "fuzzing")]
+impl<'a> arbitrary::Arbitrary<'a> for Currency {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Currency([b'A', b'A', b'A' + u.int_in_range(0..=3)?]))
+    }
+}
+
+impl FxRates {
+    /// Reads the rates from a TOML file, e.g. `base = "USD"` with `rates = { EUR = "1.08" }`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let rates: FxRates = toml::from_str(&fs::read_to_string(path)?)?;
+        match rates.rates.iter().find(|(_, rate)| !rate.is_positive()) {
+            Some((currency, rate)) => Err(anyhow!(
+                "Exchange rate {} of {} isn't positive",
+                rate,
+                currency
+            )),
+            None => Ok(rates),
+        }
+    }
+
+    /// Value of one unit of `currency` in the base currency, `None` if it has no rate.
+    pub fn rate(&self, currency: Currency) -> Option<Amount> {
+        if currency == self.base {
+            return Some(Decimal::ONE.into());
+        }
+        self.rates
+            .get(&currency)
+            .copied()
+            .filter(|rate| rate.is_positive())
+    }
+
+    /// Value of `amount` of `currency` in the base currency, `None` if it has no rate.
+    pub fn in_base(&self, currency: Currency, amount: Amount) -> Option<Amount> {
+        Some(amount.exchange(self.rate(currency)?, Decimal::ONE.into()))
+    }
+}
+
+impl Balance {
+    pub fn total(&self) -> Amount {
+        self.available + self.held
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Currency, FxRates};
+    use crate::amount::Amount;
+
+    #[test]
+    fn parse_currency() {
+        let currency: Currency = "eur".parse().unwrap();
+        assert_eq!(currency.to_string(), "EUR");
+        assert!("EURO".parse::<Currency>().is_err());
+        assert!("E1R".parse::<Currency>().is_err());
+        assert!("€".parse::<Currency>().is_err());
+    }
+
+    #[test]
+    fn convert_through_the_base_currency() {
+        let rates: FxRates = toml::from_str(concat!(
+            "base = \"USD\"\n",
+            "[rates]\n",
+            "EUR = \"1.25\"\n",
+            "GBP = \"1.6\"\n",
+        ))
+        .unwrap();
+        let currency = |code: &str| code.parse::<Currency>().unwrap();
+        let amount = |amount: &str| amount.parse::<Amount>().unwrap();
+
+        let convert = |value, from, to| {
+            let (from, to) = (rates.rate(currency(from))?, rates.rate(currency(to))?);
+            Some(amount(value).exchange(from, to))
+        };
+        assert_eq!(convert("10", "EUR", "USD"), Some(amount("12.5")));
+        assert_eq!(convert("12.5", "USD", "EUR"), Some(amount("10")));
+        assert_eq!(convert("8", "GBP", "EUR"), Some(amount("10.24")));
+        assert_eq!(convert("8", "GBP", "JPY"), None);
+
+        assert!(toml::from_str::<FxRates>("base = \"USD\"\nfees = 1").is_err());
+    }
+}
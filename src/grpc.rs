@@ -0,0 +1,389 @@
+use crate::{
+    account::AccountView,
+    error::EngineError,
+    fx::Currency,
+    health::Health,
+    outcome::TransactionOutcome,
+    payment_engine::QueryHandle,
+    rate_limit::RateLimiter,
+    transaction::{ClientId, Transaction, TransactionId, TransactionType},
+};
+use anyhow::Result;
+use std::{future::Future, net::SocketAddr};
+use tokio::sync::mpsc::Sender;
+use tonic::{transport::Server, Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("payments");
+}
+
+/// Messages of the standard gRPC health checking protocol.
+pub mod health_proto {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+use health_proto::{
+    health_check_response::ServingStatus,
+    health_server::{Health as HealthCheck, HealthServer},
+};
+use proto::payments_server::{Payments, PaymentsServer};
+
+/// gRPC service feeding submitted transactions into a running engine.
+pub struct PaymentsService {
+    transactions: Sender<Transaction>,
+    queries: QueryHandle,
+    rate_limiter: RateLimiter,
+}
+
+impl PaymentsService {
+    pub fn new(transactions: Sender<Transaction>, queries: QueryHandle) -> Self {
+        PaymentsService {
+            transactions,
+            queries,
+            rate_limiter: RateLimiter::default(),
+        }
+    }
+
+    /// Turns away transactions over the limits with `RESOURCE_EXHAUSTED`.
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl Payments for PaymentsService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::TransactionRequest>,
+    ) -> Result<Response<proto::SubmitReply>, Status> {
+        self.rate_limiter
+            .check(request.remote_addr())
+            .map_err(|backoff| {
+                Status::resource_exhausted(format!(
+                    "Rate limit exceeded, retry in {} ms",
+                    backoff.as_millis()
+                ))
+            })?;
+        let transaction = Transaction::try_from(request.into_inner())?;
+        transaction
+            .validate(self.queries.precision())
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        let outcome = self
+            .queries
+            .submit(&self.transactions, transaction)
+            .await
+            .ok_or_else(|| Status::unavailable("Engine is not processing transactions"))?
+            .map_err(Status::failed_precondition)?;
+        let outcome = match outcome {
+            TransactionOutcome::Applied => proto::TransactionOutcome::Applied,
+            TransactionOutcome::AccountLocked => proto::TransactionOutcome::AccountLocked,
+            TransactionOutcome::Queued => proto::TransactionOutcome::Queued,
+            TransactionOutcome::Filtered => proto::TransactionOutcome::Filtered,
+            TransactionOutcome::InsufficientFunds => proto::TransactionOutcome::InsufficientFunds,
+            TransactionOutcome::CounterpartyLocked => proto::TransactionOutcome::CounterpartyLocked,
+            TransactionOutcome::DepositLimitExceeded => {
+                proto::TransactionOutcome::DepositLimitExceeded
+            }
+            TransactionOutcome::WithdrawalLimitExceeded => {
+                proto::TransactionOutcome::WithdrawalLimitExceeded
+            }
+            TransactionOutcome::DailyLimitExceeded => proto::TransactionOutcome::DailyLimitExceeded,
+            TransactionOutcome::OutsideDisputeWindow => {
+                proto::TransactionOutcome::OutsideDisputeWindow
+            }
+            TransactionOutcome::NoSuchTransaction => proto::TransactionOutcome::NoSuchTransaction,
+            TransactionOutcome::NotUnderDispute => proto::TransactionOutcome::NotUnderDispute,
+            TransactionOutcome::AlreadyDisputed => proto::TransactionOutcome::AlreadyDisputed,
+            TransactionOutcome::Duplicate => proto::TransactionOutcome::Duplicate,
+            TransactionOutcome::DisputeClosed => proto::TransactionOutcome::DisputeClosed,
+            TransactionOutcome::NotChargedBack => proto::TransactionOutcome::NotChargedBack,
+            TransactionOutcome::HoldExceeded => proto::TransactionOutcome::HoldExceeded,
+            TransactionOutcome::PendingApproval => proto::TransactionOutcome::PendingApproval,
+            TransactionOutcome::NotPending => proto::TransactionOutcome::NotPending,
+        };
+        Ok(Response::new(proto::SubmitReply {
+            outcome: outcome.into(),
+        }))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::AccountRequest>,
+    ) -> Result<Response<proto::AccountReply>, Status> {
+        let client = client_id(request.into_inner().client)?;
+        self.queries
+            .account(client)
+            .await
+            .map(|account| Response::new(self.queries.round(account).into()))
+            .ok_or_else(|| Status::not_found(format!("No account for client {}", client)))
+    }
+}
+
+/// gRPC health service reporting the [`Health`] of the server, for the probes of e.g. Kubernetes.
+///
+/// The empty service name and `liveness` check the liveness, `readiness` and `payments.Payments`
+/// the readiness.
+pub struct HealthService(Health);
+
+impl HealthService {
+    pub fn new(health: Health) -> Self {
+        HealthService(health)
+    }
+}
+
+#[tonic::async_trait]
+impl HealthCheck for HealthService {
+    async fn check(
+        &self,
+        request: Request<health_proto::HealthCheckRequest>,
+    ) -> Result<Response<health_proto::HealthCheckResponse>, Status> {
+        let check = match request.into_inner().service.as_str() {
+            "" | "liveness" => self.0.check_alive(),
+            "readiness" | "payments.Payments" => self.0.check_ready(),
+            service => return Err(Status::not_found(format!("Unknown service `{service}`"))),
+        };
+        let status = match check {
+            Ok(()) => ServingStatus::Serving,
+            Err(reason) => {
+                tracing::debug!(%reason, "Failed health check");
+                ServingStatus::NotServing
+            }
+        };
+        Ok(Response::new(health_proto::HealthCheckResponse {
+            status: status.into(),
+        }))
+    }
+}
+
+/// Serves the gRPC service and the health service on `address` until `shutdown` completes.
+///
+/// The transaction sender is dropped afterwards, which lets the engine finish processing.
+pub async fn serve<F: Future<Output = ()>>(
+    address: SocketAddr,
+    transactions: Sender<Transaction>,
+    queries: QueryHandle,
+    rate_limiter: RateLimiter,
+    health: Health,
+    shutdown: F,
+) -> Result<()> {
+    let service = PaymentsService::new(transactions, queries).rate_limiter(rate_limiter);
+    Server::builder()
+        .add_service(PaymentsServer::new(service))
+        .add_service(HealthServer::new(HealthService::new(health)))
+        .serve_with_shutdown(address, shutdown)
+        .await?;
+    Ok(())
+}
+
+impl TryFrom<proto::TransactionRequest> for Transaction {
+    type Error = Status;
+
+    fn try_from(request: proto::TransactionRequest) -> Result<Self, Self::Error> {
+        let r#type = match request.r#type() {
+            proto::TransactionType::Deposit => TransactionType::Deposit,
+            proto::TransactionType::Withdrawal => TransactionType::Withdrawal,
+            proto::TransactionType::Dispute => TransactionType::Dispute,
+            proto::TransactionType::Resolve => TransactionType::Resolve,
+            proto::TransactionType::Chargeback => TransactionType::Chargeback,
+            proto::TransactionType::Unlock => TransactionType::Unlock,
+            proto::TransactionType::Transfer => TransactionType::Transfer,
+            proto::TransactionType::ChargebackReversal => TransactionType::ChargebackReversal,
+            proto::TransactionType::Hold => TransactionType::Hold,
+            proto::TransactionType::Release => TransactionType::Release,
+            proto::TransactionType::Convert => TransactionType::Convert,
+            proto::TransactionType::Approve => TransactionType::Approve,
+            proto::TransactionType::Reject => TransactionType::Reject,
+        };
+        let amount = request
+            .amount
+            .map(|amount| amount.parse())
+            .transpose()
+            .map_err(|error| Status::invalid_argument(format!("Invalid amount: {}", error)))?;
+
+        Ok(Transaction {
+            r#type,
+            client: client_id(request.client)?,
+            tx: transaction_id(request.tx)?,
+            amount,
+            counterparty: request.counterparty.map(client_id).transpose()?,
+            timestamp: request.timestamp,
+            from_ccy: request.from_ccy.as_deref().map(currency).transpose()?,
+            to_ccy: request.to_ccy.as_deref().map(currency).transpose()?,
+        })
+    }
+}
+
+impl From<AccountView> for proto::AccountReply {
+    fn from(account: AccountView) -> Self {
+        proto::AccountReply {
+            client: account.client.into(),
+            available: account.available.to_string(),
+            held: account.held.to_string(),
+            total: account.total.to_string(),
+            locked: account.locked,
+            fees_collected: account.fees_collected.map(|fees| fees.to_string()),
+        }
+    }
+}
+
+fn currency(code: &str) -> Result<Currency, Status> {
+    code.parse()
+        .map_err(|error: EngineError| Status::invalid_argument(error.to_string()))
+}
+
+fn client_id(client: u32) -> Result<ClientId, Status> {
+    client
+        .try_into()
+        .map_err(|_| Status::invalid_argument(format!("Invalid client id {}", client)))
+}
+
+fn transaction_id(tx: u64) -> Result<TransactionId, Status> {
+    tx.try_into()
+        .map_err(|_| Status::invalid_argument(format!("Invalid transaction id {}", tx)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        health_proto::{self, health_check_response::ServingStatus},
+        proto, HealthCheck, HealthService, Payments, PaymentsService,
+    };
+    use crate::{
+        error::ErrorPolicy,
+        health::Health,
+        payment_engine::PaymentsEngine,
+        rate_limit::{RateLimiter, RateLimits},
+    };
+    use tokio_util::sync::CancellationToken;
+    use tonic::{Code, Request};
+
+    #[tokio::test]
+    async fn submit_and_get_account() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        payments_engine.set_error_policy(ErrorPolicy::Lenient);
+        let service = PaymentsService::new(sender, payments_engine.query_handle());
+
+        let client = tokio::spawn(async move {
+            let deposit = proto::TransactionRequest {
+                r#type: proto::TransactionType::Deposit.into(),
+                client: 1,
+                tx: 1,
+                amount: Some("2.5".into()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            let reply = service
+                .submit_transaction(Request::new(deposit))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(reply.outcome(), proto::TransactionOutcome::Applied);
+
+            let duplicate = proto::TransactionRequest {
+                r#type: proto::TransactionType::Deposit.into(),
+                client: 1,
+                tx: 1,
+                amount: Some("1.0".into()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            let status = service
+                .submit_transaction(Request::new(duplicate))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::FailedPrecondition);
+
+            // Every client id of a request is valid with the feature `wide-ids`
+            if cfg!(not(feature = "wide-ids")) {
+                let invalid_client = proto::TransactionRequest {
+                    client: 70000,
+                    ..Default::default()
+                };
+                let status = service
+                    .submit_transaction(Request::new(invalid_client))
+                    .await
+                    .unwrap_err();
+                assert_eq!(status.code(), Code::InvalidArgument);
+            }
+
+            let account = service
+                .get_account(Request::new(proto::AccountRequest { client: 1 }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(account.available, "2.5");
+            assert!(!account.locked);
+
+            let status = service
+                .get_account(Request::new(proto::AccountRequest { client: 2 }))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::NotFound);
+        });
+
+        payments_engine.process_transactions().await.unwrap();
+        client.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let (payments_engine, sender) = PaymentsEngine::with_workers(1);
+        let rate_limiter = RateLimiter::new(RateLimits {
+            global: Some("1".parse().unwrap()),
+            per_connection: None,
+        });
+        let service =
+            PaymentsService::new(sender, payments_engine.query_handle()).rate_limiter(rate_limiter);
+
+        // The first request takes the only token, even though it is invalid
+        let invalid = proto::TransactionRequest {
+            r#type: proto::TransactionType::Deposit.into(),
+            amount: Some("-1".into()),
+            ..Default::default()
+        };
+        let status = service
+            .submit_transaction(Request::new(invalid.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let status = service
+            .submit_transaction(Request::new(invalid))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn health_checks() {
+        let (payments_engine, sender) = PaymentsEngine::with_workers(1);
+        let shutdown = CancellationToken::new();
+        let service = HealthService::new(Health::new(
+            &sender,
+            payments_engine.query_handle(),
+            shutdown.clone(),
+        ));
+        let check = |service: &'static str| health_proto::HealthCheckRequest {
+            service: service.into(),
+        };
+
+        shutdown.cancel();
+        let reply = service.check(Request::new(check(""))).await.unwrap();
+        assert_eq!(reply.into_inner().status(), ServingStatus::Serving);
+        let reply = service
+            .check(Request::new(check("readiness")))
+            .await
+            .unwrap();
+        assert_eq!(reply.into_inner().status(), ServingStatus::NotServing);
+
+        let status = service
+            .check(Request::new(check("payments.Accounts")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+}
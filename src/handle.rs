@@ -0,0 +1,124 @@
+use crate::{
+    account::AccountView,
+    outcome::TransactionOutcome,
+    payment_engine::{PaymentsEngine, QueryHandle},
+    transaction::{ClientId, Transaction},
+};
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::{
+    sync::{mpsc::Sender, Mutex},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+/// Cloneable handle to an engine processing transactions in the background, so applications can
+/// share it across tasks without handling its channels.
+#[derive(Clone)]
+pub struct EngineHandle {
+    transactions: Sender<Transaction>,
+    queries: QueryHandle,
+    shutdown: CancellationToken,
+    // Taken by the first call to `shutdown`
+    engine: Arc<Mutex<Option<JoinHandle<Result<PaymentsEngine>>>>>,
+}
+
+impl EngineHandle {
+    /// Starts processing the transactions sent through `transactions`, the sender returned with
+    /// `payments_engine`, on a new task.
+    pub fn spawn(mut payments_engine: PaymentsEngine, transactions: Sender<Transaction>) -> Self {
+        let shutdown = CancellationToken::new();
+        payments_engine.set_shutdown(shutdown.clone());
+        let queries = payments_engine.query_handle();
+        let engine = tokio::spawn(async move {
+            payments_engine.process_transactions().await?;
+            Ok(payments_engine)
+        });
+
+        EngineHandle {
+            transactions,
+            queries,
+            shutdown,
+            engine: Arc::new(Mutex::new(Some(engine))),
+        }
+    }
+
+    /// Processes `transaction` and returns its outcome, or the reason it was rejected.
+    ///
+    /// Returns `None` if the engine stopped or the outcome was missed.
+    pub async fn submit(
+        &self,
+        transaction: Transaction,
+    ) -> Option<Result<TransactionOutcome, String>> {
+        self.queries.submit(&self.transactions, transaction).await
+    }
+
+    /// Returns the balances of the account of `client`, reflecting all transactions submitted
+    /// before, or `None` if it doesn't exist or the engine stopped.
+    pub async fn account(&self, client: ClientId) -> Option<AccountView> {
+        self.queries.account(client).await
+    }
+
+    pub fn query_handle(&self) -> &QueryHandle {
+        &self.queries
+    }
+
+    /// Stops accepting transactions, waits until the ones submitted before are processed and
+    /// returns the engine, e.g. to write the accounts.
+    ///
+    /// Returns `None` if another clone of the handle shut the engine down already.
+    pub async fn shutdown(&self) -> Result<Option<PaymentsEngine>> {
+        self.shutdown.cancel();
+        let Some(engine) = self.engine.lock().await.take() else {
+            return Ok(None);
+        };
+        engine.await?.map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EngineHandle;
+    use crate::{
+        outcome::TransactionOutcome,
+        payment_engine::PaymentsEngine,
+        transaction::{Transaction, TransactionType},
+    };
+
+    #[tokio::test]
+    async fn share_across_tasks() {
+        let (payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let handle = EngineHandle::spawn(payments_engine, sender);
+
+        let tasks: Vec<_> = (1..=4)
+            .map(|client| {
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    let deposit = Transaction {
+                        r#type: TransactionType::Deposit,
+                        client,
+                        tx: client.into(),
+                        amount: Some("1.0".parse().unwrap()),
+                        counterparty: None,
+                        timestamp: None,
+                        from_ccy: None,
+                        to_ccy: None,
+                    };
+                    handle.submit(deposit).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), Some(Ok(TransactionOutcome::Applied)));
+        }
+        assert_eq!(
+            handle.account(3).await.unwrap().available,
+            "1.0".parse().unwrap()
+        );
+
+        let payments_engine = handle.shutdown().await.unwrap().unwrap();
+        assert_eq!(payments_engine.accounts().count(), 4);
+        assert!(handle.shutdown().await.unwrap().is_none());
+        assert_eq!(handle.account(3).await, None);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{payment_engine::QueryHandle, transaction::Transaction};
+use tokio::sync::mpsc::{Sender, WeakSender};
+use tokio_util::sync::CancellationToken;
+
+/// Liveness and readiness of a server feeding transactions into an engine, as reported by the
+/// `/healthz` and `/readyz` routes of the HTTP API and by the gRPC health service.
+///
+/// A server is alive while the engine processes transactions and the senders of the server are
+/// open. It is ready while it is alive, isn't shutting down, and no more than the maximum backlog
+/// of transactions are waiting in the input channel of the engine.
+#[derive(Clone)]
+pub struct Health {
+    // Weak, so the health checks don't keep the input channel of the engine open
+    transactions: WeakSender<Transaction>,
+    queries: QueryHandle,
+    shutdown: CancellationToken,
+    max_backlog: usize,
+}
+
+impl Health {
+    /// Checks the engine behind `queries` through the channel of `transactions`, and reports a
+    /// server that is stopped by `shutdown` as not ready.
+    ///
+    /// By default the server is ready unless the channel is full.
+    pub fn new(
+        transactions: &Sender<Transaction>,
+        queries: QueryHandle,
+        shutdown: CancellationToken,
+    ) -> Self {
+        Health {
+            transactions: transactions.downgrade(),
+            max_backlog: transactions.max_capacity() - 1,
+            queries,
+            shutdown,
+        }
+    }
+
+    /// Reports the server as not ready once more than `max_backlog` transactions wait in the
+    /// input channel.
+    pub fn max_backlog(mut self, max_backlog: usize) -> Self {
+        self.max_backlog = max_backlog;
+        self
+    }
+
+    /// Transactions waiting in the input channel, `None` once all senders are dropped.
+    pub fn backlog(&self) -> Option<usize> {
+        let transactions = self.transactions.upgrade()?;
+        Some(transactions.max_capacity() - transactions.capacity())
+    }
+
+    /// Whether the engine and the senders are alive, or the reason why not.
+    pub fn check_alive(&self) -> Result<(), String> {
+        if !self.queries.is_running() {
+            return Err("Engine is not processing transactions".into());
+        }
+        match self.backlog() {
+            Some(_) => Ok(()),
+            None => Err("Transactions are not accepted anymore".into()),
+        }
+    }
+
+    /// Whether the server accepts further transactions, or the reason why not.
+    pub fn check_ready(&self) -> Result<(), String> {
+        self.check_alive()?;
+        if self.shutdown.is_cancelled() {
+            return Err("Shutting down".into());
+        }
+        match self.backlog() {
+            Some(backlog) if backlog > self.max_backlog => Err(format!(
+                "{backlog} transactions are waiting, more than {}",
+                self.max_backlog
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Health;
+    use crate::{
+        payment_engine::PaymentsEngine,
+        transaction::{Transaction, TransactionType},
+    };
+    use tokio_util::sync::CancellationToken;
+
+    #[tokio::test]
+    async fn alive_and_ready() {
+        let (mut payments_engine, sender) = PaymentsEngine::builder().channel_capacity(4).build();
+        let shutdown = CancellationToken::new();
+        let health =
+            Health::new(&sender, payments_engine.query_handle(), shutdown.clone()).max_backlog(1);
+        assert_eq!(health.check_alive(), Ok(()));
+        assert_eq!(health.check_ready(), Ok(()));
+
+        // Nothing reads the channel before the engine processes transactions
+        for tx in 1..=2 {
+            let deposit = Transaction {
+                r#type: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some("1.0".parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(deposit).await.unwrap();
+        }
+        assert_eq!(health.backlog(), Some(2));
+        assert!(health.check_ready().is_err());
+        assert_eq!(health.check_alive(), Ok(()));
+
+        shutdown.cancel();
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+        assert!(health.check_alive().is_err());
+        assert!(health.check_ready().is_err());
+    }
+}
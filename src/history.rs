@@ -0,0 +1,658 @@
+use crate::{
+    amount::Amount,
+    dispute::DisputeState,
+    error::EngineError,
+    fx::Currency,
+    transaction::{ClientId, TransactionId, TransactionType},
+};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    str::FromStr,
+};
+#[cfg(feature = "runtime")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+// Distinguishes the temporary directories of several engines in the same process
+#[cfg(feature = "runtime")]
+static DIRECTORY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Deposit, withdrawal, transfer or conversion as remembered for later disputes.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub(crate) struct TransactionRecord {
+    pub kind: TransactionType,
+    pub amount: Amount,
+    /// Number of events applied to the account before
+    pub sequence: u64,
+    pub timestamp: Option<u64>,
+    pub state: DisputeState,
+    /// Fee kept out of a deposit or charged on top of a withdrawal
+    pub fee: Amount,
+    /// Currency of the amount if it isn't the base currency, only for conversions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// Entry of the funds a conversion credited, linked to the entry of the amount it debited.
+    /// Both entries are disputed together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credit: Option<LinkedEntry>,
+}
+
+/// Funds credited by a conversion, in `currency` or the base currency.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub(crate) struct LinkedEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    pub amount: Amount,
+}
+
+impl TransactionRecord {
+    /// Funds the transaction moved into or out of the available funds, apart from its fee,
+    /// which a dispute holds. Those of a conversion are the ones it credited.
+    pub fn funds(&self) -> Amount {
+        match (self.kind, self.credit) {
+            (_, Some(credit)) => credit.amount,
+            (TransactionType::Deposit, None) => self.amount - self.fee,
+            _ => self.amount,
+        }
+    }
+}
+
+/// Records of a history held in memory, as stored with the account by disk-backed stores.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub(crate) struct HistoryState {
+    records: Vec<(TransactionId, TransactionRecord)>,
+    spilled: usize,
+    inserted: VecDeque<TransactionId>,
+}
+
+/// Which transactions of an account are remembered for later disputes.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum HistoryRetention {
+    /// Every deposit, withdrawal and transfer
+    #[default]
+    All,
+    /// Only the latest transactions, older ones are forgotten unless they are in dispute
+    Latest(usize),
+}
+
+/// What happens to the record of a transaction once its dispute is settled for good, i.e. it was
+/// charged back, its chargeback was reversed, or it was resolved and can't be disputed again.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum SettledHistory {
+    /// It is remembered, so a later dispute is declined as closed
+    #[default]
+    Keep,
+    /// It is forgotten, a later dispute or chargeback reversal is declined as for an unknown
+    /// transaction
+    Drop,
+    /// It is moved to the [`HistorySpill`] right away, kept in memory without one
+    Archive,
+}
+
+impl FromStr for SettledHistory {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(SettledHistory::Keep),
+            "drop" => Ok(SettledHistory::Drop),
+            "archive" => Ok(SettledHistory::Archive),
+            _ => Err(EngineError::InvalidArgumentValue(
+                "--settled-history".into(),
+                s.into(),
+            )),
+        }
+    }
+}
+
+/// On-disk index the transaction history of an account is spilled to, once it holds more than
+/// `capacity` records in memory.
+///
+/// Without the `runtime` feature there is no disk, and no spill can be opened.
+#[derive(Clone, Debug)]
+pub struct HistorySpill {
+    tree: SpillTree,
+    capacity: usize,
+    // Declared last, so the index is closed before its directory is removed
+    #[cfg(feature = "runtime")]
+    _directory: Arc<TemporaryDirectory>,
+}
+
+#[cfg(feature = "runtime")]
+type SpillTree = sled::Tree;
+#[cfg(not(feature = "runtime"))]
+type SpillTree = NoDisk;
+
+// Stands in for the index of a spill that can't exist
+#[cfg(not(feature = "runtime"))]
+#[derive(Clone, Debug)]
+enum NoDisk {}
+
+#[cfg(not(feature = "runtime"))]
+type NoDiskResult<T> = Result<T, std::convert::Infallible>;
+
+#[cfg(not(feature = "runtime"))]
+impl NoDisk {
+    fn get<K>(&self, _: K) -> NoDiskResult<Option<Vec<u8>>> {
+        match *self {}
+    }
+
+    fn insert<K, V>(&self, _: K, _: V) -> NoDiskResult<Option<Vec<u8>>> {
+        match *self {}
+    }
+
+    fn remove<K>(&self, _: K) -> NoDiskResult<Option<Vec<u8>>> {
+        match *self {}
+    }
+
+    fn scan_prefix<P>(&self, _: P) -> std::iter::Empty<NoDiskResult<(Vec<u8>, Vec<u8>)>> {
+        match *self {}
+    }
+}
+
+// Directory that is removed with all its contents once dropped
+#[cfg(feature = "runtime")]
+#[derive(Debug)]
+pub(crate) struct TemporaryDirectory(PathBuf);
+
+#[cfg(feature = "runtime")]
+impl TemporaryDirectory {
+    // Names a new subdirectory of `parent`, which is created by the database opened in it
+    pub(crate) fn new(parent: &Path, name: &str) -> Self {
+        TemporaryDirectory(parent.join(format!(
+            "{name}-{}-{}",
+            process::id(),
+            DIRECTORY_COUNTER.fetch_add(1, Ordering::Relaxed)
+        )))
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl HistorySpill {
+    /// Creates a temporary index in a new subdirectory of `directory`, which is removed once the
+    /// engine is dropped.
+    pub fn open<P: AsRef<Path>>(directory: P, capacity: usize) -> Result<Self, EngineError> {
+        let directory = Arc::new(TemporaryDirectory::new(
+            directory.as_ref(),
+            "transaction-history",
+        ));
+        let db = sled::Config::new()
+            .path(directory.path())
+            .open()
+            .map_err(history_error)?;
+        Ok(HistorySpill {
+            tree: db.open_tree("transaction_history").map_err(history_error)?,
+            capacity: capacity.max(1),
+            _directory: directory,
+        })
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl Drop for TemporaryDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Transaction history of one account, keeping the least recently used records on disk if a
+/// [`HistorySpill`] is given.
+#[derive(Clone, Debug)]
+pub(crate) struct TransactionHistory {
+    client: ClientId,
+    records: FxHashMap<TransactionId, CachedRecord>,
+    // Transaction ids of `records` by the time they were used last
+    recently_used: BTreeMap<u64, TransactionId>,
+    clock: u64,
+    spill: Option<HistorySpill>,
+    spilled: usize,
+    retention: HistoryRetention,
+    settled: SettledHistory,
+    // Transaction ids in the order they were inserted, if only the latest are retained
+    inserted: VecDeque<TransactionId>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct CachedRecord {
+    record: TransactionRecord,
+    last_used: u64,
+    // Records are only changed by `replace`, which drops the copy on disk, so one that was loaded
+    // from disk doesn't have to be written again
+    on_disk: bool,
+}
+
+impl TransactionHistory {
+    pub fn new(client: ClientId, spill: Option<HistorySpill>) -> Self {
+        TransactionHistory {
+            client,
+            records: FxHashMap::with_capacity_and_hasher(1, Default::default()),
+            recently_used: BTreeMap::new(),
+            clock: 0,
+            spill,
+            spilled: 0,
+            retention: HistoryRetention::All,
+            settled: SettledHistory::Keep,
+            inserted: VecDeque::new(),
+        }
+    }
+
+    pub fn state(&self) -> HistoryState {
+        HistoryState {
+            // Records loaded from the spill are still there
+            records: self
+                .records
+                .iter()
+                .filter(|(_, cached)| !cached.on_disk)
+                .map(|(&transaction_id, cached)| (transaction_id, cached.record))
+                .collect(),
+            spilled: self.spilled,
+            inserted: self.inserted.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, state: HistoryState) -> Result<(), EngineError> {
+        self.records.clear();
+        self.recently_used.clear();
+        for (transaction_id, record) in state.records {
+            self.touch(transaction_id, record, false);
+        }
+        self.spilled = state.spilled;
+        self.inserted = state.inserted;
+        self.evict()
+    }
+
+    pub fn set_retention(&mut self, retention: HistoryRetention) {
+        self.retention = retention;
+    }
+
+    pub fn set_settled(&mut self, settled: SettledHistory) {
+        self.settled = settled;
+    }
+
+    /// Makes room for `additional` records, at most as many as are kept in memory.
+    pub fn reserve(&mut self, mut additional: usize) {
+        if let HistoryRetention::Latest(capacity) = self.retention {
+            additional = additional.min(capacity);
+        }
+        if let Some(spill) = &self.spill {
+            additional = additional.min(spill.capacity + 1);
+        }
+        self.records.reserve(additional);
+    }
+
+    pub fn len(&self) -> usize {
+        self.spilled
+            + self
+                .records
+                .values()
+                .filter(|cached| !cached.on_disk)
+                .count()
+    }
+
+    pub fn insert(
+        &mut self,
+        transaction_id: TransactionId,
+        record: TransactionRecord,
+    ) -> Result<(), EngineError> {
+        self.touch(transaction_id, record, false);
+        if let HistoryRetention::Latest(_) = self.retention {
+            self.inserted.push_back(transaction_id);
+        }
+        self.evict()
+    }
+
+    /// Replaces the record of a transaction that is remembered already.
+    pub fn replace(
+        &mut self,
+        transaction_id: TransactionId,
+        record: TransactionRecord,
+    ) -> Result<(), EngineError> {
+        self.remove(transaction_id)?;
+        self.touch(transaction_id, record, false);
+        self.evict()
+    }
+
+    /// Forgets the oldest records beyond the retention, except those in dispute.
+    pub fn expire(&mut self) -> Result<(), EngineError> {
+        let HistoryRetention::Latest(capacity) = self.retention else {
+            return Ok(());
+        };
+        // Pinned records are moved to the back, until only they are left
+        let mut kept = 0;
+        while self.inserted.len() > capacity && kept < self.inserted.len() {
+            let Some(transaction_id) = self.inserted.pop_front() else {
+                break;
+            };
+            let pinned = self
+                .peek(transaction_id)?
+                .is_some_and(|record| record.state == DisputeState::Disputed);
+            if pinned {
+                self.inserted.push_back(transaction_id);
+                kept += 1;
+            } else {
+                self.remove(transaction_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops or archives the record of a transaction whose dispute is settled.
+    pub fn settle(&mut self, transaction_id: TransactionId) -> Result<(), EngineError> {
+        match self.settled {
+            SettledHistory::Keep => Ok(()),
+            SettledHistory::Drop => self.remove(transaction_id),
+            SettledHistory::Archive => self.archive(transaction_id),
+        }
+    }
+
+    // Moves a record to disk, unless it is there already
+    fn archive(&mut self, transaction_id: TransactionId) -> Result<(), EngineError> {
+        let Some(spill) = &self.spill else {
+            return Ok(());
+        };
+        let Some(cached) = self.records.remove(&transaction_id) else {
+            return Ok(());
+        };
+        self.recently_used.remove(&cached.last_used);
+        if !cached.on_disk {
+            let bytes = serde_json::to_vec(&cached.record).map_err(history_error)?;
+            spill
+                .tree
+                .insert(self.key(transaction_id), bytes)
+                .map_err(history_error)?;
+            self.spilled += 1;
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, transaction_id: TransactionId) -> Result<(), EngineError> {
+        let on_disk = match self.records.remove(&transaction_id) {
+            Some(cached) => {
+                self.recently_used.remove(&cached.last_used);
+                cached.on_disk
+            }
+            None => true,
+        };
+        if let (true, Some(spill)) = (on_disk, &self.spill) {
+            let removed = spill
+                .tree
+                .remove(self.key(transaction_id))
+                .map_err(history_error)?;
+            if removed.is_some() {
+                self.spilled -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks a record up without counting it as used.
+    pub fn peek(
+        &self,
+        transaction_id: TransactionId,
+    ) -> Result<Option<TransactionRecord>, EngineError> {
+        if let Some(cached) = self.records.get(&transaction_id) {
+            return Ok(Some(cached.record));
+        }
+        let Some(spill) = &self.spill else {
+            return Ok(None);
+        };
+        spill
+            .tree
+            .get(self.key(transaction_id))
+            .map_err(history_error)?
+            .map(|bytes| serde_json::from_slice(&bytes).map_err(history_error))
+            .transpose()
+    }
+
+    pub fn get(
+        &mut self,
+        transaction_id: TransactionId,
+    ) -> Result<Option<TransactionRecord>, EngineError> {
+        if let Some(cached) = self.records.get(&transaction_id).copied() {
+            self.touch(transaction_id, cached.record, cached.on_disk);
+            return Ok(Some(cached.record));
+        }
+        let Some(spill) = &self.spill else {
+            return Ok(None);
+        };
+
+        let Some(bytes) = spill
+            .tree
+            .get(self.key(transaction_id))
+            .map_err(history_error)?
+        else {
+            return Ok(None);
+        };
+        let record: TransactionRecord = serde_json::from_slice(&bytes).map_err(history_error)?;
+        self.touch(transaction_id, record, true);
+        self.evict()?;
+        Ok(Some(record))
+    }
+
+    /// Records of the transactions in dispute, in no particular order.
+    pub fn disputed(&self) -> Result<Vec<(TransactionId, TransactionRecord)>, EngineError> {
+        let mut records = self.records()?;
+        records.retain(|(_, record)| record.state == DisputeState::Disputed);
+        Ok(records)
+    }
+
+    /// All records, in memory and on disk, in no particular order.
+    pub fn records(&self) -> Result<Vec<(TransactionId, TransactionRecord)>, EngineError> {
+        let mut records: Vec<_> = self
+            .records
+            .iter()
+            .map(|(&transaction_id, cached)| (transaction_id, cached.record))
+            .collect();
+        let Some(spill) = &self.spill else {
+            return Ok(records);
+        };
+        let prefix = self.client.to_be_bytes();
+        for entry in spill.tree.scan_prefix(prefix) {
+            let (key, bytes) = entry.map_err(history_error)?;
+            let Ok(id_bytes) = key[prefix.len()..].try_into() else {
+                continue;
+            };
+            let transaction_id = TransactionId::from_be_bytes(id_bytes);
+            // Records loaded from disk may have been changed in memory since
+            if self.records.contains_key(&transaction_id) {
+                continue;
+            }
+            let record = serde_json::from_slice(&bytes).map_err(history_error)?;
+            records.push((transaction_id, record));
+        }
+        Ok(records)
+    }
+
+    /// Replaces all records by `records`, which are inserted in the given order.
+    pub fn load(
+        &mut self,
+        records: Vec<(TransactionId, TransactionRecord)>,
+    ) -> Result<(), EngineError> {
+        for (transaction_id, _) in self.records()? {
+            self.remove(transaction_id)?;
+        }
+        self.inserted.clear();
+        for (transaction_id, record) in records {
+            self.insert(transaction_id, record)?;
+        }
+        Ok(())
+    }
+
+    fn touch(&mut self, transaction_id: TransactionId, record: TransactionRecord, on_disk: bool) {
+        let cached = CachedRecord {
+            record,
+            last_used: self.clock,
+            on_disk,
+        };
+        if let Some(previous) = self.records.insert(transaction_id, cached) {
+            self.recently_used.remove(&previous.last_used);
+        }
+        self.recently_used.insert(self.clock, transaction_id);
+        self.clock += 1;
+    }
+
+    fn evict(&mut self) -> Result<(), EngineError> {
+        let Some(spill) = &self.spill else {
+            return Ok(());
+        };
+
+        while self.records.len() > spill.capacity {
+            let Some((_, transaction_id)) = self.recently_used.pop_first() else {
+                break;
+            };
+            let Some(cached) = self.records.remove(&transaction_id) else {
+                continue;
+            };
+            if !cached.on_disk {
+                let bytes = serde_json::to_vec(&cached.record).map_err(history_error)?;
+                spill
+                    .tree
+                    .insert(self.key(transaction_id), bytes)
+                    .map_err(history_error)?;
+                self.spilled += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // The index is shared by all accounts
+    fn key(&self, transaction_id: TransactionId) -> Vec<u8> {
+        [
+            &self.client.to_be_bytes()[..],
+            &transaction_id.to_be_bytes(),
+        ]
+        .concat()
+    }
+}
+
+// Accounts rebuilt from the same events are equal, regardless of which records are in memory
+impl PartialEq for TransactionHistory {
+    fn eq(&self, other: &Self) -> bool {
+        self.client == other.client
+            && self.len() == other.len()
+            && self.records.iter().all(|(transaction_id, cached)| {
+                other
+                    .records
+                    .get(transaction_id)
+                    .is_none_or(|other| other.record == cached.record)
+            })
+    }
+}
+
+fn history_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> EngineError {
+    EngineError::TransactionHistory(Box::new(error))
+}
+
+#[cfg(all(test, feature = "runtime"))]
+mod tests {
+    use super::{
+        HistoryRetention, HistorySpill, SettledHistory, TransactionHistory, TransactionRecord,
+    };
+    use crate::{amount::Amount, dispute::DisputeState, transaction::TransactionType};
+
+    #[test]
+    fn spill_least_recently_used() {
+        let spill = HistorySpill::open(std::env::temp_dir(), 2).unwrap();
+        let mut history = TransactionHistory::new(1, Some(spill));
+
+        for transaction_id in 0..5 {
+            let record = TransactionRecord {
+                kind: TransactionType::Deposit,
+                amount: rust_decimal::Decimal::from(transaction_id).into(),
+                sequence: transaction_id.into(),
+                timestamp: None,
+                state: DisputeState::Normal,
+                fee: Amount::ZERO,
+                currency: None,
+                credit: None,
+            };
+            history.insert(transaction_id, record).unwrap();
+        }
+        assert_eq!(history.records.len(), 2);
+        assert_eq!(history.len(), 5);
+
+        assert!(history.peek(0).unwrap().is_some());
+        assert!(history.peek(5).unwrap().is_none());
+        assert_eq!(
+            history.get(1).unwrap().unwrap().amount,
+            rust_decimal::Decimal::ONE.into()
+        );
+        assert!(history.records.contains_key(&1));
+        assert_eq!(history.peek(2).unwrap().unwrap().sequence, 2);
+        assert!(!history.records.contains_key(&2));
+        assert_eq!(history.len(), 5);
+    }
+
+    #[test]
+    fn retain_latest() {
+        let spill = HistorySpill::open(std::env::temp_dir(), 1).unwrap();
+        let mut history = TransactionHistory::new(1, Some(spill));
+        history.set_retention(HistoryRetention::Latest(2));
+
+        for transaction_id in 0..5 {
+            let state = match transaction_id {
+                0 => DisputeState::Disputed,
+                _ => DisputeState::Normal,
+            };
+            let record = TransactionRecord {
+                kind: TransactionType::Deposit,
+                amount: rust_decimal::Decimal::ONE.into(),
+                sequence: transaction_id.into(),
+                timestamp: None,
+                state,
+                fee: Amount::ZERO,
+                currency: None,
+                credit: None,
+            };
+            history.insert(transaction_id, record).unwrap();
+            history.expire().unwrap();
+        }
+        assert_eq!(history.len(), 2);
+        assert!(history.peek(0).unwrap().is_some());
+        assert!(history.peek(3).unwrap().is_none());
+        assert!(history.peek(4).unwrap().is_some());
+    }
+
+    #[test]
+    fn drop_or_archive_settled() {
+        let record = TransactionRecord {
+            kind: TransactionType::Deposit,
+            amount: rust_decimal::Decimal::ONE.into(),
+            sequence: 0,
+            timestamp: None,
+            state: DisputeState::ChargedBack,
+            fee: Amount::ZERO,
+            currency: None,
+            credit: None,
+        };
+
+        let mut history = TransactionHistory::new(1, None);
+        history.set_settled(SettledHistory::Drop);
+        history.insert(1, record).unwrap();
+        history.settle(1).unwrap();
+        assert_eq!(history.len(), 0);
+        assert!(history.peek(1).unwrap().is_none());
+
+        let spill = HistorySpill::open(std::env::temp_dir(), 10).unwrap();
+        let mut history = TransactionHistory::new(1, Some(spill));
+        history.set_settled(SettledHistory::Archive);
+        history.insert(1, record).unwrap();
+        history.settle(1).unwrap();
+        assert!(history.records.is_empty());
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.peek(1).unwrap(), Some(record));
+    }
+}
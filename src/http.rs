@@ -0,0 +1,318 @@
+use crate::{
+    account::AccountView,
+    health::Health,
+    outcome::TransactionOutcome,
+    payment_engine::QueryHandle,
+    rate_limit::RateLimiter,
+    transaction::{ClientId, Transaction},
+};
+use anyhow::Result;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, State,
+    },
+    http::{header::RETRY_AFTER, Extensions, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use std::{future::Future, net::SocketAddr, time::Duration};
+use tokio::{
+    net::TcpListener,
+    sync::{
+        broadcast::{error::RecvError, Receiver},
+        mpsc::Sender,
+    },
+};
+
+#[derive(Clone)]
+struct AppState {
+    transactions: Sender<Transaction>,
+    queries: QueryHandle,
+    rate_limiter: RateLimiter,
+}
+
+/// Routes of the HTTP API:
+///
+/// * `POST /transactions` processes the JSON encoded transaction and returns its outcome, or
+///   `429 Too Many Requests` with a `Retry-After` header when it exceeds the rate limits
+/// * `GET /accounts/{client}` returns the current state of an account
+/// * `GET /ws/accounts` upgrades to a WebSocket, which receives the state of every account
+///   changed from then on as a JSON text message
+pub fn router(
+    transactions: Sender<Transaction>,
+    queries: QueryHandle,
+    rate_limiter: RateLimiter,
+) -> Router {
+    Router::new()
+        .route("/transactions", post(submit_transaction))
+        .route("/accounts/{client}", get(get_account))
+        .route("/ws/accounts", get(stream_accounts))
+        .with_state(AppState {
+            transactions,
+            queries,
+            rate_limiter,
+        })
+}
+
+/// Routes of the health checks, e.g. for the probes of Kubernetes:
+///
+/// * `GET /healthz` returns `200 OK` while the engine and the server are alive
+/// * `GET /readyz` returns `200 OK` while the server accepts transactions, i.e. it isn't shutting
+///   down and the backlog of the engine is below the maximum
+///
+/// Both return `503 Service Unavailable` with the reason otherwise.
+pub fn health_router(health: Health) -> Router {
+    Router::new()
+        .route("/healthz", get(check_alive))
+        .route("/readyz", get(check_ready))
+        .with_state(health)
+}
+
+/// Serves the HTTP API and the health checks on `address` until `shutdown` completes.
+pub async fn serve<F>(
+    address: SocketAddr,
+    transactions: Sender<Transaction>,
+    queries: QueryHandle,
+    rate_limiter: RateLimiter,
+    health: Health,
+    shutdown: F,
+) -> Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let listener = TcpListener::bind(address).await?;
+    let app = router(transactions, queries, rate_limiter).merge(health_router(health));
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown)
+    .await?;
+    Ok(())
+}
+
+async fn submit_transaction(
+    State(state): State<AppState>,
+    extensions: Extensions,
+    Json(transaction): Json<Transaction>,
+) -> Result<Json<TransactionOutcome>, Response> {
+    // The peer is only known when served with its connect info
+    let peer = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| *peer);
+    state.rate_limiter.check(peer).map_err(too_many_requests)?;
+    transaction
+        .validate(state.queries.precision())
+        .map_err(|error| (StatusCode::UNPROCESSABLE_ENTITY, error.to_string()).into_response())?;
+    match state.queries.submit(&state.transactions, transaction).await {
+        Some(Ok(outcome)) => Ok(Json(outcome)),
+        Some(Err(reason)) => Err((StatusCode::UNPROCESSABLE_ENTITY, reason).into_response()),
+        None => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Engine is not processing transactions",
+        )
+            .into_response()),
+    }
+}
+
+async fn check_alive(State(health): State<Health>) -> Response {
+    probe(health.check_alive())
+}
+
+async fn check_ready(State(health): State<Health>) -> Response {
+    probe(health.check_ready())
+}
+
+fn probe(check: Result<(), String>) -> Response {
+    match check {
+        Ok(()) => (StatusCode::OK, "ok").into_response(),
+        Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, reason).into_response(),
+    }
+}
+
+// `Retry-After` holds whole seconds, so the backoff is rounded up
+fn too_many_requests(backoff: Duration) -> Response {
+    let seconds = backoff.as_secs() + u64::from(backoff.subsec_nanos() > 0);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, seconds.to_string())],
+        format!("Rate limit exceeded, retry in {} ms", backoff.as_millis()),
+    )
+        .into_response()
+}
+
+async fn get_account(
+    State(state): State<AppState>,
+    Path(client): Path<ClientId>,
+) -> Result<Json<AccountView>, StatusCode> {
+    state
+        .queries
+        .account(client)
+        .await
+        .map(|account| Json(state.queries.round(account)))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn stream_accounts(State(state): State<AppState>, upgrade: WebSocketUpgrade) -> Response {
+    // Subscribe before the upgrade, so no update after the request is missed
+    let updates = state.queries.account_updates();
+    upgrade.on_upgrade(move |socket| send_account_updates(socket, updates, state.queries))
+}
+
+// Forwards the updates until the client disconnects or the engine stops
+async fn send_account_updates(
+    mut socket: WebSocket,
+    mut updates: Receiver<AccountView>,
+    queries: QueryHandle,
+) {
+    loop {
+        tokio::select! {
+            update = updates.recv() => {
+                let account = match update {
+                    Ok(account) => queries.round(account),
+                    // A slow client only misses the oldest updates
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                let Ok(json) = serde_json::to_string(&account) else {
+                    continue;
+                };
+                if socket.send(Message::Text(json.into())).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{health_router, router};
+    use crate::{
+        error::ErrorPolicy,
+        health::Health,
+        payment_engine::PaymentsEngine,
+        rate_limit::{RateLimiter, RateLimits},
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use tokio_util::sync::CancellationToken;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn submit_and_get_account() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        payments_engine.set_error_policy(ErrorPolicy::Lenient);
+        let app = router(
+            sender,
+            payments_engine.query_handle(),
+            RateLimiter::default(),
+        );
+
+        let client = tokio::spawn(async move {
+            let deposit = Request::post("/transactions")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}"#,
+                ))
+                .unwrap();
+            let response = app.clone().oneshot(deposit).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(body, r#""applied""#);
+
+            let dispute = Request::post("/transactions")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"type": "dispute", "client": 2, "tx": 1}"#))
+                .unwrap();
+            let response = app.clone().oneshot(dispute).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+            let account = Request::get("/accounts/1").body(Body::empty()).unwrap();
+            let response = app.clone().oneshot(account).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(
+                body,
+                r#"{"client":1,"available":"2.5","held":"0.0","total":"2.5","locked":false}"#
+            );
+
+            let missing = Request::get("/accounts/2").body(Body::empty()).unwrap();
+            let response = app.oneshot(missing).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        });
+
+        payments_engine.process_transactions().await.unwrap();
+        client.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rate_limited() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let rate_limiter = RateLimiter::new(RateLimits {
+            global: Some("1".parse().unwrap()),
+            per_connection: None,
+        });
+        let app = router(sender, payments_engine.query_handle(), rate_limiter);
+
+        let client = tokio::spawn(async move {
+            let deposit = |tx| {
+                Request::post("/transactions")
+                    .header("content-type", "application/json")
+                    .body(Body::from(format!(
+                        r#"{{"type": "deposit", "client": 1, "tx": {tx}, "amount": "1.0"}}"#
+                    )))
+                    .unwrap()
+            };
+            let response = app.clone().oneshot(deposit(1)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let response = app.oneshot(deposit(2)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(response.headers()["retry-after"], "1");
+        });
+
+        payments_engine.process_transactions().await.unwrap();
+        client.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn health_checks() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let shutdown = CancellationToken::new();
+        let app = health_router(Health::new(
+            &sender,
+            payments_engine.query_handle(),
+            shutdown.clone(),
+        ));
+        let status = |app: axum::Router, path: &'static str| async move {
+            let request = Request::get(path).body(Body::empty()).unwrap();
+            app.oneshot(request).await.unwrap().status()
+        };
+        assert_eq!(status(app.clone(), "/healthz").await, StatusCode::OK);
+        assert_eq!(status(app.clone(), "/readyz").await, StatusCode::OK);
+
+        // A server shutting down is alive until the engine stops
+        shutdown.cancel();
+        assert_eq!(status(app.clone(), "/healthz").await, StatusCode::OK);
+        assert_eq!(
+            status(app.clone(), "/readyz").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+        assert_eq!(
+            status(app, "/healthz").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use crate::{
+    account::AccountView,
+    amount::Amount,
+    handle::EngineHandle,
+    output::{self, OutputFormat},
+    transaction::{ClientId, Transaction, TransactionId, TransactionType},
+};
+use anyhow::{anyhow, Result};
+use std::{
+    collections::BTreeSet,
+    future::Future,
+    io::{self, BufRead, Write},
+    thread,
+};
+use tokio::sync::mpsc::{channel, Receiver};
+
+const PROMPT: &str = "> ";
+
+const HELP: &str = "\
+deposit <client> <amount> [tx]
+withdraw <client> <amount> [tx]
+transfer <client> <counterparty> <amount> [tx]
+dispute <tx> | resolve <tx> | chargeback <tx> | reverse <tx>
+approve <tx> | reject <tx>
+hold <client> <amount> [tx]
+release <client> <tx> [amount]
+unlock <client>
+account <client>
+dump
+quit
+";
+
+/// Lines read from stdin on a dedicated thread, so a pending read doesn't keep the runtime from
+/// shutting down.
+pub fn stdin_lines() -> Receiver<String> {
+    let (sender, lines) = channel(1);
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    lines
+}
+
+/// Reads commands from `lines` until they end, `quit` is entered or `shutdown` completes, and
+/// applies them through `handle`. Outcomes, rejections and accounts are written to `output`.
+///
+/// Transactions without id get the next one after the largest id known to the engine.
+/// Disputes, resolves, chargebacks, their reversals, approvals and rejections only take the id
+/// of the transaction, the client is looked up in the event log.
+pub async fn run<W: Write + Send, F: Future<Output = ()>>(
+    handle: &EngineHandle,
+    mut lines: Receiver<String>,
+    mut output: W,
+    shutdown: F,
+) -> Result<()> {
+    tokio::pin!(shutdown);
+    let mut next_tx = match handle.query_handle().events().await {
+        Some(events) => events
+            .iter()
+            .filter_map(|event| event.introduced_transaction())
+            .max()
+            .map_or(1, |tx| tx.saturating_add(1)),
+        None => return Err(anyhow!("The engine stopped")),
+    };
+
+    loop {
+        write!(output, "{PROMPT}")?;
+        output.flush()?;
+        let line = tokio::select! {
+            _ = &mut shutdown => break,
+            line = lines.recv() => match line {
+                Some(line) => line,
+                None => break,
+            },
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let transaction = match words.as_slice() {
+            [] => continue,
+            ["quit" | "exit"] => break,
+            ["help"] => {
+                write!(output, "{HELP}")?;
+                continue;
+            }
+            ["account", client] => {
+                match parse_client(client) {
+                    Ok(client) => match handle.account(client).await {
+                        Some(account) => write_accounts(handle, &[account], &mut output)?,
+                        None => writeln!(output, "No such account")?,
+                    },
+                    Err(error) => writeln!(output, "{error}")?,
+                }
+                continue;
+            }
+            ["dump"] => {
+                let Some(events) = handle.query_handle().events().await else {
+                    writeln!(output, "The engine stopped")?;
+                    break;
+                };
+                let clients: BTreeSet<ClientId> =
+                    events.iter().map(|event| event.client()).collect();
+                let mut accounts = Vec::with_capacity(clients.len());
+                for client in clients {
+                    accounts.extend(handle.account(client).await);
+                }
+                write_accounts(handle, &accounts, &mut output)?;
+                continue;
+            }
+            words => parse_transaction(handle, words, next_tx).await,
+        };
+
+        let transaction = match transaction {
+            Ok(transaction) => transaction,
+            Err(error) => {
+                writeln!(output, "{error}")?;
+                continue;
+            }
+        };
+        if transaction.r#type.introduces_transaction()
+            || transaction.r#type == TransactionType::Hold
+        {
+            next_tx = next_tx.max(transaction.tx.saturating_add(1));
+        }
+        match handle.submit(transaction).await {
+            Some(Ok(outcome)) => writeln!(output, "{outcome}")?,
+            Some(Err(reason)) => writeln!(output, "Rejected: {reason}")?,
+            None => {
+                writeln!(output, "The engine stopped")?;
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn parse_transaction(
+    handle: &EngineHandle,
+    words: &[&str],
+    next_tx: TransactionId,
+) -> Result<Transaction> {
+    let tx = |tx: Option<&&str>| match tx {
+        Some(tx) => tx
+            .parse::<TransactionId>()
+            .map_err(|_| anyhow!("Invalid transaction id {}", tx)),
+        None => Ok(next_tx),
+    };
+    let (r#type, client, tx, amount, counterparty) = match words {
+        [command @ ("deposit" | "withdraw"), client, amount, rest @ ..] if rest.len() <= 1 => {
+            let r#type = match *command {
+                "deposit" => TransactionType::Deposit,
+                _ => TransactionType::Withdrawal,
+            };
+            let amount = parse_amount(amount)?;
+            (
+                r#type,
+                parse_client(client)?,
+                tx(rest.first())?,
+                Some(amount),
+                None,
+            )
+        }
+        ["transfer", client, counterparty, amount, rest @ ..] if rest.len() <= 1 => (
+            TransactionType::Transfer,
+            parse_client(client)?,
+            tx(rest.first())?,
+            Some(parse_amount(amount)?),
+            Some(parse_client(counterparty)?),
+        ),
+        [command, disputed]
+            if matches!(
+                *command,
+                "dispute" | "resolve" | "chargeback" | "reverse" | "approve" | "reject"
+            ) =>
+        {
+            let r#type = match *command {
+                "dispute" => TransactionType::Dispute,
+                "resolve" => TransactionType::Resolve,
+                "chargeback" => TransactionType::Chargeback,
+                "reverse" => TransactionType::ChargebackReversal,
+                "approve" => TransactionType::Approve,
+                _ => TransactionType::Reject,
+            };
+            let disputed = tx(Some(disputed))?;
+            let events = handle
+                .query_handle()
+                .events()
+                .await
+                .ok_or_else(|| anyhow!("The engine stopped"))?;
+            let client = events
+                .iter()
+                .find(|event| event.introduced_transaction() == Some(disputed))
+                .map(|event| event.client())
+                .ok_or_else(|| anyhow!("No such transaction"))?;
+            (r#type, client, disputed, None, None)
+        }
+        ["hold", client, amount, rest @ ..] if rest.len() <= 1 => (
+            TransactionType::Hold,
+            parse_client(client)?,
+            tx(rest.first())?,
+            Some(parse_amount(amount)?),
+            None,
+        ),
+        ["release", client, hold, rest @ ..] if rest.len() <= 1 => (
+            TransactionType::Release,
+            parse_client(client)?,
+            tx(Some(hold))?,
+            rest.first()
+                .map(|amount| parse_amount(amount))
+                .transpose()?,
+            None,
+        ),
+        ["unlock", client] => (
+            TransactionType::Unlock,
+            parse_client(client)?,
+            0,
+            None,
+            None,
+        ),
+        _ => {
+            return Err(anyhow!(
+                "Unknown command, enter `help` for the list of commands"
+            ))
+        }
+    };
+
+    let transaction = Transaction {
+        r#type,
+        client,
+        tx,
+        amount,
+        counterparty,
+        timestamp: None,
+        from_ccy: None,
+        to_ccy: None,
+    };
+    transaction.validate(handle.query_handle().precision())?;
+    Ok(transaction)
+}
+
+fn parse_client(client: &str) -> Result<ClientId> {
+    client
+        .parse()
+        .map_err(|_| anyhow!("Invalid client id {}", client))
+}
+
+fn parse_amount(amount: &str) -> Result<Amount> {
+    amount
+        .parse()
+        .map_err(|_| anyhow!("Invalid amount {}", amount))
+}
+
+fn write_accounts<W: Write + Send>(
+    handle: &EngineHandle,
+    accounts: &[AccountView],
+    output: &mut W,
+) -> Result<()> {
+    let queries = handle.query_handle();
+    let accounts: Vec<_> = accounts
+        .iter()
+        .map(|&account| queries.round(account))
+        .collect();
+    output::write(&accounts, queries.precision(), OutputFormat::Csv, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use crate::{handle::EngineHandle, payment_engine::PaymentsEngine};
+    use tokio::sync::mpsc::channel;
+
+    #[tokio::test]
+    async fn session() {
+        let (payments_engine, sender) = PaymentsEngine::builder().strict(false).build();
+        let handle = EngineHandle::spawn(payments_engine, sender);
+        let (commands, lines) = channel(16);
+        for line in [
+            "deposit 1 2.5",
+            "deposit 2 1.0 7",
+            "withdraw 1 5",
+            "transfer 1 2 0.5",
+            "dispute 7",
+            "dispute 99",
+            "deposit x 1",
+            "account 2",
+            "dump",
+            "quit",
+            "deposit 1 1.0",
+        ] {
+            commands.send(line.to_string()).await.unwrap();
+        }
+
+        let mut output = Vec::new();
+        run(&handle, lines, &mut output, std::future::pending())
+            .await
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let replies: Vec<&str> = output
+            .split("> ")
+            .map(str::trim_end)
+            .filter(|reply| !reply.is_empty())
+            .collect();
+        assert_eq!(
+            replies[..7],
+            [
+                "Transaction applied",
+                "Transaction applied",
+                "Insufficient funds",
+                "Transaction applied",
+                "Transaction applied",
+                "No such transaction",
+                "Invalid client id x",
+            ]
+        );
+        assert_eq!(
+            replies[7],
+            "client,available,held,total,locked\n2,0.5,1.0,1.5,false"
+        );
+        assert_eq!(
+            replies[8],
+            "client,available,held,total,locked\n1,2.0,0.0,2.0,false\n2,0.5,1.0,1.5,false"
+        );
+        assert_eq!(replies.len(), 9);
+
+        let payments_engine = handle.shutdown().await.unwrap().unwrap();
+        assert_eq!(payments_engine.accounts().count(), 2);
+    }
+}
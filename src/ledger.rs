@@ -0,0 +1,155 @@
+use crate::{
+    account::Account,
+    amount::{Amount, RoundingMode},
+    event::AccountEvent,
+    transaction::{ClientId, TransactionId},
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Accepted transaction of an account with the balances it resulted in, as exported by
+/// [`crate::PaymentsEngine::export_ledger`].
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct LedgerEntry {
+    pub client: ClientId,
+    pub tx: Option<TransactionId>,
+    pub r#type: &'static str,
+    /// Amount the transaction moved, not known for disputes and their follow-ups
+    pub amount: Option<Amount>,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// Ledger of every account with events in `events`, by client.
+///
+/// Declined deposits and withdrawals didn't change their account and aren't part of the ledger.
+pub fn ledgers(
+    events: &[AccountEvent],
+    precision: u32,
+    rounding_mode: RoundingMode,
+) -> Result<BTreeMap<ClientId, Vec<LedgerEntry>>> {
+    let mut accounts: BTreeMap<ClientId, (Account, Vec<LedgerEntry>)> = BTreeMap::new();
+    for event in events {
+        let (account, ledger) = accounts
+            .entry(event.client())
+            .or_insert_with(|| (Account::new(event.client()), Vec::new()));
+        account.apply(event)?;
+        let Some((r#type, tx, amount)) = describe(event) else {
+            continue;
+        };
+        let view = account.view().round(precision, rounding_mode);
+        ledger.push(LedgerEntry {
+            client: view.client,
+            tx,
+            r#type,
+            amount: amount.map(|amount| amount.round(precision, rounding_mode)),
+            available: view.available,
+            held: view.held,
+            total: view.total,
+            locked: view.locked,
+        });
+    }
+    Ok(accounts
+        .into_iter()
+        .map(|(client, (_, ledger))| (client, ledger))
+        .collect())
+}
+
+/// Writes the ledger of every client to `client-<id>.csv` in `directory`, which is created if
+/// needed.
+pub fn export<P: AsRef<Path>>(
+    events: &[AccountEvent],
+    precision: u32,
+    rounding_mode: RoundingMode,
+    directory: P,
+) -> Result<()> {
+    let directory = directory.as_ref();
+    fs::create_dir_all(directory)?;
+    for (client, ledger) in ledgers(events, precision, rounding_mode)? {
+        let mut writer = csv::Writer::from_path(directory.join(format!("client-{client}.csv")))?;
+        ledger
+            .iter()
+            .try_for_each(|entry| writer.serialize(entry))?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+// Kind, transaction id and amount of an accepted transaction
+fn describe(event: &AccountEvent) -> Option<(&'static str, Option<TransactionId>, Option<Amount>)> {
+    match *event {
+        AccountEvent::Deposited { tx, amount, .. } => Some(("deposit", Some(tx), Some(amount))),
+        AccountEvent::Withdrew { tx, amount, .. } => Some(("withdrawal", Some(tx), Some(amount))),
+        // Declined and staged transactions don't change the balances
+        AccountEvent::DepositDeclined { .. }
+        | AccountEvent::WithdrawalDeclined { .. }
+        | AccountEvent::Staged { .. }
+        | AccountEvent::Unstaged { .. } => None,
+        AccountEvent::DisputeOpened { tx, .. } => Some(("dispute", Some(tx), None)),
+        AccountEvent::DisputeResolved { tx, .. } => Some(("resolve", Some(tx), None)),
+        AccountEvent::ChargedBack { tx, .. } => Some(("chargeback", Some(tx), None)),
+        AccountEvent::ChargebackReversed { tx, .. } => {
+            Some(("chargeback_reversal", Some(tx), None))
+        }
+        AccountEvent::Unlocked { .. } => Some(("unlock", None, None)),
+        AccountEvent::TransferredOut { tx, amount, .. } => {
+            Some(("transfer_out", Some(tx), Some(amount)))
+        }
+        AccountEvent::TransferredIn { tx, amount, .. } => {
+            Some(("transfer_in", Some(tx), Some(amount)))
+        }
+        AccountEvent::TransferReversed { tx, amount, .. } => {
+            Some(("transfer_reversed", Some(tx), Some(amount)))
+        }
+        AccountEvent::Held { tx, amount, .. } => Some(("hold", Some(tx), Some(amount))),
+        AccountEvent::Released { tx, amount, .. } => Some(("release", Some(tx), Some(amount))),
+        AccountEvent::Converted { tx, amount, .. } => Some(("convert", Some(tx), Some(amount))),
+        AccountEvent::Amended { tx, amount, .. } => Some(("amendment", Some(tx), Some(amount))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ledgers;
+    use crate::{amount::RoundingMode, event::AccountEvent};
+
+    #[test]
+    fn balances_after_each_transaction() {
+        let amount = |amount: &str| amount.parse().unwrap();
+        let events = [
+            AccountEvent::Deposited {
+                client: 1,
+                tx: 1,
+                amount: amount("2.0"),
+                fee: amount("0"),
+                timestamp: None,
+            },
+            AccountEvent::WithdrawalDeclined {
+                client: 1,
+                tx: 2,
+                amount: amount("5.0"),
+            },
+            AccountEvent::Deposited {
+                client: 2,
+                tx: 3,
+                amount: amount("1.0"),
+                fee: amount("0"),
+                timestamp: None,
+            },
+            AccountEvent::DisputeOpened { client: 1, tx: 1 },
+        ];
+
+        let ledgers = ledgers(&events, 4, RoundingMode::HalfEven).unwrap();
+        assert_eq!(ledgers[&2].len(), 1);
+        let ledger = &ledgers[&1];
+        assert_eq!(ledger.len(), 2);
+        assert_eq!(ledger[0].available, amount("2.0"));
+        assert_eq!(
+            (ledger[1].r#type, ledger[1].amount, ledger[1].held),
+            ("dispute", None, amount("2.0"))
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Payments engine that processes a stream of transactions and maintains the state of client accounts.
+//!
+//! The engine can be embedded by feeding transactions through the sender returned by
+//! [`PaymentsEngine::new`]:
+//!
+//! ```
+//! # #[cfg(feature = "runtime")]
+//! use rust_exercise::{PaymentsEngine, Transaction, TransactionType};
+//!
+//! # #[cfg(feature = "runtime")]
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let (mut payments_engine, sender) = PaymentsEngine::new();
+//!
+//! sender
+//!     .send(Transaction {
+//!         r#type: TransactionType::Deposit,
+//!         client: 1,
+//!         tx: 1,
+//!         amount: Some("1.5".parse()?),
+//!         counterparty: None,
+//!         timestamp: None,
+//!         from_ccy: None,
+//!         to_ccy: None,
+//!     })
+//!     .await?;
+//! drop(sender);
+//!
+//! payments_engine.process_transactions().await?;
+//! assert_eq!(payments_engine.account(1).unwrap().available, "1.5".parse()?);
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "runtime"))]
+//! # fn main() {}
+//! ```
+//!
+//! The engine and everything else depending on tokio or the file system needs the default feature
+//! `runtime`. Without it, e.g. for `wasm32-unknown-unknown`, a [`Settlement`] applies transactions
+//! to the accounts on the calling thread.
+
+// Conversions widening the ids are no-ops with the feature `wide-ids`
+#![cfg_attr(feature = "wide-ids", allow(clippy::useless_conversion))]
+// Parts of the accounts only the engine uses are left over without the feature `runtime`
+#![cfg_attr(not(feature = "runtime"), allow(dead_code))]
+
+pub mod account;
+pub mod amount;
+pub mod audit;
+#[cfg(feature = "runtime")]
+pub mod builder;
+#[cfg(feature = "runtime")]
+pub mod checkpoint;
+pub mod clock;
+#[cfg(feature = "runtime")]
+pub mod collector;
+#[cfg(feature = "runtime")]
+pub mod dead_letter;
+pub mod dedupe;
+pub mod dispute;
+pub mod dispute_window;
+pub mod error;
+pub mod event;
+mod export;
+pub mod fees;
+pub mod filter;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod fx;
+#[cfg(feature = "runtime")]
+pub mod grpc;
+#[cfg(feature = "runtime")]
+pub mod handle;
+#[cfg(feature = "runtime")]
+pub mod health;
+pub mod history;
+#[cfg(feature = "runtime")]
+pub mod http;
+#[cfg(feature = "runtime")]
+pub mod interactive;
+pub mod ledger;
+pub mod limits;
+pub mod locked;
+pub mod metrics;
+pub mod observer;
+pub mod ordering;
+pub mod outcome;
+pub mod output;
+#[cfg(feature = "runtime")]
+pub mod payment_engine;
+pub mod point_in_time;
+#[cfg(feature = "runtime")]
+pub mod policy;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "runtime")]
+pub mod progress;
+#[cfg(feature = "runtime")]
+pub mod rate_limit;
+pub mod redaction;
+// Only the tests check the engine against it
+#[cfg(all(feature = "runtime", any(test, feature = "fuzzing")))]
+pub mod reference;
+pub mod report;
+pub mod risk;
+pub mod settlement;
+#[cfg(feature = "runtime")]
+pub mod simulation;
+#[cfg(feature = "runtime")]
+mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod store;
+#[cfg(feature = "runtime")]
+pub mod tenant;
+pub mod transaction;
+mod transfer;
+pub mod unknown_client;
+#[cfg(feature = "runtime")]
+pub mod validation;
+#[cfg(feature = "runtime")]
+pub mod workload;
+
+pub use account::{Account, AccountView};
+pub use amount::{Amount, RoundingMode};
+pub use audit::AuditLog;
+#[cfg(feature = "runtime")]
+pub use builder::EngineBuilder;
+#[cfg(feature = "runtime")]
+pub use checkpoint::{Checkpoints, InputOffset};
+pub use clock::{Clock, FixedClock, SimClock, SystemClock};
+#[cfg(feature = "runtime")]
+pub use dead_letter::DeadLetters;
+pub use dedupe::DuplicatePolicy;
+pub use dispute::RedisputePolicy;
+pub use dispute_window::DisputeWindow;
+pub use error::{EngineError, ErrorContext, ErrorPolicy};
+pub use event::AccountEvent;
+pub use fees::FeeSchedule;
+pub use filter::TransactionFilter;
+pub use fx::{Balance, Currency, FxRates};
+#[cfg(feature = "runtime")]
+pub use handle::EngineHandle;
+#[cfg(feature = "runtime")]
+pub use health::Health;
+pub use history::{HistoryRetention, HistorySpill, SettledHistory};
+pub use ledger::LedgerEntry;
+pub use limits::Limits;
+pub use locked::LockedAccountPolicy;
+pub use metrics::ChannelMetrics;
+pub use observer::{EngineObserver, Rejection};
+pub use ordering::OrderingPolicy;
+pub use outcome::{Acknowledgement, TransactionOutcome};
+pub use output::OutputFormat;
+#[cfg(feature = "runtime")]
+pub use payment_engine::{PaymentsEngine, QueryHandle};
+pub use point_in_time::PointInTime;
+#[cfg(feature = "runtime")]
+pub use policy::{LivePolicy, Policy};
+#[cfg(feature = "runtime")]
+pub use progress::{Progress, ProgressSnapshot};
+#[cfg(feature = "runtime")]
+pub use rate_limit::{RateLimit, RateLimiter, RateLimits};
+pub use redaction::Redaction;
+pub use report::{OpenDispute, RunReport, RunSummary};
+pub use risk::{RiskAlert, RiskThresholds};
+pub use settlement::Settlement;
+#[cfg(feature = "runtime")]
+pub use simulation::Simulation;
+#[cfg(feature = "runtime")]
+pub use store::disk::{DiskShard, DiskStore};
+pub use store::{AccountStore, MemoryStore};
+#[cfg(feature = "runtime")]
+pub use tenant::{Tenancy, Tenants};
+pub use transaction::{Batch, ClientId, Transaction, TransactionId, TransactionType};
+pub use unknown_client::UnknownClientPolicy;
+#[cfg(feature = "runtime")]
+pub use validation::{ValidationReport, Validator};
+#[cfg(feature = "runtime")]
+pub use workload::Workload;
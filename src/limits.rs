@@ -0,0 +1,141 @@
+use crate::{amount::Amount, outcome::TransactionOutcome};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Risk limits every account is subject to, without a limit by default.
+///
+/// Withdrawal limits apply to transfers and conversions as well.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// Largest amount of a single deposit
+    pub max_deposit: Option<Amount>,
+    /// Largest amount of a single withdrawal
+    pub max_withdrawal: Option<Amount>,
+    /// Largest sum of the withdrawals of an account per UTC day, of their timestamp if given or
+    /// else of the engine's clock
+    pub max_daily_withdrawal: Option<Amount>,
+    /// Deposits, withdrawals and transfers above this amount are staged until they are approved
+    pub approval_threshold: Option<Amount>,
+}
+
+impl Limits {
+    /// Reads the limits from a TOML file, e.g. `max_withdrawal = "500.0"`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub(crate) fn check_deposit(&self, amount: Amount) -> Option<TransactionOutcome> {
+        self.max_deposit
+            .is_some_and(|max| amount > max)
+            .then_some(TransactionOutcome::DepositLimitExceeded)
+    }
+
+    /// Whether a deposit, withdrawal or transfer of `amount` is staged until it is approved.
+    pub(crate) fn requires_approval(&self, amount: Amount) -> bool {
+        self.approval_threshold
+            .is_some_and(|threshold| amount > threshold)
+    }
+
+    pub(crate) fn check_withdrawal(
+        &self,
+        amount: Amount,
+        withdrawn_today: Amount,
+    ) -> Option<TransactionOutcome> {
+        self.check_raised_withdrawal(amount, amount, withdrawn_today)
+    }
+
+    /// Checks a withdrawal of `amount` that adds `raise` to the withdrawals of today, e.g. an
+    /// earlier withdrawal amended to a larger amount.
+    pub(crate) fn check_raised_withdrawal(
+        &self,
+        amount: Amount,
+        raise: Amount,
+        withdrawn_today: Amount,
+    ) -> Option<TransactionOutcome> {
+        if self.max_withdrawal.is_some_and(|max| amount > max) {
+            Some(TransactionOutcome::WithdrawalLimitExceeded)
+        } else if self
+            .max_daily_withdrawal
+            .is_some_and(|max| withdrawn_today + raise > max)
+        {
+            Some(TransactionOutcome::DailyLimitExceeded)
+        } else {
+            None
+        }
+    }
+}
+
+/// Sum of the withdrawals of an account on one day.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug)]
+pub(crate) struct DailyVolume {
+    day: u64,
+    amount: Amount,
+}
+
+impl DailyVolume {
+    pub fn on(&self, day: u64) -> Amount {
+        if self.day == day {
+            self.amount
+        } else {
+            Amount::ZERO
+        }
+    }
+
+    pub fn add(&mut self, day: u64, amount: Amount) {
+        self.amount = self.on(day) + amount;
+        self.day = day;
+    }
+}
+
+// The volume only limits new withdrawals and isn't part of the events, so accounts rebuilt from
+// the same events are equal regardless of it
+impl PartialEq for DailyVolume {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// Day of a timestamp in seconds since the Unix epoch.
+pub(crate) fn day_of(timestamp: u64) -> u64 {
+    timestamp / SECONDS_PER_DAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DailyVolume, Limits};
+    use crate::outcome::TransactionOutcome;
+
+    #[test]
+    fn parse_limits() {
+        let limits: Limits =
+            toml::from_str("max_deposit = \"100\"\nmax_daily_withdrawal = 50.5\n").unwrap();
+        assert_eq!(limits.max_deposit, Some("100".parse().unwrap()));
+        assert_eq!(limits.max_withdrawal, None);
+        assert_eq!(limits.max_daily_withdrawal, Some("50.5".parse().unwrap()));
+
+        assert!(toml::from_str::<Limits>("max_deposits = 1").is_err());
+    }
+
+    #[test]
+    fn daily_withdrawal_limit() {
+        let limits = Limits {
+            max_daily_withdrawal: Some("10".parse().unwrap()),
+            ..Limits::default()
+        };
+        let mut volume = DailyVolume::default();
+        volume.add(1, "8".parse().unwrap());
+
+        assert_eq!(
+            limits.check_withdrawal("3".parse().unwrap(), volume.on(1)),
+            Some(TransactionOutcome::DailyLimitExceeded)
+        );
+        assert_eq!(
+            limits.check_withdrawal("3".parse().unwrap(), volume.on(2)),
+            None
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use crate::error::EngineError;
+use std::str::FromStr;
+
+/// Number of transactions queued by a locked account, unless configured otherwise
+pub const DEFAULT_LOCKED_QUEUE_CAPACITY: usize = 100;
+
+/// How transactions for a locked account are handled.
+///
+/// Unlocks and chargeback reversals are applied to locked accounts regardless.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum LockedAccountPolicy {
+    /// Skip them with the outcome `account_locked`
+    #[default]
+    Ignore,
+    /// Treat them as invalid transactions
+    Reject,
+    /// Queue up to this many of them in the account and apply them once it is unlocked. Transfers
+    /// and transactions reusing an id aren't queued, they are skipped like further transactions
+    /// once the queue is full.
+    QueueUntilUnlock(usize),
+}
+
+impl FromStr for LockedAccountPolicy {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(LockedAccountPolicy::Ignore),
+            "reject-with-error" => Ok(LockedAccountPolicy::Reject),
+            "queue-until-unlock" => Ok(LockedAccountPolicy::QueueUntilUnlock(
+                DEFAULT_LOCKED_QUEUE_CAPACITY,
+            )),
+            unknown => Err(EngineError::InvalidArgumentValue(
+                "--locked-accounts".into(),
+                unknown.into(),
+            )),
+        }
+    }
+}
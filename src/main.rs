@@ -1,20 +1,568 @@
-mod account;
-mod collector;
-mod error;
-mod payment_engine;
-mod transaction;
+mod cli;
 
-use anyhow::Result;
-use payment_engine::PaymentsEngine;
+use anyhow::{anyhow, Result};
+use cli::{Command, LogFormat, Options};
+#[cfg(feature = "postgres")]
+use rust_exercise::postgres::PostgresSink;
+use rust_exercise::{
+    amount::DEFAULT_PRECISION,
+    collector::{self, BatchSender},
+    grpc, http, interactive, AuditLog, Checkpoints, DeadLetters, DiskStore, EngineBuilder,
+    EngineError, EngineHandle, ErrorPolicy, FeeSchedule, FxRates, Health, HistoryRetention,
+    HistorySpill, Limits, LivePolicy, PaymentsEngine, QueryHandle, RateLimiter, RiskThresholds,
+    Tenants, Transaction, Validator,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, IsTerminal, Write},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+use tracing::Level;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let (mut payments_engine, sender) = PaymentsEngine::new();
+    let started = Instant::now();
+    let options = Options::from_args();
+    init_logging(options.log_level, options.log_format);
+    if let Command::Generate(workload) = &options.command {
+        return match &options.output {
+            Some(path) => workload.write_csv(File::create(path)?),
+            None => workload.write_csv(io::stdout().lock()),
+        };
+    }
+    if let Command::Validate { inputs, format } = &options.command {
+        let mut validator = Validator::new(options.precision.unwrap_or(DEFAULT_PRECISION));
+        collector::validate_files(inputs.clone(), *format, &options.csv_layout, &mut validator)
+            .await?;
+        let report = validator.report();
+        print!("{report}");
+        if !report.is_valid() {
+            return Err(EngineError::InvalidInput(report.invalid).into());
+        }
+        return Ok(());
+    }
+
+    // Mistakes in an interactive session must not stop the engine
+    let error_policy = match options.command {
+        Command::Interactive => ErrorPolicy::Lenient,
+        _ => options.error_policy,
+    };
+    let mut builder = PaymentsEngine::builder()
+        .error_policy(error_policy)
+        .admin_commands(options.admin_commands)
+        .ordering(options.ordering)
+        .redispute_policy(options.redispute)
+        .settled_history(options.settled_history)
+        .duplicate_policy(options.duplicates)
+        .locked_policy(options.locked_accounts)
+        .unknown_client_policy(options.unknown_clients)
+        .rounding_mode(options.rounding)
+        .sort_output(options.sort_output)
+        .output_threads(options.output_threads);
+    if let Some(channel_capacity) = options.channel_capacity {
+        builder = builder.channel_capacity(channel_capacity);
+    }
+    if let Some(precision) = options.precision {
+        builder = builder.precision(precision);
+    }
+    if let Some(directory) = &options.spill_history {
+        builder = builder.history_spill(HistorySpill::open(directory, options.history_capacity)?);
+    }
+    if let Some(directory) = &options.store {
+        let store = DiskStore::open(directory, options.store_cache)?;
+        builder = builder.account_store(move |worker| Box::new(store.shard(worker)));
+    }
+    if let Some(retained) = options.retain_history {
+        builder = builder.history_retention(HistoryRetention::Latest(retained));
+    }
+    if let Some(path) = &options.limits {
+        builder = builder.limits(Limits::load(path)?);
+    }
+    if let Some(path) = &options.fees {
+        builder = builder.fee_schedule(FeeSchedule::load(path)?);
+    }
+    if let Some(path) = &options.fx_rates {
+        builder = builder.fx_rates(FxRates::load(path)?);
+    }
+    // Reloads the policy until the process exits
+    let _policy_watcher = match &options.policy {
+        Some(path) => {
+            let live_policy = LivePolicy::load(path)?;
+            builder = builder.live_policy(live_policy.clone());
+            Some(live_policy.watch(path)?)
+        }
+        None => None,
+    };
+    if let Some(path) = &options.risk_thresholds {
+        builder = builder.risk_thresholds(RiskThresholds::load(path)?);
+    }
+    if let Some(dispute_window) = options.dispute_window {
+        builder = builder.dispute_window(dispute_window);
+    }
+    if let Some(expected_transactions) = options.bloom_filter {
+        builder = builder.bloom_filter(expected_transactions);
+    }
+    if let Some(clients) = options.expect_clients {
+        builder = builder.expected_clients(clients);
+    }
+    let expected_transactions = options
+        .expect_transactions
+        .or_else(|| match &options.command {
+            Command::Process { inputs, .. } => collector::estimate_transactions(inputs),
+            _ => None,
+        });
+    if let Some(transactions) = expected_transactions {
+        builder = builder.expected_transactions(transactions);
+    }
+    if let Some(seconds) = options.flush_interval {
+        builder = builder.flush_interval(Duration::from_secs(seconds));
+        if let Some(path) = &options.snapshot_out {
+            builder = builder.flush_snapshot(path);
+        }
+    }
+    #[cfg(feature = "postgres")]
+    let postgres = match &options.postgres {
+        Some(config) => {
+            let mut sink = PostgresSink::connect(config, options.postgres_batch_size).await?;
+            builder = builder.observer(sink.audit_observer());
+            Some(sink)
+        }
+        None => None,
+    };
+    let dead_letters = match &options.dead_letter {
+        Some(path) => {
+            let dead_letters = DeadLetters::create(path)?;
+            builder = builder.observer(dead_letters.clone());
+            Some(dead_letters)
+        }
+        None => None,
+    };
+    // The simulation advances the clock the engine reads
+    let sim_clock = match &options.command {
+        Command::Simulate(simulation) => Some(simulation.clock()),
+        _ => None,
+    };
+    if let Some(clock) = &sim_clock {
+        builder = builder.clock(clock.clone());
+    }
+    if let Command::Process {
+        tenancy: Some(_), ..
+    } = &options.command
+    {
+        return process_tenants(builder, options, dead_letters).await;
+    }
+    let (mut payments_engine, sender) = builder.build();
+    let audit_log = match &options.audit_log {
+        Some(path) if path.as_os_str() == "-" => Some(AuditLog::stderr()),
+        Some(path) => Some(AuditLog::create(path)?),
+        None => None,
+    };
+    if let Some(audit_log) = audit_log {
+        payments_engine.set_audit_log(audit_log.with_redaction(options.redaction.clone()));
+    }
+    if let Some(path) = &options.resume_from {
+        payments_engine.load_snapshot(path)?;
+    }
+    if let Command::ExportSnapshot { snapshot } = &options.command {
+        payments_engine.load_snapshot(snapshot)?;
+        return match &options.output {
+            Some(path) => payments_engine.export_json(File::create(path)?),
+            None => payments_engine.export_json(io::stdout()),
+        };
+    }
+    if let Command::ImportSnapshot { document } = &options.command {
+        payments_engine.import_json(BufReader::new(File::open(document)?))?;
+    }
+    if let Command::Snapshot { path, at } = &options.command {
+        payments_engine.load_snapshot(path)?;
+        if let Some(at) = at {
+            return match &options.output {
+                Some(path) => payments_engine.write_accounts_at(
+                    *at,
+                    options.output_format,
+                    File::create(path)?,
+                ),
+                None => payments_engine.write_accounts_at(*at, options.output_format, io::stdout()),
+            };
+        }
+    }
+
+    let checkpoints = options.checkpoint.as_ref().map(|path| {
+        Checkpoints::new(
+            path,
+            options.checkpoint_interval,
+            payments_engine.query_handle(),
+        )
+    });
+    let checkpoints = match checkpoints {
+        Some(checkpoints) if options.resume && checkpoints.path().exists() => {
+            let offset = payments_engine.load_checkpoint(checkpoints.path())?;
+            Some(checkpoints.resume_at(offset))
+        }
+        checkpoints => checkpoints,
+    };
+
+    let progress = payments_engine.progress();
+    let progress_reporter = options
+        .progress
+        .then(|| progress.report_every(PROGRESS_INTERVAL));
+
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+
+    if options.command == Command::Interactive {
+        let handle = EngineHandle::spawn(payments_engine, sender);
+        interactive::run(
+            &handle,
+            interactive::stdin_lines(),
+            io::stdout(),
+            shutdown.cancelled(),
+        )
+        .await?;
+        payments_engine = handle
+            .shutdown()
+            .await?
+            .expect("only this handle shuts the engine down");
+    } else {
+        // Stops the collector on a signal, or when the engine fails and stops reading transactions
+        let stop_collector = shutdown.child_token();
+        let collector_thread = match options.command {
+            Command::Process {
+                inputs,
+                format,
+                clients,
+                tenancy: _,
+            } => {
+                let (shutdown, stop_collector) = (shutdown.clone(), stop_collector.clone());
+                let mut batch_sink =
+                    BatchSender::new(payments_engine.batch_sender(), options.batch_size)
+                        .clients(clients);
+                if let Some(dead_letters) = &dead_letters {
+                    batch_sink = batch_sink.dead_letters(dead_letters.clone());
+                }
+                drop(sender);
+                tokio::spawn(async move {
+                    // Stopping the collector drops the senders, the engine then processes the
+                    // transactions still queued and the accounts so far are written as usual
+                    tokio::select! {
+                        result = collector::process_files(
+                            inputs,
+                            format,
+                            options.csv_layout,
+                            batch_sink,
+                            options.error_policy,
+                            progress,
+                            checkpoints,
+                        ) => result,
+                        _ = stop_collector.cancelled() => {
+                            if shutdown.is_cancelled() {
+                                eprintln!("Interrupted, writing the accounts processed so far");
+                            }
+                            Ok(())
+                        }
+                    }
+                })
+            }
+            Command::Watch {
+                directory,
+                format,
+                clients,
+            } => {
+                let mut batch_sink =
+                    BatchSender::new(payments_engine.batch_sender(), options.batch_size)
+                        .clients(clients);
+                if let Some(dead_letters) = &dead_letters {
+                    batch_sink = batch_sink.dead_letters(dead_letters.clone());
+                }
+                drop(sender);
+                let stop_collector = stop_collector.clone();
+                tokio::spawn(async move {
+                    // A file interrupted halfway stays in the directory, the accounts so far are
+                    // written as usual
+                    tokio::select! {
+                        result = collector::watch::watch(
+                            directory,
+                            format,
+                            options.csv_layout,
+                            batch_sink,
+                            options.error_policy,
+                            progress,
+                        ) => result,
+                        _ = stop_collector.cancelled() => Ok(()),
+                    }
+                })
+            }
+            #[cfg(feature = "kafka")]
+            Command::Kafka(source) => tokio::spawn(collector::kafka::consume(
+                source,
+                sender,
+                payments_engine.query_handle(),
+                options.error_policy,
+                progress,
+                stop_collector.clone().cancelled_owned(),
+            )),
+            Command::Tcp {
+                listen,
+                format,
+                rate_limits,
+            } => tokio::spawn(collector::tcp::listen(
+                listen,
+                format,
+                sender,
+                options.error_policy,
+                progress,
+                RateLimiter::new(rate_limits),
+                stop_collector.clone().cancelled_owned(),
+            )),
+            Command::Serve {
+                grpc_listen,
+                listen,
+                rate_limits,
+                max_backlog,
+            } => {
+                let mut health = Health::new(
+                    &sender,
+                    payments_engine.query_handle(),
+                    stop_collector.clone(),
+                );
+                if let Some(max_backlog) = max_backlog {
+                    health = health.max_backlog(max_backlog);
+                }
+                tokio::spawn(serve(
+                    grpc_listen,
+                    listen,
+                    sender,
+                    payments_engine.query_handle(),
+                    RateLimiter::new(rate_limits),
+                    health,
+                    stop_collector.clone(),
+                ))
+            }
+            Command::Simulate(simulation) => {
+                let clock = sim_clock.expect("simulations have a clock");
+                let stop_collector = stop_collector.clone();
+                tokio::spawn(async move {
+                    tokio::select! {
+                        result = simulation.run(clock, sender) => result,
+                        _ = stop_collector.cancelled() => Ok(()),
+                    }
+                })
+            }
+            // Nothing to process, the accounts of the snapshot are written as they are
+            Command::Snapshot { .. } | Command::ImportSnapshot { .. } => tokio::spawn(async move {
+                drop(sender);
+                Ok(())
+            }),
+            Command::ExportSnapshot { .. } => {
+                unreachable!("snapshots are exported without processing")
+            }
+            Command::Validate { .. } => unreachable!("input files are validated without an engine"),
+            Command::Generate(_) => unreachable!("workloads are generated without an engine"),
+            Command::Interactive => unreachable!("interactive sessions share the engine"),
+        };
+
+        let processed = payments_engine.process_transactions().await;
+        if processed.is_err() {
+            // The engine doesn't read the channels anymore, a collector waiting for room in them
+            // would never finish
+            stop_collector.cancel();
+        }
+        let collected = collector_thread.await?;
+        match (processed, collected) {
+            (Err(engine_error), Err(collector_error)) => {
+                return Err(anyhow!(
+                    "{:#}, and the collector failed as well: {:#}",
+                    engine_error,
+                    collector_error
+                ))
+            }
+            (processed, collected) => processed.and(collected)?,
+        }
+    }
+
+    if let Some(progress_reporter) = progress_reporter {
+        progress_reporter.finish();
+    }
+
+    if let Some(dead_letters) = &dead_letters {
+        dead_letters.flush().await?;
+    }
+
+    if options.channel_metrics {
+        eprintln!("Channel metrics: {}", payments_engine.channel_metrics());
+    }
+
+    match &options.report {
+        Some(path) if path.as_os_str() == "-" => eprint!("{}", payments_engine.report()?),
+        Some(path) => write!(File::create(path)?, "{}", payments_engine.report()?)?,
+        None => {}
+    }
+    match &options.summary_json {
+        Some(path) if path.as_os_str() == "-" => {
+            payments_engine.write_summary_json(io::stderr(), started.elapsed())?
+        }
+        Some(path) => payments_engine.write_summary_json(File::create(path)?, started.elapsed())?,
+        None => {}
+    }
+    match &options.disputes_report {
+        Some(path) if path.as_os_str() == "-" => {
+            payments_engine.write_disputes_report(io::stderr())?
+        }
+        Some(path) => payments_engine.write_disputes_report(BufWriter::new(File::create(path)?))?,
+        None => {}
+    }
+
+    if let Some(directory) = &options.export_ledger {
+        payments_engine.export_ledger(directory)?;
+    }
 
-    let collector_thread = tokio::spawn(collector::start_processing_input_data(sender));
+    if let Some(path) = &options.snapshot_out {
+        payments_engine.save_snapshot(path)?;
+    }
+
+    #[cfg(feature = "postgres")]
+    if let Some(sink) = &postgres {
+        sink.flush_audit().await?;
+        payments_engine.write_postgres(sink).await?;
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &options.sqlite_output {
+        return payments_engine.write_sqlite(path, options.sqlite_ledger);
+    }
+    match options.output {
+        Some(path) => payments_engine.write_accounts_as(options.output_format, File::create(path)?),
+        None => payments_engine.write_accounts_as(options.output_format, std::io::stdout()),
+    }
+}
+
+// Processes the input files of every tenant with an engine of its own, one tenant after another,
+// and writes the accounts of all tenants
+async fn process_tenants(
+    builder: EngineBuilder,
+    options: Options,
+    dead_letters: Option<DeadLetters>,
+) -> Result<()> {
+    let Command::Process {
+        inputs,
+        format,
+        clients,
+        tenancy: Some(tenancy),
+    } = options.command
+    else {
+        unreachable!("only input files are processed by tenant");
+    };
+    let mut tenants = Tenants::new(builder);
+    for (tenant, inputs) in tenancy.group(inputs)? {
+        let payments_engine = tenants.engine(&tenant);
+        let mut batch_sink = BatchSender::new(payments_engine.batch_sender(), options.batch_size)
+            .clients(clients.clone());
+        if let Some(dead_letters) = &dead_letters {
+            batch_sink = batch_sink.dead_letters(dead_letters.clone());
+        }
+        let collector = tokio::spawn(collector::process_files(
+            inputs,
+            format,
+            options.csv_layout.clone(),
+            batch_sink,
+            options.error_policy,
+            payments_engine.progress(),
+            None,
+        ));
+        if let Err(error) = payments_engine.process_transactions().await {
+            // The engine doesn't read the channel anymore, the collector would wait for room
+            collector.abort();
+            return Err(error.context(format!("Processing the input of tenant `{tenant}`")));
+        }
+        collector.await??;
+    }
+
+    if let Some(dead_letters) = &dead_letters {
+        dead_letters.flush().await?;
+    }
+    match options.output {
+        Some(path) => tenants.write_accounts_as(options.output_format, File::create(path)?),
+        None => tenants.write_accounts_as(options.output_format, io::stdout()),
+    }
+}
+
+// Logs to stderr, so the accounts can be written to stdout
+fn init_logging(level: Level, format: LogFormat) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(io::stderr);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+// Cancels `shutdown` on SIGINT (Ctrl-C) or, on Unix, SIGTERM
+async fn cancel_on_signal(shutdown: CancellationToken) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    shutdown.cancel();
+    Ok(())
+}
 
-    payments_engine.process_transactions().await?;
-    collector_thread.await??;
+// Runs the gRPC and the optional HTTP server until `shutdown` is cancelled, the global rate limit
+// applies to both together, and both report the same health
+async fn serve(
+    grpc_listen: SocketAddr,
+    listen: Option<SocketAddr>,
+    sender: Sender<Transaction>,
+    queries: QueryHandle,
+    rate_limiter: RateLimiter,
+    health: Health,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let http_server = listen.map(|listen| {
+        tokio::spawn(http::serve(
+            listen,
+            sender.clone(),
+            queries.clone(),
+            rate_limiter.clone(),
+            health.clone(),
+            shutdown.clone().cancelled_owned(),
+        ))
+    });
+    let grpc_server = tokio::spawn(grpc::serve(
+        grpc_listen,
+        sender,
+        queries,
+        rate_limiter,
+        health,
+        shutdown.clone().cancelled_owned(),
+    ));
 
-    payments_engine.print_accounts()
+    // Either server failing, e.g. to bind its address, stops the other one right away
+    let grpc_server = async { grpc_server.await? };
+    let http_server = async {
+        match http_server {
+            Some(http_server) => http_server.await?,
+            None => Ok(()),
+        }
+    };
+    let served = tokio::try_join!(grpc_server, http_server);
+    if served.is_err() {
+        shutdown.cancel();
+    }
+    served.map(drop)
 }
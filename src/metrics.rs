@@ -0,0 +1,37 @@
+use std::fmt;
+
+/// Counters showing whether the channels of the engine limit the throughput.
+///
+/// A high `saturated` count means the engine can't keep up with the input and a bigger channel
+/// won't help, many `worker_stalls` mean the workers are the bottleneck.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct ChannelMetrics {
+    /// Transactions received from the input channels
+    pub received: u64,
+    /// Largest number of transactions, or batches of them, waiting in an input channel
+    pub peak_backlog: usize,
+    /// Transactions received while the input channel was full
+    pub saturated: u64,
+    /// Transactions, or batches of them, that had to wait for a worker because its channel was full
+    pub worker_stalls: u64,
+}
+
+impl ChannelMetrics {
+    pub(crate) fn record_received(&mut self, transactions: usize, backlog: usize, capacity: usize) {
+        self.received += transactions as u64;
+        self.peak_backlog = self.peak_backlog.max(backlog);
+        if backlog >= capacity {
+            self.saturated += transactions as u64;
+        }
+    }
+}
+
+impl fmt::Display for ChannelMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "received: {}, peak backlog: {}, saturated: {}, worker stalls: {}",
+            self.received, self.peak_backlog, self.saturated, self.worker_stalls
+        )
+    }
+}
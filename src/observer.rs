@@ -0,0 +1,123 @@
+use crate::{
+    account::AccountView, error::EngineError, outcome::TransactionOutcome, risk::RiskAlert,
+    transaction::Transaction,
+};
+use std::fmt;
+
+/// Hooks into the lifecycle of the accounts, registered with
+/// [`crate::EngineBuilder::observer`], e.g. for alerting.
+///
+/// The hooks are called by the worker owning the account, so they should return quickly and
+/// hand expensive work off to another task. Only the calls for the same account are ordered,
+/// invalid transactions may be reported before the transactions sent ahead of them are applied.
+/// All hooks do nothing by default.
+pub trait EngineObserver: Send + Sync + fmt::Debug {
+    /// `transaction` was applied, `account` are the balances of its client afterwards.
+    fn on_transaction_applied(&self, _transaction: &Transaction, _account: &AccountView) {}
+
+    /// `transaction` was declined or is invalid.
+    fn on_rejected(&self, _transaction: &Transaction, _rejection: Rejection<'_>) {}
+
+    /// A chargeback locked the account, `account` are its balances afterwards.
+    fn on_account_locked(&self, _account: &AccountView) {}
+
+    /// A client crossed a threshold of [`crate::EngineBuilder::risk_thresholds`].
+    fn on_risk_alert(&self, _alert: &RiskAlert) {}
+}
+
+/// Why a transaction wasn't applied.
+#[derive(Clone, Copy, Debug)]
+pub enum Rejection<'a> {
+    /// The transaction is valid but was declined, e.g. for insufficient funds
+    Declined(TransactionOutcome),
+    /// The transaction is invalid
+    Invalid(&'a EngineError),
+}
+
+impl fmt::Display for Rejection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Declined(outcome) => outcome.fmt(f),
+            Rejection::Invalid(error) => error.fmt(f),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "runtime"))]
+mod tests {
+    use super::{EngineObserver, Rejection};
+    use crate::{
+        account::AccountView,
+        transaction::{Transaction, TransactionType},
+        PaymentsEngine,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default, Debug)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl EngineObserver for Recorder {
+        fn on_transaction_applied(&self, transaction: &Transaction, account: &AccountView) {
+            let line = format!("applied {} {}", transaction.tx, account.available);
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn on_rejected(&self, transaction: &Transaction, rejection: Rejection<'_>) {
+            let line = format!("rejected {}: {rejection}", transaction.tx);
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn on_account_locked(&self, account: &AccountView) {
+            let line = format!("locked {}", account.client);
+            self.0.lock().unwrap().push(line);
+        }
+    }
+
+    #[tokio::test]
+    async fn lifecycle_hooks() {
+        let recorder = Recorder::default();
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .strict(false)
+            .observer(recorder.clone())
+            .build();
+        let transaction = |r#type, tx, amount: Option<&str>| Transaction {
+            r#type,
+            client: 1,
+            tx,
+            amount: amount.map(|amount| amount.parse().unwrap()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        for transaction in [
+            transaction(TransactionType::Deposit, 1, Some("2.0")),
+            transaction(TransactionType::Withdrawal, 2, Some("5.0")),
+            transaction(TransactionType::Deposit, 3, Some("-1.0")),
+            transaction(TransactionType::Dispute, 1, None),
+            transaction(TransactionType::Chargeback, 1, None),
+        ] {
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        // Invalid transactions are rejected before they reach the worker of the account
+        let mut calls = recorder.0.lock().unwrap().clone();
+        let invalid = calls.iter().position(|call| call.starts_with("rejected 3"));
+        assert_eq!(
+            calls.remove(invalid.unwrap()),
+            "rejected 3: Amount of transaction `3` must be positive"
+        );
+        assert_eq!(
+            calls,
+            [
+                "applied 1 2.0",
+                "rejected 2: Insufficient funds",
+                "applied 1 0.0",
+                "locked 1",
+                "applied 1 0.0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use crate::{
+    error::EngineError,
+    transaction::{ClientId, Transaction},
+};
+use rustc_hash::FxHashMap;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    str::FromStr,
+};
+
+/// Number of transactions buffered to restore their order, unless configured otherwise
+pub const DEFAULT_REORDER_WINDOW: usize = 1024;
+
+/// How transactions with a timestamp older than a previous transaction of the same client are
+/// handled.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum OrderingPolicy {
+    /// Treat them as invalid transactions
+    Reject,
+    /// Report them on stderr and process them anyway
+    #[default]
+    Warn,
+    /// Buffer this many transactions and process them in the order of their timestamps, those
+    /// arriving even later are treated as invalid
+    Reorder(usize),
+}
+
+impl FromStr for OrderingPolicy {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(OrderingPolicy::Reject),
+            "warn" => Ok(OrderingPolicy::Warn),
+            "reorder" => Ok(OrderingPolicy::Reorder(DEFAULT_REORDER_WINDOW)),
+            unknown => Err(EngineError::InvalidArgumentValue(
+                "--out-of-order".into(),
+                unknown.into(),
+            )),
+        }
+    }
+}
+
+/// Detects, and optionally restores, the chronological order of the transactions of each client.
+#[derive(Debug)]
+pub(crate) struct OrderingGuard {
+    policy: OrderingPolicy,
+    // Latest timestamp of every client
+    latest: FxHashMap<ClientId, u64>,
+    buffer: BinaryHeap<Reverse<Buffered>>,
+    received: u64,
+}
+
+// Orders by timestamp, and transactions with the same timestamp in the order they arrived
+#[derive(Debug)]
+struct Buffered {
+    timestamp: u64,
+    sequence: u64,
+    transaction: Transaction,
+    line: Option<u64>,
+}
+
+impl OrderingGuard {
+    pub fn new(policy: OrderingPolicy) -> Self {
+        OrderingGuard {
+            policy,
+            latest: FxHashMap::default(),
+            buffer: BinaryHeap::new(),
+            received: 0,
+        }
+    }
+
+    /// Takes a received transaction, read from `line` of the input if known, and returns the
+    /// transactions ready to be processed with their lines.
+    pub fn push(
+        &mut self,
+        transaction: Transaction,
+        line: Option<u64>,
+    ) -> Vec<(Transaction, Option<u64>)> {
+        let OrderingPolicy::Reorder(window) = self.policy else {
+            return vec![(transaction, line)];
+        };
+        // Transactions without timestamp can't be ordered, but stay behind the ones before them
+        let Some(timestamp) = transaction.timestamp else {
+            let mut ready = self.flush();
+            ready.push((transaction, line));
+            return ready;
+        };
+
+        self.buffer.push(Reverse(Buffered {
+            timestamp,
+            sequence: self.received,
+            transaction,
+            line,
+        }));
+        self.received += 1;
+        let mut ready = Vec::new();
+        while self.buffer.len() > window {
+            ready.extend(
+                self.buffer
+                    .pop()
+                    .map(|Reverse(buffered)| (buffered.transaction, buffered.line)),
+            );
+        }
+        ready
+    }
+
+    /// Returns all buffered transactions in order.
+    pub fn flush(&mut self) -> Vec<(Transaction, Option<u64>)> {
+        let mut ready = Vec::with_capacity(self.buffer.len());
+        while let Some(Reverse(buffered)) = self.buffer.pop() {
+            ready.push((buffered.transaction, buffered.line));
+        }
+        ready
+    }
+
+    /// Checks that `transaction` isn't older than the previous transactions of its client.
+    pub fn check(&mut self, transaction: &Transaction) -> Result<(), EngineError> {
+        let Some(timestamp) = transaction.timestamp else {
+            return Ok(());
+        };
+        let latest = self.latest.entry(transaction.client).or_insert(timestamp);
+        if timestamp >= *latest {
+            *latest = timestamp;
+            return Ok(());
+        }
+
+        let error = EngineError::OutOfOrder(transaction.tx, transaction.client);
+        match self.policy {
+            OrderingPolicy::Warn => {
+                tracing::warn!(%error, "Transaction out of order");
+                Ok(())
+            }
+            OrderingPolicy::Reject | OrderingPolicy::Reorder(_) => Err(error),
+        }
+    }
+}
+
+impl PartialEq for Buffered {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Buffered {}
+
+impl PartialOrd for Buffered {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Buffered {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.timestamp, self.sequence).cmp(&(other.timestamp, other.sequence))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OrderingGuard, OrderingPolicy};
+    use crate::{
+        error::EngineError,
+        transaction::{Transaction, TransactionId, TransactionType},
+    };
+
+    #[test]
+    fn reorder_within_window() {
+        let mut guard = OrderingGuard::new(OrderingPolicy::Reorder(2));
+        let mut ready = Vec::new();
+        for (tx, timestamp) in [(1, 10), (2, 30), (3, 20), (4, 40), (5, 5)] {
+            ready.extend(guard.push(deposit(tx, Some(timestamp)), Some(tx.into())));
+        }
+        ready.extend(guard.flush());
+
+        let order: Vec<_> = ready
+            .iter()
+            .map(|(transaction, _)| transaction.tx)
+            .collect();
+        assert_eq!(order, [1, 3, 5, 2, 4]);
+        let lines: Vec<_> = ready.iter().map(|&(_, line)| line.unwrap()).collect();
+        assert_eq!(lines, [1, 3, 5, 2, 4]);
+        let rejected: Vec<_> = ready
+            .iter()
+            .filter(|(transaction, _)| guard.check(transaction).is_err())
+            .map(|(transaction, _)| transaction.tx)
+            .collect();
+        assert_eq!(rejected, [5]);
+    }
+
+    #[test]
+    fn reject_out_of_order() {
+        let mut guard = OrderingGuard::new(OrderingPolicy::Reject);
+        assert!(guard.check(&deposit(1, Some(20))).is_ok());
+        assert!(guard.check(&deposit(2, None)).is_ok());
+        assert!(matches!(
+            guard.check(&deposit(3, Some(10))),
+            Err(EngineError::OutOfOrder(3, 1))
+        ));
+
+        let mut guard = OrderingGuard::new(OrderingPolicy::Warn);
+        assert!(guard.check(&deposit(1, Some(20))).is_ok());
+        assert!(guard.check(&deposit(3, Some(10))).is_ok());
+    }
+
+    fn deposit(tx: TransactionId, timestamp: Option<u64>) -> Transaction {
+        Transaction {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx,
+            amount: Some("1.0".parse().unwrap()),
+            counterparty: None,
+            timestamp,
+            from_ccy: None,
+            to_ccy: None,
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::transaction::Transaction;
+use serde::Serialize;
+use std::fmt;
+
+/// Result of applying a valid transaction to an account.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionOutcome {
+    Applied,
+    /// The account is locked and ignores all further transactions
+    AccountLocked,
+    /// The account is locked and applies the transaction once it is unlocked
+    Queued,
+    /// The withdrawal or transfer exceeds the available funds and didn't happen
+    InsufficientFunds,
+    /// The account receiving a transfer is locked, so the transfer didn't happen
+    CounterpartyLocked,
+    /// The deposit exceeds the largest allowed deposit and didn't happen
+    DepositLimitExceeded,
+    /// The withdrawal or transfer exceeds the largest allowed withdrawal and didn't happen
+    WithdrawalLimitExceeded,
+    /// The withdrawal or transfer exceeds the daily withdrawal limit and didn't happen
+    DailyLimitExceeded,
+    /// The disputed transaction is older than the dispute window, so the dispute wasn't opened
+    OutsideDisputeWindow,
+    /// The dispute, resolve or chargeback refers to a transaction the account doesn't know
+    NoSuchTransaction,
+    /// The resolve or chargeback refers to a transaction that isn't disputed
+    NotUnderDispute,
+    /// The dispute refers to a transaction that is disputed already
+    AlreadyDisputed,
+    /// The dispute, resolve or chargeback refers to a transaction that was charged back, or the
+    /// dispute to one that was resolved if disputes can't be reopened
+    DisputeClosed,
+    /// The transaction was processed before and is skipped
+    Duplicate,
+    /// The chargeback reversal refers to a transaction that wasn't charged back
+    NotChargedBack,
+    /// The release exceeds the amount still held by its hold and didn't happen
+    HoldExceeded,
+    /// A filter of the engine dropped the transaction or passed on others in its place
+    Filtered,
+    /// The transaction exceeds the approval threshold and waits for its approval
+    PendingApproval,
+    /// The approval or rejection refers to a transaction that isn't staged
+    NotPending,
+}
+
+impl TransactionOutcome {
+    pub fn is_applied(self) -> bool {
+        self == TransactionOutcome::Applied
+    }
+}
+
+impl fmt::Display for TransactionOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionOutcome::Applied => f.write_str("Transaction applied"),
+            TransactionOutcome::AccountLocked => f.write_str("Account is locked"),
+            TransactionOutcome::Queued => f.write_str("Queued until the account is unlocked"),
+            TransactionOutcome::InsufficientFunds => f.write_str("Insufficient funds"),
+            TransactionOutcome::CounterpartyLocked => f.write_str("Receiving account is locked"),
+            TransactionOutcome::DepositLimitExceeded => f.write_str("Deposit limit exceeded"),
+            TransactionOutcome::WithdrawalLimitExceeded => f.write_str("Withdrawal limit exceeded"),
+            TransactionOutcome::DailyLimitExceeded => {
+                f.write_str("Daily withdrawal limit exceeded")
+            }
+            TransactionOutcome::OutsideDisputeWindow => {
+                f.write_str("Transaction is too old to be disputed")
+            }
+            TransactionOutcome::NoSuchTransaction => f.write_str("No such transaction"),
+            TransactionOutcome::NotUnderDispute => f.write_str("Transaction is not disputed"),
+            TransactionOutcome::AlreadyDisputed => f.write_str("Transaction is disputed already"),
+            TransactionOutcome::DisputeClosed => f.write_str("Transaction can't be disputed again"),
+            TransactionOutcome::Duplicate => f.write_str("Transaction was processed already"),
+            TransactionOutcome::NotChargedBack => f.write_str("Transaction is not charged back"),
+            TransactionOutcome::HoldExceeded => f.write_str("Release exceeds the held amount"),
+            TransactionOutcome::Filtered => f.write_str("Dropped or replaced by a filter"),
+            TransactionOutcome::PendingApproval => f.write_str("Pending until approved"),
+            TransactionOutcome::NotPending => f.write_str("Transaction is not pending"),
+        }
+    }
+}
+
+/// Outcome of a processed transaction, as published to [`crate::QueryHandle::outcomes`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct Acknowledgement {
+    pub transaction: Transaction,
+    /// Outcome of the transaction, or the reason it was rejected
+    pub outcome: Result<TransactionOutcome, String>,
+}
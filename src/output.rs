@@ -0,0 +1,301 @@
+use crate::{account::AccountView, amount::Amount, error::EngineError, transaction::ClientId};
+use anyhow::Result;
+use serde::Serialize;
+use std::{io::Write, str::FromStr, thread};
+
+/// Format the final state of the accounts is written in.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// One JSON object per account and line
+    JsonLines,
+    /// Parquet file with decimal columns of the configured precision
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl FromStr for OutputFormat {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" | "jsonl" | "ndjson" => Ok(OutputFormat::JsonLines),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(OutputFormat::Parquet),
+            unknown => Err(EngineError::InvalidOutputFormat(unknown.into())),
+        }
+    }
+}
+
+/// Writes `accounts`, whose amounts are rounded to `precision` decimal places.
+// Only Parquet declares the precision in its schema
+#[cfg_attr(not(feature = "parquet"), allow(unused_variables))]
+pub(crate) fn write<W: Write + Send>(
+    accounts: &[AccountView],
+    precision: u32,
+    format: OutputFormat,
+    writer: W,
+) -> Result<()> {
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            accounts
+                .iter()
+                .try_for_each(|account| writer.serialize(account))?;
+            writer.flush()?;
+        }
+        OutputFormat::JsonLines => {
+            let mut writer = std::io::BufWriter::new(writer);
+            for account in accounts {
+                serde_json::to_writer(&mut writer, account)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => parquet::write(accounts, None, precision, writer)?,
+    }
+    Ok(())
+}
+
+// Balances of an account of one of several tenants, as written to the output
+#[derive(Serialize)]
+struct TenantAccount<'a> {
+    tenant: &'a str,
+    client: ClientId,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fees_collected: Option<Amount>,
+}
+
+impl<'a> From<&'a (&'a str, AccountView)> for TenantAccount<'a> {
+    fn from((tenant, account): &'a (&'a str, AccountView)) -> Self {
+        TenantAccount {
+            tenant,
+            client: account.client,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+            fees_collected: account.fees_collected,
+        }
+    }
+}
+
+/// Writes the `accounts` of several tenants like [`write`], with the tenant of each account in
+/// the first column.
+#[cfg_attr(not(feature = "parquet"), allow(unused_variables))]
+pub(crate) fn write_tenants<W: Write + Send>(
+    accounts: &[(&str, AccountView)],
+    precision: u32,
+    format: OutputFormat,
+    writer: W,
+) -> Result<()> {
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            accounts
+                .iter()
+                .try_for_each(|account| writer.serialize(TenantAccount::from(account)))?;
+            writer.flush()?;
+        }
+        OutputFormat::JsonLines => {
+            let mut writer = std::io::BufWriter::new(writer);
+            for account in accounts {
+                serde_json::to_writer(&mut writer, &TenantAccount::from(account))?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => {
+            let (tenants, accounts): (Vec<&str>, Vec<AccountView>) =
+                accounts.iter().copied().unzip();
+            parquet::write(&accounts, Some(&tenants), precision, writer)?
+        }
+    }
+    Ok(())
+}
+
+/// Writes `accounts` like [`write`], serializing `threads` consecutive parts of them at the same
+/// time. Each part is written as soon as the parts before it are, so the order is kept.
+///
+/// A Parquet file has a single writer, it is written by [`write`].
+pub(crate) fn write_parallel<W: Write + Send>(
+    accounts: &[AccountView],
+    precision: u32,
+    format: OutputFormat,
+    threads: usize,
+    mut writer: W,
+) -> Result<()> {
+    let sequential = threads <= 1 || accounts.len() <= 1;
+    #[cfg(feature = "parquet")]
+    let sequential = sequential || format == OutputFormat::Parquet;
+    if sequential {
+        return write(accounts, precision, format, writer);
+    }
+    let part_size = accounts.len().div_ceil(threads);
+    thread::scope(|scope| {
+        let parts: Vec<_> = accounts
+            .chunks(part_size)
+            .enumerate()
+            .map(|(index, part)| scope.spawn(move || serialize(part, format, index == 0)))
+            .collect();
+        for part in parts {
+            writer.write_all(&part.join().expect("serializing doesn't panic")?)?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+// Serializes a part of the output, only the first part of a CSV file has the header
+fn serialize(accounts: &[AccountView], format: OutputFormat, first: bool) -> Result<Vec<u8>> {
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(first)
+                .from_writer(Vec::new());
+            accounts
+                .iter()
+                .try_for_each(|account| writer.serialize(account))?;
+            Ok(writer.into_inner()?)
+        }
+        _ => {
+            let mut output = Vec::new();
+            write(accounts, 0, format, &mut output)?;
+            Ok(output)
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet {
+    use crate::account::AccountView;
+    use anyhow::Result;
+    use arrow_array::{
+        types::ArrowPrimitiveType, ArrayRef, BooleanArray, Decimal128Array, PrimitiveArray,
+        RecordBatch, StringArray,
+    };
+    use arrow_schema::{DataType, Field, Schema};
+    use parquet::arrow::ArrowWriter;
+    use std::{io::Write, sync::Arc};
+
+    // Largest number of digits of a 128 bit decimal
+    const DECIMAL_PRECISION: u8 = 38;
+
+    #[cfg(not(feature = "wide-ids"))]
+    type ClientIdType = arrow_array::types::UInt16Type;
+    #[cfg(feature = "wide-ids")]
+    type ClientIdType = arrow_array::types::UInt32Type;
+
+    // Writes the tenant of each account in the first column, if there are several
+    pub fn write<W: Write + Send>(
+        accounts: &[AccountView],
+        tenants: Option<&[&str]>,
+        precision: u32,
+        writer: W,
+    ) -> Result<()> {
+        let scale = precision as i8;
+        let decimal = DataType::Decimal128(DECIMAL_PRECISION, scale);
+        let mut fields = vec![
+            Field::new("client", ClientIdType::DATA_TYPE, false),
+            Field::new("available", decimal.clone(), false),
+            Field::new("held", decimal.clone(), false),
+            Field::new("total", decimal.clone(), false),
+            Field::new("locked", DataType::Boolean, false),
+        ];
+        // Only engines charging fees report them
+        let fees = accounts
+            .iter()
+            .any(|account| account.fees_collected.is_some());
+        if fees {
+            fields.push(Field::new("fees_collected", decimal, false));
+        }
+        if tenants.is_some() {
+            fields.insert(0, Field::new("tenant", DataType::Utf8, false));
+        }
+        let schema = Arc::new(Schema::new(fields));
+
+        let amounts = |amount: fn(&AccountView) -> crate::Amount| -> Result<ArrayRef> {
+            let values = accounts
+                .iter()
+                .map(|account| amount(account).to_scaled_integer(precision));
+            Ok(Arc::new(
+                Decimal128Array::from_iter_values(values)
+                    .with_precision_and_scale(DECIMAL_PRECISION, scale)?,
+            ))
+        };
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(PrimitiveArray::<ClientIdType>::from_iter_values(
+                accounts.iter().map(|account| account.client),
+            )),
+            amounts(|account| account.available)?,
+            amounts(|account| account.held)?,
+            amounts(|account| account.total)?,
+            Arc::new(BooleanArray::from_iter(
+                accounts.iter().map(|account| Some(account.locked)),
+            )),
+        ];
+        if fees {
+            columns.push(amounts(|account| {
+                account.fees_collected.unwrap_or_default()
+            })?);
+        }
+        if let Some(tenants) = tenants {
+            columns.insert(0, Arc::new(StringArray::from(tenants.to_vec())));
+        }
+
+        let batch = RecordBatch::try_new(schema.clone(), columns)?;
+        let mut writer = ArrowWriter::try_new(writer, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write, write_parallel, OutputFormat};
+    use crate::account::Account;
+
+    #[test]
+    fn json_lines() {
+        let accounts = [Account::new(1).view(), Account::new(2).view()];
+        let mut output = Vec::new();
+        write(&accounts, 4, OutputFormat::JsonLines, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap().lines().next(),
+            Some(r#"{"client":1,"available":"0","held":"0","total":"0","locked":false}"#)
+        );
+    }
+
+    #[test]
+    fn parallel_output_keeps_the_order() {
+        let accounts: Vec<_> = (1..=10).map(|client| Account::new(client).view()).collect();
+        for format in [OutputFormat::Csv, OutputFormat::JsonLines] {
+            let mut sequential = Vec::new();
+            write(&accounts, 4, format, &mut sequential).unwrap();
+            for threads in [1, 3, 4, 16] {
+                let mut parallel = Vec::new();
+                write_parallel(&accounts, 4, format, threads, &mut parallel).unwrap();
+                assert_eq!(parallel, sequential, "{:?} on {} threads", format, threads);
+            }
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet() {
+        let mut account = Account::new(1).view();
+        account.available = "1.23456".parse().unwrap();
+        let mut output = Vec::new();
+        write(&[account], 4, OutputFormat::Parquet, &mut output).unwrap();
+        assert_eq!(&output[..4], b"PAR1");
+    }
+}
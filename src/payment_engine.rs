@@ -1,44 +1,2768 @@
-use crate::{account::Account, transaction::Transaction};
-use anyhow::{Error, Result};
-use std::collections::HashMap;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use crate::{
+    account::{Account, AccountRules, AccountView},
+    amount::RoundingMode,
+    audit::AuditLog,
+    builder::EngineBuilder,
+    checkpoint::InputOffset,
+    dedupe::{Reuse, TransactionIds},
+    error::{EngineError, ErrorContext, ErrorPolicy},
+    event::AccountEvent,
+    export::{Export, ExportedAccount},
+    filter::TransactionFilter,
+    history::{HistoryRetention, HistorySpill, SettledHistory},
+    ledger,
+    metrics::ChannelMetrics,
+    observer::{EngineObserver, Rejection},
+    ordering::OrderingGuard,
+    outcome::{Acknowledgement, TransactionOutcome},
+    output::{self, OutputFormat},
+    point_in_time::PointInTime,
+    policy::LivePolicy,
+    progress::Progress,
+    report::{OpenDispute, RunReport, RunSummary, Tally},
+    risk::RiskMonitor,
+    snapshot::Snapshot,
+    store::AccountStore,
+    transaction::{Batch, ClientId, Transaction, TransactionId, TransactionType},
+    transfer::Transfers,
+    unknown_client::UnknownClientPolicy,
+};
+use anyhow::Result;
+use rustc_hash::FxHashMap;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc::{channel, error::TrySendError, Receiver, Sender},
+        oneshot,
+    },
+    task::JoinHandle,
+    time::{self, Instant, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug_span, info_span, Instrument};
+
+// Number of outcomes or account updates a subscriber can fall behind before missing some
+const OUTCOME_CAPACITY: usize = 1024;
+
+type Shard = Box<dyn AccountStore>;
+
+// Accounts of a worker, the events it appended to them, and the error it stopped with
+type WorkerResult = (Shard, Vec<AccountEvent>, Result<(), EngineError>);
+
+/// Cloneable handle to read accounts while the engine is processing transactions.
+#[derive(Clone)]
+pub struct QueryHandle {
+    queries: Sender<Query>,
+    outcomes: broadcast::Sender<Acknowledgement>,
+    account_updates: broadcast::Sender<AccountView>,
+    workers: usize,
+    precision: u32,
+    rounding_mode: RoundingMode,
+}
+
+enum Query {
+    Account(AccountQuery),
+    // Acknowledged by every worker once it processed the transactions dispatched before
+    Barrier(Sender<()>),
+    // Replied with the events known before the workers started, and by every worker with its own
+    Events(Sender<Vec<AccountEvent>>),
+}
+
+struct AccountQuery {
+    client: ClientId,
+    reply: oneshot::Sender<Option<AccountView>>,
+}
+
+// Queries are sent through the same channel as the transactions of a shard, so they observe all
+// transactions dispatched before them.
+enum ShardMessage {
+    // Transactions changing a single account, in order, with the lines they were read from
+    Transactions(Vec<(Transaction, Reuse, Option<u64>)>),
+    // First half of a transaction changing two accounts, replied with the event it caused
+    Transfer(
+        Transaction,
+        Reuse,
+        Option<u64>,
+        oneshot::Sender<Option<AccountEvent>>,
+    ),
+    // Second half of such a transaction, for the account of the counterparty
+    Counterpart(AccountEvent),
+    Query(AccountQuery),
+    Barrier(Sender<()>),
+    Events(Sender<Vec<AccountEvent>>),
+}
+
+impl QueryHandle {
+    /// Returns the balances of the account of `client`, or `None` if it doesn't exist or the
+    /// engine isn't processing transactions anymore.
+    ///
+    /// The view reflects all transactions dispatched to the engine before the query.
+    pub async fn account(&self, client: ClientId) -> Option<AccountView> {
+        let (reply, account) = oneshot::channel();
+        self.queries
+            .send(Query::Account(AccountQuery { client, reply }))
+            .await
+            .ok()?;
+        account.await.ok().flatten()
+    }
+
+    /// Waits until all transactions sent to the engine before have been processed.
+    ///
+    /// Returns `false` if the engine stopped before, e.g. because of an invalid transaction.
+    pub async fn sync(&self) -> bool {
+        let (ack, mut acks) = channel(self.workers);
+        if self.queries.send(Query::Barrier(ack)).await.is_err() {
+            return false;
+        }
+        for _ in 0..self.workers {
+            if acks.recv().await.is_none() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Returns the event log of all transactions dispatched to the engine before, or `None` if it
+    /// stopped.
+    pub async fn events(&self) -> Option<Vec<AccountEvent>> {
+        let (reply, mut logs) = channel(self.workers + 1);
+        self.queries.send(Query::Events(reply)).await.ok()?;
+        let mut events = Vec::new();
+        for _ in 0..=self.workers {
+            events.extend(logs.recv().await?);
+        }
+        Some(events)
+    }
+
+    /// Subscribes to the outcomes of all transactions processed from now on.
+    ///
+    /// A subscriber that falls behind by more than 1024 outcomes misses the oldest ones.
+    pub fn outcomes(&self) -> broadcast::Receiver<Acknowledgement> {
+        self.outcomes.subscribe()
+    }
+
+    /// Subscribes to the balances of every account changed by a transaction from now on.
+    ///
+    /// A subscriber that falls behind by more than 1024 updates misses the oldest ones.
+    pub fn account_updates(&self) -> broadcast::Receiver<AccountView> {
+        self.account_updates.subscribe()
+    }
+
+    /// Sends `transaction` through `transactions` and waits for its outcome, or the reason it
+    /// was rejected.
+    ///
+    /// Returns `None` if the engine isn't processing transactions or the outcome was missed.
+    pub async fn submit(
+        &self,
+        transactions: &Sender<Transaction>,
+        transaction: Transaction,
+    ) -> Option<Result<TransactionOutcome, String>> {
+        let mut outcomes = self.outcomes();
+        transactions.send(transaction).await.ok()?;
+        loop {
+            let acknowledgement = outcomes.recv().await.ok()?;
+            if acknowledgement.transaction == transaction {
+                return Some(acknowledgement.outcome);
+            }
+        }
+    }
+
+    /// Whether the engine still answers queries, which it stops doing once it finished processing
+    /// transactions or failed.
+    pub fn is_running(&self) -> bool {
+        !self.queries.is_closed()
+    }
+
+    /// Number of decimal places amounts are validated and reported with by the engine.
+    pub fn precision(&self) -> u32 {
+        self.precision
+    }
+
+    /// How the engine rounds amounts to its precision.
+    pub fn rounding_mode(&self) -> RoundingMode {
+        self.rounding_mode
+    }
+
+    /// Rounds the balances of `account` the way the engine reports them.
+    pub fn round(&self, account: AccountView) -> AccountView {
+        account.round(self.precision, self.rounding_mode)
+    }
+}
 
 pub struct PaymentsEngine {
-    accounts: HashMap<u16, Account>,
+    // Store of every worker, taken by the worker while processing transactions
+    stores: Vec<Shard>,
     transactions: Receiver<Transaction>,
+    batches: Receiver<Batch>,
+    // Handed out by `batch_sender`, dropped once processing starts so the channel can end
+    batch_sink: Option<Sender<Batch>>,
+    queries: Receiver<Query>,
+    query_sink: Sender<Query>,
+    // Client of every deposit, withdrawal and transfer
+    transaction_ids: TransactionIds,
+    // Counterparty and amount of every transfer
+    transfers: Transfers,
+    events: Vec<AccountEvent>,
+    workers: usize,
+    channel_capacity: usize,
+    channel_metrics: ChannelMetrics,
+    admin_commands: bool,
+    precision: u32,
+    rounding_mode: RoundingMode,
+    sort_output: bool,
+    output_threads: usize,
+    // Period of the flushes while processing, and the snapshot they write
+    flush_interval: Option<Duration>,
+    flush_snapshot: Option<PathBuf>,
+    account_settings: AccountSettings,
+    ordering: OrderingGuard,
+    error_policy: ErrorPolicy,
+    filters: Vec<Arc<dyn TransactionFilter>>,
+    observers: Observers,
+    // Cancelled to stop accepting transactions, even if senders are left
+    shutdown: CancellationToken,
+}
+
+// Settings every new account is created with
+#[derive(Clone)]
+struct AccountSettings {
+    rules: AccountRules,
+    history_spill: Option<HistorySpill>,
+    history_retention: HistoryRetention,
+    settled_history: SettledHistory,
+    history_capacity: usize,
+    live_policy: Option<LivePolicy>,
+    unknown_client_policy: UnknownClientPolicy,
+}
+
+// Transactions a filter changed, by their client and id, with the transaction received, which
+// they are acknowledged as
+type Enriched = FxHashMap<(ClientId, TransactionId), (Transaction, Transaction)>;
+
+// Everyone told about the outcome of each transaction
+#[derive(Clone)]
+struct Observers {
+    audit_log: Option<AuditLog>,
+    progress: Progress,
+    outcomes: broadcast::Sender<Acknowledgement>,
+    enriched: Arc<Mutex<Enriched>>,
+    account_updates: broadcast::Sender<AccountView>,
+    tally: Tally,
+    risk: Option<RiskMonitor>,
+    engine_observers: Vec<Arc<dyn EngineObserver>>,
 }
 
 impl PaymentsEngine {
     pub fn new() -> (Self, Sender<Transaction>) {
-        let (transaction_sink, transactions) = channel::<Transaction>(16);
-        let accounts = HashMap::new();
+        Self::builder().build()
+    }
+
+    /// Creates an engine that distributes the accounts over `workers` tasks.
+    pub fn with_workers(workers: usize) -> (Self, Sender<Transaction>) {
+        Self::builder().workers(workers).build()
+    }
+
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    pub(crate) fn from_builder(
+        EngineBuilder {
+            workers,
+            channel_capacity,
+            admin_commands,
+            precision,
+            rounding_mode,
+            error_policy,
+            history_spill,
+            history_retention,
+            settled_history,
+            limits,
+            fee_schedule,
+            fx_rates,
+            live_policy,
+            dispute_window,
+            redispute_policy,
+            duplicate_policy,
+            locked_policy,
+            unknown_client_policy,
+            risk_thresholds,
+            bloom_filter,
+            expected_clients,
+            expected_transactions,
+            ordering,
+            sort_output,
+            output_threads,
+            flush_interval,
+            flush_snapshot,
+            clock,
+            stores,
+            observers,
+            filters,
+        }: EngineBuilder,
+    ) -> (Self, Sender<Transaction>) {
+        let (transaction_sink, transactions) = channel::<Transaction>(channel_capacity);
+        let (batch_sink, batches) = channel(channel_capacity);
+        let (query_sink, queries) = channel(channel_capacity);
+        let stores = (0..workers)
+            .map(|worker| {
+                let mut store = stores.open(worker);
+                if let Some(clients) = expected_clients {
+                    store.reserve(clients.div_ceil(workers));
+                }
+                store
+            })
+            .collect();
+        // Transactions an account is expected to remember, if both hints are given
+        let history_capacity = expected_clients
+            .zip(expected_transactions)
+            .map_or(0, |(clients, transactions)| transactions / clients.max(1));
 
         (
             Self {
-                accounts,
+                stores,
                 transactions,
+                batches,
+                batch_sink: Some(batch_sink),
+                queries,
+                query_sink,
+                transaction_ids: TransactionIds::new(
+                    bloom_filter,
+                    expected_transactions.unwrap_or(0),
+                ),
+                transfers: Transfers::default(),
+                events: Vec::new(),
+                workers,
+                channel_capacity,
+                channel_metrics: ChannelMetrics::default(),
+                admin_commands,
+                precision,
+                rounding_mode,
+                sort_output,
+                output_threads,
+                flush_interval,
+                flush_snapshot,
+                account_settings: AccountSettings {
+                    rules: AccountRules {
+                        precision,
+                        rounding_mode,
+                        limits,
+                        fee_schedule,
+                        fx_rates,
+                        dispute_window,
+                        redispute_policy,
+                        duplicate_policy,
+                        locked_policy,
+                        clock,
+                    },
+                    history_spill,
+                    history_retention,
+                    settled_history,
+                    history_capacity,
+                    live_policy,
+                    unknown_client_policy,
+                },
+                ordering: OrderingGuard::new(ordering),
+                error_policy,
+                filters,
+                observers: Observers {
+                    audit_log: None,
+                    progress: Progress::default(),
+                    outcomes: broadcast::channel(OUTCOME_CAPACITY).0,
+                    enriched: Arc::default(),
+                    account_updates: broadcast::channel(OUTCOME_CAPACITY).0,
+                    tally: Tally::default(),
+                    risk: risk_thresholds.map(RiskMonitor::new),
+                    engine_observers: observers,
+                },
+                shutdown: CancellationToken::new(),
             },
             transaction_sink,
         )
     }
 
+    /// Stops accepting transactions once `shutdown` is cancelled, the transactions sent before
+    /// are still processed.
+    pub fn set_shutdown(&mut self, shutdown: CancellationToken) {
+        self.shutdown = shutdown;
+    }
+
+    pub fn set_error_policy(&mut self, error_policy: ErrorPolicy) {
+        self.error_policy = error_policy;
+    }
+
+    // The error policy in effect, which the live policy can override
+    fn error_policy(&self) -> ErrorPolicy {
+        self.account_settings.error_policy(self.error_policy)
+    }
+
+    /// Records the outcome of every transaction in `audit_log`.
+    pub fn set_audit_log(&mut self, audit_log: AuditLog) {
+        self.observers.audit_log = Some(audit_log);
+    }
+
+    /// Returns the counters of applied and rejected transactions, which can be shared with the
+    /// collector to count the rows read as well.
+    pub fn progress(&self) -> Progress {
+        self.observers.progress.clone()
+    }
+
+    pub fn query_handle(&self) -> QueryHandle {
+        QueryHandle {
+            queries: self.query_sink.clone(),
+            outcomes: self.observers.outcomes.clone(),
+            account_updates: self.observers.account_updates.clone(),
+            workers: self.workers,
+            precision: self.precision,
+            rounding_mode: self.rounding_mode,
+        }
+    }
+
+    /// Returns a sender feeding batches of transactions into the engine, which saves
+    /// synchronizing on every single transaction.
+    ///
+    /// Batches are processed alongside the transactions of the sender the engine was built with,
+    /// the processing ends once all senders are dropped. Senders taken after the processing
+    /// started are closed. Errors caused by a transaction of a batch point at its line, if the
+    /// batch has it.
+    pub fn batch_sender(&self) -> Sender<Batch> {
+        self.batch_sink.clone().unwrap_or_else(|| channel(1).0)
+    }
+
+    /// Processes the transactions of all senders until they are dropped.
+    ///
+    /// On an error, e.g. an invalid transaction in strict mode, the channels aren't read anymore
+    /// while the engine is alive, so tasks still sending into them should be stopped.
     pub async fn process_transactions(&mut self) -> Result<()> {
-        while let Some(transaction) = self.transactions.recv().await {
-            let account = self
-                .accounts
-                .entry(transaction.client)
-                .or_insert_with(|| Account::new(transaction.client));
-            account.apply_transaction(transaction)?;
+        self.batch_sink = None;
+        let (shard_sinks, workers) = self.spawn_workers();
+        let dispatched = self.dispatch_transactions(&shard_sinks).await;
+        drop(shard_sinks);
+
+        // Pending queries can't be answered anymore, dropping them notifies the callers
+        self.queries.close();
+        while self.queries.try_recv().is_ok() {}
+
+        self.finish(workers, dispatched).await
+    }
+
+    /// Applies `transactions` directly, without going through a channel, and returns once all of
+    /// them are processed.
+    ///
+    /// The workers are started for every batch, so this pays off for large batches.
+    pub async fn apply_batch(&mut self, transactions: Vec<Transaction>) -> Result<()> {
+        let (shard_sinks, workers) = self.spawn_workers();
+        let mut ready = Vec::with_capacity(transactions.len());
+        for transaction in transactions {
+            self.receive(transaction, None, &mut ready);
         }
+        ready.extend(self.ordering.flush());
+        let dispatched = self.dispatch_all(ready, &shard_sinks).await.map(drop);
+        drop(shard_sinks);
+
+        self.finish(workers, dispatched).await
+    }
+
+    fn spawn_workers(&mut self) -> (Vec<Sender<ShardMessage>>, Vec<JoinHandle<WorkerResult>>) {
+        self.take_shards()
+            .into_iter()
+            .enumerate()
+            .map(|(worker, shard)| {
+                let (shard_sink, shard_messages) = channel(self.channel_capacity);
+                let worker = run_worker(
+                    shard,
+                    shard_messages,
+                    self.error_policy,
+                    self.observers.clone(),
+                    self.account_settings.clone(),
+                )
+                .instrument(info_span!("worker", worker));
+                (shard_sink, tokio::spawn(worker))
+            })
+            .unzip()
+    }
+
+    async fn finish(
+        &mut self,
+        workers: Vec<JoinHandle<WorkerResult>>,
+        dispatched: Result<(), EngineError>,
+    ) -> Result<()> {
+        self.join_workers(workers).await?;
+        dispatched?;
 
+        if let Some(audit_log) = &self.observers.audit_log {
+            audit_log.flush()?;
+        }
         Ok(())
     }
 
-    pub fn print_accounts(&self) -> Result<()> {
-        let mut writer = csv::Writer::from_writer(std::io::stdout());
-        self.accounts
+    async fn dispatch_transactions(
+        &mut self,
+        shard_sinks: &[Sender<ShardMessage>],
+    ) -> Result<(), EngineError> {
+        let (mut transactions_open, mut batches_open) = (true, true);
+        let mut flushes = self.flush_interval.map(|period| {
+            let mut flushes = time::interval_at(Instant::now() + period, period);
+            flushes.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            flushes
+        });
+        // Whether transactions were dispatched since the last flush
+        let mut unflushed = false;
+        while transactions_open || batches_open {
+            // Transactions already sent are dispatched before a query, so it observes them
+            let ready = tokio::select! {
+                biased;
+                transaction = self.transactions.recv(), if transactions_open => match transaction {
+                    Some(transaction) => {
+                        let backlog = self.transactions.len() + 1;
+                        self.channel_metrics
+                            .record_received(1, backlog, self.channel_capacity);
+                        let mut ready = Vec::with_capacity(1);
+                        self.receive(transaction, None, &mut ready);
+                        ready
+                    }
+                    None => {
+                        transactions_open = false;
+                        continue;
+                    }
+                },
+                batch = self.batches.recv(), if batches_open => match batch {
+                    Some(batch) => {
+                        let backlog = self.batches.len() + 1;
+                        self.channel_metrics
+                            .record_received(batch.len(), backlog, self.channel_capacity);
+                        let mut ready = Vec::with_capacity(batch.len());
+                        for (transaction, line) in batch.with_lines() {
+                            self.receive(transaction, line, &mut ready);
+                        }
+                        ready
+                    }
+                    None => {
+                        batches_open = false;
+                        continue;
+                    }
+                },
+                Some(query) = self.queries.recv() => {
+                    // Transactions held back for reordering were sent before the query as well
+                    let ready = self.ordering.flush();
+                    if !self.dispatch_all(ready, shard_sinks).await? {
+                        break;
+                    }
+                    dispatch_query(query, &self.events, shard_sinks).await;
+                    continue;
+                }
+                _ = async { flushes.as_mut().expect("checked by the condition").tick().await },
+                    if flushes.is_some() => {
+                    if unflushed {
+                        if !self.flush(shard_sinks).await? {
+                            break;
+                        }
+                        unflushed = false;
+                    }
+                    continue;
+                }
+                _ = self.shutdown.cancelled(),
+                    if !self.transactions.is_closed() || !self.batches.is_closed() => {
+                    // The transactions still queued are received before the channels end
+                    self.transactions.close();
+                    self.batches.close();
+                    continue;
+                }
+            };
+
+            unflushed |= !ready.is_empty();
+            if !self.dispatch_all(ready, shard_sinks).await? {
+                break;
+            }
+        }
+
+        let ready = self.ordering.flush();
+        self.dispatch_all(ready, shard_sinks).await?;
+        Ok(())
+    }
+
+    // Waits until the workers processed the transactions dispatched so far, writes the flush
+    // snapshot of their events and flushes the audit log. Returns `false` if a worker stopped
+    // because of an error, which is reported when joining it.
+    async fn flush(&mut self, shard_sinks: &[Sender<ShardMessage>]) -> Result<bool, EngineError> {
+        match &self.flush_snapshot {
+            Some(path) => {
+                let Some(events) = collect_events(&self.events, shard_sinks).await else {
+                    return Ok(false);
+                };
+                let snapshot = Snapshot {
+                    events,
+                    offset: None,
+                };
+                // Processing goes on, the next flush tries again
+                if let Err(error) = snapshot.replace(path) {
+                    tracing::warn!(path = %path.display(), "Failed to write the snapshot: {error:#}");
+                }
+            }
+            None => {
+                let (ack, mut acks) = channel(shard_sinks.len());
+                for shard_sink in shard_sinks {
+                    let _ = shard_sink.send(ShardMessage::Barrier(ack.clone())).await;
+                }
+                drop(ack);
+                for _ in shard_sinks {
+                    if acks.recv().await.is_none() {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+        if let Some(audit_log) = &self.observers.audit_log {
+            audit_log.flush()?;
+        }
+        Ok(true)
+    }
+
+    // Passes `transaction`, read from `line` of the input if known, through the filters and the
+    // ordering guard, and appends the transactions ready to be dispatched to `ready`. The
+    // transactions a filter makes of it keep its line, the first of them is acknowledged as
+    // `transaction`.
+    fn receive(
+        &mut self,
+        transaction: Transaction,
+        line: Option<u64>,
+        ready: &mut Vec<(Transaction, Option<u64>)>,
+    ) {
+        if self.filters.is_empty() {
+            ready.extend(self.ordering.push(transaction, line));
+            return;
+        }
+        let mut passed = vec![transaction];
+        for filter in &self.filters {
+            let mut output = Vec::with_capacity(passed.len());
+            for transaction in passed {
+                filter.filter(transaction, &mut output);
+            }
+            passed = output;
+        }
+        if self.observers.outcomes.receiver_count() > 0 {
+            match passed.first() {
+                None => {
+                    let _ = self.observers.outcomes.send(Acknowledgement {
+                        transaction,
+                        outcome: Ok(TransactionOutcome::Filtered),
+                    });
+                }
+                Some(enriched) if *enriched != transaction => {
+                    let mut enriched_transactions = self.observers.enriched.lock().unwrap();
+                    enriched_transactions
+                        .insert((enriched.client, enriched.tx), (*enriched, transaction));
+                }
+                Some(_) => {}
+            }
+        }
+        for transaction in passed {
+            ready.extend(self.ordering.push(transaction, line));
+        }
+    }
+
+    // Returns `false` if a worker stopped because of an error, which is reported when joining it
+    async fn dispatch_all(
+        &mut self,
+        transactions: Vec<(Transaction, Option<u64>)>,
+        shard_sinks: &[Sender<ShardMessage>],
+    ) -> Result<bool, EngineError> {
+        if transactions.is_empty() {
+            return Ok(true);
+        }
+        let span = debug_span!("batch", transactions = transactions.len());
+        self.dispatch_batch(transactions, shard_sinks)
+            .instrument(span)
+            .await
+    }
+
+    async fn dispatch_batch(
+        &mut self,
+        transactions: Vec<(Transaction, Option<u64>)>,
+        shard_sinks: &[Sender<ShardMessage>],
+    ) -> Result<bool, EngineError> {
+        // Transactions of each shard are sent together, which saves synchronizing on every one
+        let mut pending = vec![Vec::new(); shard_sinks.len()];
+        for (transaction, line) in transactions {
+            let reuse = match self.check_transaction(&transaction) {
+                Ok(reuse) => reuse,
+                Err(error) => {
+                    let rejected = Err(error);
+                    self.observers.record(&transaction, &rejected)?;
+                    self.error_policy()
+                        .check_in_context(rejected, ErrorContext::of(&transaction, line))?;
+                    continue;
+                }
+            };
+
+            match self.transfers.counterpart_of(&transaction) {
+                Some(counterpart) => {
+                    // The transactions before have to reach the workers before the transfer
+                    if !self.send_pending(&mut pending, shard_sinks).await
+                        || !self
+                            .dispatch_transfer(transaction, reuse, line, counterpart, shard_sinks)
+                            .await?
+                    {
+                        return Ok(false);
+                    }
+                }
+                None => {
+                    let shard = shard_of(transaction.client, shard_sinks.len());
+                    pending[shard].push((transaction, reuse, line));
+                }
+            }
+        }
+        Ok(self.send_pending(&mut pending, shard_sinks).await)
+    }
+
+    async fn send_pending(
+        &mut self,
+        pending: &mut [Vec<(Transaction, Reuse, Option<u64>)>],
+        shard_sinks: &[Sender<ShardMessage>],
+    ) -> bool {
+        for (shard, transactions) in pending.iter_mut().enumerate() {
+            if transactions.is_empty() {
+                continue;
+            }
+            let message = ShardMessage::Transactions(std::mem::take(transactions));
+            if !self.send_to_shard(shard_sinks, shard, message).await {
+                return false;
+            }
+        }
+        true
+    }
+
+    async fn send_to_shard(
+        &mut self,
+        shard_sinks: &[Sender<ShardMessage>],
+        shard: usize,
+        message: ShardMessage,
+    ) -> bool {
+        match shard_sinks[shard].try_send(message) {
+            Err(TrySendError::Full(message)) => {
+                self.channel_metrics.worker_stalls += 1;
+                shard_sinks[shard].send(message).await.is_ok()
+            }
+            result => result.is_ok(),
+        }
+    }
+
+    // Changes the account of the client first, and the one of the counterparty only if that
+    // succeeded. No other transaction is dispatched in between, so queries observe either both
+    // changes or none.
+    async fn dispatch_transfer(
+        &mut self,
+        transaction: Transaction,
+        reuse: Reuse,
+        line: Option<u64>,
+        counterparty: ClientId,
+        shard_sinks: &[Sender<ShardMessage>],
+    ) -> Result<bool, EngineError> {
+        let counterparty_shard = shard_of(counterparty, shard_sinks.len());
+        if matches!(
+            transaction.r#type,
+            TransactionType::Transfer | TransactionType::Approve
+        ) {
+            let (reply, view) = oneshot::channel();
+            let query = ShardMessage::Query(AccountQuery {
+                client: counterparty,
+                reply,
+            });
+            if !self
+                .send_to_shard(shard_sinks, counterparty_shard, query)
+                .await
+            {
+                return Ok(false);
+            }
+            if view.await.ok().flatten().is_some_and(|view| view.locked) {
+                self.observers
+                    .record(&transaction, &Ok(TransactionOutcome::CounterpartyLocked))?;
+                return Ok(true);
+            }
+        }
+
+        let (reply, event) = oneshot::channel();
+        let shard = shard_of(transaction.client, shard_sinks.len());
+        let message = ShardMessage::Transfer(transaction, reuse, line, reply);
+        if !self.send_to_shard(shard_sinks, shard, message).await {
+            return Ok(false);
+        }
+        // Declined, or the worker stopped because of an error
+        let Some(counterpart) = event
+            .await
+            .ok()
+            .flatten()
+            .and_then(|event| self.transfers.counterpart(event, counterparty))
+        else {
+            return Ok(true);
+        };
+        let message = ShardMessage::Counterpart(counterpart);
+        Ok(self
+            .send_to_shard(shard_sinks, counterparty_shard, message)
+            .await)
+    }
+
+    fn check_transaction(&mut self, transaction: &Transaction) -> Result<Reuse, EngineError> {
+        transaction.validate(self.precision)?;
+        if transaction.r#type.is_admin_command() && !self.admin_commands {
+            return Err(EngineError::AdminCommandsDisabled(transaction.tx));
+        }
+        self.ordering.check(transaction)?;
+        self.transaction_ids.admit(transaction)
+    }
+
+    // Hands the accounts known so far, e.g. from a snapshot, over to the workers owning them
+    fn take_shards(&mut self) -> Vec<Shard> {
+        std::mem::take(&mut self.stores)
+    }
+
+    async fn join_workers(&mut self, workers: Vec<JoinHandle<WorkerResult>>) -> Result<()> {
+        // Accounts only depend on their own events, so the logs of the workers are concatenated
+        let mut result = Ok(());
+        for worker in workers {
+            let (shard, events, stopped) = worker.await?;
+            self.stores.push(shard);
+            self.events.extend(events);
+            result = result.and(stopped);
+        }
+
+        Ok(result?)
+    }
+
+    fn store_of(&self, client: ClientId) -> Option<&Shard> {
+        self.stores.get(shard_of(client, self.workers))
+    }
+
+    /// Folds `events` into the accounts and appends them to the event log.
+    ///
+    /// Replaying the [`Self::events`] of an engine on a new one reconstructs the same accounts.
+    /// Events that leave an account inconsistent, e.g. of a snapshot edited by hand, fail with
+    /// [`EngineError::InvariantViolated`].
+    pub fn replay<I: IntoIterator<Item = AccountEvent>>(&mut self, events: I) -> Result<()> {
+        for event in events {
+            if let Some(tx) = event.introduced_transaction() {
+                self.transaction_ids.register(tx, event.client())?;
+            }
+            match event {
+                AccountEvent::TransferredOut {
+                    tx,
+                    counterparty,
+                    amount,
+                    ..
+                } => self.transfers.insert(tx, counterparty, amount),
+                AccountEvent::Staged {
+                    tx,
+                    kind: TransactionType::Transfer,
+                    counterparty: Some(counterparty),
+                    ..
+                } => self.transfers.stage(tx, counterparty),
+                _ => {}
+            }
+            let shard = shard_of(event.client(), self.workers);
+            let account = self.stores[shard]
+                .get_or_create(event.client(), &|client| self.account_settings.open(client))?;
+            account.apply(&event)?;
+            // Snapshots may have been edited by hand
+            account.check_invariants()?;
+            self.events.push(event);
+        }
+        Ok(())
+    }
+
+    /// Events of all processed transactions, in order for each account.
+    pub fn events(&self) -> &[AccountEvent] {
+        &self.events
+    }
+
+    /// Balances of the accounts as they were at a historical point of the event log, e.g. to
+    /// investigate how an account got into its state. The engine itself isn't changed.
+    ///
+    /// The accounts are rounded to the precision and sorted by client.
+    pub fn state_at(&self, at: PointInTime) -> Result<Vec<AccountView>> {
+        let mut accounts: BTreeMap<ClientId, Account> = BTreeMap::new();
+        for event in at.events(&self.events) {
+            accounts
+                .entry(event.client())
+                .or_insert_with(|| Account::new(event.client()))
+                .apply(event)?;
+        }
+        Ok(accounts
             .values()
-            .try_for_each(|transaction| writer.serialize(transaction))
-            .map_err(Error::from)
+            .map(|account| account.view().round(self.precision, self.rounding_mode))
+            .collect())
+    }
+
+    /// Writes the event log to `path` as JSON.
+    pub fn save_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        Snapshot {
+            events: self.events.clone(),
+            offset: None,
+        }
+        .save(path)
+    }
+
+    /// Replaces the state of the engine by replaying a snapshot written by
+    /// [`Self::save_snapshot`].
+    pub fn load_snapshot<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.restore(Snapshot::load(path)?).map(|_| ())
+    }
+
+    /// Replaces the state of the engine by the one of a checkpoint written by
+    /// [`Checkpoints`](crate::Checkpoints), and returns the input offset it reflects.
+    pub fn load_checkpoint<P: AsRef<Path>>(&mut self, path: P) -> Result<InputOffset> {
+        self.restore(Snapshot::load(path)?)
+            .map(Option::unwrap_or_default)
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) -> Result<Option<InputOffset>> {
+        for store in &mut self.stores {
+            store.clear()?;
+        }
+        self.transaction_ids.clear();
+        self.transfers.clear();
+        self.events.clear();
+        self.replay(snapshot.events)?;
+        Ok(snapshot.offset)
+    }
+
+    /// Writes the accounts with their transaction histories and disputes, and the event log, as
+    /// a self-describing JSON document, e.g. to inspect them or to move them to another version
+    /// of the engine.
+    pub fn export_json<W: Write>(&self, writer: W) -> Result<()> {
+        let mut accounts = Vec::new();
+        for account in self.accounts() {
+            let mut account = account?.export()?;
+            for transaction in &mut account.transactions {
+                if transaction.kind == TransactionType::Transfer {
+                    transaction.counterparty = self.transfers.counterparty(transaction.tx);
+                }
+            }
+            accounts.push(account);
+        }
+        accounts.sort_unstable_by_key(|account| account.client);
+        Export::new(self.precision, accounts, self.events.clone()).write(writer)
+    }
+
+    /// Replaces the state of the engine by a document written by [`Self::export_json`].
+    ///
+    /// The accounts are taken as they are in the document, the event log is only kept for the
+    /// ledger and later snapshots.
+    pub fn import_json<R: Read>(&mut self, reader: R) -> Result<()> {
+        let export = Export::read(reader)?;
+        for store in &mut self.stores {
+            store.clear()?;
+        }
+        self.transaction_ids.clear();
+        self.transfers.clear();
+        self.events.clear();
+
+        for event in &export.events {
+            if let Some(tx) = event.introduced_transaction() {
+                self.transaction_ids.register(tx, event.client())?;
+            }
+        }
+        for account in export.accounts {
+            self.import_account(account)?;
+        }
+        self.events = export.events;
+        Ok(())
+    }
+
+    fn import_account(&mut self, account: ExportedAccount) -> Result<()> {
+        for transaction in &account.transactions {
+            self.transaction_ids
+                .register(transaction.tx, account.client)?;
+            if let Some(counterparty) = transaction.counterparty {
+                self.transfers
+                    .insert(transaction.tx, counterparty, transaction.amount);
+            }
+        }
+        for transaction in &account.pending {
+            self.transaction_ids
+                .register(transaction.tx, account.client)?;
+            if let (TransactionType::Transfer, Some(counterparty)) =
+                (transaction.r#type, transaction.counterparty)
+            {
+                self.transfers.stage(transaction.tx, counterparty);
+            }
+        }
+        let shard = shard_of(account.client, self.workers);
+        self.stores[shard]
+            .get_or_create(account.client, &|client| self.account_settings.open(client))?
+            .import(account)?;
+        Ok(())
+    }
+
+    /// Writes the ledger of every account, each accepted transaction with the balances it
+    /// resulted in, to a CSV file per client in `directory`.
+    pub fn export_ledger<P: AsRef<Path>>(&self, directory: P) -> Result<()> {
+        ledger::export(&self.events, self.precision, self.rounding_mode, directory)
+    }
+
+    pub fn channel_metrics(&self) -> ChannelMetrics {
+        self.channel_metrics
+    }
+
+    /// Summarizes the transactions processed so far, the rejected ones by reason, and the
+    /// resulting accounts.
+    pub fn report(&self) -> Result<RunReport> {
+        let mut report = self.observers.tally.report();
+        for account in self.accounts() {
+            let account = account?;
+            report.accounts += 1;
+            report.locked_accounts += u64::from(account.locked());
+            report.ghost_accounts += u64::from(account.is_ghost());
+            report.total_available += account.available();
+            report.total_held += account.held();
+        }
+        Ok(report)
+    }
+
+    /// Writes the [`RunReport`] as a JSON [`RunSummary`], with `runtime` as the duration of the
+    /// run, for pipelines that check the health of a run.
+    pub fn write_summary_json<W: Write>(&self, writer: W, runtime: Duration) -> Result<()> {
+        let mut writer = BufWriter::new(writer);
+        serde_json::to_writer_pretty(&mut writer, &RunSummary::new(self.report()?, runtime))?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Transactions still in dispute, ordered by client and transaction id, with their amounts
+    /// rounded like the balances.
+    pub fn open_disputes(&self) -> Result<Vec<OpenDispute>> {
+        let mut disputes = Vec::new();
+        for account in self.accounts() {
+            disputes.extend(account?.open_disputes()?);
+        }
+        disputes.sort_unstable_by_key(|dispute| (dispute.client, dispute.tx));
+        for dispute in &mut disputes {
+            dispute.amount = dispute.amount.round(self.precision, self.rounding_mode);
+        }
+        Ok(disputes)
+    }
+
+    /// Writes the transactions still in dispute as CSV, with the columns `client`, `tx`,
+    /// `amount` and `age`, which is empty for transactions without timestamp.
+    pub fn write_disputes_report<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for dispute in self.open_disputes()? {
+            writer.serialize(dispute)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns the balances of the account of `client`, or `None` if it doesn't exist or can't
+    /// be read from the store.
+    pub fn account(&self, client: ClientId) -> Option<AccountView> {
+        let store = self.store_of(client)?;
+        store
+            .get(client)
+            .ok()
+            .flatten()
+            .map(|account| account.view())
+    }
+
+    /// All accounts, in no particular order.
+    pub fn accounts(&self) -> impl Iterator<Item = Result<Cow<'_, Account>, EngineError>> {
+        self.stores.iter().flat_map(|store| store.iter())
+    }
+
+    pub fn print_accounts(&self) -> Result<()> {
+        self.write_accounts(std::io::stdout())
+    }
+
+    pub fn write_accounts<W: Write + Send>(&self, writer: W) -> Result<()> {
+        self.write_accounts_as(OutputFormat::Csv, writer)
+    }
+
+    pub fn write_accounts_as<W: Write + Send>(
+        &self,
+        format: OutputFormat,
+        writer: W,
+    ) -> Result<()> {
+        output::write_parallel(
+            &self.rounded_accounts()?,
+            self.precision,
+            format,
+            self.output_threads,
+            writer,
+        )
+    }
+
+    /// Writes the accounts as they were at a historical point, see [`Self::state_at`].
+    pub fn write_accounts_at<W: Write + Send>(
+        &self,
+        at: PointInTime,
+        format: OutputFormat,
+        writer: W,
+    ) -> Result<()> {
+        output::write_parallel(
+            &self.state_at(at)?,
+            self.precision,
+            format,
+            self.output_threads,
+            writer,
+        )
+    }
+
+    /// Writes the accounts into the tables of the SQLite database at `path`, with the ledger of
+    /// every account if `with_ledger` is set.
+    #[cfg(feature = "sqlite")]
+    pub fn write_sqlite<P: AsRef<Path>>(&self, path: P, with_ledger: bool) -> Result<()> {
+        let ledgers = if with_ledger {
+            Some(ledger::ledgers(
+                &self.events,
+                self.precision,
+                self.rounding_mode,
+            )?)
+        } else {
+            None
+        };
+        crate::sqlite::write(path, &self.rounded_accounts()?, ledgers.as_ref())
+    }
+
+    /// Inserts or updates the accounts in the tables of `sink`.
+    #[cfg(feature = "postgres")]
+    pub async fn write_postgres(&self, sink: &crate::postgres::PostgresSink) -> Result<()> {
+        sink.upsert_accounts(&self.rounded_accounts()?).await
+    }
+
+    // Balances of the accounts as they are written
+    pub(crate) fn rounded_accounts(&self) -> Result<Vec<AccountView>> {
+        let mut accounts = self
+            .accounts()
+            .map(|account| Ok(account?.view().round(self.precision, self.rounding_mode)))
+            .collect::<Result<Vec<_>, EngineError>>()?;
+        if self.sort_output {
+            accounts.sort_unstable_by_key(|account| account.client);
+        }
+        Ok(accounts)
+    }
+}
+
+impl AccountSettings {
+    fn open(&self, client: ClientId) -> Account {
+        Account::with_history_spill(client, self.history_spill.clone())
+            .with_history_retention(self.history_retention)
+            .with_settled_history(self.settled_history)
+            .with_history_capacity(self.history_capacity)
+            .with_rules(&self.rules)
+    }
+
+    // Subjects `account` to the live policy, if there is one, and returns the error policy in
+    // effect
+    fn apply_live_policy(&self, account: &mut Account, error_policy: ErrorPolicy) -> ErrorPolicy {
+        let Some(live_policy) = &self.live_policy else {
+            return error_policy;
+        };
+        let policy = live_policy.current();
+        let fees = policy
+            .fees
+            .as_ref()
+            .map(|fee_schedule| fee_schedule.fees_of(account.client()));
+        account.set_policy(policy.limits, fees);
+        policy.error_policy.unwrap_or(error_policy)
+    }
+
+    fn error_policy(&self, error_policy: ErrorPolicy) -> ErrorPolicy {
+        self.live_policy
+            .as_ref()
+            .and_then(|live_policy| live_policy.current().error_policy)
+            .unwrap_or(error_policy)
+    }
+}
+
+fn shard_of(client: ClientId, shards: usize) -> usize {
+    client as usize % shards
+}
+
+impl Observers {
+    // The transaction received, which a filter may have changed into `transaction`
+    fn received(&self, transaction: &Transaction) -> Transaction {
+        let mut enriched = self.enriched.lock().unwrap();
+        let id = (transaction.client, transaction.tx);
+        match enriched.get(&id) {
+            Some((changed, received)) if changed == transaction => {
+                let received = *received;
+                enriched.remove(&id);
+                received
+            }
+            _ => *transaction,
+        }
+    }
+
+    fn record(
+        &self,
+        transaction: &Transaction,
+        result: &Result<TransactionOutcome, EngineError>,
+    ) -> Result<(), EngineError> {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(transaction, result)?;
+        }
+        self.tally.record(transaction, result);
+        match result {
+            // Invalid transactions are reported by the error policy
+            Ok(TransactionOutcome::Applied) | Err(_) => {}
+            Ok(outcome) => tracing::info!(
+                client = transaction.client,
+                tx = transaction.tx,
+                %outcome,
+                "Transaction declined"
+            ),
+        }
+        match result {
+            Ok(TransactionOutcome::Applied) => self.progress.record_applied(),
+            _ => self.progress.record_rejected(),
+        }
+        let rejection = match result {
+            Ok(TransactionOutcome::Applied) => None,
+            Ok(outcome) => Some(Rejection::Declined(*outcome)),
+            Err(error) => Some(Rejection::Invalid(error)),
+        };
+        if let Some(rejection) = rejection {
+            for observer in &self.engine_observers {
+                observer.on_rejected(transaction, rejection);
+            }
+        }
+        if self.outcomes.receiver_count() > 0 {
+            // Without subscribers there is no one to tell
+            let _ = self.outcomes.send(Acknowledgement {
+                transaction: self.received(transaction),
+                outcome: result.as_ref().copied().map_err(ToString::to_string),
+            });
+        }
+        Ok(())
+    }
+
+    // Raises the alerts for the risk thresholds `transaction` crossed
+    fn check_risk(
+        &self,
+        transaction: &Transaction,
+        result: &Result<TransactionOutcome, EngineError>,
+        account: &AccountView,
+    ) {
+        let Some(risk) = &self.risk else {
+            return;
+        };
+        let applied = matches!(result, Ok(TransactionOutcome::Applied));
+        for alert in risk.check(transaction, applied, account) {
+            tracing::warn!(client = alert.client(), %alert, "Risk threshold crossed");
+            for observer in &self.engine_observers {
+                observer.on_risk_alert(&alert);
+            }
+        }
+    }
+
+    // Tells subscribers about the balances of `account` if they differ from `before`, and
+    // returns them
+    fn publish_update(&self, before: AccountView, account: &Account) -> AccountView {
+        let after = account.view();
+        if after != before && self.account_updates.receiver_count() > 0 {
+            let _ = self.account_updates.send(after);
+        }
+        if after.locked && !before.locked {
+            for observer in &self.engine_observers {
+                observer.on_account_locked(&after);
+            }
+        }
+        after
+    }
+}
+
+// Events known before the workers started followed by those of every worker, once they processed
+// the transactions dispatched before, or `None` if a worker stopped
+async fn collect_events(
+    events: &[AccountEvent],
+    shard_sinks: &[Sender<ShardMessage>],
+) -> Option<Vec<AccountEvent>> {
+    let (reply, mut logs) = channel(shard_sinks.len() + 1);
+    let _ = reply.try_send(events.to_vec());
+    for shard_sink in shard_sinks {
+        let _ = shard_sink.send(ShardMessage::Events(reply.clone())).await;
+    }
+    drop(reply);
+    let mut collected = Vec::new();
+    for _ in 0..=shard_sinks.len() {
+        collected.extend(logs.recv().await?);
+    }
+    Some(collected)
+}
+
+// A failed worker drops the query, which is answered with `None` or a failed sync
+async fn dispatch_query(
+    query: Query,
+    events: &[AccountEvent],
+    shard_sinks: &[Sender<ShardMessage>],
+) {
+    match query {
+        Query::Account(query) => {
+            let shard = shard_of(query.client, shard_sinks.len());
+            let _ = shard_sinks[shard].send(ShardMessage::Query(query)).await;
+        }
+        Query::Barrier(ack) => {
+            for shard_sink in shard_sinks {
+                let _ = shard_sink.send(ShardMessage::Barrier(ack.clone())).await;
+            }
+        }
+        Query::Events(reply) => {
+            let _ = reply.try_send(events.to_vec());
+            for shard_sink in shard_sinks {
+                let _ = shard_sink.send(ShardMessage::Events(reply.clone())).await;
+            }
+        }
+    }
+}
+
+// Returns the store even if the worker stopped because of an error, so its accounts can be
+// inspected
+async fn run_worker(
+    mut store: Shard,
+    messages: Receiver<ShardMessage>,
+    error_policy: ErrorPolicy,
+    observers: Observers,
+    account_settings: AccountSettings,
+) -> WorkerResult {
+    let mut events = Vec::new();
+    let result = process_messages(
+        &mut store,
+        &mut events,
+        messages,
+        error_policy,
+        observers,
+        account_settings,
+    )
+    .await
+    .and_then(|()| store.persist());
+    (store, events, result)
+}
+
+async fn process_messages(
+    accounts: &mut Shard,
+    events: &mut Vec<AccountEvent>,
+    mut messages: Receiver<ShardMessage>,
+    error_policy: ErrorPolicy,
+    observers: Observers,
+    account_settings: AccountSettings,
+) -> Result<(), EngineError> {
+    let open = |client| account_settings.open(client);
+    while let Some(message) = messages.recv().await {
+        let (transactions, mut reply) = match message {
+            ShardMessage::Transactions(transactions) => (transactions, None),
+            ShardMessage::Transfer(transaction, reuse, line, reply) => {
+                (vec![(transaction, reuse, line)], Some(reply))
+            }
+            ShardMessage::Counterpart(event) => {
+                let account = accounts.get_or_create(event.client(), &open)?;
+                let before = account.view();
+                account.apply(&event)?;
+                observers.publish_update(before, account);
+                events.push(event);
+                continue;
+            }
+            ShardMessage::Query(AccountQuery { client, reply }) => {
+                let _ = reply.send(accounts.get(client)?.map(|account| account.view()));
+                continue;
+            }
+            ShardMessage::Barrier(ack) => {
+                let _ = ack.try_send(());
+                continue;
+            }
+            ShardMessage::Events(reply) => {
+                let _ = reply.try_send(events.clone());
+                continue;
+            }
+        };
+
+        let mut transactions = transactions.into_iter();
+        // Transactions queued by a locked account, applied right after it is unlocked. Their
+        // lines aren't kept while they are queued.
+        let mut unlocked = VecDeque::new();
+        while let Some((transaction, reuse, line)) = unlocked
+            .pop_front()
+            .map(|transaction| (transaction, Reuse::No, None))
+            .or_else(|| transactions.next())
+        {
+            let context = ErrorContext::of(&transaction, line);
+            // The policy decides whether a reference to a transaction opens an account
+            let unknown_client = match account_settings.unknown_client_policy.outcome(&transaction)
+            {
+                Some(result) if transaction.r#type.refers_to_transaction() => accounts
+                    .get(transaction.client)?
+                    .is_none()
+                    .then_some(result),
+                _ => None,
+            };
+            if let Some(result) = unknown_client {
+                observers.record(&transaction, &result)?;
+                account_settings
+                    .error_policy(error_policy)
+                    .check_in_context(result, context)?;
+                continue;
+            }
+
+            let account = accounts.get_or_create(transaction.client, &open)?;
+            let error_policy = account_settings.apply_live_policy(account, error_policy);
+            let before = account.view();
+            let (result, queued) = account.settle(transaction, reuse);
+            unlocked.extend(queued);
+            let after = observers.publish_update(before, account);
+            let result = result.map(|(outcome, event)| {
+                events.extend(event);
+                if let Some(reply) = reply.take() {
+                    let _ = reply.send(event);
+                }
+                outcome
+            });
+            observers.record(&transaction, &result)?;
+            if let Ok(TransactionOutcome::Applied) = result {
+                for observer in &observers.engine_observers {
+                    observer.on_transaction_applied(&transaction, &after);
+                }
+            }
+            observers.check_risk(&transaction, &result, &after);
+            error_policy.check_in_context(result, context)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PaymentsEngine;
+    use crate::{
+        amount::{Amount, RoundingMode},
+        clock::FixedClock,
+        dedupe::DuplicatePolicy,
+        error::{EngineError, ErrorContext, ErrorPolicy},
+        event::AccountEvent,
+        fees::{Fee, FeeSchedule},
+        history::{HistoryRetention, HistorySpill},
+        limits::Limits,
+        locked::LockedAccountPolicy,
+        ordering::OrderingPolicy,
+        point_in_time::PointInTime,
+        policy::{LivePolicy, Policy},
+        transaction::{ClientId, Transaction, TransactionType},
+        unknown_client::UnknownClientPolicy,
+        EngineHandle, TransactionOutcome,
+    };
+
+    #[tokio::test]
+    async fn sharded_processing_preserves_client_order() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(4);
+
+        let producer = tokio::spawn(async move {
+            for tx in 0..100 {
+                let client = (tx % 10) as ClientId;
+                let r#type = if tx < 50 {
+                    TransactionType::Deposit
+                } else {
+                    TransactionType::Withdrawal
+                };
+                let transaction = Transaction {
+                    r#type,
+                    client,
+                    tx,
+                    amount: Some("1.0".parse().unwrap()),
+                    counterparty: None,
+                    timestamp: None,
+                    from_ccy: None,
+                    to_ccy: None,
+                };
+                sender.send(transaction).await.unwrap();
+            }
+        });
+
+        payments_engine.process_transactions().await.unwrap();
+        producer.await.unwrap();
+
+        assert_eq!(payments_engine.accounts().count(), 10);
+        for account in payments_engine.accounts().map(Result::unwrap) {
+            assert_eq!(account.available(), "0".parse().unwrap());
+            assert_eq!(account.total(), "0".parse().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicate_transaction_id() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+
+        for client in [1, 2] {
+            let transaction = Transaction {
+                r#type: TransactionType::Deposit,
+                client,
+                tx: 7,
+                amount: Some("1.0".parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+
+        let error = payments_engine.process_transactions().await.unwrap_err();
+        let error = error.downcast_ref::<EngineError>().unwrap();
+        assert!(matches!(
+            error.without_context(),
+            EngineError::DuplicateTransactionId(7)
+        ));
+        // The error points at the second deposit
+        assert_eq!(
+            error.context(),
+            Some(ErrorContext {
+                line: None,
+                client: Some(2),
+                tx: Some(7),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn skip_or_replace_duplicates() {
+        let transaction = |r#type, tx, amount: &str| Transaction {
+            r#type,
+            client: 1,
+            tx,
+            amount: amount.parse().ok(),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        // The second file overlaps the first one
+        let transactions = [
+            transaction(TransactionType::Deposit, 1, "5.0"),
+            transaction(TransactionType::Withdrawal, 2, "1.0"),
+            transaction(TransactionType::Deposit, 1, "3.0"),
+            transaction(TransactionType::Withdrawal, 2, "1.0"),
+            transaction(TransactionType::Deposit, 3, "1.0"),
+        ];
+
+        for (policy, bloom_filter, available) in [
+            (DuplicatePolicy::Skip, None, "5.0"),
+            (DuplicatePolicy::Skip, Some(100), "5.0"),
+            (DuplicatePolicy::LastWriteWins, None, "3.0"),
+        ] {
+            let mut builder = PaymentsEngine::builder().duplicate_policy(policy);
+            if let Some(expected_transactions) = bloom_filter {
+                builder = builder.bloom_filter(expected_transactions);
+            }
+            let (mut payments_engine, sender) = builder.build();
+            for transaction in transactions {
+                sender.send(transaction).await.unwrap();
+            }
+            drop(sender);
+
+            payments_engine.process_transactions().await.unwrap();
+            let account = payments_engine.accounts().next().unwrap().unwrap();
+            assert_eq!(
+                account.available(),
+                available.parse().unwrap(),
+                "{policy:?}"
+            );
+            let report = payments_engine.report().unwrap();
+            let duplicates = match policy {
+                DuplicatePolicy::LastWriteWins => 1,
+                _ => 2,
+            };
+            assert_eq!(
+                report.rejected["Transaction was processed already"],
+                duplicates
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn dispute_of_another_client() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+
+        let transactions = [
+            (TransactionType::Deposit, 1, Some("1.0")),
+            (TransactionType::Dispute, 2, None),
+        ];
+        for (r#type, client, amount) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client,
+                tx: 7,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+
+        let error = payments_engine.process_transactions().await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref().map(EngineError::without_context),
+            Some(EngineError::ClientMismatchOnDispute(7, 2))
+        ));
+    }
+
+    #[tokio::test]
+    async fn configured_precision() {
+        let (mut payments_engine, sender) = PaymentsEngine::builder().precision(2).build();
+        payments_engine.set_error_policy(ErrorPolicy::Lenient);
+
+        for (tx, amount) in [(1, "1.25"), (2, "0.125")] {
+            let transaction = Transaction {
+                r#type: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(amount.parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let mut output = Vec::new();
+        payments_engine.write_accounts(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1.25,0.0,1.25,false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn swap_live_policy() {
+        let live_policy = LivePolicy::new(Policy {
+            limits: Limits {
+                max_withdrawal: Some("1".parse().unwrap()),
+                ..Limits::default()
+            },
+            ..Policy::default()
+        });
+        let (payments_engine, sender) = PaymentsEngine::builder()
+            .workers(2)
+            .live_policy(live_policy.clone())
+            .build();
+        let handle = EngineHandle::spawn(payments_engine, sender);
+        let transaction = |r#type, tx, amount: Option<&str>| Transaction {
+            r#type,
+            client: 1,
+            tx,
+            amount: amount.map(|amount| amount.parse().unwrap()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+
+        let deposit = transaction(TransactionType::Deposit, 1, Some("10"));
+        assert_eq!(
+            handle.submit(deposit).await,
+            Some(Ok(TransactionOutcome::Applied))
+        );
+        let withdrawal = transaction(TransactionType::Withdrawal, 2, Some("5"));
+        assert_eq!(
+            handle.submit(withdrawal).await,
+            Some(Ok(TransactionOutcome::WithdrawalLimitExceeded))
+        );
+
+        live_policy.set(Policy {
+            error_policy: Some(ErrorPolicy::Lenient),
+            fees: Some(FeeSchedule {
+                withdrawal: Fee {
+                    flat: "1".parse().unwrap(),
+                    percent: Amount::ZERO,
+                },
+                ..FeeSchedule::default()
+            }),
+            ..Policy::default()
+        });
+        let withdrawal = transaction(TransactionType::Withdrawal, 3, Some("5"));
+        assert_eq!(
+            handle.submit(withdrawal).await,
+            Some(Ok(TransactionOutcome::Applied))
+        );
+        // Invalid transactions are skipped instead of stopping the engine
+        let invalid = transaction(TransactionType::Withdrawal, 4, None);
+        assert!(matches!(handle.submit(invalid).await, Some(Err(_))));
+
+        let account = handle.account(1).await.unwrap();
+        assert_eq!(account.available, "4".parse().unwrap());
+        handle.shutdown().await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn configured_rounding_mode() {
+        let fee_schedule = FeeSchedule {
+            withdrawal: Fee {
+                flat: Amount::ZERO,
+                percent: "1".parse().unwrap(),
+            },
+            ..FeeSchedule::default()
+        };
+        let mut outputs = Vec::new();
+        for rounding_mode in [RoundingMode::HalfEven, RoundingMode::HalfUp] {
+            let (mut payments_engine, sender) = PaymentsEngine::builder()
+                .precision(2)
+                .rounding_mode(rounding_mode)
+                .fee_schedule(fee_schedule.clone())
+                .build();
+            // The fee of the withdrawal is 0.005
+            for (r#type, tx, amount) in [
+                (TransactionType::Deposit, 1, "10"),
+                (TransactionType::Withdrawal, 2, "0.5"),
+            ] {
+                let transaction = Transaction {
+                    r#type,
+                    client: 1,
+                    tx,
+                    amount: Some(amount.parse().unwrap()),
+                    counterparty: None,
+                    timestamp: None,
+                    from_ccy: None,
+                    to_ccy: None,
+                };
+                sender.send(transaction).await.unwrap();
+            }
+            drop(sender);
+            payments_engine.process_transactions().await.unwrap();
+
+            let mut output = Vec::new();
+            payments_engine.write_accounts(&mut output).unwrap();
+            outputs.push(String::from_utf8(output).unwrap());
+        }
+        let header = "client,available,held,total,locked,fees_collected\n";
+        assert_eq!(
+            outputs,
+            [
+                format!("{}1,9.5,0.0,9.5,false,0.0\n", header),
+                format!("{}1,9.49,0.0,9.49,false,0.01\n", header)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lenient_mode_skips_invalid_transactions() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        payments_engine.set_error_policy(ErrorPolicy::Lenient);
+
+        let transactions = [
+            (TransactionType::Deposit, 1, Some("1.0")),
+            (TransactionType::Deposit, 1, Some("2.0")),
+            (TransactionType::Withdrawal, 2, None),
+            (TransactionType::Deposit, 3, Some("3.0")),
+        ];
+        for (r#type, tx, amount) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client: 1,
+                tx,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+
+        payments_engine.process_transactions().await.unwrap();
+        assert_eq!(
+            payments_engine.account(1).unwrap().available,
+            "4.0".parse().unwrap()
+        );
+
+        let progress = payments_engine.progress().snapshot();
+        assert_eq!((progress.applied, progress.rejected), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn resume_from_snapshot() {
+        let path = std::env::temp_dir().join("rust-exercise-resume-from-snapshot.json");
+
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let deposit = Transaction {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some("1.0005".parse().unwrap()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        sender.send(deposit).await.unwrap();
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+        payments_engine.save_snapshot(&path).unwrap();
+
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(3);
+        payments_engine.load_snapshot(&path).unwrap();
+        let dispute = Transaction {
+            r#type: TransactionType::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        sender.send(dispute).await.unwrap();
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let account = payments_engine.account(1).unwrap();
+        assert_eq!(account.available, "0".parse().unwrap());
+        assert_eq!(account.held, "1.0005".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn disputes_across_resumed_runs() {
+        let directory = std::env::temp_dir();
+        let snapshots = [
+            directory.join("rust-exercise-resumed-run-1.json"),
+            directory.join("rust-exercise-resumed-run-2.json"),
+        ];
+        let transaction = |r#type, client, tx, amount: Option<&str>| Transaction {
+            r#type,
+            client,
+            tx,
+            amount: amount.map(|amount| amount.parse().unwrap()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        // Processes `transactions` after restoring the snapshot of the run before, if any
+        let run = |resume_from: Option<usize>, transactions: Vec<Transaction>| {
+            let snapshots = snapshots.clone();
+            async move {
+                let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+                if let Some(run) = resume_from {
+                    payments_engine.load_snapshot(&snapshots[run]).unwrap();
+                }
+                for transaction in transactions {
+                    sender.send(transaction).await.unwrap();
+                }
+                drop(sender);
+                let processed = payments_engine.process_transactions().await;
+                (payments_engine, processed)
+            }
+        };
+
+        let (payments_engine, processed) = run(
+            None,
+            vec![transaction(TransactionType::Deposit, 1, 1, Some("2.0"))],
+        )
+        .await;
+        processed.unwrap();
+        payments_engine.save_snapshot(&snapshots[0]).unwrap();
+
+        // The snapshot of a resumed run still holds the transactions of the run before
+        let (payments_engine, processed) = run(
+            Some(0),
+            vec![transaction(TransactionType::Deposit, 2, 2, Some("3.0"))],
+        )
+        .await;
+        processed.unwrap();
+        payments_engine.save_snapshot(&snapshots[1]).unwrap();
+
+        let (payments_engine, processed) = run(
+            Some(1),
+            vec![
+                transaction(TransactionType::Dispute, 1, 1, None),
+                transaction(TransactionType::Dispute, 2, 2, None),
+            ],
+        )
+        .await;
+        processed.unwrap();
+        assert_eq!(
+            payments_engine.account(1).unwrap().held,
+            "2.0".parse().unwrap()
+        );
+        assert_eq!(
+            payments_engine.account(2).unwrap().held,
+            "3.0".parse().unwrap()
+        );
+
+        // Disputes stay scoped to the client of the transaction
+        let (_, processed) = run(
+            Some(1),
+            vec![transaction(TransactionType::Dispute, 2, 1, None)],
+        )
+        .await;
+        assert!(matches!(
+            processed
+                .unwrap_err()
+                .downcast_ref()
+                .map(EngineError::without_context),
+            Some(EngineError::ClientMismatchOnDispute(1, 2))
+        ));
+        for snapshot in &snapshots {
+            std::fs::remove_file(snapshot).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn periodic_flush() {
+        let path = std::env::temp_dir().join("rust-exercise-periodic-flush.json");
+        let _ = std::fs::remove_file(&path);
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .flush_interval(std::time::Duration::from_millis(10))
+            .flush_snapshot(&path)
+            .build();
+
+        let service = async {
+            let deposit = Transaction {
+                r#type: TransactionType::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some("1.0".parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(deposit).await.unwrap();
+            // The engine keeps running, idle, while the snapshot is written
+            while !path.exists() {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+            drop(sender);
+        };
+        let (processed, ()) = tokio::join!(payments_engine.process_transactions(), service);
+        processed.unwrap();
+
+        let (mut restored, _) = PaymentsEngine::new();
+        restored.load_snapshot(&path).unwrap();
+        assert_eq!(
+            restored.account(1).unwrap().available,
+            "1.0".parse().unwrap()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_event_log() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let transactions = [
+            (TransactionType::Deposit, 1, 1, Some("3.0")),
+            (TransactionType::Deposit, 2, 2, Some("1.0")),
+            (TransactionType::Withdrawal, 1, 3, Some("1.0")),
+            (TransactionType::Dispute, 2, 2, None),
+        ];
+        for (r#type, client, tx, amount) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client,
+                tx,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+        assert_eq!(payments_engine.events().len(), 4);
+
+        let (mut replayed, _) = PaymentsEngine::new();
+        replayed
+            .replay(payments_engine.events().iter().copied())
+            .unwrap();
+        for client in [1, 2] {
+            assert_eq!(replayed.account(client), payments_engine.account(client));
+        }
+    }
+
+    #[test]
+    fn reject_inconsistent_event_log() {
+        // An amendment of a disputed deposit, as no engine writes it, takes the held funds
+        // below zero once the dispute is resolved
+        let amount = |amount: &str| amount.parse().unwrap();
+        let events = [
+            AccountEvent::Deposited {
+                client: 1,
+                tx: 1,
+                amount: amount("1.0"),
+                fee: Amount::ZERO,
+                timestamp: None,
+            },
+            AccountEvent::DisputeOpened { client: 1, tx: 1 },
+            AccountEvent::Amended {
+                client: 1,
+                tx: 1,
+                amount: amount("3.0"),
+                fee: Amount::ZERO,
+            },
+            AccountEvent::DisputeResolved { client: 1, tx: 1 },
+        ];
+        let (mut payments_engine, _) = PaymentsEngine::new();
+        let error = payments_engine.replay(events).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(EngineError::InvariantViolated { client: 1, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn export_and_import_json() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let transactions = [
+            (TransactionType::Deposit, 1, 1, Some("3.0"), None),
+            (TransactionType::Deposit, 2, 2, Some("1.0"), None),
+            (TransactionType::Transfer, 1, 3, Some("1.0"), Some(2)),
+            (TransactionType::Dispute, 2, 2, None, None),
+        ];
+        for (r#type, client, tx, amount, counterparty) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client,
+                tx,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let mut document = Vec::new();
+        payments_engine.export_json(&mut document).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&document).unwrap();
+        assert_eq!(json["accounts"][0]["transactions"][1]["counterparty"], 2);
+        assert_eq!(
+            json["accounts"][1]["transactions"][0]["dispute"],
+            "Disputed"
+        );
+
+        let (mut imported, sender) = PaymentsEngine::with_workers(3);
+        imported.import_json(document.as_slice()).unwrap();
+        for client in [1, 2] {
+            assert_eq!(imported.account(client), payments_engine.account(client));
+        }
+        assert_eq!(imported.events(), payments_engine.events());
+        let mut exported_again = Vec::new();
+        imported.export_json(&mut exported_again).unwrap();
+        assert_eq!(exported_again, document);
+
+        // The dispute stays open, and the ids stay taken
+        imported.set_error_policy(ErrorPolicy::Lenient);
+        for (r#type, client, tx, amount) in [
+            (TransactionType::Resolve, 2, 2, None),
+            (TransactionType::Deposit, 2, 1, Some("5.0")),
+        ] {
+            let transaction = Transaction {
+                r#type,
+                client,
+                tx,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        imported.process_transactions().await.unwrap();
+        let account = imported.account(2).unwrap();
+        assert_eq!(account.available, "2.0".parse().unwrap());
+        assert_eq!(account.held, "0".parse().unwrap());
+
+        let corrupted =
+            String::from_utf8(document)
+                .unwrap()
+                .replacen("\"held\": \"0\"", "\"held\": \"1\"", 1);
+        assert!(imported.import_json(corrupted.as_bytes()).is_err());
+    }
+
+    #[tokio::test]
+    async fn state_at_point_in_time() {
+        let (mut payments_engine, sender) = PaymentsEngine::new();
+        let transactions = [
+            (TransactionType::Deposit, 1, Some("3.0"), Some(100)),
+            (TransactionType::Withdrawal, 2, Some("1.0"), Some(200)),
+            (TransactionType::Dispute, 1, None, None),
+            (TransactionType::Deposit, 3, Some("1.0"), Some(300)),
+        ];
+        for (r#type, tx, amount, timestamp) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client: 1,
+                tx,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty: None,
+                timestamp,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let balances = |at| {
+            let accounts = payments_engine.state_at(at).unwrap();
+            accounts
+                .iter()
+                .map(|account| (account.available.to_string(), account.held.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(balances(PointInTime::Events(0)), []);
+        assert_eq!(
+            balances(PointInTime::Events(1)),
+            [("3.0".into(), "0.0".into())]
+        );
+        assert_eq!(
+            balances(PointInTime::Timestamp(250)),
+            [("-1.0".into(), "3.0".into())]
+        );
+        assert_eq!(
+            balances(PointInTime::Timestamp(300)),
+            [("0.0".into(), "3.0".into())]
+        );
+        // The engine itself keeps its state
+        assert_eq!(
+            payments_engine.account(1).unwrap().held,
+            "3".parse().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn transfer_between_shards() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let transactions = [
+            (TransactionType::Deposit, 1, 1, Some("5.0"), None),
+            (TransactionType::Transfer, 1, 2, Some("2.0"), Some(2)),
+            (TransactionType::Transfer, 1, 3, Some("10.0"), Some(2)),
+            (TransactionType::Dispute, 1, 2, None, None),
+            (TransactionType::Chargeback, 1, 2, None, None),
+            (TransactionType::Deposit, 3, 4, Some("1.0"), None),
+            (TransactionType::Dispute, 3, 4, None, None),
+            (TransactionType::Chargeback, 3, 4, None, None),
+            (TransactionType::Deposit, 2, 5, Some("1.0"), None),
+            (TransactionType::Transfer, 2, 6, Some("0.5"), Some(3)),
+        ];
+        for (r#type, client, tx, amount, counterparty) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client,
+                tx,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let sender = payments_engine.account(1).unwrap();
+        assert_eq!(sender.available, "5.0".parse().unwrap());
+        assert!(sender.locked);
+        let receiver = payments_engine.account(2).unwrap();
+        assert_eq!(receiver.available, "1.0".parse().unwrap());
+        assert!(!receiver.locked);
+        assert_eq!(
+            payments_engine.account(3).unwrap().available,
+            "0".parse().unwrap()
+        );
+        let progress = payments_engine.progress().snapshot();
+        assert_eq!((progress.applied, progress.rejected), (8, 2));
+
+        let (mut replayed, _) = PaymentsEngine::new();
+        replayed
+            .replay(payments_engine.events().iter().copied())
+            .unwrap();
+        for client in [1, 2, 3] {
+            assert_eq!(replayed.account(client), payments_engine.account(client));
+        }
+    }
+
+    #[tokio::test]
+    async fn approve_staged_transfer() {
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(2)
+            .admin_commands(true)
+            .limits(Limits {
+                approval_threshold: Some("1.0".parse().unwrap()),
+                ..Limits::default()
+            })
+            .build();
+        let transactions = [
+            (TransactionType::Deposit, 1, Some("5.0"), None),
+            (TransactionType::Approve, 1, None, None),
+            (TransactionType::Transfer, 2, Some("2.0"), Some(2)),
+            (TransactionType::Transfer, 3, Some("3.0"), Some(2)),
+            (TransactionType::Approve, 2, None, None),
+            (TransactionType::Reject, 3, None, None),
+            (TransactionType::Approve, 3, None, None),
+        ];
+        for (r#type, tx, amount, counterparty) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client: 1,
+                tx,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let sender = payments_engine.account(1).unwrap();
+        assert_eq!(sender.available, "3.0".parse().unwrap());
+        let receiver = payments_engine.account(2).unwrap();
+        assert_eq!(receiver.available, "2.0".parse().unwrap());
+
+        let (mut replayed, _) = PaymentsEngine::new();
+        replayed
+            .replay(payments_engine.events().iter().copied())
+            .unwrap();
+        for client in [1, 2] {
+            assert_eq!(replayed.account(client), payments_engine.account(client));
+        }
+    }
+
+    #[tokio::test]
+    async fn reverse_transfer_chargeback() {
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(2)
+            .admin_commands(true)
+            .build();
+        let transactions = [
+            (TransactionType::Deposit, 1, Some("5.0"), None),
+            (TransactionType::Transfer, 2, Some("2.0"), Some(2)),
+            (TransactionType::Dispute, 2, None, None),
+            (TransactionType::Chargeback, 2, None, None),
+            (TransactionType::ChargebackReversal, 2, None, None),
+        ];
+        for (r#type, tx, amount, counterparty) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client: 1,
+                tx,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let sender = payments_engine.account(1).unwrap();
+        assert_eq!(sender.available, "3.0".parse().unwrap());
+        assert!(!sender.locked);
+        assert_eq!(
+            payments_engine.account(2).unwrap().available,
+            "2.0".parse().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn queue_until_unlock() {
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(2)
+            .admin_commands(true)
+            .locked_policy(LockedAccountPolicy::QueueUntilUnlock(8))
+            .build();
+        let transactions = [
+            (TransactionType::Deposit, 1, Some("5.0")),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Chargeback, 1, None),
+            (TransactionType::Deposit, 2, Some("2.0")),
+            (TransactionType::Withdrawal, 3, Some("1.5")),
+            (TransactionType::Unlock, 0, None),
+            (TransactionType::Deposit, 4, Some("1.0")),
+        ];
+        for (r#type, tx, amount) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client: 1,
+                tx,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let account = payments_engine.account(1).unwrap();
+        assert_eq!(account.available, "1.5".parse().unwrap());
+        assert!(!account.locked);
+        // The queued transactions are applied right after the unlock
+        let applied: Vec<_> = payments_engine.events()[3..]
+            .iter()
+            .map(|event| event.introduced_transaction())
+            .collect();
+        assert_eq!(applied, [None, Some(2), Some(3), Some(4)]);
+    }
+
+    #[tokio::test]
+    async fn reorder_by_timestamp() {
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(2)
+            .ordering(OrderingPolicy::Reorder(4))
+            .build();
+        let transactions = [
+            (TransactionType::Withdrawal, 2, "1.0", 20),
+            (TransactionType::Deposit, 1, "1.0", 10),
+        ];
+        for (r#type, tx, amount, timestamp) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client: 1,
+                tx,
+                amount: Some(amount.parse().unwrap()),
+                counterparty: None,
+                timestamp: Some(timestamp),
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        assert_eq!(
+            payments_engine.account(1).unwrap().available,
+            "0".parse().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn query_account_while_processing() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let queries = payments_engine.query_handle();
+
+        let producer = tokio::spawn(async move {
+            let deposit = Transaction {
+                r#type: TransactionType::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some("1.0".parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(deposit).await.unwrap();
+
+            let account = queries.account(1).await.unwrap();
+            assert_eq!(account.available, "1.0".parse().unwrap());
+            assert!(queries.account(2).await.is_none());
+            assert!(queries.sync().await);
+            queries
+        });
+
+        payments_engine.process_transactions().await.unwrap();
+        let queries = producer.await.unwrap();
+        assert!(queries.account(1).await.is_none());
+        assert!(!queries.sync().await);
+    }
+
+    #[tokio::test]
+    async fn publish_account_updates() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let mut updates = payments_engine.query_handle().account_updates();
+        let transaction = |r#type, client, tx, amount: &str, counterparty| Transaction {
+            r#type,
+            client,
+            tx,
+            amount: amount.parse().ok(),
+            counterparty,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        for transaction in [
+            transaction(TransactionType::Deposit, 1, 1, "2.0", None),
+            // Declined, so nothing changes
+            transaction(TransactionType::Withdrawal, 1, 2, "5.0", None),
+            transaction(TransactionType::Transfer, 1, 3, "0.5", Some(2)),
+        ] {
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let mut received = Vec::new();
+        while let Ok(account) = updates.try_recv() {
+            received.push((account.client, account.available));
+        }
+        received.sort_unstable();
+        let amount = |amount: &str| amount.parse().unwrap();
+        assert_eq!(
+            received,
+            [(1, amount("1.5")), (1, amount("2.0")), (2, amount("0.5"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn channel_metrics() {
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(1)
+            .channel_capacity(4)
+            .build();
+
+        for tx in 0..4 {
+            let deposit = Transaction {
+                r#type: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some("1.0".parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(deposit).await.unwrap();
+        }
+        drop(sender);
+
+        payments_engine.process_transactions().await.unwrap();
+        let metrics = payments_engine.channel_metrics();
+        assert_eq!(metrics.received, 4);
+        assert_eq!(metrics.peak_backlog, 4);
+        assert_eq!(metrics.saturated, 1);
+    }
+
+    #[tokio::test]
+    async fn process_batches() {
+        let deposit = |client, tx| Transaction {
+            r#type: TransactionType::Deposit,
+            client,
+            tx,
+            amount: Some("1.0".parse().unwrap()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let batch_sender = payments_engine.batch_sender();
+        batch_sender
+            .send(vec![deposit(1, 1), deposit(2, 2), deposit(1, 3)].into())
+            .await
+            .unwrap();
+        sender.send(deposit(2, 4)).await.unwrap();
+        drop((sender, batch_sender));
+
+        payments_engine.process_transactions().await.unwrap();
+        assert_eq!(payments_engine.channel_metrics().received, 4);
+        assert!(payments_engine.batch_sender().is_closed());
+
+        // Batches are applied directly as well, one after another
+        let (mut payments_engine, _) = PaymentsEngine::with_workers(2);
+        payments_engine
+            .apply_batch(vec![deposit(1, 1), deposit(2, 2)])
+            .await
+            .unwrap();
+        payments_engine
+            .apply_batch(vec![deposit(1, 3)])
+            .await
+            .unwrap();
+        let account = payments_engine.account(1).unwrap();
+        assert_eq!(account.available, "2.0".parse().unwrap());
+        assert_eq!(payments_engine.events().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn report_rejections_by_reason() {
+        let (mut payments_engine, sender) = PaymentsEngine::builder().strict(false).build();
+        let transaction = |r#type, client, tx, amount: &str| Transaction {
+            r#type,
+            client,
+            tx,
+            amount: amount.parse().ok(),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        for transaction in [
+            transaction(TransactionType::Deposit, 1, 1, "2.0"),
+            transaction(TransactionType::Withdrawal, 1, 2, "3.0"),
+            transaction(TransactionType::Deposit, 2, 2, "1.0"),
+            transaction(TransactionType::Deposit, 2, 3, "1.0"),
+            transaction(TransactionType::Dispute, 2, 3, ""),
+            transaction(TransactionType::Chargeback, 2, 3, ""),
+            transaction(TransactionType::Deposit, 1, 4, "1.0"),
+            transaction(TransactionType::Dispute, 1, 4, ""),
+        ] {
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+
+        payments_engine.process_transactions().await.unwrap();
+        let report = payments_engine.report().unwrap();
+        assert_eq!(report.transactions, 8);
+        assert_eq!(report.transactions_by_type[&TransactionType::Deposit], 4);
+        assert_eq!(report.transactions_by_type[&TransactionType::Dispute], 2);
+        assert_eq!(report.rejected["Insufficient funds"], 1);
+        assert_eq!(report.rejected["Duplicate transaction id"], 1);
+        assert_eq!(report.rejected.len(), 2);
+        assert_eq!(report.locked_accounts, 1);
+        assert_eq!(report.ghost_accounts, 0);
+        assert_eq!(report.total_held, "1.0".parse().unwrap());
+        assert_eq!(report.accounts, 2);
+        assert_eq!(report.total_available, "2.0".parse().unwrap());
+
+        let mut summary = Vec::new();
+        payments_engine
+            .write_summary_json(&mut summary, std::time::Duration::from_millis(1500))
+            .unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&summary).unwrap();
+        assert_eq!(summary["transactions"], 8);
+        assert_eq!(summary["applied"], 6);
+        assert_eq!(summary["rejected"], 2);
+        assert_eq!(summary["transactions_by_type"]["deposit"], 4);
+        assert_eq!(summary["rejected_by_reason"]["Insufficient funds"], 1);
+        assert_eq!(summary["total"], "3.0");
+        assert_eq!(summary["runtime_seconds"], 1.5);
+    }
+
+    #[tokio::test]
+    async fn unknown_clients() {
+        let transaction = |r#type, client, tx| Transaction {
+            r#type,
+            client,
+            tx,
+            amount: (r#type == TransactionType::Deposit).then(|| "1.0".parse().unwrap()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        let transactions = vec![
+            transaction(TransactionType::Deposit, 1, 1),
+            transaction(TransactionType::Dispute, 1, 1),
+            transaction(TransactionType::Dispute, 2, 2),
+            transaction(TransactionType::Chargeback, 3, 3),
+        ];
+
+        for (policy, accounts, ghosts, rejected) in [
+            (UnknownClientPolicy::Create, 3, 2, None),
+            (UnknownClientPolicy::Skip, 1, 0, Some("No such transaction")),
+            (UnknownClientPolicy::Reject, 1, 0, Some("Unknown client")),
+        ] {
+            let (mut payments_engine, _) = PaymentsEngine::builder()
+                .workers(2)
+                .strict(false)
+                .unknown_client_policy(policy)
+                .build();
+            payments_engine
+                .apply_batch(transactions.clone())
+                .await
+                .unwrap();
+            let report = payments_engine.report().unwrap();
+            assert_eq!(report.accounts, accounts, "{policy:?}");
+            assert_eq!(report.ghost_accounts, ghosts, "{policy:?}");
+            assert_eq!(report.total_held, "1.0".parse().unwrap());
+            if let Some(reason) = rejected {
+                assert_eq!(report.rejected[reason], 2, "{policy:?}");
+            }
+        }
+
+        // A strict engine stops at the first rejected reference
+        let (mut payments_engine, _) = PaymentsEngine::builder()
+            .unknown_client_policy(UnknownClientPolicy::Reject)
+            .build();
+        let error = payments_engine.apply_batch(transactions).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref().map(EngineError::without_context),
+            Some(EngineError::UnknownClient(2, 2))
+        ));
+    }
+
+    #[tokio::test]
+    async fn report_open_disputes() {
+        // Only one record is kept in memory, the others are found on disk
+        let (mut payments_engine, _) = PaymentsEngine::builder()
+            .history_spill(HistorySpill::open(std::env::temp_dir(), 1).unwrap())
+            .clock(FixedClock(1000))
+            .build();
+        let transaction = |r#type, client, tx, timestamp| Transaction {
+            r#type,
+            client,
+            tx,
+            amount: (r#type == TransactionType::Deposit).then(|| "1.5".parse().unwrap()),
+            counterparty: None,
+            timestamp,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        payments_engine
+            .apply_batch(vec![
+                transaction(TransactionType::Deposit, 2, 1, Some(400)),
+                transaction(TransactionType::Deposit, 1, 2, None),
+                transaction(TransactionType::Deposit, 1, 3, Some(900)),
+                transaction(TransactionType::Deposit, 1, 4, None),
+                transaction(TransactionType::Dispute, 2, 1, None),
+                transaction(TransactionType::Dispute, 1, 3, None),
+                transaction(TransactionType::Dispute, 1, 2, None),
+                transaction(TransactionType::Dispute, 1, 4, None),
+                transaction(TransactionType::Resolve, 1, 4, None),
+            ])
+            .await
+            .unwrap();
+
+        let disputes = payments_engine.open_disputes().unwrap();
+        let summary: Vec<_> = disputes
+            .iter()
+            .map(|dispute| (dispute.client, dispute.tx, dispute.age))
+            .collect();
+        assert_eq!(
+            summary,
+            [(1, 2, None), (1, 3, Some(100)), (2, 1, Some(600))]
+        );
+
+        let mut report = Vec::new();
+        payments_engine.write_disputes_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,tx,amount,age\n1,2,1.5,\n1,3,1.5,100\n2,1,1.5,600\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn accounts_sorted_by_client() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(4);
+        for client in [7, 3, 12, 1] {
+            let deposit = Transaction {
+                r#type: TransactionType::Deposit,
+                client,
+                tx: client.into(),
+                amount: Some("1.0".parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(deposit).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let mut output = Vec::new();
+        payments_engine.write_accounts(&mut output).unwrap();
+        let clients: Vec<_> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().to_owned())
+            .collect();
+        assert_eq!(clients, ["1", "3", "7", "12"]);
+    }
+
+    #[tokio::test]
+    async fn configure_with_builder() {
+        const DAY: u64 = 24 * 60 * 60;
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(2)
+            .strict(false)
+            .history_retention(HistoryRetention::Latest(1))
+            .limits(Limits {
+                max_daily_withdrawal: Some("10.0".parse().unwrap()),
+                ..Limits::default()
+            })
+            .clock(FixedClock(100 * DAY))
+            .build();
+
+        let transactions = [
+            (TransactionType::Deposit, 1, Some("20.0"), None),
+            (TransactionType::Deposit, 2, Some("5.0"), None),
+            // Forgotten, since only the latest transaction is retained
+            (TransactionType::Dispute, 1, None, None),
+            // Invalid and skipped in lenient mode
+            (TransactionType::Deposit, 3, None, None),
+            (TransactionType::Withdrawal, 4, Some("8.0"), None),
+            // Exceeds the daily limit on the day of the clock
+            (
+                TransactionType::Withdrawal,
+                5,
+                Some("5.0"),
+                Some(100 * DAY + 60),
+            ),
+        ];
+        for (r#type, tx, amount, timestamp) in transactions {
+            let transaction = Transaction {
+                r#type,
+                client: 1,
+                tx,
+                amount: amount.map(|amount| amount.parse().unwrap()),
+                counterparty: None,
+                timestamp,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let account = payments_engine.account(1).unwrap();
+        assert_eq!(account.available, "17.0".parse().unwrap());
+        assert_eq!(account.held, "0".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn admin_commands_disabled_by_default() {
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(1);
+
+        let unlock = Transaction {
+            r#type: TransactionType::Unlock,
+            client: 1,
+            tx: 1,
+            amount: None,
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        sender.send(unlock).await.unwrap();
+        drop(sender);
+
+        let error = payments_engine.process_transactions().await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref().map(EngineError::without_context),
+            Some(EngineError::AdminCommandsDisabled(1))
+        ));
     }
 }
@@ -0,0 +1,127 @@
+use crate::{error::EngineError, event::AccountEvent, transaction::TransactionId};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+/// Historical point the accounts are reconstructed at, see
+/// [`crate::PaymentsEngine::state_at`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PointInTime {
+    /// After the first `n` events of the event log, as written to a snapshot
+    Events(usize),
+    /// After the last transaction of each account with a timestamp up to these seconds since
+    /// the Unix epoch
+    Timestamp(u64),
+}
+
+impl PointInTime {
+    /// Events of `events` that happened up to this point.
+    ///
+    /// Events without timestamp, like disputes, happened at the time of the latest event of
+    /// their account with one. Received transfers happened at the time they were sent.
+    pub(crate) fn events(self, events: &[AccountEvent]) -> Vec<&AccountEvent> {
+        let at = match self {
+            PointInTime::Events(n) => return events.iter().take(n).collect(),
+            PointInTime::Timestamp(at) => at,
+        };
+        let sent: HashMap<TransactionId, u64> = events
+            .iter()
+            .filter_map(|event| match *event {
+                AccountEvent::TransferredOut {
+                    tx,
+                    timestamp: Some(timestamp),
+                    ..
+                } => Some((tx, timestamp)),
+                _ => None,
+            })
+            .collect();
+        // Accounts whose events from here on are later than `at`
+        let mut later = HashSet::new();
+        events
+            .iter()
+            .filter(|event| {
+                let timestamp = match **event {
+                    AccountEvent::TransferredIn { tx, .. } => sent.get(&tx).copied(),
+                    _ => event.timestamp(),
+                };
+                if timestamp.is_some_and(|timestamp| timestamp > at) {
+                    later.insert(event.client());
+                }
+                !later.contains(&event.client())
+            })
+            .collect()
+    }
+}
+
+/// Parses `<n>` as a number of events and `@<seconds>` as a timestamp.
+impl FromStr for PointInTime {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || EngineError::InvalidArgumentValue("--at".into(), s.into());
+        match s.strip_prefix('@') {
+            Some(seconds) => seconds
+                .parse()
+                .map(PointInTime::Timestamp)
+                .map_err(|_| invalid()),
+            None => s.parse().map(PointInTime::Events).map_err(|_| invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PointInTime;
+    use crate::{amount::Amount, event::AccountEvent};
+
+    #[test]
+    fn events_up_to_a_point() {
+        assert_eq!("2".parse::<PointInTime>().unwrap(), PointInTime::Events(2));
+        assert_eq!(
+            "@1700000000".parse::<PointInTime>().unwrap(),
+            PointInTime::Timestamp(1_700_000_000)
+        );
+        assert!("@today".parse::<PointInTime>().is_err());
+
+        let one: Amount = "1".parse().unwrap();
+        let deposit = |client, tx, timestamp| AccountEvent::Deposited {
+            client,
+            tx,
+            amount: one,
+            fee: Amount::ZERO,
+            timestamp,
+        };
+        let events = [
+            deposit(1, 1, Some(100)),
+            AccountEvent::DisputeOpened { client: 1, tx: 1 },
+            deposit(1, 2, Some(200)),
+            AccountEvent::DisputeResolved { client: 1, tx: 1 },
+            deposit(2, 3, None),
+            AccountEvent::TransferredOut {
+                client: 1,
+                tx: 4,
+                counterparty: 2,
+                amount: one,
+                timestamp: Some(300),
+            },
+            AccountEvent::TransferredIn {
+                client: 2,
+                tx: 4,
+                counterparty: 1,
+                amount: one,
+            },
+        ];
+        let at = |point: PointInTime| -> Vec<AccountEvent> {
+            point.events(&events).into_iter().copied().collect()
+        };
+        assert_eq!(at(PointInTime::Events(2)), events[..2]);
+        assert_eq!(at(PointInTime::Events(99)), events);
+        assert_eq!(
+            at(PointInTime::Timestamp(150)),
+            [events[0], events[1], events[4]]
+        );
+        assert_eq!(at(PointInTime::Timestamp(250)), events[..5]);
+        assert_eq!(at(PointInTime::Timestamp(300)), events);
+    }
+}
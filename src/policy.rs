@@ -0,0 +1,158 @@
+use crate::{error::ErrorPolicy, fees::FeeSchedule, limits::Limits};
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+// Files are often truncated before they are written, so a changed file is read after this delay
+const RELOAD_DELAY: Duration = Duration::from_millis(50);
+
+/// Policies that can be changed while the engine runs, read from a TOML file like
+///
+/// ```toml
+/// error_policy = "lenient"
+///
+/// [limits]
+/// max_withdrawal = "500.0"
+///
+/// [fees]
+/// withdrawal = { flat = "0.5" }
+/// ```
+#[derive(Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Handling of invalid transactions, the one the engine was built with if not given
+    pub error_policy: Option<ErrorPolicy>,
+    #[serde(default)]
+    pub limits: Limits,
+    pub fees: Option<FeeSchedule>,
+}
+
+impl Policy {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+/// Policy shared with the engine, see [`crate::EngineBuilder::live_policy`], which is swapped
+/// atomically. Transactions processed after a swap are subject to the new policy.
+#[derive(Clone, Default, Debug)]
+pub struct LivePolicy {
+    current: Arc<ArcSwap<Policy>>,
+}
+
+impl LivePolicy {
+    pub fn new(policy: Policy) -> Self {
+        LivePolicy {
+            current: Arc::new(ArcSwap::from_pointee(policy)),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(Policy::load(path)?))
+    }
+
+    pub fn current(&self) -> Arc<Policy> {
+        self.current.load_full()
+    }
+
+    pub fn set(&self, policy: Policy) {
+        self.current.store(Arc::new(policy));
+    }
+
+    /// Reloads the policy whenever the file at `path` changes, until the returned watcher is
+    /// dropped. A file that can't be read or parsed is logged, the policy before is kept.
+    pub fn watch<P: AsRef<Path>>(&self, path: P) -> Result<RecommendedWatcher> {
+        let path = path.as_ref().to_path_buf();
+        let file_name = path.file_name().map(ToOwned::to_owned);
+        // Editors often replace the file instead of writing it, so its directory is watched
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let live_policy = self.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            let changed = event.kind.is_create() || event.kind.is_modify();
+            if !changed
+                || !event
+                    .paths
+                    .iter()
+                    .any(|changed| changed.file_name() == file_name.as_deref())
+            {
+                return;
+            }
+            thread::sleep(RELOAD_DELAY);
+            match Policy::load(&path) {
+                Ok(policy) if *live_policy.current() == policy => {}
+                Ok(policy) => {
+                    tracing::info!(path = %path.display(), "Reloaded the policy");
+                    live_policy.set(policy);
+                }
+                Err(error) => {
+                    tracing::warn!(path = %path.display(), %error, "Keeping the previous policy")
+                }
+            }
+        })?;
+        watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LivePolicy, Policy};
+    use crate::error::ErrorPolicy;
+    use std::{fs, thread, time::Duration};
+
+    #[test]
+    fn parse_policy() {
+        let policy: Policy = toml::from_str(concat!(
+            "error_policy = \"lenient\"\n",
+            "[limits]\n",
+            "max_withdrawal = \"500.0\"\n",
+            "[fees]\n",
+            "withdrawal = { flat = \"0.5\" }\n",
+        ))
+        .unwrap();
+        assert_eq!(policy.error_policy, Some(ErrorPolicy::Lenient));
+        assert_eq!(policy.limits.max_withdrawal, Some("500".parse().unwrap()));
+        assert_eq!(policy.fees.unwrap().withdrawal.flat, "0.5".parse().unwrap());
+
+        assert!(toml::from_str::<Policy>("workers = 4").is_err());
+    }
+
+    #[test]
+    fn reload_on_change() {
+        let path = std::env::temp_dir().join("rust-exercise-reload-on-change.toml");
+        fs::write(&path, "[limits]\nmax_deposit = \"10\"\n").unwrap();
+        let live_policy = LivePolicy::load(&path).unwrap();
+        let _watcher = live_policy.watch(&path).unwrap();
+
+        fs::write(&path, "[limits]\nmax_deposit = \"20\"\n").unwrap();
+        let reloaded = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            live_policy.current().limits.max_deposit == Some("20".parse().unwrap())
+        });
+        assert!(reloaded);
+
+        // An invalid file keeps the policy before
+        fs::write(&path, "[limits\n").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(
+            live_policy.current().limits.max_deposit,
+            Some("20".parse().unwrap())
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+}
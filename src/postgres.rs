@@ -0,0 +1,299 @@
+use crate::{
+    account::AccountView,
+    observer::{EngineObserver, Rejection},
+    transaction::Transaction,
+};
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    oneshot,
+};
+use tokio_postgres::{Client, NoTls};
+
+/// Rows written with a single statement, unless configured otherwise.
+pub const DEFAULT_POSTGRES_BATCH_SIZE: usize = 1000;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client BIGINT PRIMARY KEY,
+        available NUMERIC NOT NULL,
+        held NUMERIC NOT NULL,
+        total NUMERIC NOT NULL,
+        locked BOOLEAN NOT NULL,
+        fees_collected NUMERIC,
+        updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    CREATE TABLE IF NOT EXISTS audit (
+        id BIGSERIAL PRIMARY KEY,
+        client BIGINT NOT NULL,
+        tx NUMERIC(20) NOT NULL,
+        type TEXT NOT NULL,
+        amount NUMERIC,
+        counterparty BIGINT,
+        outcome TEXT NOT NULL,
+        reason TEXT,
+        recorded_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+";
+
+// Amounts are passed as text and converted by Postgres, which keeps them exact
+const UPSERT_ACCOUNTS: &str = "
+    INSERT INTO accounts (client, available, held, total, locked, fees_collected)
+    SELECT client, available::numeric, held::numeric, total::numeric, locked, fees::numeric
+    FROM UNNEST($1::int8[], $2::text[], $3::text[], $4::text[], $5::bool[], $6::text[])
+        AS row(client, available, held, total, locked, fees)
+    ON CONFLICT (client) DO UPDATE SET
+        available = EXCLUDED.available,
+        held = EXCLUDED.held,
+        total = EXCLUDED.total,
+        locked = EXCLUDED.locked,
+        fees_collected = EXCLUDED.fees_collected,
+        updated_at = now()
+";
+
+// Transaction ids are passed as text as well, they may exceed a BIGINT
+const APPEND_AUDIT: &str = "
+    INSERT INTO audit (client, tx, type, amount, counterparty, outcome, reason)
+    SELECT client, tx::numeric, type, amount::numeric, counterparty, outcome, reason
+    FROM UNNEST(
+        $1::int8[], $2::text[], $3::text[], $4::text[], $5::int8[], $6::text[], $7::text[]
+    ) AS row(client, tx, type, amount, counterparty, outcome, reason)
+";
+
+/// Writes the accounts and the outcome of every transaction to Postgres, for reconciliation.
+///
+/// The tables `accounts` and `audit` are created if they don't exist. Accounts are upserted by
+/// client, audit records are appended while the engine processes the transactions.
+pub struct PostgresSink {
+    client: Arc<Client>,
+    batch_size: usize,
+    // Feeds the background writer of the audit records, once it is started
+    audit: Option<UnboundedSender<AuditMessage>>,
+}
+
+/// Observer appending the outcome of every transaction to the audit table of a
+/// [`PostgresSink`].
+#[derive(Clone, Debug)]
+pub struct PostgresAudit {
+    sink: UnboundedSender<AuditMessage>,
+}
+
+#[derive(Debug)]
+enum AuditMessage {
+    Record(AuditRow),
+    // Write the records received so far and report whether all writes succeeded
+    Flush(oneshot::Sender<Result<(), String>>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct AuditRow {
+    client: i64,
+    tx: String,
+    r#type: String,
+    amount: Option<String>,
+    counterparty: Option<i64>,
+    outcome: &'static str,
+    reason: Option<String>,
+}
+
+impl PostgresSink {
+    /// Connects to the database given by the connection string `config`, e.g.
+    /// `host=localhost user=postgres`, and creates the tables.
+    pub async fn connect(config: &str, batch_size: usize) -> Result<Self> {
+        let (client, connection) = tokio_postgres::connect(config, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(error) = connection.await {
+                tracing::error!(%error, "Postgres connection failed");
+            }
+        });
+        client.batch_execute(SCHEMA).await?;
+        Ok(PostgresSink {
+            client: Arc::new(client),
+            batch_size: batch_size.max(1),
+            audit: None,
+        })
+    }
+
+    /// Returns the observer to register with [`crate::EngineBuilder::observer`], whose records
+    /// are written in batches in the background.
+    pub fn audit_observer(&mut self) -> PostgresAudit {
+        let (client, batch_size) = (self.client.clone(), self.batch_size);
+        let sink = self.audit.get_or_insert_with(|| {
+            let (sink, messages) = unbounded_channel();
+            tokio::spawn(write_audit(client, messages, batch_size));
+            sink
+        });
+        PostgresAudit { sink: sink.clone() }
+    }
+
+    /// Waits until the audit records of all transactions processed so far are written.
+    pub async fn flush_audit(&self) -> Result<()> {
+        let Some(sink) = &self.audit else {
+            return Ok(());
+        };
+        let (reply, written) = oneshot::channel();
+        sink.send(AuditMessage::Flush(reply))
+            .map_err(|_| anyhow!("The Postgres audit writer stopped"))?;
+        written
+            .await
+            .map_err(|_| anyhow!("The Postgres audit writer stopped"))?
+            .map_err(|error| anyhow!("Writing audit records to Postgres failed: {}", error))
+    }
+
+    /// Inserts or updates the rows of `accounts`, `batch_size` accounts per statement.
+    pub async fn upsert_accounts(&self, accounts: &[AccountView]) -> Result<()> {
+        for accounts in accounts.chunks(self.batch_size) {
+            let amounts = |amount: fn(&AccountView) -> String| -> Vec<String> {
+                accounts.iter().map(amount).collect()
+            };
+            let clients: Vec<i64> = accounts
+                .iter()
+                .map(|account| i64::from(account.client))
+                .collect();
+            let locked: Vec<bool> = accounts.iter().map(|account| account.locked).collect();
+            let fees: Vec<Option<String>> = accounts
+                .iter()
+                .map(|account| account.fees_collected.map(|fees| fees.to_string()))
+                .collect();
+            self.client
+                .execute(
+                    UPSERT_ACCOUNTS,
+                    &[
+                        &clients,
+                        &amounts(|account| account.available.to_string()),
+                        &amounts(|account| account.held.to_string()),
+                        &amounts(|account| account.total.to_string()),
+                        &locked,
+                        &fees,
+                    ],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+impl EngineObserver for PostgresAudit {
+    fn on_transaction_applied(&self, transaction: &Transaction, _account: &AccountView) {
+        self.record(AuditRow::new(transaction, "accepted", None));
+    }
+
+    fn on_rejected(&self, transaction: &Transaction, rejection: Rejection<'_>) {
+        let outcome = match rejection {
+            Rejection::Declined(_) => "ignored",
+            Rejection::Invalid(_) => "rejected",
+        };
+        let reason = Some(rejection.to_string());
+        self.record(AuditRow::new(transaction, outcome, reason));
+    }
+}
+
+impl PostgresAudit {
+    fn record(&self, row: AuditRow) {
+        // A stopped writer reports its error on the next flush
+        let _ = self.sink.send(AuditMessage::Record(row));
+    }
+}
+
+impl AuditRow {
+    fn new(transaction: &Transaction, outcome: &'static str, reason: Option<String>) -> Self {
+        AuditRow {
+            client: i64::from(transaction.client),
+            tx: transaction.tx.to_string(),
+            r#type: transaction.r#type.to_string(),
+            amount: transaction.amount.map(|amount| amount.to_string()),
+            counterparty: transaction.counterparty.map(i64::from),
+            outcome,
+            reason,
+        }
+    }
+}
+
+// Writes the audit records in batches until all observers are dropped, the first failure is
+// reported to every later flush
+async fn write_audit(
+    client: Arc<Client>,
+    mut messages: UnboundedReceiver<AuditMessage>,
+    batch_size: usize,
+) {
+    let mut received = Vec::with_capacity(batch_size);
+    let mut failure = None;
+    while messages.recv_many(&mut received, batch_size).await > 0 {
+        let mut rows = Vec::with_capacity(received.len());
+        for message in received.drain(..) {
+            match message {
+                AuditMessage::Record(row) => rows.push(row),
+                AuditMessage::Flush(reply) => {
+                    append(&client, &mut rows, &mut failure).await;
+                    let _ = reply.send(failure.clone().map_or(Ok(()), Err));
+                }
+            }
+        }
+        append(&client, &mut rows, &mut failure).await;
+    }
+}
+
+async fn append(client: &Client, rows: &mut Vec<AuditRow>, failure: &mut Option<String>) {
+    if rows.is_empty() || failure.is_some() {
+        rows.clear();
+        return;
+    }
+    let column = |value: fn(&AuditRow) -> Option<String>| -> Vec<Option<String>> {
+        rows.iter().map(value).collect()
+    };
+    let clients: Vec<i64> = rows.iter().map(|row| row.client).collect();
+    let counterparties: Vec<Option<i64>> = rows.iter().map(|row| row.counterparty).collect();
+    let result = client
+        .execute(
+            APPEND_AUDIT,
+            &[
+                &clients,
+                &column(|row| Some(row.tx.clone())),
+                &column(|row| Some(row.r#type.clone())),
+                &column(|row| row.amount.clone()),
+                &counterparties,
+                &column(|row| Some(row.outcome.to_owned())),
+                &column(|row| row.reason.clone()),
+            ],
+        )
+        .await;
+    if let Err(error) = result {
+        tracing::error!(%error, "Writing audit records to Postgres failed");
+        *failure = Some(error.to_string());
+    }
+    rows.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AuditRow;
+    use crate::transaction::{Transaction, TransactionType};
+
+    #[test]
+    fn audit_rows() {
+        let transfer = Transaction {
+            r#type: TransactionType::Transfer,
+            client: 1,
+            tx: 4_294_967_295,
+            amount: Some("1.5".parse().unwrap()),
+            counterparty: Some(2),
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        let row = AuditRow::new(&transfer, "ignored", Some("Insufficient funds".into()));
+        assert_eq!(
+            row,
+            AuditRow {
+                client: 1,
+                tx: "4294967295".into(),
+                r#type: "transfer".into(),
+                amount: Some("1.5".into()),
+                counterparty: Some(2),
+                outcome: "ignored",
+                reason: Some("Insufficient funds".into()),
+            }
+        );
+    }
+}
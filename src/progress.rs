@@ -0,0 +1,111 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+/// Cloneable counters of the transactions flowing through the collector and the engine.
+#[derive(Clone, Default, Debug)]
+pub struct Progress {
+    counters: Arc<Counters>,
+}
+
+#[derive(Default, Debug)]
+struct Counters {
+    rows_read: AtomicU64,
+    applied: AtomicU64,
+    rejected: AtomicU64,
+}
+
+/// Values of the [`Progress`] counters at one point in time.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct ProgressSnapshot {
+    /// Rows read from the input, including invalid ones
+    pub rows_read: u64,
+    /// Transactions applied to an account
+    pub applied: u64,
+    /// Transactions that were invalid, or not applied because their account is locked
+    pub rejected: u64,
+}
+
+impl Progress {
+    pub(crate) fn record_row(&self) {
+        self.counters.rows_read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_applied(&self) {
+        self.counters.applied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rejected(&self) {
+        self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            rows_read: self.counters.rows_read.load(Ordering::Relaxed),
+            applied: self.counters.applied.load(Ordering::Relaxed),
+            rejected: self.counters.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reports the progress on stderr every `interval` until [`ProgressReporter::finish`] is called.
+    pub fn report_every(&self, interval: Duration) -> ProgressReporter {
+        let progress = self.clone();
+        let started = Instant::now();
+        let task = tokio::spawn(async move {
+            let mut ticks =
+                tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            let mut last = ProgressSnapshot::default();
+            loop {
+                ticks.tick().await;
+                let current = progress.snapshot();
+                let rate = (current.rows_read - last.rows_read) as f64 / interval.as_secs_f64();
+                eprintln!("Progress: {current}, rows/sec: {rate:.0}");
+                last = current;
+            }
+        });
+
+        ProgressReporter {
+            progress: self.clone(),
+            started,
+            task,
+        }
+    }
+}
+
+impl fmt::Display for ProgressSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rows read: {}, applied: {}, rejected: {}",
+            self.rows_read, self.applied, self.rejected
+        )
+    }
+}
+
+/// Background task started by [`Progress::report_every`].
+pub struct ProgressReporter {
+    progress: Progress,
+    started: Instant,
+    task: JoinHandle<()>,
+}
+
+impl ProgressReporter {
+    /// Stops the periodic reports and reports the totals with the average throughput.
+    pub fn finish(self) {
+        self.task.abort();
+        let total = self.progress.snapshot();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            total.rows_read as f64 / elapsed
+        } else {
+            0.0
+        };
+        eprintln!("Finished: {total}, rows/sec: {rate:.0}");
+    }
+}
@@ -0,0 +1,213 @@
+use crate::error::EngineError;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Buckets of connections that are idle long enough to be full again are dropped once there are
+// more connections than this
+const MAX_CONNECTIONS: usize = 1024;
+
+/// Transactions per second, of which up to a second's worth are admitted at once.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RateLimit(f64);
+
+impl RateLimit {
+    pub fn per_second(self) -> f64 {
+        self.0
+    }
+
+    // Transactions admitted at once after an idle second, at least one
+    fn burst(self) -> f64 {
+        self.0.max(1.0)
+    }
+}
+
+/// Parses a positive number of transactions per second, e.g. `100` or `0.5`.
+impl FromStr for RateLimit {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<f64>() {
+            Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(RateLimit(rate)),
+            _ => Err(EngineError::InvalidArgumentValue(
+                "--rate-limit".into(),
+                s.into(),
+            )),
+        }
+    }
+}
+
+/// Rate limits of the transactions submitted to a server.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct RateLimits {
+    /// Over all connections
+    pub global: Option<RateLimit>,
+    /// Of every connection
+    pub per_connection: Option<RateLimit>,
+}
+
+/// Token buckets enforcing [`RateLimits`], which are checked before a transaction is queued for
+/// the engine. A transaction over the limits is turned away with the time the client should back
+/// off, so bursts don't build up queues.
+#[derive(Clone, Default, Debug)]
+pub struct RateLimiter {
+    global: Option<Arc<Mutex<TokenBucket>>>,
+    per_connection: Option<RateLimit>,
+    connections: Arc<Mutex<HashMap<SocketAddr, TokenBucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        let now = Instant::now();
+        RateLimiter {
+            global: limits
+                .global
+                .map(|limit| Arc::new(Mutex::new(TokenBucket::new(limit, now)))),
+            per_connection: limits.per_connection,
+            connections: Arc::default(),
+        }
+    }
+
+    /// Admits a transaction of the connection from `peer`, or returns how long to wait until it
+    /// would be. Without a peer only the global limit applies.
+    pub fn check(&self, peer: Option<SocketAddr>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut connections = lock(&self.connections);
+        let connection = match (self.per_connection, peer) {
+            (Some(limit), Some(peer)) => {
+                if connections.len() >= MAX_CONNECTIONS && !connections.contains_key(&peer) {
+                    connections.retain(|_, bucket| !bucket.is_full(now));
+                }
+                Some(
+                    connections
+                        .entry(peer)
+                        .or_insert_with(|| TokenBucket::new(limit, now)),
+                )
+            }
+            _ => None,
+        };
+        let mut global = self.global.as_deref().map(lock);
+
+        // A token is only taken once both buckets have one, a denied transaction costs nothing
+        let backoff = [connection.as_deref(), global.as_deref()]
+            .into_iter()
+            .flatten()
+            .map(|bucket| bucket.backoff(now))
+            .max()
+            .unwrap_or_default();
+        if !backoff.is_zero() {
+            return Err(backoff);
+        }
+        for bucket in [connection, global.as_deref_mut()].into_iter().flatten() {
+            bucket.take(now);
+        }
+        Ok(())
+    }
+
+    /// Waits until a transaction of the connection from `peer` is admitted, for protocols
+    /// without replies. The connection isn't read meanwhile, which slows the sender down.
+    pub async fn wait(&self, peer: Option<SocketAddr>) {
+        while let Err(backoff) = self.check(peer) {
+            tokio::time::sleep(backoff).await;
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit, now: Instant) -> Self {
+        TokenBucket {
+            limit,
+            tokens: limit.burst(),
+            refilled: now,
+        }
+    }
+
+    // Tokens available at `now`
+    fn available(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        (self.tokens + elapsed * self.limit.per_second()).min(self.limit.burst())
+    }
+
+    fn is_full(&self, now: Instant) -> bool {
+        self.available(now) >= self.limit.burst()
+    }
+
+    // Time until a token is available, zero if one is
+    fn backoff(&self, now: Instant) -> Duration {
+        let missing = 1.0 - self.available(now);
+        if missing > 0.0 {
+            Duration::from_secs_f64(missing / self.limit.per_second())
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    fn take(&mut self, now: Instant) {
+        self.tokens = self.available(now) - 1.0;
+        self.refilled = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, RateLimiter, RateLimits};
+    use std::{net::SocketAddr, time::Duration};
+
+    #[test]
+    fn parse_rate_limit() {
+        assert_eq!("0.5".parse::<RateLimit>().unwrap().per_second(), 0.5);
+        assert!("0".parse::<RateLimit>().is_err());
+        assert!("-1".parse::<RateLimit>().is_err());
+        assert!("inf".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn global_and_per_connection() {
+        let limiter = RateLimiter::new(RateLimits {
+            global: Some("3".parse().unwrap()),
+            per_connection: Some("2".parse().unwrap()),
+        });
+        let first: SocketAddr = "127.0.0.1:1000".parse().unwrap();
+        let second: SocketAddr = "127.0.0.1:1001".parse().unwrap();
+
+        // Each connection has a burst of 2, all of them together one of 3
+        assert!(limiter.check(Some(first)).is_ok());
+        assert!(limiter.check(Some(first)).is_ok());
+        let backoff = limiter.check(Some(first)).unwrap_err();
+        assert!(backoff > Duration::ZERO && backoff <= Duration::from_millis(500));
+        assert!(limiter.check(Some(second)).is_ok());
+        assert!(limiter.check(Some(second)).is_err());
+        assert!(limiter.check(None).is_err());
+
+        assert!(RateLimiter::default().check(Some(first)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn wait_for_tokens() {
+        let limiter = RateLimiter::new(RateLimits {
+            global: Some("1000".parse().unwrap()),
+            per_connection: None,
+        });
+        while limiter.check(None).is_ok() {}
+        tokio::time::timeout(Duration::from_secs(1), limiter.wait(None))
+            .await
+            .unwrap();
+    }
+}
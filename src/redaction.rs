@@ -0,0 +1,104 @@
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+
+/// Replaces the redacted values, or the redacted parts of them.
+pub const REDACTED: &str = "***";
+
+/// Fields and patterns masked in the records of the [`crate::AuditLog`] before they are written,
+/// e.g. to keep personal data of the clients out of the log.
+///
+/// The whole value of a redacted field is replaced, and every match of a pattern in any other
+/// value. Numbers are matched in their decimal form, and written as a string once redacted.
+#[derive(Clone, Default, Debug)]
+pub struct Redaction {
+    fields: BTreeSet<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Redaction {
+    /// Masks the value of `field`, e.g. `client` or `reason`, in every record.
+    pub fn field<S: Into<String>>(mut self, field: S) -> Self {
+        self.fields.insert(field.into());
+        self
+    }
+
+    /// Masks every match of `pattern` in the values of every record.
+    pub fn pattern(mut self, pattern: Regex) -> Self {
+        self.patterns.push(pattern);
+        self
+    }
+
+    /// Whether nothing is redacted.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.patterns.is_empty()
+    }
+
+    pub(crate) fn apply(&self, record: &mut Map<String, Value>) {
+        for (field, value) in record.iter_mut() {
+            if self.fields.contains(field) {
+                *value = Value::String(REDACTED.into());
+                continue;
+            }
+            let mut text = match value {
+                Value::String(text) => text.clone(),
+                Value::Number(number) => number.to_string(),
+                _ => continue,
+            };
+            let mut redacted = false;
+            for pattern in &self.patterns {
+                if pattern.is_match(&text) {
+                    text = pattern.replace_all(&text, REDACTED).into_owned();
+                    redacted = true;
+                }
+            }
+            if redacted {
+                *value = Value::String(text);
+            }
+        }
+    }
+}
+
+impl PartialEq for Redaction {
+    fn eq(&self, other: &Self) -> bool {
+        self.fields == other.fields
+            && self
+                .patterns
+                .iter()
+                .map(Regex::as_str)
+                .eq(other.patterns.iter().map(Regex::as_str))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Redaction;
+    use regex::Regex;
+    use serde_json::json;
+
+    #[test]
+    fn masks_fields_and_patterns() {
+        let redaction = Redaction::default()
+            .field("amount")
+            .pattern(Regex::new("^4[0-9]$").unwrap())
+            .pattern(Regex::new("client `[0-9]+`").unwrap());
+        let mut record = json!({
+            "client": 42,
+            "tx": 7,
+            "amount": "1.5",
+            "outcome": "rejected",
+            "reason": "Transaction `7` is for the locked account of client `42`",
+        });
+        redaction.apply(record.as_object_mut().unwrap());
+        assert_eq!(
+            record,
+            json!({
+                "client": "***",
+                "tx": 7,
+                "amount": "***",
+                "outcome": "rejected",
+                "reason": "Transaction `7` is for the locked account of ***",
+            })
+        );
+    }
+}
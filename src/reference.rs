@@ -0,0 +1,272 @@
+//! Slow, single-threaded model of the engine with its default settings, which the sharded engine
+//! is checked against.
+//!
+//! It only knows deposits, withdrawals, disputes, resolves and chargebacks, and deliberately
+//! shares no code with the accounts of the engine apart from the validation of the input.
+
+use crate::{
+    account::AccountView,
+    amount::{Amount, DEFAULT_PRECISION},
+    transaction::{ClientId, Transaction, TransactionId, TransactionType},
+};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+
+/// Applies transactions one after another the way a lenient engine does: invalid ones are
+/// skipped, the others change the accounts or are declined.
+#[derive(Default, Debug)]
+pub struct ReferenceEngine {
+    accounts: BTreeMap<ClientId, ReferenceAccount>,
+    // Client of every id a deposit or withdrawal introduced, even if it was declined
+    owners: HashMap<TransactionId, ClientId>,
+}
+
+#[derive(Default, Debug)]
+struct ReferenceAccount {
+    available: Amount,
+    held: Amount,
+    locked: bool,
+    // Deposits and withdrawals that happened, which can be disputed
+    transactions: HashMap<TransactionId, Recorded>,
+}
+
+#[derive(Debug)]
+struct Recorded {
+    deposit: bool,
+    amount: Amount,
+    state: State,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Normal,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl ReferenceEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies `transaction`, and returns `false` if it was skipped as invalid or because the
+    /// reference doesn't know its type, e.g. a transfer.
+    pub fn apply(&mut self, transaction: Transaction) -> bool {
+        if transaction.validate(DEFAULT_PRECISION).is_err() {
+            return false;
+        }
+        let Transaction {
+            r#type, client, tx, ..
+        } = transaction;
+        match r#type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                // Ids are unique across all clients, and taken even by declined transactions
+                match self.owners.entry(tx) {
+                    Entry::Occupied(_) => return false,
+                    Entry::Vacant(entry) => entry.insert(client),
+                };
+                let account = self.accounts.entry(client).or_default();
+                let Some(amount) = transaction.amount else {
+                    return false;
+                };
+                if account.locked {
+                    return true;
+                }
+                let deposit = r#type == TransactionType::Deposit;
+                if deposit {
+                    account.available += amount;
+                } else if account.available >= amount {
+                    account.available -= amount;
+                } else {
+                    return true;
+                }
+                let recorded = Recorded {
+                    deposit,
+                    amount,
+                    state: State::Normal,
+                };
+                account.transactions.insert(tx, recorded);
+                true
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                if self.owners.get(&tx).is_some_and(|&owner| owner != client) {
+                    return false;
+                }
+                let account = self.accounts.entry(client).or_default();
+                if account.locked {
+                    return true;
+                }
+                let Some(recorded) = account.transactions.get_mut(&tx) else {
+                    return true;
+                };
+                let amount = recorded.amount;
+                match (r#type, recorded.state) {
+                    (TransactionType::Dispute, State::Normal | State::Resolved) => {
+                        recorded.state = State::Disputed;
+                        if recorded.deposit {
+                            account.available -= amount;
+                        }
+                        account.held += amount;
+                    }
+                    (TransactionType::Resolve, State::Disputed) => {
+                        recorded.state = State::Resolved;
+                        if recorded.deposit {
+                            account.available += amount;
+                        }
+                        account.held -= amount;
+                    }
+                    (TransactionType::Chargeback, State::Disputed) => {
+                        recorded.state = State::ChargedBack;
+                        if !recorded.deposit {
+                            account.available += amount;
+                        }
+                        account.held -= amount;
+                        account.locked = true;
+                    }
+                    _ => {}
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Balances of every client a transaction was applied to, sorted by client.
+    pub fn accounts(&self) -> Vec<AccountView> {
+        self.accounts
+            .iter()
+            .map(|(&client, account)| AccountView {
+                client,
+                available: account.available,
+                held: account.held,
+                total: account.available + account.held,
+                locked: account.locked,
+                fees_collected: None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReferenceEngine;
+    use crate::{
+        account::AccountView,
+        error::ErrorPolicy,
+        transaction::{Transaction, TransactionType},
+        PaymentsEngine, Workload,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use rust_decimal::Decimal;
+
+    const TYPES: [TransactionType; 5] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+    ];
+
+    // Transactions of a few clients with colliding ids, so that most of them are duplicates,
+    // refer to other clients or arrive at locked accounts
+    fn adversarial(seed: u64, transactions: usize) -> Vec<Transaction> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..transactions)
+            .map(|_| {
+                let r#type = TYPES[rng.random_range(0..TYPES.len())];
+                let amount = matches!(
+                    r#type,
+                    TransactionType::Deposit | TransactionType::Withdrawal
+                )
+                .then(|| Decimal::new(rng.random_range(-100..100_000), 4).into());
+                Transaction {
+                    r#type,
+                    client: rng.random_range(1..=5),
+                    tx: rng.random_range(1..=200),
+                    amount,
+                    counterparty: None,
+                    timestamp: None,
+                    from_ccy: None,
+                    to_ccy: None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_unknown_types() {
+        let mut reference = ReferenceEngine::new();
+        let transfer = Transaction {
+            r#type: TransactionType::Transfer,
+            client: 1,
+            tx: 1,
+            amount: Some("1.0".parse().unwrap()),
+            counterparty: Some(2),
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        assert!(!reference.apply(transfer));
+        assert!(reference.accounts().is_empty());
+    }
+
+    // Final accounts of the engine with `workers` and of the reference after processing
+    // `transactions`
+    async fn run_both(
+        transactions: Vec<Transaction>,
+        workers: usize,
+    ) -> (Vec<AccountView>, Vec<AccountView>) {
+        let mut reference = ReferenceEngine::new();
+        for &transaction in &transactions {
+            reference.apply(transaction);
+        }
+
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(workers)
+            .error_policy(ErrorPolicy::Lenient)
+            .build();
+        let batches = payments_engine.batch_sender();
+        drop(sender);
+        // Batches keep the order of the transactions, unlike mixing them with single ones
+        let producer = tokio::spawn(async move {
+            for batch in transactions.chunks(7) {
+                batches.send(batch.to_vec().into()).await.unwrap();
+            }
+        });
+        payments_engine.process_transactions().await.unwrap();
+        producer.await.unwrap();
+
+        let mut accounts: Vec<_> = payments_engine
+            .accounts()
+            .map(|account| account.unwrap().view())
+            .collect();
+        accounts.sort_unstable_by_key(|account| account.client);
+        (accounts, reference.accounts())
+    }
+
+    #[tokio::test]
+    async fn generated_workloads() {
+        for seed in 0..5 {
+            let workload = Workload {
+                clients: 50,
+                transactions: 5000,
+                dispute_ratio: 0.1,
+                seed,
+            };
+            for workers in [1, 3, 8] {
+                let (engine, reference) = run_both(workload.generate().collect(), workers).await;
+                assert_eq!(engine, reference, "seed {seed}, {workers} workers");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn adversarial_workloads() {
+        for seed in 0..20 {
+            for workers in [1, 4] {
+                let (engine, reference) = run_both(adversarial(seed, 2000), workers).await;
+                assert_eq!(engine, reference, "seed {seed}, {workers} workers");
+            }
+        }
+    }
+}
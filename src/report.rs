@@ -0,0 +1,149 @@
+use crate::{
+    amount::Amount,
+    error::EngineError,
+    outcome::TransactionOutcome,
+    transaction::{ClientId, Transaction, TransactionId, TransactionType},
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Summary of the transactions processed by the engine and the resulting accounts, see
+/// [`crate::PaymentsEngine::report`].
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct RunReport {
+    /// Transactions processed, including rejected ones
+    pub transactions: u64,
+    pub transactions_by_type: BTreeMap<TransactionType, u64>,
+    /// Transactions that were invalid or not applied, by the reason why
+    pub rejected: BTreeMap<String, u64>,
+    pub accounts: u64,
+    pub locked_accounts: u64,
+    /// Accounts no transaction changed, see [`crate::Account::is_ghost`]
+    pub ghost_accounts: u64,
+    /// Funds available over all accounts
+    pub total_available: Amount,
+    /// Funds held for disputes over all accounts
+    pub total_held: Amount,
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Transactions: {}", self.transactions)?;
+        for (r#type, count) in &self.transactions_by_type {
+            writeln!(f, "  {type}: {count}")?;
+        }
+        let rejected: u64 = self.rejected.values().sum();
+        writeln!(f, "Rejected: {rejected}")?;
+        for (reason, count) in &self.rejected {
+            writeln!(f, "  {reason}: {count}")?;
+        }
+        writeln!(f, "Locked accounts: {}", self.locked_accounts)?;
+        writeln!(f, "Ghost accounts: {}", self.ghost_accounts)?;
+        writeln!(f, "Total held: {}", self.total_held)
+    }
+}
+
+/// Machine-readable [`RunReport`] with the runtime of the run, written as JSON by
+/// [`crate::PaymentsEngine::write_summary_json`].
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct RunSummary {
+    /// Transactions processed, including rejected ones
+    pub transactions: u64,
+    pub transactions_by_type: BTreeMap<TransactionType, u64>,
+    /// Transactions that changed an account
+    pub applied: u64,
+    /// Transactions that were invalid or not applied
+    pub rejected: u64,
+    pub rejected_by_reason: BTreeMap<String, u64>,
+    pub accounts: u64,
+    pub locked_accounts: u64,
+    pub ghost_accounts: u64,
+    pub total_available: Amount,
+    pub total_held: Amount,
+    /// Sum of the available and held funds
+    pub total: Amount,
+    /// Seconds since the run started
+    pub runtime_seconds: f64,
+}
+
+impl RunSummary {
+    pub fn new(report: RunReport, runtime: Duration) -> Self {
+        let rejected = report.rejected.values().sum();
+        RunSummary {
+            transactions: report.transactions,
+            applied: report.transactions - rejected,
+            rejected,
+            transactions_by_type: report.transactions_by_type,
+            rejected_by_reason: report.rejected,
+            accounts: report.accounts,
+            locked_accounts: report.locked_accounts,
+            ghost_accounts: report.ghost_accounts,
+            total_available: report.total_available,
+            total_held: report.total_held,
+            total: report.total_available + report.total_held,
+            runtime_seconds: runtime.as_secs_f64(),
+        }
+    }
+}
+
+/// Transaction still in dispute at the end of a run, see [`crate::PaymentsEngine::open_disputes`].
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct OpenDispute {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    /// Funds held for the dispute
+    pub amount: Amount,
+    /// Seconds since the transaction, if it has a timestamp
+    pub age: Option<u64>,
+}
+
+// Counts the transactions by type and the rejected ones by reason, shared by all workers
+#[derive(Clone, Default, Debug)]
+pub(crate) struct Tally(Arc<Mutex<Counts>>);
+
+#[derive(Default, Debug)]
+struct Counts {
+    by_type: BTreeMap<TransactionType, u64>,
+    rejected: BTreeMap<String, u64>,
+}
+
+impl Tally {
+    pub(crate) fn record(
+        &self,
+        transaction: &Transaction,
+        result: &Result<TransactionOutcome, EngineError>,
+    ) {
+        let reason = match result {
+            Ok(TransactionOutcome::Applied) => None,
+            Ok(outcome) => Some(outcome.to_string()),
+            Err(error) => Some(error.rejection_reason().to_owned()),
+        };
+        let mut counts = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *counts.by_type.entry(transaction.r#type).or_default() += 1;
+        if let Some(reason) = reason {
+            *counts.rejected.entry(reason).or_default() += 1;
+        }
+    }
+
+    // Report of the transactions, the accounts are added by the engine
+    pub(crate) fn report(&self) -> RunReport {
+        let counts = self
+            .0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        RunReport {
+            transactions: counts.by_type.values().sum(),
+            transactions_by_type: counts.by_type.clone(),
+            rejected: counts.rejected.clone(),
+            ..RunReport::default()
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    account::AccountView,
+    amount::Amount,
+    transaction::{ClientId, Transaction, TransactionType},
+};
+use anyhow::Result;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Exposure of a client beyond which a [`RiskAlert`] is raised, without a threshold by default.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RiskThresholds {
+    /// Largest amount held for disputes and holds of an account
+    pub max_held: Option<Amount>,
+    /// Most disputes opened by a client during a run
+    pub max_disputes: Option<u32>,
+}
+
+/// Threshold of [`RiskThresholds`] a client crossed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RiskAlert {
+    /// The funds held by the account rose above `threshold`
+    HeldExceeded {
+        client: ClientId,
+        held: Amount,
+        threshold: Amount,
+    },
+    /// The client opened more than `threshold` disputes
+    DisputesExceeded {
+        client: ClientId,
+        disputes: u32,
+        threshold: u32,
+    },
+}
+
+impl RiskThresholds {
+    /// Reads the thresholds from a TOML file, e.g. `max_held = "1000.0"`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+impl RiskAlert {
+    pub fn client(&self) -> ClientId {
+        match *self {
+            RiskAlert::HeldExceeded { client, .. } | RiskAlert::DisputesExceeded { client, .. } => {
+                client
+            }
+        }
+    }
+}
+
+impl fmt::Display for RiskAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskAlert::HeldExceeded {
+                held, threshold, ..
+            } => write!(f, "Held funds of {held} exceed {threshold}"),
+            RiskAlert::DisputesExceeded {
+                disputes,
+                threshold,
+                ..
+            } => write!(f, "{disputes} disputes exceed {threshold}"),
+        }
+    }
+}
+
+// Exposure of every client, shared by all workers
+#[derive(Clone, Debug)]
+pub(crate) struct RiskMonitor {
+    thresholds: RiskThresholds,
+    exposures: Arc<Mutex<HashMap<ClientId, Exposure>>>,
+}
+
+#[derive(Default, Debug)]
+struct Exposure {
+    disputes: u32,
+    // Whether the held funds are above the threshold, so crossing it again raises a new alert
+    held_exceeded: bool,
+}
+
+impl RiskMonitor {
+    pub(crate) fn new(thresholds: RiskThresholds) -> Self {
+        RiskMonitor {
+            thresholds,
+            exposures: Arc::default(),
+        }
+    }
+
+    /// Alerts for the thresholds `transaction` crossed, `account` are the balances of its
+    /// client afterwards.
+    pub(crate) fn check(
+        &self,
+        transaction: &Transaction,
+        applied: bool,
+        account: &AccountView,
+    ) -> Vec<RiskAlert> {
+        let mut alerts = Vec::new();
+        let mut exposures = self.exposures.lock().unwrap();
+        let exposure = exposures.entry(account.client).or_default();
+        if let Some(threshold) = self.thresholds.max_held {
+            let held_exceeded = account.held > threshold;
+            if held_exceeded && !exposure.held_exceeded {
+                alerts.push(RiskAlert::HeldExceeded {
+                    client: account.client,
+                    held: account.held,
+                    threshold,
+                });
+            }
+            exposure.held_exceeded = held_exceeded;
+        }
+        if applied && transaction.r#type == TransactionType::Dispute {
+            exposure.disputes += 1;
+            match self.thresholds.max_disputes {
+                Some(threshold) if exposure.disputes == threshold.saturating_add(1) => {
+                    alerts.push(RiskAlert::DisputesExceeded {
+                        client: account.client,
+                        disputes: exposure.disputes,
+                        threshold,
+                    })
+                }
+                _ => {}
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(all(test, feature = "runtime"))]
+mod tests {
+    use super::{RiskAlert, RiskThresholds};
+    use crate::{
+        observer::EngineObserver,
+        transaction::{Transaction, TransactionType},
+        PaymentsEngine,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default, Debug)]
+    struct Alerts(Arc<Mutex<Vec<RiskAlert>>>);
+
+    impl EngineObserver for Alerts {
+        fn on_risk_alert(&self, alert: &RiskAlert) {
+            self.0.lock().unwrap().push(*alert);
+        }
+    }
+
+    #[tokio::test]
+    async fn alerts_on_crossing_thresholds() {
+        let alerts = Alerts::default();
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(2)
+            .risk_thresholds(RiskThresholds {
+                max_held: Some("2.0".parse().unwrap()),
+                max_disputes: Some(2),
+            })
+            .observer(alerts.clone())
+            .build();
+        let transaction = |r#type, tx, amount: Option<&str>| Transaction {
+            r#type,
+            client: 1,
+            tx,
+            amount: amount.map(|amount| amount.parse().unwrap()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        for transaction in [
+            transaction(TransactionType::Deposit, 1, Some("1.5")),
+            transaction(TransactionType::Deposit, 2, Some("1.5")),
+            transaction(TransactionType::Dispute, 1, None),
+            // Held funds cross the threshold
+            transaction(TransactionType::Dispute, 2, None),
+            transaction(TransactionType::Resolve, 2, None),
+            // Held funds cross it again, and the third dispute exceeds the limit of two
+            transaction(TransactionType::Dispute, 2, None),
+            transaction(TransactionType::Resolve, 2, None),
+            // Only the held funds cross the threshold again
+            transaction(TransactionType::Dispute, 2, None),
+        ] {
+            sender.send(transaction).await.unwrap();
+        }
+        drop(sender);
+        payments_engine.process_transactions().await.unwrap();
+
+        let held = RiskAlert::HeldExceeded {
+            client: 1,
+            held: "3.0".parse().unwrap(),
+            threshold: "2.0".parse().unwrap(),
+        };
+        let disputes = RiskAlert::DisputesExceeded {
+            client: 1,
+            disputes: 3,
+            threshold: 2,
+        };
+        assert_eq!(*alerts.0.lock().unwrap(), [held, held, disputes, held]);
+        assert_eq!(disputes.to_string(), "3 disputes exceed 2");
+    }
+}
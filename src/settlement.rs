@@ -0,0 +1,269 @@
+use crate::{
+    account::{Account, AccountRules, AccountView},
+    amount::{RoundingMode, MAX_PRECISION},
+    clock::Clock,
+    dedupe::{DuplicatePolicy, Reuse, TransactionIds},
+    dispute::RedisputePolicy,
+    dispute_window::DisputeWindow,
+    error::EngineError,
+    event::AccountEvent,
+    fees::FeeSchedule,
+    fx::FxRates,
+    limits::Limits,
+    locked::LockedAccountPolicy,
+    outcome::TransactionOutcome,
+    store::{AccountStore, MemoryStore},
+    transaction::{ClientId, Transaction, TransactionType},
+    transfer::Transfers,
+    unknown_client::UnknownClientPolicy,
+};
+use std::{borrow::Cow, sync::Arc};
+
+/// Settles transactions one at a time on the calling thread: the rules of the
+/// [`PaymentsEngine`](crate::PaymentsEngine) without its workers, channels and files.
+///
+/// It needs neither tokio nor a file system, so it is built without the `runtime` feature as
+/// well, e.g. for `wasm32-unknown-unknown` to run the settlement in a simulator in the browser.
+/// Transactions without timestamp are dated by the system clock unless another one is set, e.g. a
+/// [`crate::SimClock`] for a simulation. The browser has no system clock, so there the default is
+/// a [`crate::SimClock`] at the epoch.
+///
+/// Transactions are validated, deduplicated and applied as the engine does, but aren't reordered
+/// by their timestamps. The accounts are kept in memory.
+#[derive(Debug)]
+pub struct Settlement {
+    accounts: MemoryStore,
+    transaction_ids: TransactionIds,
+    transfers: Transfers,
+    events: Vec<AccountEvent>,
+    admin_commands: bool,
+    unknown_client_policy: UnknownClientPolicy,
+    // How new accounts are opened
+    account_rules: AccountRules,
+}
+
+impl Default for Settlement {
+    fn default() -> Self {
+        Settlement {
+            accounts: MemoryStore::default(),
+            transaction_ids: TransactionIds::new(None, 0),
+            transfers: Transfers::default(),
+            events: Vec::new(),
+            admin_commands: false,
+            unknown_client_policy: UnknownClientPolicy::default(),
+            account_rules: AccountRules::default(),
+        }
+    }
+}
+
+impl Settlement {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept administrative commands like `unlock`, which are rejected otherwise.
+    pub fn admin_commands(mut self, admin_commands: bool) -> Self {
+        self.admin_commands = admin_commands;
+        self
+    }
+
+    /// Number of decimal places amounts may have, and are reported with.
+    pub fn precision(mut self, precision: u32) -> Self {
+        self.account_rules.precision = precision.min(MAX_PRECISION);
+        self
+    }
+
+    /// How amounts are rounded to the precision, in the views and when fees are charged.
+    pub fn rounding_mode(mut self, rounding_mode: RoundingMode) -> Self {
+        self.account_rules.rounding_mode = rounding_mode;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.account_rules.limits = limits;
+        self
+    }
+
+    pub fn fee_schedule(mut self, fee_schedule: FeeSchedule) -> Self {
+        self.account_rules.fee_schedule = Some(fee_schedule);
+        self
+    }
+
+    /// Exchange rates `convert` transactions are applied with, without them they are invalid.
+    pub fn fx_rates(mut self, fx_rates: FxRates) -> Self {
+        self.account_rules.fx_rates = Some(Arc::new(fx_rates));
+        self
+    }
+
+    pub fn dispute_window(mut self, dispute_window: DisputeWindow) -> Self {
+        self.account_rules.dispute_window = Some(dispute_window);
+        self
+    }
+
+    pub fn redispute_policy(mut self, redispute_policy: RedisputePolicy) -> Self {
+        self.account_rules.redispute_policy = redispute_policy;
+        self
+    }
+
+    pub fn duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        self.account_rules.duplicate_policy = duplicate_policy;
+        self
+    }
+
+    pub fn locked_policy(mut self, locked_policy: LockedAccountPolicy) -> Self {
+        self.account_rules.locked_policy = locked_policy;
+        self
+    }
+
+    pub fn unknown_client_policy(mut self, unknown_client_policy: UnknownClientPolicy) -> Self {
+        self.unknown_client_policy = unknown_client_policy;
+        self
+    }
+
+    /// Time of transactions without timestamp, by default the system clock.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.account_rules.clock = Arc::new(clock);
+        self
+    }
+
+    /// Applies `transaction` and returns its outcome, or why it is invalid.
+    ///
+    /// Transactions a locked account queued are applied once it is unlocked, those of them that
+    /// turn out to be invalid are skipped.
+    pub fn apply(&mut self, transaction: Transaction) -> Result<TransactionOutcome, EngineError> {
+        transaction.validate(self.account_rules.precision)?;
+        if transaction.r#type.is_admin_command() && !self.admin_commands {
+            return Err(EngineError::AdminCommandsDisabled(transaction.tx));
+        }
+        let reuse = self.transaction_ids.admit(&transaction)?;
+        if transaction.r#type.refers_to_transaction() {
+            if let Some(result) = self.unknown_client_policy.outcome(&transaction) {
+                if self.accounts.get(transaction.client)?.is_none() {
+                    return result;
+                }
+            }
+        }
+
+        let counterparty = self.transfers.counterpart_of(&transaction);
+        let counterparty_locked = match counterparty {
+            Some(counterparty)
+                if matches!(
+                    transaction.r#type,
+                    TransactionType::Transfer | TransactionType::Approve
+                ) =>
+            {
+                self.accounts
+                    .get(counterparty)?
+                    .is_some_and(|account| account.locked())
+            }
+            _ => false,
+        };
+        if counterparty_locked {
+            return Ok(TransactionOutcome::CounterpartyLocked);
+        }
+
+        let open = |client| Account::new(client).with_rules(&self.account_rules);
+        let account = self.accounts.get_or_create(transaction.client, &open)?;
+        let (result, unlocked) = account.settle(transaction, reuse);
+        let (outcome, event) = result?;
+        if let Some(event) = event {
+            self.events.push(event);
+            let counterpart = counterparty
+                .and_then(|counterparty| self.transfers.counterpart(event, counterparty));
+            if let Some(counterpart) = counterpart {
+                self.accounts
+                    .get_or_create(counterpart.client(), &open)?
+                    .apply(&counterpart)?;
+                self.events.push(counterpart);
+            }
+        }
+        for transaction in unlocked {
+            let account = self.accounts.get_or_create(transaction.client, &open)?;
+            if let (Ok((_, event)), _) = account.settle(transaction, Reuse::No) {
+                self.events.extend(event);
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Balances of the account of `client`, rounded to the precision, or `None` if it doesn't
+    /// exist.
+    pub fn account(&self, client: ClientId) -> Option<AccountView> {
+        let account = self.accounts.get(client).ok().flatten()?;
+        let AccountRules {
+            precision,
+            rounding_mode,
+            ..
+        } = &self.account_rules;
+        Some(account.view().round(*precision, *rounding_mode))
+    }
+
+    /// All accounts, in no particular order.
+    pub fn accounts(&self) -> impl Iterator<Item = Result<Cow<'_, Account>, EngineError>> {
+        self.accounts.iter()
+    }
+
+    /// Events of all applied transactions, in the order they were applied.
+    pub fn events(&self) -> &[AccountEvent] {
+        &self.events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Settlement;
+    use crate::{
+        clock::FixedClock,
+        error::EngineError,
+        outcome::TransactionOutcome,
+        transaction::{Transaction, TransactionType},
+    };
+
+    #[test]
+    fn settles_like_the_engine() {
+        let transaction = |r#type, client, tx, amount: &str| Transaction {
+            r#type,
+            client,
+            tx,
+            amount: amount.parse().ok(),
+            counterparty: (r#type == TransactionType::Transfer).then_some(2),
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        let mut settlement = Settlement::new().clock(FixedClock(0));
+        let outcomes: Vec<_> = [
+            transaction(TransactionType::Deposit, 1, 1, "5.0"),
+            transaction(TransactionType::Transfer, 1, 2, "2.0"),
+            transaction(TransactionType::Withdrawal, 2, 3, "3.0"),
+            transaction(TransactionType::Chargeback, 1, 1, ""),
+        ]
+        .into_iter()
+        .map(|transaction| settlement.apply(transaction).unwrap())
+        .collect();
+        assert_eq!(
+            outcomes,
+            [
+                TransactionOutcome::Applied,
+                TransactionOutcome::Applied,
+                TransactionOutcome::InsufficientFunds,
+                TransactionOutcome::NotUnderDispute,
+            ]
+        );
+        assert!(matches!(
+            settlement.apply(transaction(TransactionType::Deposit, 2, 1, "1.0")),
+            Err(EngineError::DuplicateTransactionId(1))
+        ));
+        assert!(matches!(
+            settlement.apply(transaction(TransactionType::Unlock, 1, 4, "")),
+            Err(EngineError::AdminCommandsDisabled(4))
+        ));
+
+        let first = settlement.account(1).unwrap();
+        assert_eq!(first.available, "3".parse().unwrap());
+        let second = settlement.account(2).unwrap();
+        assert_eq!(second.available, "2".parse().unwrap());
+        assert_eq!(settlement.events().len(), 4);
+        assert_eq!(settlement.accounts().count(), 2);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{clock::SimClock, transaction::Transaction, workload::Workload};
+use anyhow::{anyhow, Result};
+use tokio::sync::mpsc::Sender;
+
+/// Time the simulated clock starts at by default, in seconds since the Unix epoch.
+pub const DEFAULT_SIMULATION_START: u64 = 1_700_000_000;
+
+/// Deterministic simulation of a run: the transactions of a seeded [`Workload`], stamped one
+/// simulated second apart, processed by an engine whose [`SimClock`] follows them.
+///
+/// The same simulation always results in the same accounts and reports, byte for byte and
+/// regardless of the number of workers, so a divergence found with it is reproduced from its
+/// seed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Simulation {
+    pub workload: Workload,
+    /// Timestamp of the first transaction
+    pub start: u64,
+}
+
+impl Simulation {
+    pub fn new(workload: Workload) -> Self {
+        Simulation {
+            workload,
+            start: DEFAULT_SIMULATION_START,
+        }
+    }
+
+    /// Clock the engine is built with, at the start of the simulation.
+    pub fn clock(&self) -> SimClock {
+        SimClock::new(self.start)
+    }
+
+    /// Transactions of the workload with their simulated timestamps.
+    pub fn transactions(&self) -> impl Iterator<Item = Transaction> {
+        self.workload
+            .generate()
+            .zip(self.start..)
+            .map(|(transaction, timestamp)| Transaction {
+                timestamp: Some(timestamp),
+                ..transaction
+            })
+    }
+
+    /// Sends the transactions to an engine built with `clock`, which is advanced to the time of
+    /// every transaction before it is sent.
+    pub async fn run(&self, clock: SimClock, transactions: Sender<Transaction>) -> Result<()> {
+        for transaction in self.transactions() {
+            clock.advance_to(transaction.timestamp.unwrap_or(self.start));
+            transactions
+                .send(transaction)
+                .await
+                .map_err(|_| anyhow!("The engine stopped"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Simulation;
+    use crate::{Limits, PaymentsEngine, Workload};
+
+    // Accounts and open disputes written after running `simulation` on `workers` workers
+    async fn outputs(simulation: Simulation, workers: usize) -> (Vec<u8>, Vec<u8>) {
+        let clock = simulation.clock();
+        let (mut payments_engine, sender) = PaymentsEngine::builder()
+            .workers(workers)
+            .clock(clock.clone())
+            .limits(Limits {
+                max_daily_withdrawal: Some("50".parse().unwrap()),
+                ..Limits::default()
+            })
+            .build();
+        let feeder = tokio::spawn(async move { simulation.run(clock, sender).await });
+        payments_engine.process_transactions().await.unwrap();
+        feeder.await.unwrap().unwrap();
+
+        let (mut accounts, mut disputes) = (Vec::new(), Vec::new());
+        payments_engine.write_accounts(&mut accounts).unwrap();
+        payments_engine
+            .write_disputes_report(&mut disputes)
+            .unwrap();
+        (accounts, disputes)
+    }
+
+    #[tokio::test]
+    async fn same_seed_same_outputs() {
+        let simulation = Simulation::new(Workload {
+            clients: 20,
+            transactions: 2000,
+            dispute_ratio: 0.05,
+            seed: 11,
+        });
+        let expected = outputs(simulation, 1).await;
+        assert_eq!(outputs(simulation, 1).await, expected);
+        assert_eq!(outputs(simulation, 4).await, expected);
+
+        let other_seed = Simulation {
+            workload: Workload {
+                seed: 12,
+                ..simulation.workload
+            },
+            ..simulation
+        };
+        assert_ne!(outputs(other_seed, 1).await.0, expected.0);
+    }
+}
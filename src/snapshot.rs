@@ -0,0 +1,46 @@
+use crate::{checkpoint::InputOffset, event::AccountEvent};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Event log of the engine, from which a run can be continued by replaying it.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub(crate) struct Snapshot {
+    pub events: Vec<AccountEvent>,
+    /// Input already fed into the engine, if the snapshot is a checkpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<InputOffset>,
+}
+
+impl Snapshot {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Saves the snapshot next to `path` first, and then replaces `path` by it, so a crash
+    /// leaves the previous snapshot intact.
+    pub fn replace<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let temporary = temporary_path(path.as_ref());
+        self.save(&temporary)?;
+        fs::rename(temporary, path)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    temporary.into()
+}
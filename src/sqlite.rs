@@ -0,0 +1,132 @@
+use crate::{account::AccountView, ledger::LedgerEntry, transaction::ClientId};
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use std::{collections::BTreeMap, path::Path};
+
+// Amounts are stored as text, SQLite has no exact decimal type
+const SCHEMA: &str = "
+    DROP TABLE IF EXISTS accounts;
+    CREATE TABLE accounts (
+        client INTEGER PRIMARY KEY,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL,
+        fees_collected TEXT
+    );
+    DROP TABLE IF EXISTS ledger;
+";
+
+const LEDGER_SCHEMA: &str = "
+    CREATE TABLE ledger (
+        client INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        tx INTEGER,
+        type TEXT NOT NULL,
+        amount TEXT,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL,
+        PRIMARY KEY (client, position)
+    );
+";
+
+/// Writes `accounts`, and the `ledgers` of the accounts if given, into the SQLite database at
+/// `path`, which is created if needed.
+///
+/// The tables `accounts` and `ledger` are replaced, amounts are stored as text to keep them
+/// exact. The entries of a ledger are numbered by `position`.
+pub fn write<P: AsRef<Path>>(
+    path: P,
+    accounts: &[AccountView],
+    ledgers: Option<&BTreeMap<ClientId, Vec<LedgerEntry>>>,
+) -> Result<()> {
+    let mut connection = Connection::open(path)?;
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(SCHEMA)?;
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO accounts (client, available, held, total, locked, fees_collected)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for account in accounts {
+            insert.execute(params![
+                account.client,
+                account.available.to_string(),
+                account.held.to_string(),
+                account.total.to_string(),
+                account.locked,
+                account.fees_collected.map(|fees| fees.to_string()),
+            ])?;
+        }
+    }
+
+    if let Some(ledgers) = ledgers {
+        transaction.execute_batch(LEDGER_SCHEMA)?;
+        let mut insert = transaction.prepare(
+            "INSERT INTO ledger
+                (client, position, tx, type, amount, available, held, total, locked)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+        for (client, ledger) in ledgers {
+            for (position, entry) in ledger.iter().enumerate() {
+                insert.execute(params![
+                    client,
+                    position,
+                    entry.tx,
+                    entry.r#type,
+                    entry.amount.map(|amount| amount.to_string()),
+                    entry.available.to_string(),
+                    entry.held.to_string(),
+                    entry.total.to_string(),
+                    entry.locked,
+                ])?;
+            }
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write;
+    use crate::{account::Account, amount::RoundingMode, event::AccountEvent, ledger};
+
+    #[test]
+    fn accounts_and_ledger() {
+        let path = std::env::temp_dir().join("rust-exercise-sqlite-output.db");
+        let _ = std::fs::remove_file(&path);
+        let events = [AccountEvent::Deposited {
+            client: 1,
+            tx: 1,
+            amount: "1.5".parse().unwrap(),
+            fee: "0".parse().unwrap(),
+            timestamp: None,
+        }];
+        let mut account = Account::new(1);
+        account.apply(&events[0]).unwrap();
+        let ledgers = ledger::ledgers(&events, 4, RoundingMode::HalfEven).unwrap();
+
+        write(&path, &[account.view()], Some(&ledgers)).unwrap();
+        // Writing again replaces the tables
+        write(&path, &[account.view()], Some(&ledgers)).unwrap();
+
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let (available, locked): (String, bool) = connection
+            .query_row(
+                "SELECT available, locked FROM accounts WHERE client = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((available.as_str(), locked), ("1.5", false));
+        let entries: u32 = connection
+            .query_row("SELECT COUNT(*) FROM ledger", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(entries, 1);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+#[cfg(feature = "runtime")]
+pub mod disk;
+
+use crate::{account::Account, error::EngineError, transaction::ClientId};
+use rustc_hash::FxHashMap;
+use std::{borrow::Cow, fmt, sync::Arc};
+
+/// Storage of the accounts owned by one worker of the engine.
+///
+/// Every worker has a store of its own, and every client is always handled by the same worker.
+pub trait AccountStore: Send {
+    /// Account of `client`, if it exists.
+    fn get(&self, client: ClientId) -> Result<Option<Cow<'_, Account>>, EngineError>;
+
+    /// Account of `client`, created with `open` if it doesn't exist yet.
+    fn get_or_create(
+        &mut self,
+        client: ClientId,
+        open: &dyn Fn(ClientId) -> Account,
+    ) -> Result<&mut Account, EngineError>;
+
+    /// All accounts, in no particular order.
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Cow<'_, Account>, EngineError>> + '_>;
+
+    /// Removes all accounts.
+    fn clear(&mut self) -> Result<(), EngineError>;
+
+    /// Makes room for `additional` accounts, if the store keeps them in memory.
+    fn reserve(&mut self, _additional: usize) {}
+
+    /// Writes the accounts changed since the last call to the backend, after the worker
+    /// processed all transactions.
+    fn persist(&mut self) -> Result<(), EngineError>;
+}
+
+/// Keeps all accounts in memory, the default store.
+#[derive(Default, Debug)]
+pub struct MemoryStore {
+    accounts: FxHashMap<ClientId, Account>,
+}
+
+impl AccountStore for MemoryStore {
+    fn get(&self, client: ClientId) -> Result<Option<Cow<'_, Account>>, EngineError> {
+        Ok(self.accounts.get(&client).map(Cow::Borrowed))
+    }
+
+    fn get_or_create(
+        &mut self,
+        client: ClientId,
+        open: &dyn Fn(ClientId) -> Account,
+    ) -> Result<&mut Account, EngineError> {
+        Ok(self.accounts.entry(client).or_insert_with(|| open(client)))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Cow<'_, Account>, EngineError>> + '_> {
+        Box::new(
+            self.accounts
+                .values()
+                .map(|account| Ok(Cow::Borrowed(account))),
+        )
+    }
+
+    fn clear(&mut self) -> Result<(), EngineError> {
+        self.accounts.clear();
+        Ok(())
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.accounts.reserve(additional);
+    }
+
+    fn persist(&mut self) -> Result<(), EngineError> {
+        Ok(())
+    }
+}
+
+// Creates the store of each worker, given the index of the worker
+#[derive(Clone)]
+pub(crate) struct StoreFactory(Arc<dyn Fn(usize) -> Box<dyn AccountStore> + Send + Sync>);
+
+impl StoreFactory {
+    pub(crate) fn new<F>(open: F) -> Self
+    where
+        F: Fn(usize) -> Box<dyn AccountStore> + Send + Sync + 'static,
+    {
+        StoreFactory(Arc::new(open))
+    }
+
+    pub(crate) fn open(&self, worker: usize) -> Box<dyn AccountStore> {
+        (self.0)(worker)
+    }
+}
+
+impl Default for StoreFactory {
+    fn default() -> Self {
+        StoreFactory::new(|_| Box::new(MemoryStore::default()))
+    }
+}
+
+impl fmt::Debug for StoreFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StoreFactory")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccountStore, MemoryStore};
+    use crate::{
+        account::Account,
+        transaction::{Transaction, TransactionType},
+    };
+
+    #[test]
+    fn memory_store() {
+        let mut store = MemoryStore::default();
+        assert!(store.get(1).unwrap().is_none());
+
+        let deposit = Transaction {
+            r#type: TransactionType::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some("1.0".parse().unwrap()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        };
+        let account = store.get_or_create(1, &Account::new).unwrap();
+        account.apply_transaction(deposit).unwrap();
+        assert_eq!(
+            store.get_or_create(1, &Account::new).unwrap().available(),
+            "1.0".parse().unwrap()
+        );
+        assert_eq!(store.iter().count(), 1);
+
+        store.clear().unwrap();
+        assert!(store.get(1).unwrap().is_none());
+    }
+}
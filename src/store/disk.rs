@@ -0,0 +1,280 @@
+use super::AccountStore;
+use crate::{
+    account::{Account, AccountState},
+    error::EngineError,
+    history::TemporaryDirectory,
+    transaction::ClientId,
+};
+use rustc_hash::FxHashMap;
+use std::{borrow::Cow, collections::BTreeMap, path::Path, sync::Arc};
+
+/// On-disk index of the accounts of all workers, each worker keeps up to `capacity` of its
+/// accounts in memory and the least recently used ones on disk.
+#[derive(Clone, Debug)]
+pub struct DiskStore {
+    tree: sled::Tree,
+    capacity: usize,
+    // Declared last, so the index is closed before its directory is removed
+    _directory: Arc<TemporaryDirectory>,
+}
+
+impl DiskStore {
+    /// Creates a temporary index in a new subdirectory of `directory`, which is removed once the
+    /// engine is dropped.
+    pub fn open<P: AsRef<Path>>(directory: P, capacity: usize) -> Result<Self, EngineError> {
+        let directory = Arc::new(TemporaryDirectory::new(directory.as_ref(), "accounts"));
+        let db = sled::Config::new()
+            .path(directory.path())
+            .open()
+            .map_err(store_error)?;
+        Ok(DiskStore {
+            tree: db.open_tree("accounts").map_err(store_error)?,
+            capacity: capacity.max(1),
+            _directory: directory,
+        })
+    }
+
+    /// Store of the accounts of `worker`.
+    pub fn shard(&self, worker: usize) -> DiskShard {
+        DiskShard {
+            store: self.clone(),
+            prefix: (worker as u64).to_be_bytes(),
+            accounts: FxHashMap::default(),
+            recently_used: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+}
+
+/// Accounts of one worker in a [`DiskStore`].
+#[derive(Debug)]
+pub struct DiskShard {
+    store: DiskStore,
+    // Keys of the accounts of this worker start with the index of the worker
+    prefix: [u8; 8],
+    accounts: FxHashMap<ClientId, CachedAccount>,
+    // Clients of `accounts` by the time they were used last
+    recently_used: BTreeMap<u64, ClientId>,
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct CachedAccount {
+    account: Account,
+    last_used: u64,
+    // Changed since it was written to disk last
+    dirty: bool,
+}
+
+impl DiskShard {
+    fn load(&self, client: ClientId) -> Result<Option<AccountState>, EngineError> {
+        self.store
+            .tree
+            .get(key(self.prefix, client))
+            .map_err(store_error)?
+            .map(|bytes| serde_json::from_slice(&bytes).map_err(store_error))
+            .transpose()
+    }
+
+    fn write(&self, account: &Account) -> Result<(), EngineError> {
+        let bytes = serde_json::to_vec(&account.state()).map_err(store_error)?;
+        self.store
+            .tree
+            .insert(key(self.prefix, account.client()), bytes)
+            .map_err(store_error)?;
+        Ok(())
+    }
+
+    // Moves the least recently used accounts to disk until at most `capacity` are left in memory
+    fn evict(&mut self, capacity: usize) -> Result<(), EngineError> {
+        while self.accounts.len() > capacity {
+            let Some((_, client)) = self.recently_used.pop_first() else {
+                break;
+            };
+            if let Some(cached) = self.accounts.remove(&client) {
+                if cached.dirty {
+                    self.write(&cached.account)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn key(prefix: [u8; 8], client: ClientId) -> Vec<u8> {
+    [&prefix[..], &client.to_be_bytes()].concat()
+}
+
+// Account without the settings of the engine, which don't matter for queries and the output
+fn restored(client: ClientId, state: AccountState) -> Result<Cow<'static, Account>, EngineError> {
+    let mut account = Account::new(client);
+    account.restore(state)?;
+    Ok(Cow::Owned(account))
+}
+
+impl AccountStore for DiskShard {
+    fn get(&self, client: ClientId) -> Result<Option<Cow<'_, Account>>, EngineError> {
+        if let Some(cached) = self.accounts.get(&client) {
+            return Ok(Some(Cow::Borrowed(&cached.account)));
+        }
+        self.load(client)?
+            .map(|state| restored(client, state))
+            .transpose()
+    }
+
+    fn get_or_create(
+        &mut self,
+        client: ClientId,
+        open: &dyn Fn(ClientId) -> Account,
+    ) -> Result<&mut Account, EngineError> {
+        self.clock += 1;
+        let last_used = self.clock;
+        if let Some(cached) = self.accounts.get_mut(&client) {
+            self.recently_used.remove(&cached.last_used);
+            cached.last_used = last_used;
+            cached.dirty = true;
+        } else {
+            let mut account = open(client);
+            if let Some(state) = self.load(client)? {
+                account.restore(state)?;
+            }
+            self.evict(self.store.capacity - 1)?;
+            let cached = CachedAccount {
+                account,
+                last_used,
+                dirty: true,
+            };
+            self.accounts.insert(client, cached);
+        }
+        self.recently_used.insert(last_used, client);
+        Ok(&mut self
+            .accounts
+            .get_mut(&client)
+            .expect("account is cached above")
+            .account)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Cow<'_, Account>, EngineError>> + '_> {
+        let cached = self
+            .accounts
+            .values()
+            .map(|cached| Ok(Cow::Borrowed(&cached.account)));
+        let on_disk = self
+            .store
+            .tree
+            .scan_prefix(self.prefix)
+            .filter_map(|entry| {
+                let (key, bytes) = match entry {
+                    Ok(entry) => entry,
+                    Err(error) => return Some(Err(store_error(error))),
+                };
+                let client = ClientId::from_be_bytes(
+                    key[8..].try_into().expect("keys end with the client id"),
+                );
+                // The cached account is newer than the one on disk
+                if self.accounts.contains_key(&client) {
+                    return None;
+                }
+                Some(
+                    serde_json::from_slice(&bytes)
+                        .map_err(store_error)
+                        .and_then(|state| restored(client, state)),
+                )
+            });
+        Box::new(cached.chain(on_disk))
+    }
+
+    fn clear(&mut self) -> Result<(), EngineError> {
+        self.accounts.clear();
+        self.recently_used.clear();
+        for key in self.store.tree.scan_prefix(self.prefix).keys() {
+            self.store
+                .tree
+                .remove(key.map_err(store_error)?)
+                .map_err(store_error)?;
+        }
+        Ok(())
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        self.accounts
+            .reserve(additional.min(self.store.capacity + 1));
+    }
+
+    fn persist(&mut self) -> Result<(), EngineError> {
+        for cached in self.accounts.values_mut().filter(|cached| cached.dirty) {
+            let bytes = serde_json::to_vec(&cached.account.state()).map_err(store_error)?;
+            self.store
+                .tree
+                .insert(key(self.prefix, cached.account.client()), bytes)
+                .map_err(store_error)?;
+            cached.dirty = false;
+        }
+        self.store.tree.flush().map_err(store_error)?;
+        Ok(())
+    }
+}
+
+fn store_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> EngineError {
+    EngineError::AccountStore(Box::new(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiskStore;
+    use crate::{
+        account::Account,
+        store::AccountStore,
+        transaction::{ClientId, Transaction, TransactionId, TransactionType},
+    };
+    use rust_decimal::Decimal;
+
+    fn deposit(client: ClientId, tx: TransactionId) -> Transaction {
+        Transaction {
+            r#type: TransactionType::Deposit,
+            client,
+            tx,
+            amount: Some(Decimal::from(tx).into()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        }
+    }
+
+    #[test]
+    fn evict_least_recently_used_accounts() {
+        let store = DiskStore::open(std::env::temp_dir(), 1).unwrap();
+        let mut shard = store.shard(0);
+        for client in 1..=3 {
+            let account = shard.get_or_create(client, &Account::new).unwrap();
+            account
+                .apply_transaction(deposit(client, client.into()))
+                .unwrap();
+        }
+
+        // Client 1 was written to disk, its history still allows disputes
+        assert_eq!(
+            shard.get(1).unwrap().unwrap().total(),
+            Decimal::from(1).into()
+        );
+        let account = shard.get_or_create(1, &Account::new).unwrap();
+        account
+            .apply_transaction(Transaction {
+                r#type: TransactionType::Dispute,
+                amount: None,
+                ..deposit(1, 1)
+            })
+            .unwrap();
+        assert_eq!(account.held(), Decimal::from(1).into());
+        assert_eq!(shard.iter().count(), 3);
+
+        // Other workers don't see the accounts
+        assert_eq!(store.shard(1).iter().count(), 0);
+
+        shard.persist().unwrap();
+        shard.clear().unwrap();
+        assert!(shard.get(2).unwrap().is_none());
+        assert_eq!(shard.iter().count(), 0);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    builder::EngineBuilder, collector::expand_paths, output, output::OutputFormat,
+    payment_engine::PaymentsEngine,
+};
+use anyhow::Result;
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Which tenant the input files belong to, e.g. partners whose client and transaction ids
+/// collide.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Tenancy {
+    /// All files belong to the tenant of this name
+    Named(String),
+    /// Every file belongs to the tenant named like the file without its extension
+    PerFile,
+}
+
+impl Tenancy {
+    /// Groups the files at `paths` by their tenant, glob patterns are expanded first.
+    pub fn group(&self, paths: Vec<PathBuf>) -> Result<BTreeMap<String, Vec<PathBuf>>> {
+        let mut tenants: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        match self {
+            Tenancy::Named(tenant) => {
+                tenants.insert(tenant.clone(), paths);
+            }
+            Tenancy::PerFile => {
+                for path in expand_paths(paths)? {
+                    tenants.entry(file_tenant(&path)).or_default().push(path);
+                }
+            }
+        }
+        Ok(tenants)
+    }
+}
+
+// Name of the file without its directory and extension, e.g. `partner-a` for
+// `s3://bucket/partner-a.csv`
+fn file_tenant(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Engines of several tenants, so their accounts are keyed by tenant and client.
+///
+/// Every tenant gets an engine of its own, built by a clone of the same builder. Anything the
+/// builder shares, e.g. observers, is shared by all tenants, while account stores and history
+/// spills on disk would mix up the accounts of the tenants.
+pub struct Tenants {
+    builder: EngineBuilder,
+    engines: BTreeMap<String, PaymentsEngine>,
+}
+
+impl Tenants {
+    pub fn new(builder: EngineBuilder) -> Self {
+        Tenants {
+            builder,
+            engines: BTreeMap::new(),
+        }
+    }
+
+    /// Engine of `tenant`, built on first use. It processes the batches sent through its
+    /// [`PaymentsEngine::batch_sender`].
+    pub fn engine(&mut self, tenant: &str) -> &mut PaymentsEngine {
+        let builder = &self.builder;
+        self.engines
+            .entry(tenant.to_owned())
+            .or_insert_with(|| builder.clone().build().0)
+    }
+
+    /// Engines of all tenants, ordered by tenant.
+    pub fn engines(&self) -> impl Iterator<Item = (&str, &PaymentsEngine)> {
+        self.engines
+            .iter()
+            .map(|(tenant, engine)| (tenant.as_str(), engine))
+    }
+
+    /// Writes the accounts of all tenants in `format`, with the tenant in the first column.
+    pub fn write_accounts_as<W: Write + Send>(
+        &self,
+        format: OutputFormat,
+        writer: W,
+    ) -> Result<()> {
+        let mut accounts = Vec::new();
+        for (tenant, engine) in self.engines() {
+            let rounded = engine.rounded_accounts()?;
+            accounts.extend(rounded.into_iter().map(|account| (tenant, account)));
+        }
+        output::write_tenants(&accounts, self.builder.precision, format, writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tenancy, Tenants};
+    use crate::{
+        output::OutputFormat,
+        transaction::{Transaction, TransactionType},
+        PaymentsEngine,
+    };
+    use std::path::PathBuf;
+
+    #[test]
+    fn group_files() {
+        let paths = vec![PathBuf::from("a/partner-1.csv"), PathBuf::from("b.csv")];
+        let tenants = Tenancy::Named("bank".into()).group(paths.clone()).unwrap();
+        assert_eq!(tenants.len(), 1);
+        assert_eq!(tenants["bank"], paths);
+
+        let paths = vec![
+            PathBuf::from("a/partner-1.csv"),
+            PathBuf::from("partner-2.json"),
+            PathBuf::from("b/partner-1.csv"),
+        ];
+        let tenants = Tenancy::PerFile.group(paths).unwrap();
+        assert_eq!(
+            tenants["partner-1"],
+            [PathBuf::from("a/partner-1.csv"), "b/partner-1.csv".into()]
+        );
+        assert_eq!(tenants["partner-2"], [PathBuf::from("partner-2.json")]);
+    }
+
+    #[tokio::test]
+    async fn colliding_ids() {
+        let mut tenants = Tenants::new(PaymentsEngine::builder());
+        for (tenant, amount) in [("b", "2.0"), ("a", "1.0")] {
+            let deposit = Transaction {
+                r#type: TransactionType::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(amount.parse().unwrap()),
+                counterparty: None,
+                timestamp: None,
+                from_ccy: None,
+                to_ccy: None,
+            };
+            tenants
+                .engine(tenant)
+                .apply_batch(vec![deposit])
+                .await
+                .unwrap();
+        }
+
+        let mut output = Vec::new();
+        tenants
+            .write_accounts_as(OutputFormat::Csv, &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "tenant,client,available,held,total,locked\na,1,1.0,0.0,1.0,false\nb,1,2.0,0.0,2.0,false\n"
+        );
+    }
+}
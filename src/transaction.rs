@@ -1,7 +1,310 @@
-#[derive(serde::Deserialize, Debug)]
+use crate::{amount::Amount, error::EngineError, fx::Currency};
+use std::fmt;
+
+/// Id of a client, a `u32` with the feature `wide-ids` and a `u16` otherwise
+#[cfg(not(feature = "wide-ids"))]
+pub type ClientId = u16;
+/// Id of a client, a `u32` with the feature `wide-ids` and a `u16` otherwise
+#[cfg(feature = "wide-ids")]
+pub type ClientId = u32;
+
+/// Id of a transaction, a `u64` with the feature `wide-ids` and a `u32` otherwise
+#[cfg(not(feature = "wide-ids"))]
+pub type TransactionId = u32;
+/// Id of a transaction, a `u64` with the feature `wide-ids` and a `u32` otherwise
+#[cfg(feature = "wide-ids")]
+pub type TransactionId = u64;
+
+#[derive(
+    serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug,
+)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Administrative command re-enabling a locked account
+    Unlock,
+    /// Moves funds from the account of the client to the account of the counterparty
+    Transfer,
+    /// Administrative command undoing the chargeback of a transaction
+    #[serde(rename = "chargeback_reversal")]
+    ChargebackReversal,
+    /// Holds an amount of the available funds, identified by its own id within the account
+    Hold,
+    /// Releases the held amount of an earlier hold, or a part of it
+    Release,
+    /// Administrative command committing a transaction staged until it is approved
+    Approve,
+    /// Administrative command discarding a transaction staged until it is approved
+    Reject,
+    /// Exchanges funds of the client from one currency into another at the configured rates
+    Convert,
+}
+
+impl TransactionType {
+    /// Whether the transaction introduces a new transaction id, instead of referring to a
+    /// previous transaction or being an administrative command.
+    pub fn introduces_transaction(self) -> bool {
+        matches!(
+            self,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Transfer
+                | TransactionType::Convert
+        )
+    }
+
+    /// Whether the transaction refers to a previous deposit, withdrawal or transfer.
+    pub fn refers_to_transaction(self) -> bool {
+        matches!(
+            self,
+            TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::ChargebackReversal
+                | TransactionType::Approve
+                | TransactionType::Reject
+        )
+    }
+
+    pub fn is_admin_command(self) -> bool {
+        matches!(
+            self,
+            TransactionType::Unlock
+                | TransactionType::ChargebackReversal
+                | TransactionType::Approve
+                | TransactionType::Reject
+        )
+    }
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Unlock => "unlock",
+            TransactionType::Transfer => "transfer",
+            TransactionType::ChargebackReversal => "chargeback_reversal",
+            TransactionType::Hold => "hold",
+            TransactionType::Release => "release",
+            TransactionType::Approve => "approve",
+            TransactionType::Reject => "reject",
+            TransactionType::Convert => "convert",
+        })
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct Transaction {
-    pub r#type: String,
-    pub client: u16,
-    pub tx: u32,
-    pub amount: Option<f32>,
+    pub r#type: TransactionType,
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Option<Amount>,
+    /// Client receiving the funds of a transfer
+    #[serde(default)]
+    pub counterparty: Option<ClientId>,
+    /// Seconds since the Unix epoch
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Currency a conversion takes the amount from
+    #[serde(default)]
+    pub from_ccy: Option<Currency>,
+    /// Currency a conversion credits the converted amount in
+    #[serde(default)]
+    pub to_ccy: Option<Currency>,
+}
+
+impl Transaction {
+    /// Checks that a given amount is positive, at most [`Amount::MAX`] and has at most
+    /// `precision` decimal places, that a transfer goes to another client and that a conversion
+    /// is between two currencies.
+    pub fn validate(&self, precision: u32) -> Result<(), EngineError> {
+        if self.r#type == TransactionType::Transfer
+            && self
+                .counterparty
+                .is_none_or(|counterparty| counterparty == self.client)
+        {
+            return Err(EngineError::InvalidCounterparty(self.tx));
+        }
+        if self.r#type == TransactionType::Convert
+            && !matches!((self.from_ccy, self.to_ccy), (Some(from), Some(to)) if from != to)
+        {
+            return Err(EngineError::InvalidConversion(self.tx));
+        }
+        match self.amount {
+            Some(amount) if !amount.is_positive() => Err(EngineError::NonPositiveAmount(self.tx)),
+            Some(amount) if amount > Amount::MAX => Err(EngineError::AmountOutOfRange(self.tx)),
+            Some(amount) if !amount.has_valid_precision(precision) => {
+                Err(EngineError::AmountTooPrecise(self.tx, precision))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Transactions fed into the engine together, see [`crate::PaymentsEngine::batch_sender`].
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Batch {
+    pub transactions: Vec<Transaction>,
+    /// Line of the record of every transaction in its input, which errors caused by it point
+    /// at. Lines missing at the end are unknown.
+    pub lines: Vec<Option<u64>>,
+}
+
+impl Batch {
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// The transactions, each with its line if it is known.
+    pub fn with_lines(self) -> impl Iterator<Item = (Transaction, Option<u64>)> {
+        let lines = self.lines.into_iter().chain(std::iter::repeat(None));
+        self.transactions.into_iter().zip(lines)
+    }
+}
+
+impl From<Vec<Transaction>> for Batch {
+    fn from(transactions: Vec<Transaction>) -> Self {
+        Batch {
+            transactions,
+            lines: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Transaction, TransactionType};
+    use crate::error::EngineError;
+    use csv::{ReaderBuilder, Trim};
+
+    #[test]
+    fn parse_transaction_type() {
+        let transactions = parse("type, client, tx, amount\nchargeback, 1, 2,\n");
+        let transaction = transactions[0].as_ref().unwrap();
+        assert_eq!(transaction.r#type, TransactionType::Chargeback);
+        assert!(transaction.amount.is_none());
+    }
+
+    #[test]
+    fn invalid_transaction_type() {
+        let transactions = parse("type, client, tx, amount\ninvalid, 1, 2, 1.0\n");
+        assert!(transactions[0].is_err());
+    }
+
+    #[test]
+    fn validate_amount() {
+        let transactions = parse(concat!(
+            "type, client, tx, amount\n",
+            "deposit, 1, 1, 1.5\n",
+            "deposit, 1, 2, 0\n",
+            "withdrawal, 1, 3, -1.0\n",
+            "deposit, 1, 4, 0.00001\n",
+            "dispute, 1, 1,\n",
+            "deposit, 1, 6, 1000000000000000000\n",
+            "deposit, 1, 7, 1000000000000000001\n",
+        ));
+        let results: Vec<_> = transactions
+            .into_iter()
+            .map(|transaction| transaction.unwrap().validate(4))
+            .collect();
+
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(EngineError::NonPositiveAmount(2))));
+        assert!(matches!(results[2], Err(EngineError::NonPositiveAmount(3))));
+        assert!(matches!(
+            results[3],
+            Err(EngineError::AmountTooPrecise(4, 4))
+        ));
+        assert!(results[4].is_ok());
+        assert!(results[5].is_ok());
+        assert!(matches!(results[6], Err(EngineError::AmountOutOfRange(7))));
+
+        // The error names the configured precision
+        let transactions = parse("type, client, tx, amount\ndeposit, 1, 8, 0.125\n");
+        let error = transactions[0].as_ref().unwrap().validate(2).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Amount of transaction `8` has more than 2 decimal places"
+        );
+    }
+
+    #[test]
+    fn validate_counterparty() {
+        let transactions = parse(concat!(
+            "type, client, tx, amount, counterparty\n",
+            "transfer, 1, 1, 1.5, 2\n",
+            "transfer, 1, 2, 1.5,\n",
+            "transfer, 1, 3, 1.5, 1\n",
+        ));
+        let results: Vec<_> = transactions
+            .into_iter()
+            .map(|transaction| transaction.unwrap().validate(4))
+            .collect();
+
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(EngineError::InvalidCounterparty(2))
+        ));
+        assert!(matches!(
+            results[2],
+            Err(EngineError::InvalidCounterparty(3))
+        ));
+    }
+
+    #[test]
+    fn validate_conversion() {
+        let transactions = parse(concat!(
+            "type, client, tx, amount, from_ccy, to_ccy\n",
+            "convert, 1, 1, 1.5, eur, USD\n",
+            "convert, 1, 2, 1.5, EUR,\n",
+            "convert, 1, 3, 1.5, EUR, EUR\n",
+        ));
+        let results: Vec<_> = transactions
+            .into_iter()
+            .map(|transaction| transaction.unwrap().validate(4))
+            .collect();
+
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(EngineError::InvalidConversion(2))));
+        assert!(matches!(results[2], Err(EngineError::InvalidConversion(3))));
+        assert!(
+            parse("type, client, tx, amount, from_ccy\nconvert, 1, 4, 1.5, EURO\n")[0].is_err()
+        );
+    }
+
+    #[test]
+    fn wide_ids() {
+        let transactions = parse("type, client, tx, amount\ndeposit, 70000, 5000000000, 1.0\n");
+        if cfg!(feature = "wide-ids") {
+            let transaction = transactions[0].as_ref().unwrap();
+            assert_eq!(u64::from(transaction.client), 70_000);
+            assert_eq!(u64::from(transaction.tx), 5_000_000_000);
+        } else {
+            assert!(transactions[0].is_err());
+        }
+    }
+
+    fn parse(input: &str) -> Vec<csv::Result<Transaction>> {
+        ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(input.as_bytes())
+            .deserialize()
+            .collect()
+    }
 }
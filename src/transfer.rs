@@ -0,0 +1,99 @@
+use crate::{
+    amount::Amount,
+    event::AccountEvent,
+    transaction::{ClientId, Transaction, TransactionId, TransactionType},
+};
+use rustc_hash::FxHashMap;
+
+/// Counterparty and amount of every transfer, so its chargeback and the reversal of that are
+/// booked on the account of the counterparty as well, and the counterparty of every transfer
+/// staged until it is approved.
+#[derive(Default, Debug)]
+pub(crate) struct Transfers {
+    transfers: FxHashMap<TransactionId, (ClientId, Amount)>,
+    staged: FxHashMap<TransactionId, ClientId>,
+}
+
+impl Transfers {
+    pub fn insert(&mut self, tx: TransactionId, counterparty: ClientId, amount: Amount) {
+        self.transfers.insert(tx, (counterparty, amount));
+    }
+
+    /// Remembers the counterparty of the staged transfer with id `tx` until it is approved or
+    /// rejected.
+    pub fn stage(&mut self, tx: TransactionId, counterparty: ClientId) {
+        self.staged.insert(tx, counterparty);
+    }
+
+    /// Counterparty of the transfer with id `tx`, if there is one.
+    pub fn counterparty(&self, tx: TransactionId) -> Option<ClientId> {
+        self.transfers
+            .get(&tx)
+            .map(|&(counterparty, _)| counterparty)
+    }
+
+    pub fn clear(&mut self) {
+        self.transfers.clear();
+        self.staged.clear();
+    }
+
+    /// Account a transfer, or the chargeback of one or its reversal, changes besides the one of
+    /// its client. The approval or rejection of a staged transfer is routed like the transfer.
+    pub fn counterpart_of(&self, transaction: &Transaction) -> Option<ClientId> {
+        match transaction.r#type {
+            TransactionType::Transfer => transaction.counterparty,
+            TransactionType::Chargeback | TransactionType::ChargebackReversal => {
+                self.counterparty(transaction.tx)
+            }
+            TransactionType::Approve | TransactionType::Reject => {
+                self.staged.get(&transaction.tx).copied()
+            }
+            _ => None,
+        }
+    }
+
+    /// Event of the account of `counterparty` matching `event` of the client, if it moved funds
+    /// between them. A new or staged transfer is remembered.
+    pub fn counterpart(
+        &mut self,
+        event: AccountEvent,
+        counterparty: ClientId,
+    ) -> Option<AccountEvent> {
+        match event {
+            AccountEvent::TransferredOut {
+                client, tx, amount, ..
+            } => {
+                self.staged.remove(&tx);
+                self.insert(tx, counterparty, amount);
+                Some(AccountEvent::TransferredIn {
+                    client: counterparty,
+                    tx,
+                    counterparty: client,
+                    amount,
+                })
+            }
+            AccountEvent::ChargedBack { tx, .. } => Some(AccountEvent::TransferReversed {
+                client: counterparty,
+                tx,
+                amount: self.transfers[&tx].1,
+            }),
+            // The counterparty receives the transfer again
+            AccountEvent::ChargebackReversed { client, tx } => Some(AccountEvent::TransferredIn {
+                client: counterparty,
+                tx,
+                counterparty: client,
+                amount: self.transfers[&tx].1,
+            }),
+            AccountEvent::Staged { tx, .. } => {
+                self.stage(tx, counterparty);
+                None
+            }
+            // Rejected, or declined once it was approved
+            AccountEvent::Unstaged { tx, .. } | AccountEvent::WithdrawalDeclined { tx, .. } => {
+                self.staged.remove(&tx);
+                None
+            }
+            _ => None,
+        }
+    }
+}
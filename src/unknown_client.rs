@@ -0,0 +1,49 @@
+use crate::{error::EngineError, outcome::TransactionOutcome, transaction::Transaction};
+use std::str::FromStr;
+
+/// How a dispute, resolve, chargeback or chargeback reversal is handled for a client that has no
+/// account yet, e.g. in a file holding only disputes.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum UnknownClientPolicy {
+    /// Open an empty account for the client, which appears in the output
+    #[default]
+    Create,
+    /// Skip it with the outcome `no_such_transaction`, without opening an account
+    Skip,
+    /// Treat it as an invalid transaction, without opening an account
+    Reject,
+}
+
+impl UnknownClientPolicy {
+    /// Result of `transaction` for a client without account, `None` if an account is opened
+    /// for it.
+    pub(crate) fn outcome(
+        self,
+        transaction: &Transaction,
+    ) -> Option<Result<TransactionOutcome, EngineError>> {
+        match self {
+            UnknownClientPolicy::Create => None,
+            UnknownClientPolicy::Skip => Some(Ok(TransactionOutcome::NoSuchTransaction)),
+            UnknownClientPolicy::Reject => Some(Err(EngineError::UnknownClient(
+                transaction.tx,
+                transaction.client,
+            ))),
+        }
+    }
+}
+
+impl FromStr for UnknownClientPolicy {
+    type Err = EngineError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(UnknownClientPolicy::Create),
+            "skip" => Ok(UnknownClientPolicy::Skip),
+            "reject" => Ok(UnknownClientPolicy::Reject),
+            _ => Err(EngineError::InvalidArgumentValue(
+                "--unknown-clients".into(),
+                s.into(),
+            )),
+        }
+    }
+}
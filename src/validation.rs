@@ -0,0 +1,212 @@
+use crate::{
+    amount::DEFAULT_PRECISION,
+    collector::TransactionSource,
+    error::{with_sources, EngineError},
+    transaction::{ClientId, Transaction, TransactionId, TransactionType},
+};
+use rustc_hash::FxHashMap;
+use std::{
+    collections::{hash_map::Entry, BTreeMap},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// Number of invalid records a [`ValidationReport`] lists, the others are only counted.
+pub const LISTED_ISSUES: usize = 20;
+
+/// Checks transactions the way the engine does, without applying them to any account.
+#[derive(Debug)]
+pub struct Validator {
+    precision: u32,
+    // Client of every deposit, withdrawal and transfer
+    transaction_ids: FxHashMap<TransactionId, ClientId>,
+    report: ValidationReport,
+}
+
+/// Result of validating the input, see [`Validator`].
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct ValidationReport {
+    pub records: u64,
+    pub invalid: u64,
+    /// Invalid records by the reason why
+    pub reasons: BTreeMap<String, u64>,
+    /// The first [`LISTED_ISSUES`] invalid records
+    pub issues: Vec<Issue>,
+}
+
+/// Invalid record of the input.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Issue {
+    pub path: PathBuf,
+    /// Position among the records of the file, starting at 1
+    pub record: u64,
+    pub reason: String,
+}
+
+impl Validator {
+    pub fn new(precision: u32) -> Self {
+        Validator {
+            precision,
+            transaction_ids: FxHashMap::default(),
+            report: ValidationReport::default(),
+        }
+    }
+
+    /// Validates all records of `source`, which were read from `path`.
+    pub async fn check_source<S: TransactionSource>(&mut self, mut source: S, path: &Path) {
+        let mut record = 0;
+        while let Some(result) = source.next_transaction().await {
+            record += 1;
+            self.report.records += 1;
+            let invalid = match result {
+                Ok(transaction) => self
+                    .check(&transaction)
+                    .map_err(|error| (error.rejection_reason(), error.to_string())),
+                Err(error) => Err(("Malformed record", with_sources(&error))),
+            };
+            if let Err((reason, details)) = invalid {
+                self.report.add_issue(path, record, reason, details);
+            }
+        }
+    }
+
+    /// Checks the amount and counterparty of `transaction`, that its id is unique and that
+    /// disputes, resolves and chargebacks refer to an earlier transaction of the same client.
+    pub fn check(&mut self, transaction: &Transaction) -> Result<(), EngineError> {
+        transaction.validate(self.precision)?;
+        let Transaction {
+            r#type,
+            client,
+            tx,
+            amount,
+            ..
+        } = *transaction;
+        match (r#type, amount) {
+            (TransactionType::Deposit, None) => return Err(EngineError::NoAmountInDeposit),
+            (TransactionType::Withdrawal, None) => return Err(EngineError::NoAmountInWitdrawal),
+            (TransactionType::Transfer, None) => return Err(EngineError::NoAmountInTransfer),
+            (TransactionType::Hold, None) => return Err(EngineError::NoAmountInHold),
+            (TransactionType::Convert, None) => return Err(EngineError::NoAmountInConversion),
+            _ => {}
+        }
+
+        if r#type.introduces_transaction() {
+            match self.transaction_ids.entry(tx) {
+                Entry::Occupied(_) => return Err(EngineError::DuplicateTransactionId(tx)),
+                Entry::Vacant(entry) => {
+                    entry.insert(client);
+                }
+            }
+        } else if r#type.refers_to_transaction() {
+            match self.transaction_ids.get(&tx) {
+                None => return Err(EngineError::UnknownTransaction(tx)),
+                Some(&owner) if owner != client => {
+                    return Err(EngineError::ClientMismatchOnDispute(tx, client))
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn report(&self) -> &ValidationReport {
+        &self.report
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Validator::new(DEFAULT_PRECISION)
+    }
+}
+
+impl ValidationReport {
+    fn add_issue(&mut self, path: &Path, record: u64, reason: &str, details: String) {
+        self.invalid += 1;
+        *self.reasons.entry(reason.to_owned()).or_default() += 1;
+        if self.issues.len() < LISTED_ISSUES {
+            self.issues.push(Issue {
+                path: path.to_owned(),
+                record,
+                reason: details,
+            });
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.invalid == 0
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Records: {}", self.records)?;
+        writeln!(f, "Invalid: {}", self.invalid)?;
+        for (reason, count) in &self.reasons {
+            writeln!(f, "  {reason}: {count}")?;
+        }
+        for issue in &self.issues {
+            writeln!(
+                f,
+                "{} record {}: {}",
+                issue.path.display(),
+                issue.record,
+                issue.reason
+            )?;
+        }
+        if self.invalid > self.issues.len() as u64 {
+            writeln!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Validator;
+    use crate::{
+        collector::MemorySource,
+        transaction::{ClientId, Transaction, TransactionId, TransactionType},
+    };
+    use std::path::Path;
+
+    fn transaction(
+        r#type: TransactionType,
+        client: ClientId,
+        tx: TransactionId,
+        amount: &str,
+    ) -> Transaction {
+        Transaction {
+            r#type,
+            client,
+            tx,
+            amount: amount.parse().ok(),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn report_invalid_records() {
+        let mut validator = Validator::default();
+        let source = MemorySource::from(vec![
+            transaction(TransactionType::Deposit, 1, 1, "1.0"),
+            transaction(TransactionType::Deposit, 2, 1, "1.0"),
+            transaction(TransactionType::Deposit, 1, 2, "-1.0"),
+            transaction(TransactionType::Dispute, 1, 3, ""),
+            transaction(TransactionType::Dispute, 2, 1, ""),
+            transaction(TransactionType::Dispute, 1, 1, ""),
+        ]);
+        validator.check_source(source, Path::new("input.csv")).await;
+
+        let report = validator.report();
+        assert_eq!((report.records, report.invalid), (6, 4));
+        assert_eq!(report.reasons["Duplicate transaction id"], 1);
+        assert_eq!(report.reasons["Non-positive amount"], 1);
+        assert_eq!(report.reasons["Unknown transaction"], 1);
+        assert_eq!(report.reasons["Client mismatch on dispute"], 1);
+        assert_eq!(report.issues[0].record, 2);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::transaction::{ClientId, Transaction, TransactionId, TransactionType};
+use anyhow::Result;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_decimal::Decimal;
+use std::io::Write;
+
+// Share of the settled disputes that are charged back instead of resolved
+const CHARGEBACK_RATIO: f64 = 0.1;
+
+/// Synthetic workload to measure the throughput of the engine.
+///
+/// The same workload always generates the same transactions, which are valid in strict mode.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Workload {
+    pub clients: ClientId,
+    pub transactions: TransactionId,
+    /// Share of the transactions disputing a previous deposit, about as many settle a dispute
+    pub dispute_ratio: f64,
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload {
+            clients: 1000,
+            transactions: 100_000,
+            dispute_ratio: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+impl Workload {
+    pub fn generate(&self) -> impl Iterator<Item = Transaction> {
+        let mut generator = Generator {
+            workload: *self,
+            rng: StdRng::seed_from_u64(self.seed),
+            next_tx: 1,
+            deposits: Vec::new(),
+            disputes: Vec::new(),
+        };
+        (0..self.transactions).map(move |_| generator.next_transaction())
+    }
+
+    /// Writes the transactions as CSV, in the format read by the collector.
+    pub fn write_csv<W: Write>(&self, output: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(output);
+        self.generate()
+            .try_for_each(|transaction| writer.serialize(transaction))?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+struct Generator {
+    workload: Workload,
+    rng: StdRng,
+    next_tx: TransactionId,
+    // Deposits that can be disputed, and the deposits in dispute, by client and id
+    deposits: Vec<(ClientId, TransactionId)>,
+    disputes: Vec<(ClientId, TransactionId)>,
+}
+
+impl Generator {
+    fn next_transaction(&mut self) -> Transaction {
+        let dispute_ratio = self.workload.dispute_ratio;
+        if !self.deposits.is_empty() && self.rng.random_bool(dispute_ratio) {
+            let (client, tx) = self.take_random(false);
+            self.disputes.push((client, tx));
+            return reference(TransactionType::Dispute, client, tx);
+        }
+        if !self.disputes.is_empty() && self.rng.random_bool(dispute_ratio) {
+            let (client, tx) = self.take_random(true);
+            let r#type = if self.rng.random_bool(CHARGEBACK_RATIO) {
+                TransactionType::Chargeback
+            } else {
+                TransactionType::Resolve
+            };
+            return reference(r#type, client, tx);
+        }
+
+        let client = self.rng.random_range(1..=self.workload.clients.max(1));
+        let tx = self.next_tx;
+        self.next_tx += 1;
+        let r#type = if self.rng.random_bool(0.6) {
+            self.deposits.push((client, tx));
+            TransactionType::Deposit
+        } else {
+            TransactionType::Withdrawal
+        };
+        Transaction {
+            r#type,
+            client,
+            tx,
+            amount: Some(Decimal::new(self.rng.random_range(1..10_000_000), 4).into()),
+            counterparty: None,
+            timestamp: None,
+            from_ccy: None,
+            to_ccy: None,
+        }
+    }
+
+    fn take_random(&mut self, dispute: bool) -> (ClientId, TransactionId) {
+        let candidates = if dispute {
+            &mut self.disputes
+        } else {
+            &mut self.deposits
+        };
+        let index = self.rng.random_range(0..candidates.len());
+        candidates.swap_remove(index)
+    }
+}
+
+fn reference(r#type: TransactionType, client: ClientId, tx: TransactionId) -> Transaction {
+    Transaction {
+        r#type,
+        client,
+        tx,
+        amount: None,
+        counterparty: None,
+        timestamp: None,
+        from_ccy: None,
+        to_ccy: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Workload;
+    use crate::{transaction::TransactionType, PaymentsEngine};
+
+    #[tokio::test]
+    async fn generate_valid_workload() {
+        let workload = Workload {
+            clients: 10,
+            transactions: 1000,
+            dispute_ratio: 0.1,
+            seed: 7,
+        };
+        let transactions: Vec<_> = workload.generate().collect();
+        assert_eq!(transactions.len(), 1000);
+        assert!(transactions
+            .iter()
+            .any(|transaction| transaction.r#type == TransactionType::Dispute));
+        assert_eq!(transactions, workload.generate().collect::<Vec<_>>());
+
+        let (mut payments_engine, sender) = PaymentsEngine::with_workers(2);
+        let producer = tokio::spawn(async move {
+            for transaction in transactions {
+                sender.send(transaction).await.unwrap();
+            }
+        });
+        payments_engine.process_transactions().await.unwrap();
+        producer.await.unwrap();
+        let progress = payments_engine.progress().snapshot();
+        assert_eq!(progress.applied + progress.rejected, 1000);
+    }
+}